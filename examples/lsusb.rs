@@ -0,0 +1,37 @@
+extern crate usbfs;
+
+use usbfs::*;
+use std::env;
+use std::process;
+
+
+// A minimal lsusb.  Lists devices by default; `-t` shows the bus topology and `-v` dumps
+// the descriptors of every device.  Everything comes from sysfs, so no permissions on the
+// devices themselves are needed.
+
+fn main() {
+    let tree = match DeviceTree::scan() {
+        Ok(tree) => tree,
+        Err(err) => {
+            eprintln!("can't scan USB devices: {}", err);
+            process::exit(1);
+        }
+    };
+
+    match env::args().nth(1).as_deref() {
+        None => {
+            for device in tree.devices() {
+                let s = &device.summary;
+                println!("Bus {:03} Device {:03}: ID {:04x}:{:04x} {}",
+                         s.busnum, s.devnum, s.descriptor.idVendor, s.descriptor.idProduct,
+                         s.product.as_deref().unwrap_or(""));
+            }
+        }
+        Some("-t") => print!("{}", tree.render_tree()),
+        Some("-v") => print!("{}", tree.render_verbose()),
+        Some(_) => {
+            eprintln!("usage: lsusb [-t | -v]");
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+extern crate usbfs;
+extern crate mio;
+
+use usbfs::*;
+use mio::{Events, Interest, Poll, Token};
+use std::io;
+
+
+// This demo repeatedly reads the HW serial number of a custom USB device with asynchronous
+// control transfers driven by a mio event loop.
+//
+// Run with `cargo run --example mio_demo --features mio`.
+
+const DEVICE: Token = Token(0);
+
+fn main() {
+    mio_demo().unwrap();
+}
+
+
+/// Perform asynchronous transfers using nonblocking reap and mio.
+fn mio_demo() -> io::Result<()> {
+    println!("mio_demo()");
+
+    let mut device: AsyncDevice<Box<StdBufTransfer<Vec<u8>>>> = AsyncDevice::new(&get_my_device())?;
+
+    // completed transfers make the device writable
+    let mut poll = Poll::new()?;
+    poll.registry().register(&mut device, DEVICE, Interest::WRITABLE)?;
+
+    // start the first transfer
+    device.submit(Box::new(make_transfer()))?;
+
+    // run the loop a few times
+    let mut events = Events::with_capacity(8);
+    let mut count = 0;
+    while count < 10 {
+        poll.poll(&mut events, None)?;
+        for event in events.iter() {
+            if event.token() != DEVICE {
+                continue;
+            }
+            // events are edge triggered, so reap everything that is available
+            loop {
+                match device.reap_nowait() {
+                    Ok((_slot, xfer, result)) => {
+                        let len = result.into_io_result()?;
+                        print!("HW serial = ");
+                        printbuf(&xfer.buf[8..8 + len]);
+                        count += 1;
+                        device.submit(xfer)?;
+                    }
+                    Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+    }
+
+    poll.registry().deregister(&mut device)?;
+    Ok(())
+}
+
+
+fn make_transfer() -> StdBufTransfer<Vec<u8>> {
+    StdBufTransfer::control(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Interface,
+                            3, // request (gets HW serial number)
+                            0, // value (ignored for this request)
+                            0, // index (Watchdog)
+                            UrbFlags::empty(),
+                            vec![0; 8 + 16])
+}
+
+
+fn get_my_device() -> DeviceInfo {
+    // find my custom LPCxpresso device
+    deviceinfo_find(0xffff, 4).unwrap()
+}
+
+
+fn printbuf(buf: &[u8]) {
+    for &byte in buf.iter() {
+        print!("{:02x}", byte);
+    }
+    println!();
+}
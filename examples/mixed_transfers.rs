@@ -0,0 +1,84 @@
+extern crate usbfs;
+
+use usbfs::*;
+use std::io;
+
+
+// This demo queues isochronous IN transfers and a control transfer on the same AsyncDevice,
+// telling them apart after reaping by downcasting.
+
+#[derive(Debug)]
+struct Packets(Vec<u8>);
+
+impl AsMut<[u8]> for Packets {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl IsoBuffer for Packets {
+    fn packet_length(&self) -> usize {
+        192
+    }
+}
+
+type IsoXfer = IsoBufTransfer<Packets, 8>;
+
+fn main() {
+    mixed_demo().unwrap();
+}
+
+
+fn mixed_demo() -> io::Result<()> {
+    println!("mixed_demo()");
+
+    let mut device: AsyncDevice<DynTransfer> = AsyncDevice::new(&get_my_device())?;
+
+    // keep a couple of iso transfers streaming
+    for _ in 0..2 {
+        let xfer: IsoXfer = IsoBufTransfer::isochronous(0x81, UrbFlags::URB_ISO_ASAP, Packets(vec![0; 8 * 192]));
+        device.submit(Box::new(xfer))?;
+    }
+
+    // and ask for the serial number in between
+    device.submit(Box::new(make_control_transfer()))?;
+
+    for _ in 0..20 {
+        let (_slot, xfer, result) = device.reap_wait()?;
+        if xfer.is::<IsoXfer>() {
+            let iso = xfer.downcast_ref::<IsoXfer>().unwrap();
+            let total: i32 = iso.status().iter().map(|p| p.actual_length).sum();
+            println!("iso {:?}, {} bytes", result, total);
+            device.submit(xfer)?;
+        } else if let Some(control) = xfer.downcast_ref::<StdBufTransfer<Vec<u8>>>() {
+            let len = result.into_io_result()?;
+            print!("HW serial = ");
+            printbuf(&control.buf[8..8 + len]);
+        }
+    }
+    Ok(())
+}
+
+
+fn make_control_transfer() -> StdBufTransfer<Vec<u8>> {
+    StdBufTransfer::control(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Interface,
+                            3, // request (gets HW serial number)
+                            0, // value (ignored for this request)
+                            0, // index (Watchdog)
+                            UrbFlags::empty(),
+                            vec![0; 8 + 16])
+}
+
+
+fn get_my_device() -> DeviceInfo {
+    // find my custom LPCxpresso device
+    deviceinfo_find(0xffff, 4).unwrap()
+}
+
+
+fn printbuf(buf: &[u8]) {
+    for &byte in buf.iter() {
+        print!("{:02x}", byte);
+    }
+    println!();
+}
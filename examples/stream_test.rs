@@ -3,13 +3,11 @@ extern crate usbfs;
 
 use usbfs::*;
 //use mio::*;
-use std::thread::sleep;
-use std::time::Duration;
 use std::io;
 
 
-/// This demo application performs a control transfer with a custom USB device using
-/// the various techniques supported by the usbfs crate.
+// This demo application performs a control transfer with a custom USB device using
+// the various techniques supported by the usbfs crate.
 
 fn main() {
     my_test().unwrap();
@@ -55,10 +53,6 @@ impl IsoBuffer for UrbDataFrame {
     fn packet_length(&self) -> usize {
         std::mem::size_of_val(&self.streamframes[0])
     }
-//    fn packet_lengths(&self) -> impl Iterator<Item=usize> {
-//        let packet_size = size_of_val(&self.streamframes[0]);
-//        iter::repeat(packet_size) // infinite iterator, will be constrained by buffer size
-//    }
 }
 
 
@@ -109,79 +103,50 @@ fn my_test() -> io::Result<()> {
 //                             1000).unwrap();
 
 
-//    let asyncdevice = AsyncDevice::new(&deviceinfo).unwrap();
-    let mut asyncdevice : AsyncDevice<_> = device.into();
-
-
     // get the transfers rolling
-    for _ in 0..2 {
-        let buf: UrbDataFrame = Default::default();
-        let xfer: IsoBufTransfer<UrbDataFrame,32> = IsoBufTransfer::isochronous(
+    let mut stream: IsoStream<UrbDataFrame,32> = IsoStream::with_depth(device,
                                  0x81,  // endpoint
                                  UrbFlags::empty(),  // flags (none)
-                                 buf );
-//        println!("{:?}", xfer);
-        asyncdevice.submit(Box::new(xfer)).unwrap();
-    }
+                                 2).unwrap();  // queue depth
 
 
-
-    //for _ in 0..10 {
     let mut count = 0;      // urb cycles
     let mut seq_faults: usize = 0;
     let mut seq = None;
-//    let mut samples = 0;    // number of u32
-//    let mut expected = 50;
     loop {
         count += 1;
-        if (count%100) == 0 {
-//            println!("samples = {:?}",samples);
-        };
-
-        match asyncdevice.reap_wait() {
-            Ok(xfer) => {
-
-                println!("urb seq = {}", count);
-                println!("seq_faults = {}", seq_faults);
-                for packet in xfer.status() {
-                    println!("status={}, actual_length={}, length={}", packet.status, packet.actual_length, packet.length)
-                }
-
-                // iterate StreamFrames
-                xfer.status().iter().zip(xfer.buf.streamframes.iter())
-                    .flat_map(|(status, streamframes)| {
-                        let cnt = match status.status {
-                            0 => (status.actual_length as usize) / std::mem::size_of_val(&streamframes[0]),
-                            _ => 0,
-                        };
-                        streamframes[..cnt].iter()
-                    })
-                    .for_each(|x| {
-                        match (seq, x.seq) {
-                            (None, _t) => (), // intialize seq
-                            (Some(65535), 0) => (),
-                            (Some(s), t) if (s+1)==t => (),
-                            _ => {seq_faults +=1;},
-                        }
-                        seq = Some(x.seq);
-
-//                        println!("{:?}",x)
-                    });
-
-
-                println!("streamframes[0][0] = {:?}", xfer.buf.streamframes[0][0]);
-                println!("streamframes[0][1] = {:?}", xfer.buf.streamframes[0][1]);
-                println!();
-
-                asyncdevice.submit(xfer).unwrap();
-            },
-            // Ok((_slot, _xfer, Err(err))) => {
-            //     //println!("error={:?}",err);
-            //     panic!("transfer had an error!")
-            // },
-            Err(_err) =>
-                panic!("reap had an error!"),
-        };
+
+        stream.next_transfer(|xfer| {
+            println!("urb seq = {}", count);
+            println!("seq_faults = {}", seq_faults);
+            for (packet, _data) in xfer.packets() {
+                println!("status={}, actual_length={}, length={}", packet.status, packet.actual_length, packet.length)
+            }
+
+            // iterate StreamFrames
+            xfer.packets().zip(xfer.buf.streamframes.iter())
+                .flat_map(|((packet, data), streamframes)| {
+                    let cnt = match packet.status {
+                        0 => data.len() / std::mem::size_of_val(&streamframes[0]),
+                        _ => 0,
+                    };
+                    streamframes[..cnt].iter()
+                })
+                .for_each(|x| {
+                    match (seq, x.seq) {
+                        (None, _t) => (), // intialize seq
+                        (Some(65535), 0) => (),
+                        (Some(s), t) if (s+1)==t => (),
+                        _ => {seq_faults +=1;},
+                    }
+                    seq = Some(x.seq);
+                });
+
+
+            println!("streamframes[0][0] = {:?}", xfer.buf.streamframes[0][0]);
+            println!("streamframes[0][1] = {:?}", xfer.buf.streamframes[0][1]);
+            println!();
+        }).expect("stream failed");
     }; //loop
 //    Ok(())
 }
@@ -219,7 +184,7 @@ fn my_test() -> io::Result<()> {
 //             0, // index (ignored for this request)
 //             64,
 //             );
-//     write_setup_struct(&setup.to_bus(), &mut xfer.buf);
+//     write_setup_struct(&setup, &mut xfer.buf);
 
 //     xfer
 // }
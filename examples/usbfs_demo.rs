@@ -6,8 +6,8 @@ use usbfs::*;
 use std::io;
 
 
-/// This demo application performs a control transfer with a custom USB device using
-/// the various techniques supported by the usbfs crate.
+// This demo application performs a control transfer with a custom USB device using
+// the various techniques supported by the usbfs crate.
 
 fn main() {
     sync_demo().unwrap();
@@ -125,7 +125,7 @@ fn sync_demo() -> io::Result<()> {
 //    Ok(())
 //}
 
-/// Perform asynchronous transfers using nonblocking reap and mio.
+///// Perform asynchronous transfers using nonblocking reap and mio.
 //fn async_transfer_mio_demo() -> io::Result<()> {
 //    println!("async_transfer_mio_demo()");
 //
@@ -0,0 +1,117 @@
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::ptr::NonNull;
+use std::slice;
+
+/// Heap buffer of `N` bytes aligned to `ALIGN` bytes.
+///
+/// Host controllers DMA straight from transfer buffers, but a buffer that is misaligned for
+/// the controller or the IOMMU may be bounced through a kernel copy, costing throughput on fast
+/// streams.  Allocating transfer buffers as `AlignedBuf`, commonly with the 4096 byte page size
+/// or the 64 byte cache line, avoids this.  It implements `AsRef<[u8]>` and `AsMut<[u8]>` so it
+/// can be used as the buffer of any transfer type.
+///
+/// `ALIGN` must be a power of two and `N` must be non-zero; both are checked at compile time.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let buf = AlignedBuf::<16384, 4096>::new();
+/// let xfer = Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), buf));
+/// let mut asyncdevice: AsyncDevice<_> = device.into();
+/// asyncdevice.submit(xfer).unwrap();
+/// ```
+pub struct AlignedBuf<const N: usize, const ALIGN: usize> {
+    buf: HeapBuf,
+}
+
+impl<const N: usize, const ALIGN: usize> AlignedBuf<N, ALIGN> {
+    const LAYOUT: Layout = {
+        assert!(N > 0, "AlignedBuf must not be empty");
+        match Layout::from_size_align(N, ALIGN) {
+            Ok(layout) => layout,
+            Err(_) => panic!("AlignedBuf alignment must be a power of two"),
+        }
+    };
+
+    /// Allocate a zeroed buffer.
+    pub fn new() -> AlignedBuf<N, ALIGN> {
+        AlignedBuf{buf: HeapBuf::new(Self::LAYOUT)}
+    }
+
+    pub fn len(&self) -> usize {
+        N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+impl<const N: usize, const ALIGN: usize> Default for AlignedBuf<N, ALIGN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const ALIGN: usize> Clone for AlignedBuf<N, ALIGN> {
+    fn clone(&self) -> Self {
+        let mut buf = Self::new();
+        buf.as_mut().copy_from_slice(self.as_ref());
+        buf
+    }
+}
+
+impl<const N: usize, const ALIGN: usize> fmt::Debug for AlignedBuf<N, ALIGN> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AlignedBuf").field("len", &N).field("align", &ALIGN).finish()
+    }
+}
+
+impl<const N: usize, const ALIGN: usize> AsRef<[u8]> for AlignedBuf<N, ALIGN> {
+    fn as_ref(&self) -> &[u8] {
+        self.buf.as_slice()
+    }
+}
+
+impl<const N: usize, const ALIGN: usize> AsMut<[u8]> for AlignedBuf<N, ALIGN> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self.buf.as_mut_slice()
+    }
+}
+
+// Zeroed heap allocation with a given layout, shared with `BufferPool`.
+pub(crate) struct HeapBuf {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+// The allocation is exclusively owned by the HeapBuf.
+unsafe impl Send for HeapBuf {}
+unsafe impl Sync for HeapBuf {}
+
+impl HeapBuf {
+    // `layout` must have a non-zero size.
+    pub(crate) fn new(layout: Layout) -> HeapBuf {
+        match NonNull::new(unsafe { alloc::alloc_zeroed(layout) }) {
+            Some(ptr) => HeapBuf{ptr, layout},
+            None => alloc::handle_alloc_error(layout),
+        }
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+    }
+
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for HeapBuf {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
@@ -0,0 +1,138 @@
+use super::*;
+
+/// Parsed interface association descriptor, which groups consecutive interfaces into one
+/// function of a composite device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct InterfaceAssociation {
+    pub bFirstInterface: u8,
+    pub bInterfaceCount: u8,
+    pub bFunctionClass: u8,
+    pub bFunctionSubClass: u8,
+    pub bFunctionProtocol: u8,
+    pub iFunction: u8,
+}
+
+impl InterfaceAssociation {
+    /// Whether interface `number` belongs to the function.
+    pub fn contains(&self, number: u8) -> bool {
+        number >= self.bFirstInterface && (number - self.bFirstInterface) < self.bInterfaceCount
+    }
+}
+
+/// A function of a device: the interfaces grouped by an interface association descriptor, or
+/// a single interface that no association covers.  See `ConfigDescriptor::functions()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct Function {
+    pub association: Option<InterfaceAssociation>,
+    /// `bInterfaceNumber` of each interface, in ascending order.
+    pub interfaces: Vec<u8>,
+    /// Class, subclass, and protocol of the association, or of the interface's first
+    /// alternate setting.
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+}
+
+impl ConfigDescriptor {
+    /// Interface association descriptors of the configuration, in the order they appear.
+    /// They are kept among the `extra` descriptors of the configuration, interfaces, and
+    /// endpoints they follow.
+    pub fn associations(&self) -> Vec<InterfaceAssociation> {
+        let interfaces = self.interfaces.iter().flat_map(|intf| {
+            intf.extra.iter().chain(intf.endpoints.iter().flat_map(|ep| ep.extra.iter()))
+        });
+        self.extra.iter().chain(interfaces)
+            .filter(|d| d.descriptor_type() == DescriptorType::InterfaceAssociation as u8 && d.0.len() >= 8)
+            .map(|d| InterfaceAssociation {
+                bFirstInterface: d.0[2],
+                bInterfaceCount: d.0[3],
+                bFunctionClass: d.0[4],
+                bFunctionSubClass: d.0[5],
+                bFunctionProtocol: d.0[6],
+                iFunction: d.0[7],
+            })
+            .collect()
+    }
+
+    /// The functions of the configuration, in order of their first interface.
+    ///
+    /// Composite devices such as CDC serial ports and UVC cameras combine several interfaces
+    /// in each function, for example a control interface and a data interface, which are
+    /// driven and claimed together.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// let config = &deviceinfo_find(0xffff, 3).unwrap().configurations().unwrap()[0];
+    /// for function in config.functions() {
+    ///     println!("class {:02x}: interfaces {:?}", function.class, function.interfaces);
+    /// }
+    /// ```
+    pub fn functions(&self) -> Vec<Function> {
+        let associations = self.associations();
+        let mut functions: Vec<Function> = Vec::new();
+        for intf in &self.interfaces {
+            let number = intf.bInterfaceNumber;
+            if functions.iter().any(|f| f.interfaces.contains(&number)) {
+                continue;
+            }
+            let function = match associations.iter().find(|a| a.contains(number)) {
+                Some(a) => {
+                    let mut interfaces: Vec<u8> = self.interfaces.iter()
+                        .map(|i| i.bInterfaceNumber)
+                        .filter(|&n| a.contains(n))
+                        .collect();
+                    interfaces.sort_unstable();
+                    interfaces.dedup();
+                    Function {
+                        association: Some(*a),
+                        interfaces,
+                        class: a.bFunctionClass,
+                        subclass: a.bFunctionSubClass,
+                        protocol: a.bFunctionProtocol,
+                    }
+                }
+                None => Function {
+                    association: None,
+                    interfaces: vec![number],
+                    class: intf.bInterfaceClass,
+                    subclass: intf.bInterfaceSubClass,
+                    protocol: intf.bInterfaceProtocol,
+                },
+            };
+            functions.push(function);
+        }
+        functions.sort_by_key(|f| f.interfaces[0]);
+        functions
+    }
+
+    /// The function that interface `number` belongs to.
+    pub fn function(&self, number: u8) -> Option<Function> {
+        self.functions().into_iter().find(|f| f.interfaces.contains(&number))
+    }
+}
+
+impl Device {
+    /// Claim every interface of the function that interface `interface` of the active
+    /// configuration belongs to, returning their handles in interface order.  If any claim
+    /// fails, those already made are released and the error returned.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+    /// let function = device.claim_function(0).unwrap();
+    /// let (control, data) = (&function[0], &function[1]);
+    /// ```
+    pub fn claim_function(&self, interface: u8) -> Result<Vec<Interface<'_>>> {
+        let config = self.active_config_descriptor()?;
+        let function = config.as_ref()
+            .and_then(|config| config.function(interface))
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no such interface in active configuration"))?;
+        function.interfaces.iter().map(|&number| self.claim_in(config.as_ref(), number, false)).collect()
+    }
+}
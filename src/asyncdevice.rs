@@ -1,14 +1,23 @@
 
-use std::{io, mem};
+use std::{mem, ptr, thread};
+use std::collections::VecDeque;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::ops::{DerefMut};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use nix::poll::{poll, PollFd, PollFlags};
 
 use super::*;
+use logging::LogName;
 
 #[cfg(feature="mio")]
-use mio::{Token, PollOpt, Evented};
+use std::io;
+#[cfg(feature="mio")]
+use mio::{Token, Interest, Registry};
 #[cfg(feature="mio")]
-use mio::unix::EventedFd;
+use mio::event::Source;
+#[cfg(feature="mio")]
+use mio::unix::SourceFd;
 
 /// Low level URB-rendering trait for async transfers.
 ///
@@ -16,13 +25,54 @@ use mio::unix::EventedFd;
 /// to the usbfs driver.  It is unsafe because undefined behavior can be invoked by improper `Urb`
 /// setup.  Types implementing `Transfer` will typically contain an `Urb` struct and buffer, with the `Urb`
 /// configured to read or write to the associated buffer.
-
+///
+/// # Safety
+/// The `Urb` returned by `wire_urb()`, and any buffer or iso packet descriptors it refers to,
+/// must remain valid and at a stable address until the transfer has been reaped.
 pub unsafe trait Transfer {
 
     /// Prepare an URB for submission to usbfs driver.
     fn wire_urb(&mut self) -> &mut Urb;
 }
 
+/// Owning or borrowing pointer whose target stays at a fixed address.
+///
+/// `AsyncDevice` hands the kernel pointers into the transfer it has been given, so the transfer
+/// must not move while it is in flight, even if the pointer itself does.  This holds for `Box`
+/// and `Pin<Box>`, but not for arbitrary `DerefMut` types; a `Vec`, for instance, reallocates
+/// its contents.  Nor is it enough for a plain `&mut`: the `AsyncDevice` holding it could be
+/// leaked, ending the borrow while the kernel still writes through it.  Borrowed transfers go
+/// through `Device::transfer_scope()` instead.
+///
+/// # Safety
+/// The target of `stable_mut()` must stay at the same address, and remain valid, for as long as
+/// the pointer exists, no matter how the pointer is moved.
+pub unsafe trait StableDeref {
+    type Target: ?Sized;
+
+    /// Access the target.
+    ///
+    /// # Safety
+    /// Callers must not move out of the target, since it may be pinned.
+    unsafe fn stable_mut(&mut self) -> &mut Self::Target;
+}
+
+unsafe impl<T: ?Sized> StableDeref for Box<T> {
+    type Target = T;
+
+    unsafe fn stable_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+unsafe impl<T: ?Sized> StableDeref for Pin<Box<T>> {
+    type Target = T;
+
+    unsafe fn stable_mut(&mut self) -> &mut T {
+        self.as_mut().get_unchecked_mut()
+    }
+}
+
 // ///
 // /// This type represents a single USB transfer.  It contains parameters
 // /// for the transfer (an URB structure, USB Request Block) and a buffer
@@ -46,38 +96,162 @@ pub unsafe trait Transfer {
 /// later *reaping* the transfer when it has completed.  `AsyncDevice` takes exclusive ownership of
 /// transfer objects while they are being processed.
 ///
-/// The transfer object has trait bound `DerefMut`, which in practice means `Box` or `&mut` (this
-/// is what allows `AsyncDevice` to hold exclusive ownership).  The derefed type must also implement
-/// `Transfer` so that an `Urb` can be acquired for the underlying usbfs driver.
+/// The transfer object has trait bound `StableDeref`, which means `Box` or `Pin<Box>`
+/// (this is what allows `AsyncDevice` to hold exclusive ownership without the transfer moving).
+/// The derefed type must also implement `Transfer` so that an `Urb` can be acquired for the
+/// underlying usbfs driver.
+///
+/// The optional second type parameter `U` is data of the caller's own kept with each transfer,
+/// see `submit_with_data()`.  It defaults to `()`.
 ///
 /// `AsyncDevice` implements `AsRawFd` so that it can partake in external select/poll event loops.
 /// The underlying file descriptor becomes *writable* when a transfer is ready to be reaped.
-
-pub struct AsyncDevice<R>
-//    where R: DerefMut,
+///
+/// Dropping an `AsyncDevice` with transfers in flight blocks until they have been cancelled and
+/// reaped, so the kernel never refers to a freed `Urb` or buffer.
+///
+/// When the device is disconnected, transfers in flight are not lost: the `reap_*()` methods
+/// keep returning them, with `TransferResult::NoDevice` for those the kernel killed, and only
+/// fail with `ErrorKind::Disconnected` once none remain.  Kernels with
+/// `Capabilities::REAP_AFTER_DISCONNECT` hand back every transfer, with the real results of
+/// those that completed before the disconnect, but report the disconnect before they have
+/// killed the rest; the `reap_*()` methods then poll until the last one is back, so
+/// `reap_nowait()` may briefly wait.  Older kernels refuse to reap from a disconnected device
+/// but no longer touch its transfers, which are all returned with `NoDevice`.
+pub struct AsyncDevice<R, U = ()>
+//    where R: StableDeref,
 //          R::Target: Transfer
-
-// DerefMut isn't quite what we want because there is no garantee of stable references.
-// Box and &mut do provide this, but it is coincidence.
-// Possible future alternatives are Pin, Anchor, StableDeref.
-
 {
     pub device: Device,
-    transfers: Vec<Option<R>>,
+    pub(crate) transfers: Vec<Entry<R, U>>,
+    pub(crate) reaped: VecDeque<Reaped<R, U>>,  // transfers reaped on the caller's behalf, eg. during discard()
+    pub(crate) signr: u32,  // completion signal of submitted urbs, or 0
+    pub(crate) max_in_flight: Option<usize>,
+    pub(crate) stats: QueueStats,
+    // queried on the first submission, since a disconnected device can't be asked; `Some(None)`
+    // if the kernel is too old to report them
+    pub(crate) capabilities: Option<Option<Capabilities>>,
+    pub(crate) submit_check: Option<SubmitCheck>,
+}
+
+// Configuration that submitted urbs are checked against, see `set_submit_check()`.
+pub(crate) struct SubmitCheck {
+    config: ConfigDescriptor,
+    // unknown on kernels that can't report it, in which case packet lengths aren't checked
+    speed: Option<Speed>,
 }
 
+// A transfer reaped from the kernel but not yet handed to the caller.
+pub(crate) type Reaped<R, U> = (SlotId, R, U, TransferResult, TransferTiming);
+
+/// Counts of transfers through an `AsyncDevice`, as returned by `AsyncDevice::stats()`.
+///
+/// Streaming applications can watch these to judge the health of their queue: a rising
+/// `errored` count, or `completed` falling behind the expected rate, points to underruns or
+/// a struggling device.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct QueueStats {
+    /// Transfers successfully submitted.
+    pub submitted: u64,
+    /// Transfers reaped with `TransferResult::Completed`.
+    pub completed: u64,
+    /// Transfers reaped with any failure other than cancellation, including those killed by a
+    /// disconnect.
+    pub errored: u64,
+    /// Transfers reaped with `TransferResult::Cancelled`.
+    pub cancelled: u64,
+}
 
-impl<R> From<Device> for AsyncDevice<R>
-//    where R: DerefMut,
+impl QueueStats {
+    fn count(&mut self, result: TransferResult) {
+        match result {
+            TransferResult::Completed{..} => self.completed += 1,
+            TransferResult::Cancelled => self.cancelled += 1,
+            _ => self.errored += 1,
+        }
+    }
+}
+
+/// When a transfer was submitted and when it was reaped, as returned by the
+/// `AsyncDevice::reap_with_timing_*()` methods.
+///
+/// Both are taken from the monotonic clock, around the submit and reap calls to the kernel.
+/// The reap time follows the actual completion by however long the application took to reap,
+/// so for latency or jitter measurements reap promptly, eg. from a thread waiting in
+/// `reap_wait()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TransferTiming {
+    pub submitted: Instant,
+    pub reaped: Instant,
+}
+
+impl TransferTiming {
+    /// Time from submission to reaping.
+    pub fn latency(&self) -> Duration {
+        self.reaped.saturating_duration_since(self.submitted)
+    }
+}
+
+// An in-flight transfer along with the address of its wired Urb and the caller's data.  The Urb
+// lives inside the transfer, so the pointer stays valid for as long as the slot owns the transfer.
+pub(crate) struct Slot<R, U = ()> {
+    pub(crate) transfer: R,
+    pub(crate) urb: *mut Urb,
+    pub(crate) data: U,
+    pub(crate) submitted: Instant,
+}
+
+unsafe impl<R: Send, U: Send> Send for Slot<R, U> {}
+
+// A slot in the transfer table.  The generation is bumped whenever a transfer leaves the slot, so
+// that stale `SlotId`s are detected rather than acting on whatever transfer reuses the slot.
+pub(crate) struct Entry<R, U = ()> {
+    pub(crate) generation: u32,
+    pub(crate) slot: Option<Slot<R, U>>,
+}
+
+/// Identifies a submitted transfer.
+///
+/// Returned by `submit()` and `reap_*()`, and accepted by `discard()`.  Slots are reused once
+/// their transfer has been reaped, but each `SlotId` also carries a generation count, so an id
+/// kept after its transfer was reaped is rejected instead of referring to a newer transfer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SlotId {
+    index: usize,
+    generation: u32,
+}
+
+impl SlotId {
+    pub(crate) fn new(index: usize, generation: u32) -> SlotId {
+        SlotId{index, generation}
+    }
+
+    /// Position of the slot in the transfer table.  Not unique over time; compare whole
+    /// `SlotId`s to tell transfers apart.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub(crate) fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+
+impl<R, U> From<Device> for AsyncDevice<R, U>
+//    where R: StableDeref,
 //          R::Target: Transfer
 {
     fn from(d: Device) -> Self {
-        AsyncDevice{device: d, transfers: Default::default()}
+        AsyncDevice{device: d, transfers: Default::default(), reaped: Default::default(), signr: 0,
+                    max_in_flight: None, stats: QueueStats::default(), capabilities: None,
+                    submit_check: None}
     }
 }
 
-impl<R> AsRawFd for AsyncDevice<R>
-//    where R: DerefMut,
+impl<R, U> AsRawFd for AsyncDevice<R, U>
+//    where R: StableDeref,
 //          R::Target: Transfer
 {
     fn as_raw_fd(&self) -> RawFd {
@@ -86,43 +260,175 @@ impl<R> AsRawFd for AsyncDevice<R>
 }
 
 #[allow(non_snake_case)]
-impl<R> AsyncDevice<R>
-    where R: DerefMut,
+impl<R, U> AsyncDevice<R, U>
+    where R: StableDeref,
           R::Target: Transfer
 {
 
     /// Create new AsyncDevice given a DeviceInfo struct.
-    pub fn new(device: &DeviceInfo) -> io::Result<Self> {
+    pub fn new(device: &DeviceInfo) -> Result<Self> {
         Device::new(device)
-            .map(|d| AsyncDevice{device: d, transfers: Default::default() })
+            .map(AsyncDevice::from)
+    }
+
+    /// Wrap `device`, checking every submitted transfer against `config`, its active
+    /// configuration.  See `set_submit_check()`.
+    pub fn with_submit_check(device: Device, config: ConfigDescriptor) -> Self {
+        let mut device = AsyncDevice::from(device);
+        device.set_submit_check(Some(config));
+        device
+    }
+
+    /// Check transfers against `config` before submitting them, or with `None` (the default)
+    /// leave all checking to the kernel.
+    ///
+    /// A transfer fails with `ErrorKind::InvalidParam` and a message saying what is wrong if
+    /// its endpoint is not in any interface of `config`, if the endpoint is of another type
+    /// (bulk transfers may go to interrupt endpoints, as the kernel allows), or if an
+    /// isochronous packet is longer than the endpoint can move per service interval.  The
+    /// kernel usually refuses such transfers with a bare `EINVAL`, or a host controller lets
+    /// them through to fail on the bus.  Every alternate setting is searched, since the one
+    /// selected isn't known.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// let info = deviceinfo_find(0xffff, 3).unwrap();
+    /// let config = info.configurations().unwrap().remove(0);
+    /// let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> =
+    ///     AsyncDevice::with_submit_check(Device::new(&info).unwrap(), config);
+    /// if let Err(err) = device.submit(Box::new(BulkTransferMut::new(0x85, UrbFlags::empty(), vec![0; 64]))) {
+    ///     println!("{}", err);
+    /// }
+    /// ```
+    pub fn set_submit_check(&mut self, config: Option<ConfigDescriptor>) {
+        self.submit_check = config.map(|config| SubmitCheck { config, speed: self.device.speed().ok() });
+    }
+
+    /// The configuration set with `set_submit_check()`.
+    pub fn submit_check(&self) -> Option<&ConfigDescriptor> {
+        self.submit_check.as_ref().map(|check| &check.config)
+    }
+
+
+    /// Have the kernel send signal `signr` to this process whenever a transfer submitted from
+    /// now on completes, as an alternative to polling the file descriptor.  A `signr` of 0
+    /// (the default) disables the signal.
+    ///
+    /// The signal's `si_addr` is the address of the completed `Urb`, and `si_errno` its
+    /// status.  Since the signal only says that something can be reaped, the simplest handling
+    /// is to call `reap_nowait()` until it fails with `ErrorKind::WouldBlock`.  Rather than
+    /// reaping from a signal handler, block the signal and receive it through a `signalfd`, or
+    /// with `sigwaitinfo()` on a dedicated thread; a realtime signal (`SIGRTMIN` and up) keeps
+    /// completions from being coalesced.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// extern crate nix;
+    /// use nix::sys::signal::{SigSet, Signal};
+    /// use nix::sys::signalfd::SignalFd;
+    /// use usbfs::*;
+    ///
+    /// let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> =
+    ///     AsyncDevice::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+    ///
+    /// let mut mask = SigSet::empty();
+    /// mask.add(Signal::SIGUSR1);
+    /// mask.thread_block().unwrap();
+    /// let mut sfd = SignalFd::new(&mask).unwrap();
+    ///
+    /// device.set_completion_signal(Signal::SIGUSR1 as i32);
+    /// device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0u8; 512]))).unwrap();
+    ///
+    /// while let Ok(Some(_siginfo)) = sfd.read_signal() {
+    ///     while let Ok((_slot, xfer, result)) = device.reap_nowait() {
+    ///         println!("{:?} {:?}", result, &xfer.buf[..8]);
+    ///     }
+    /// }
+    /// ```
+    pub fn set_completion_signal(&mut self, signr: i32) {
+        self.signr = signr as u32;
     }
 
+    /// The signal set with `set_completion_signal()`, or 0.
+    pub fn completion_signal(&self) -> i32 {
+        self.signr as i32
+    }
 
     /// Submit a transfer for processing
     ///
     /// This method takes ownership of the provided transfer, invokes `wire_urb()`, and begins
     /// processing it asynchronously.  A result is returned immediately without
-    /// waiting for completion.  The `Ok` result is a `slot` number that can later
+    /// waiting for completion.  The `Ok` result is a `SlotId` that can later
     /// be used to `discard()` the transfer or identify it when `reap()`ed.  The `Err`
     /// result is a 2-tuple containing the error code and the original transfer.
-    pub fn submit_give_back_on_fail(&mut self, mut transfer: R) -> Result<usize, (io::Error, R)> {
+    ///
+    /// With a limit set by `set_max_in_flight()`, submitting to a full queue fails with
+    /// `ErrorKind::WouldBlock`.  Flags the kernel or host controller doesn't support, as
+    /// reported by `Device::capabilities()`, fail with `ErrorKind::Unsupported` before the urb
+    /// reaches the kernel.
+    pub fn submit_give_back_on_fail(&mut self, transfer: R) -> Result<SlotId, (Error, R)>
+        where U: Default
+    {
+        self.submit_with_data(transfer, U::default())
+            .map_err(|(err, transfer, _data)| (err, transfer))
+    }
 
+    /// Submit a transfer along with data of the caller's own, such as a request id or a
+    /// completion callback, which is held with the transfer and handed back by
+    /// `reap_with_data_*()`.  Other methods that return the transfer drop its data.
+    ///
+    /// This is the way to attach context to a transfer: the `Urb`'s `usercontext` field is
+    /// overwritten with the slot number on submission.  On failure the error is returned along with the transfer and data.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>, &str> =
+    ///     AsyncDevice::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+    /// device.submit_with_data(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 64])), "status")
+    ///     .map_err(|(err, _, _)| err).unwrap();
+    /// device.submit_with_data(Box::new(BulkTransferMut::new(0x82, UrbFlags::empty(), vec![0; 512])), "data")
+    ///     .map_err(|(err, _, _)| err).unwrap();
+    /// let (_slot, _xfer, name, result) = device.reap_with_data_wait().unwrap();
+    /// println!("{}: {:?}", name, result);
+    /// ```
+    pub fn submit_with_data(&mut self, mut transfer: R, data: U) -> Result<SlotId, (Error, R, U)> {
+        if self.capacity() == Some(0) {
+            return Err((Error::new(ErrorKind::WouldBlock, "transfer queue full"), transfer, data));
+        }
 
-        let urbp: *mut Urb = transfer.wire_urb();
+        let urbp: *mut Urb = unsafe { transfer.stable_mut() }.wire_urb();
+        let checked = self.kernel_capabilities()
+            .and_then(|_| self.check_flags(unsafe { (*urbp).flags }))
+            .and_then(|_| unsafe { self.check_endpoint(urbp) });
+        if let Err(err) = checked {
+            log_debug!("{} submit to endpoint {:#04x} rejected: {}", LogName(&self.device), unsafe { (*urbp).endpoint }, err);
+            return Err((err, transfer, data));
+        }
 
-        let id = self.insert_transfer(transfer);
+        let id = self.insert_transfer(transfer, urbp, data);
         unsafe {
-            (*urbp).usercontext = id as usize;
+            (*urbp).usercontext = id.index();
+            (*urbp).signr = self.signr;
         }
 
-        match unsafe { devfs::nix_result_to_io_result(devfs::submiturb(self.as_raw_fd(), urbp)) } {
+        let urb = unsafe { &*urbp };
+        match unsafe { devfs::nix_result_to_result(devfs::submiturb(&self.device, urbp)) } {
             Ok(_result) => {
+                log_trace!("{} submitted slot {} to endpoint {:#04x}, {} bytes",
+                           LogName(&self.device), id.index(), urb.endpoint, urb.buffer_length);
                 // keep transfer, return slot for later reference
+                self.stats.submitted += 1;
                 Ok(id)
             }
             Err(err) => {
+                log_debug!("{} submit to endpoint {:#04x} failed: {}", LogName(&self.device), urb.endpoint, err);
                 // return error, give transfer back
-                Err((err, self.take_transfer(id).unwrap()))
+                let slot = self.take_transfer(id).unwrap();
+                Err((err, slot.transfer, slot.data))
             }
         }
     }
@@ -130,42 +436,97 @@ impl<R> AsyncDevice<R>
     /// Submit a transfer for processing
     ///
     /// Same as `submit_give_back_on_fail()`, but drop transfer upon failure.
-    pub fn submit(&mut self, transfer: R) -> io::Result<usize> {
+    pub fn submit(&mut self, transfer: R) -> Result<SlotId>
+        where U: Default
+    {
         self.submit_give_back_on_fail(transfer).map_err(|(err, _)| err)
     }
 
+    /// Submit a control request, the async counterpart of `Device::control_transfer_in()` and
+    /// `control_transfer_out()`.
+    ///
+    /// `buf` holds 8 bytes of room for the setup packet, which is filled in, followed by the
+    /// data stage: the OUT payload, or space for the IN data.  The transfer type `R` of the
+    /// device must be constructible from the resulting `ControlTransferMut`, eg.
+    /// `Box<ControlTransferMut<Vec<u8>>>`.  A buffer shorter than 8 bytes fails with
+    /// `ErrorKind::InvalidParam`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+    /// let mut device: AsyncDevice<Box<ControlTransferMut<Vec<u8>>>> = device.into();
+    /// device.submit_control(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Device,
+    ///                       0x01, 0, 0, vec![0; 8 + 64]).unwrap();
+    /// let (_slot, xfer, result) = device.reap_wait().unwrap();
+    /// println!("{:?} {:?}", result, xfer.payload());
+    /// ```
+    pub fn submit_control<B>(&mut self,
+                             direction: SetupDirection,
+                             stype: SetupType,
+                             recipient: SetupRecipient,
+                             request: u8,
+                             value: u16,
+                             index: u16,
+                             buf: B)
+                             -> Result<SlotId>
+        where B: AsMut<[u8]>,
+              R: From<ControlTransferMut<B>>,
+              U: Default
+    {
+        let xfer = ControlTransferMut::try_new(direction, stype, recipient, request, value, index,
+                                               UrbFlags::empty(), buf)?;
+        self.submit(R::from(xfer))
+    }
+
+    /// Like `submit_control()`, with the request given as a prepared setup packet whose
+    /// `wLength` is ignored.
+    pub fn submit_control_setup<B>(&mut self, setup: Setup, buf: B) -> Result<SlotId>
+        where B: AsMut<[u8]>,
+              R: From<ControlTransferMut<B>>,
+              U: Default
+    {
+        self.submit(R::from(ControlTransferMut::try_from_setup(setup, buf)?))
+    }
 
     /// Collect a previously submitted transfer
     ///
-    /// If no transfer has been completed the error kind will be `io::ErrorKind::WouldBlock`.
+    /// If no transfer has been completed the error kind will be `ErrorKind::WouldBlock`.
     /// The `Ok` result is a 3-tuple consisting of:
-    /// * The `slot` of the reaped transfer.  This is the number that is returned by `submit()`.
+    /// * The `SlotId` of the reaped transfer.  This is the id that is returned by `submit()`.
     /// * The `Transfer` itself.
-    /// * The result of the transfer.  The `Ok` value is the number of bytes transferred.
+    /// * The result of the transfer, decoded from its `Urb`.
     ///
     /// # Examples
     /// Recipe for processing the return result:
-    /// ```
+    /// ```no_run
+    /// # use usbfs::*;
+    /// # use std::io;
+    /// # fn example(device: &mut AsyncDevice<Box<BulkTransferMut<Vec<u8>>>>) {
     /// match device.reap_nowait() {
-    ///     Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+    ///     Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
     ///         // no transfers have finished
     ///         // ...
     ///     }
-    ///     Ok((_slot, xfer, Ok(actual_length))) => {
+    ///     Ok((_slot, xfer, TransferResult::Completed{len})) => {
     ///         // transfer completed!
-    ///         let buf = xfer.result_buf(actual_length);
+    ///         let buf = &xfer.buf[..len];
     ///         // ...
     ///     },
-    ///     Ok((_slot, _xfer, Err(_err))) =>
+    ///     Ok((_slot, _xfer, _result)) => {
     ///         // transfer returned failure!
     ///         // ...
-    ///     Err(_err) =>
+    ///     },
+    ///     Err(_err) => {
     ///         // underlying call to reap failed.  Device unplugged?
     ///         // ...
+    ///     }
     /// }
+    /// # }
     /// ```
-    pub fn reap_nowait(&mut self) -> io::Result<R> {
-        self.reap_main(false)
+    pub fn reap_nowait(&mut self) -> Result<(SlotId, R, TransferResult)> {
+        self.reap_with_data_nowait().map(without_data)
     }
 
     /// Wait for a previously submitted `Transfer` to finish.
@@ -174,132 +535,577 @@ impl<R> AsyncDevice<R>
     ///
     /// # Examples
     /// The recipe for processing is slightly simpler than for `reap_nowait()`:
-    /// ```
-    /// match device.reap_nowait() {
-    ///     Ok((_slot, xfer, Ok(actual_length))) => {
+    /// ```no_run
+    /// # use usbfs::*;
+    /// # fn example(device: &mut AsyncDevice<Box<BulkTransferMut<Vec<u8>>>>) {
+    /// match device.reap_wait() {
+    ///     Ok((_slot, xfer, TransferResult::Completed{len})) => {
     ///         // transfer completed!
-    ///         let buf = xfer.result_buf(actual_length);
+    ///         let buf = &xfer.buf[..len];
     ///         // ...
     ///     },
-    ///     Ok((_slot, _xfer, Err(_err))) =>
+    ///     Ok((_slot, _xfer, _result)) => {
     ///         // transfer returned failure!
     ///         // ...
-    ///     Err(_err) =>
+    ///     },
+    ///     Err(_err) => {
     ///         // underlying call to reap failed.  Device unplugged?
     ///         // ...
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn reap_wait(&mut self) -> Result<(SlotId, R, TransferResult)> {
+        self.reap_with_data_wait().map(without_data)
+    }
+
+
+    /// Wait at most `timeout` for a previously submitted `Transfer` to finish.
+    /// Similar to `reap_wait()`, but fails with `ErrorKind::Timeout` if no transfer
+    /// completes in time.  A zero `timeout` behaves like `reap_nowait()`, except for the error
+    /// kind.
+    ///
+    /// This allows a reaping thread to periodically check for shutdown requests rather than
+    /// blocking forever.
+    pub fn reap_timeout(&mut self, timeout: Duration) -> Result<(SlotId, R, TransferResult)> {
+        self.reap_with_data_timeout(timeout).map(without_data)
+    }
+
+    /// Like `reap_nowait()`, but also returns the data given to `submit_with_data()`.
+    pub fn reap_with_data_nowait(&mut self) -> Result<(SlotId, R, U, TransferResult)> {
+        self.reap_main(false).map(without_timing)
+    }
+
+    /// Like `reap_wait()`, but also returns the data given to `submit_with_data()`.
+    pub fn reap_with_data_wait(&mut self) -> Result<(SlotId, R, U, TransferResult)> {
+        self.reap_main(true).map(without_timing)
+    }
+
+    /// Like `reap_timeout()`, but also returns the data given to `submit_with_data()`.
+    pub fn reap_with_data_timeout(&mut self, timeout: Duration) -> Result<(SlotId, R, U, TransferResult)> {
+        self.reap_main_timeout(timeout).map(without_timing)
+    }
+
+    /// Like `reap_nowait()`, but also returns when the transfer was submitted and reaped.
+    pub fn reap_with_timing_nowait(&mut self) -> Result<(SlotId, R, TransferResult, TransferTiming)> {
+        self.reap_main(false).map(without_data_timed)
+    }
+
+    /// Like `reap_wait()`, but also returns when the transfer was submitted and reaped.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use usbfs::*;
+    /// # fn example(device: &mut AsyncDevice<Box<InterruptTransferMut<Vec<u8>>>>) -> Result<()> {
+    /// let mut previous = None;
+    /// loop {
+    ///     let (_slot, xfer, _result, timing) = device.reap_with_timing_wait()?;
+    ///     if let Some(previous) = previous {
+    ///         println!("latency {:?}, period {:?}", timing.latency(), timing.reaped - previous);
+    ///     }
+    ///     previous = Some(timing.reaped);
+    ///     device.submit(xfer)?;
+    /// }
+    /// # }
+    /// ```
+    pub fn reap_with_timing_wait(&mut self) -> Result<(SlotId, R, TransferResult, TransferTiming)> {
+        self.reap_main(true).map(without_data_timed)
+    }
+
+    /// Like `reap_timeout()`, but also returns when the transfer was submitted and reaped.
+    pub fn reap_with_timing_timeout(&mut self, timeout: Duration) -> Result<(SlotId, R, TransferResult, TransferTiming)> {
+        self.reap_main_timeout(timeout).map(without_data_timed)
+    }
+
+    fn reap_main_timeout(&mut self, timeout: Duration) -> Result<Reaped<R, U>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.reap_main(false) {
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => (),
+                result => return result,
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::ZERO {
+                return Err(Error::new(ErrorKind::Timeout, "reap timed out"));
+            }
+            // round up so we don't spin on sub-millisecond remainders
+            let timeout_ms = std::cmp::min(remaining.as_nanos().div_ceil(1_000_000), i32::MAX as u128) as i32;
+            let mut fds = [PollFd::new(self.as_raw_fd(), PollFlags::POLLOUT)];
+            devfs::nix_result_to_result(poll(&mut fds, timeout_ms))?;
+        }
+    }
+
+    /// Reap every transfer that has already completed, without waiting.  The result is empty if
+    /// none have.
+    ///
+    /// This saves event loop handlers from calling `reap_nowait()` until `WouldBlock`, and
+    /// includes transfers held after `discard()`, which don't make the file descriptor
+    /// writable.  If reaping fails after some transfers have been collected, they are returned
+    /// and the error is left for the next call.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use usbfs::*;
+    /// # fn example(device: &mut AsyncDevice<Box<BulkTransferMut<Vec<u8>>>>) -> Result<()> {
+    /// for (_slot, xfer, result) in device.reap_all_nowait()? {
+    ///     if let TransferResult::Completed{len} = result {
+    ///         println!("{:?}", &xfer.buf[..len]);
+    ///     }
+    ///     device.submit(xfer)?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn reap_all_nowait(&mut self) -> Result<Vec<(SlotId, R, TransferResult)>> {
+        let mut reaped = Vec::new();
+        loop {
+            match self.reap_main(false) {
+                Ok(transfer) => reaped.push(without_data(without_timing(transfer))),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(reaped),
+                Err(_) if !reaped.is_empty() => return Ok(reaped),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Reap the next transfer to complete, pass it and its result to `f`, and submit it
+    /// again, keeping the number of transfers in flight constant.
+    ///
+    /// This is the core of a streaming loop: with a queue of transfers submitted up front,
+    /// calling this repeatedly consumes each transfer's data and puts it straight back in line,
+    /// so the queue never runs dry while the application is busy.  Transfers of any type and
+    /// on any number of endpoints may share the queue; `IsoStream` packages the common case of
+    /// a single isochronous IN endpoint.
+    ///
+    /// Cancelled transfers, and those killed by a disconnect, are dropped instead of being
+    /// resubmitted after `f` has seen them.  If resubmission fails, the transfer is dropped and
+    /// the error returned.  Either way the queue shrinks by one.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> =
+    ///     AsyncDevice::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+    /// for _ in 0..8 {
+    ///     device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 512]))).unwrap();
+    /// }
+    /// loop {
+    ///     device.reap_resubmit_wait(|xfer, result| {
+    ///         if let TransferResult::Completed{len} = result {
+    ///             println!("{:?}", &xfer.buf[..len]);
+    ///         }
+    ///     }).unwrap();
     /// }
     /// ```
-    pub fn reap_wait(&mut self) -> io::Result<R> {
-        self.reap_main(true)
+    pub fn reap_resubmit_wait<F, T>(&mut self, f: F) -> Result<T>
+        where F: FnOnce(&mut R::Target, TransferResult) -> T
+    {
+        self.reap_resubmit(true, f)
+    }
+
+    /// Like `reap_resubmit_wait()`, but fails with `ErrorKind::WouldBlock` instead of waiting.
+    pub fn reap_resubmit_nowait<F, T>(&mut self, f: F) -> Result<T>
+        where F: FnOnce(&mut R::Target, TransferResult) -> T
+    {
+        self.reap_resubmit(false, f)
+    }
+
+    fn reap_resubmit<F, T>(&mut self, wait: bool, f: F) -> Result<T>
+        where F: FnOnce(&mut R::Target, TransferResult) -> T
+    {
+        let (_slot, mut xfer, data, result, _timing) = self.reap_main(wait)?;
+        let value = f(unsafe { xfer.stable_mut() }, result);
+        match result {
+            TransferResult::Cancelled | TransferResult::NoDevice => (),
+            _ => { self.submit_with_data(xfer, data).map_err(|(err, _, _)| err)?; }
+        }
+        Ok(value)
     }
 
 
     // start abstracting transfer tracking so it can be traitified in the future
 
-    fn insert_transfer(&mut self, transfer: R) -> usize {
+    fn insert_transfer(&mut self, transfer: R, urb: *mut Urb, data: U) -> SlotId {
+        let slot = Slot{transfer, urb, data, submitted: Instant::now()};
+
         // find empty slot to stash this transfer
-        let slot = {
-            match self.transfers.iter().enumerate().find(|t| t.1.is_none()) {
-                Some((i, _)) => {
-                    self.transfers[i] = Some(transfer);
-                    i
+        let index = match self.transfers.iter().position(|e| e.slot.is_none()) {
+            Some(i) => i,
+            None => {
+                self.transfers.push(Entry{generation: 0, slot: None});
+                self.transfers.len() - 1
+            }
+        };
+        let entry = &mut self.transfers[index];
+        entry.slot = Some(slot);
+        SlotId::new(index, entry.generation)
+    }
+
+    fn reap_main(&mut self, wait: bool) -> Result<Reaped<R, U>> {
+        // hand out transfers that were reaped on our behalf first
+        let reaped = match self.reaped.pop_front() {
+            Some(reaped) => reaped,
+            None => match self.reap_id(wait) {
+                Ok(Some((id, result))) => self.take_reaped(id, result),
+                Ok(None) => {
+                    log_debug!("{} reaped an urb of another handle on the same file", LogName(&self.device));
+                    return Err(Error::new(ErrorKind::Other, "reaped an URB not submitted through this AsyncDevice"));
+                }
+                Err(ref err) if err.kind() == ErrorKind::Disconnected && self.pending_count() > 0 => {
+                    self.reap_disconnected()?;
+                    self.reaped.pop_front().unwrap()
                 }
-                None => {
-                    self.transfers.push(Some(transfer));
-                    self.transfers.len() - 1
+                Err(err) => {
+                    if err.kind() != ErrorKind::WouldBlock {
+                        log_debug!("{} reap failed: {}", LogName(&self.device), err);
+                    }
+                    return Err(err);
                 }
             }
         };
+        log_trace!("{} reaped slot {}: {}", LogName(&self.device), reaped.0.index(), reaped.3);
+        Ok(reaped)
+    }
+
+    /// Abort an in-flight transfer by slot number.
+    ///
+    /// The transfer is cancelled with `USBDEVFS_DISCARDURB` and then reaped.  The `Ok` result is
+    /// the aborted transfer; its `Urb` status will normally be `-ENOENT`, or reflect the outcome
+    /// of the transfer if it completed before it could be cancelled.
+    ///
+    /// URBs of other handles on the same file that are reaped meanwhile are lost, as with
+    /// `drain()`.
+    ///
+    /// This operation fails with `ErrorKind::InvalidParam` if `id` does not refer to an
+    /// in-flight transfer, for example because it was already reaped, or if the transfer
+    /// has already completed and been queued for `reap()`ing.  Any other transfers reaped while
+    /// waiting for the discarded one are held and returned by subsequent `reap_*()` calls.  Event
+    /// loop users should therefore keep calling `reap_nowait()` until it returns `WouldBlock`, as
+    /// held transfers do not make the file descriptor writable.
+    pub fn discard(&mut self, id: SlotId) -> Result<R> {
+        let urbp = self.get_urb(id)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidParam, "invalid transfer id"))?;
+
+        unsafe { devfs::nix_result_to_result(devfs::discardurb(&self.device, urbp))? };
+        log_debug!("{} discarded slot {}", LogName(&self.device), id.index());
 
-        slot
+        loop {
+            let (reaped_id, result) = match self.reap_id(true)? {
+                Some(reaped) => reaped,
+                None => continue,
+            };
+            let reaped = self.take_reaped(reaped_id, result);
+            if reaped_id == id {
+                return Ok(reaped.1);
+            }
+            self.reaped.push_back(reaped);
+        }
     }
+}
 
-    fn take_transfer(&mut self, id: usize) -> Option<R> {
-        self.transfers.get_mut(id).and_then(|e| e.take())
+// Operations that don't need the `Transfer` bound, so they are also available to `Drop`.
+impl<R, U> AsyncDevice<R, U> {
+    /// Request cancellation of every in-flight transfer with `USBDEVFS_DISCARDURB`.
+    ///
+    /// This does not wait; the cancelled transfers must still be reaped, normally with `drain()`.
+    /// Transfers that complete before they can be cancelled are reaped with their usual result.
+    pub fn cancel_all(&mut self) {
+        for slot in self.transfers.iter().filter_map(|e| e.slot.as_ref()) {
+            // fails harmlessly for urbs that have already completed
+            let _ = unsafe { devfs::discardurb(&self.device, slot.urb) };
+        }
     }
 
-    // fn get_transfer(&self, id: usize) -> Option<&R> {
-    //     match self.transfers.get(id) {
-    //         Some(&Some(ref t)) => Some(t),
-    //         _ => None,
-    //     }
-    // }
+    /// Reap until no transfers remain in flight, returning all of them along with any already
+    /// held for `reap()`ing.
+    ///
+    /// Use after `cancel_all()` for a graceful shutdown.  If the device has been disconnected,
+    /// transfers come back as described for `AsyncDevice`.
+    ///
+    /// URBs submitted through other handles on the same file, such as an `AsyncDevice` on a
+    /// `Device::try_clone()`, are reaped from the same kernel queue.  Any reaped here are
+    /// dropped and lost to their owner.
+    pub fn drain(&mut self) -> Result<Vec<(SlotId, R, TransferResult)>> {
+        let mut drained: Vec<_> = self.reaped.drain(..).map(without_timing).map(without_data).collect();
+        while self.pending_count() > 0 {
+            match self.reap_id(true) {
+                Ok(Some((id, result))) => {
+                    let slot = self.take_transfer(id).unwrap();
+                    drained.push((id, slot.transfer, result));
+                }
+                Ok(None) => (),
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(ref err) if err.kind() == ErrorKind::Disconnected => {
+                    self.reap_disconnected()?;
+                    drained.extend(self.reaped.drain(..).map(without_timing).map(without_data));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(drained)
+    }
 
-    fn reap_main(&mut self, wait: bool) -> io::Result<R> {
-        // get urb pointer
-        let mut urbp: *mut Urb = unsafe { mem::MaybeUninit::uninit().assume_init() };
+    /// Number of transfers in flight.  Transfers already reaped on the caller's behalf (see
+    /// `discard()`) are not included.
+    pub fn pending_count(&self) -> usize {
+        self.transfers.iter().filter(|e| e.slot.is_some()).count()
+    }
 
-        match wait {
-            false => unsafe { devfs::nix_result_to_io_result(devfs::reapurbndelay(self.as_raw_fd(), &mut urbp))? },
-            true => unsafe { devfs::nix_result_to_io_result(devfs::reapurb(self.as_raw_fd(), &mut urbp))? },
+    /// Limit the number of transfers in flight to `max`, so that submitting more fails with
+    /// `ErrorKind::WouldBlock` rather than queueing without bound.  `None`, the default, lifts
+    /// the limit.  Transfers already in flight are unaffected.
+    pub fn set_max_in_flight(&mut self, max: Option<usize>) {
+        self.max_in_flight = max;
+    }
+
+    pub fn max_in_flight(&self) -> Option<usize> {
+        self.max_in_flight
+    }
+
+    /// Number of transfers that can be submitted before the queue is full, or `None` if there
+    /// is no limit.
+    pub fn capacity(&self) -> Option<usize> {
+        self.max_in_flight.map(|max| max.saturating_sub(self.pending_count()))
+    }
+
+    /// Counts of transfers submitted and reaped since the device was created or the counts
+    /// were last reset.  Reaped transfers are counted when the kernel hands them back, even
+    /// if they are held for a later `reap_*()` call.
+    pub fn stats(&self) -> QueueStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = QueueStats::default();
+    }
+
+    /// Ids of all transfers in flight.
+    pub fn iterate_pending(&self) -> impl Iterator<Item=SlotId> + '_ {
+        self.transfers.iter().enumerate()
+            .filter(|(_, e)| e.slot.is_some())
+            .map(|(index, e)| SlotId::new(index, e.generation))
+    }
+
+    /// The data given to `submit_with_data()` for an in-flight transfer, or `None` if `id`
+    /// is not in flight.
+    pub fn data(&self, id: SlotId) -> Option<&U> {
+        match self.transfers.get(id.index()) {
+            Some(Entry{generation, slot: Some(slot)}) if *generation == id.generation() => Some(&slot.data),
+            _ => None,
+        }
+    }
+
+    pub fn data_mut(&mut self, id: SlotId) -> Option<&mut U> {
+        match self.transfers.get_mut(id.index()) {
+            Some(Entry{generation, slot: Some(slot)}) if *generation == id.generation() => Some(&mut slot.data),
+            _ => None,
+        }
+    }
+
+    // Check a wired urb against the configuration set with `set_submit_check()`.
+    unsafe fn check_endpoint(&self, urbp: *const Urb) -> Result<()> {
+        let check = match self.submit_check {
+            Some(ref check) => check,
+            None => return Ok(()),
+        };
+        let urb = &*urbp;
+        let urbtype = match UrbType::from_raw(urb.urbtype) {
+            Some(UrbType::Control) if urb.endpoint & 0x0f == 0 => return Ok(()),
+            Some(urbtype) => urbtype,
+            None => return Ok(()),
+        };
+        let ep = check.config.interfaces.iter()
+            .flat_map(|interface| interface.endpoints.iter())
+            .find(|ep| ep.bEndpointAddress == urb.endpoint)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidParam,
+                                      &format!("endpoint {:#04x} is not in configuration {}",
+                                               urb.endpoint, check.config.bConfigurationValue)))?;
+        let matches = match (urbtype, ep.transfer_type()) {
+            (UrbType::Bulk, UrbType::Interrupt) => true,
+            (urbtype, ep_type) => urbtype == ep_type,
         };
+        if !matches {
+            return Err(Error::new(ErrorKind::InvalidParam,
+                                  &format!("{:?} transfer to {:?} endpoint {:#04x}",
+                                           urbtype, ep.transfer_type(), urb.endpoint)));
+        }
+        if let (UrbType::Iso, Some(speed)) = (urbtype, check.speed) {
+            let max = ep.max_bytes_per_interval(speed);
+            if let Some((i, packet)) = devfs::iso_frame_desc(urbp).iter().enumerate().find(|(_, p)| p.length as usize > max) {
+                return Err(Error::new(ErrorKind::InvalidParam,
+                                      &format!("packet {} of {} bytes exceeds the {} bytes per interval of endpoint {:#04x}",
+                                               i, packet.length, max, urb.endpoint)));
+            }
+        }
+        Ok(())
+    }
 
-        // get enclosing Transfer
-        let id = unsafe { (*urbp).usercontext };
-        Ok(self.take_transfer(id).unwrap())
+    // Reject flags that depend on a capability the kernel doesn't report.  Kernels too old
+    // to report capabilities are left to judge for themselves.
+    fn check_flags(&mut self, flags: UrbFlags) -> Result<()> {
+        const NEEDS: [(UrbFlags, Capabilities, &str); 2] = [
+            (UrbFlags::URB_ZERO_PACKET, Capabilities::ZERO_PACKET, "URB_ZERO_PACKET"),
+            (UrbFlags::URB_BULK_CONTINUATION, Capabilities::BULK_CONTINUATION, "URB_BULK_CONTINUATION"),
+        ];
+        if !NEEDS.iter().any(|&(flag, _, _)| flags.contains(flag)) {
+            return Ok(());
+        }
+        let caps = self.kernel_capabilities()?.unwrap_or(Capabilities::all());
+        match NEEDS.iter().find(|&&(flag, cap, _)| flags.contains(flag) && !caps.contains(cap)) {
+            Some(&(_, _, name)) => Err(Error::new(ErrorKind::Unsupported,
+                                                  &format!("{} not supported by the kernel or host controller", name))),
+            None => Ok(()),
+        }
     }
 
-    // /// Abort an in-flight transfer by slot number.
-    // /// The `Ok` result is the aborted transfer.  This operation will
-    // /// fail if the transfer has already been queued for `reap()`ing.
+    // Capabilities of the kernel, or `None` if it can't report them.
+    pub(crate) fn kernel_capabilities(&mut self) -> Result<Option<Capabilities>> {
+        if let Some(caps) = self.capabilities {
+            return Ok(caps);
+        }
+        let caps = match self.device.capabilities() {
+            Ok(caps) => Some(caps),
+            Err(ref err) if err.kind() == ErrorKind::Unsupported => None,
+            Err(err) => return Err(err),
+        };
+        Ok(*self.capabilities.get_or_insert(caps))
+    }
 
+    // Queue everything still in flight for reaping, once the device is gone and the kernel has
+    // nothing to reap right now.  The kernel may not have killed every urb yet; those that
+    // REAP_AFTER_DISCONNECT kernels will hand back are waited for.  Older kernels refuse to
+    // reap at all, but no longer touch the urbs either.
+    fn reap_disconnected(&mut self) -> Result<()> {
+        let reapable = matches!(self.capabilities, Some(Some(caps)) if caps.contains(Capabilities::REAP_AFTER_DISCONNECT));
+        if !reapable {
+            for index in 0..self.transfers.len() {
+                let id = self.slot_id(index);
+                if self.get_urb(id).is_some() {
+                    self.stats.count(TransferResult::NoDevice);
+                    let reaped = self.take_reaped(id, TransferResult::NoDevice);
+                    self.reaped.push_back(reaped);
+                }
+            }
+            return Ok(());
+        }
+        while self.pending_count() > 0 {
+            match self.reap_id(false) {
+                Ok(Some((id, result))) => {
+                    let reaped = self.take_reaped(id, result);
+                    self.reaped.push_back(reaped);
+                }
+                Ok(None) => log_debug!("{} reaped an urb of another handle on the same file", LogName(&self.device)),
+                Err(ref err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::Disconnected | ErrorKind::Interrupted) => {
+                    thread::sleep(devfs::DISCONNECT_REAP_INTERVAL);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    // current id of the slot at `index`
+    fn slot_id(&self, index: usize) -> SlotId {
+        SlotId::new(index, self.transfers[index].generation)
+    }
+
+    fn take_transfer(&mut self, id: SlotId) -> Option<Slot<R, U>> {
+        match self.transfers.get_mut(id.index()) {
+            Some(entry) if entry.generation == id.generation() => {
+                let slot = entry.slot.take()?;
+                entry.generation = entry.generation.wrapping_add(1);
+                Some(slot)
+            }
+            _ => None,
+        }
+    }
+
+    // take the in-flight transfer `id`, which has just been reaped
+    fn take_reaped(&mut self, id: SlotId, result: TransferResult) -> Reaped<R, U> {
+        let slot = self.take_transfer(id).unwrap();
+        let timing = TransferTiming{submitted: slot.submitted, reaped: Instant::now()};
+        (id, slot.transfer, slot.data, result, timing)
+    }
 
-    // FIXME: can't get address of URB from current Transfer impl or from get_transfer(). Something has to bend...
+    fn get_urb(&self, id: SlotId) -> Option<*mut Urb> {
+        match self.transfers.get(id.index()) {
+            Some(Entry{generation, slot: Some(slot)}) if *generation == id.generation() => Some(slot.urb),
+            _ => None,
+        }
+    }
 
-    pub fn discard(&mut self, _id: usize) -> io::Result<R> {
-        panic!("not implemented");
+    // reap one urb from the kernel and return its slot id and result, or `None` if the urb isn't
+    // in the transfer table, having been submitted through another handle on the same file
+    fn reap_id(&mut self, wait: bool) -> Result<Option<(SlotId, TransferResult)>> {
+        // get urb pointer
+        let mut urbp: *mut Urb = ptr::null_mut();
 
-        // match self.get_transfer(id) {
-        //     Some(ref xfer) => {
-        //         try!(unsafe{from_libc_result( libc::ioctl(self.as_raw_fd(),
-        //             devfs::DISCARDURB_IOCTL,
-        //             &xfer.urb as *const Urb))});
-        //     },
-        //     None =>
-        //         return Err(io::Error::new(io::ErrorKind::Other, "invalid transfer id")),
-        // };
+        match wait {
+            false => unsafe { devfs::nix_result_to_result(devfs::reapurbndelay(&self.device, &mut urbp))? },
+            true => unsafe { devfs::nix_result_to_result(devfs::reapurb(&self.device, &mut urbp))? },
+        };
 
-        // Ok(self.take_transfer(id).unwrap())
+        // a foreign urb belongs to another handle, and may already be freed, so nothing is read
+        // through the pointer until it is known to be ours
+        let index = self.transfers.iter()
+            .position(|e| e.slot.as_ref().is_some_and(|slot| ptr::eq(slot.urb, urbp)));
+        let id = match index {
+            Some(index) => self.slot_id(index),
+            None => return Ok(None),
+        };
+        let result = TransferResult::from_urb(unsafe { &*urbp });
+        self.stats.count(result);
+        Ok(Some((id, result)))
     }
 }
 
-/// [mio](https://github.com/carllerche/mio) integration.
+// Leave out the caller's data, for the `reap_*()` methods that don't return it.
+fn without_data<R, U>((id, transfer, _data, result): (SlotId, R, U, TransferResult)) -> (SlotId, R, TransferResult) {
+    (id, transfer, result)
+}
+
+fn without_timing<R, U>((id, transfer, data, result, _timing): Reaped<R, U>) -> (SlotId, R, U, TransferResult) {
+    (id, transfer, data, result)
+}
+
+fn without_data_timed<R, U>((id, transfer, _data, result, timing): Reaped<R, U>) -> (SlotId, R, TransferResult, TransferTiming) {
+    (id, transfer, result, timing)
+}
+
+/// Dropping an `AsyncDevice` cancels all in-flight transfers and waits for the kernel to hand
+/// them back before they are freed.
+impl<R, U> Drop for AsyncDevice<R, U> {
+    fn drop(&mut self) {
+        self.cancel_all();
+        if self.drain().is_err() {
+            // can't tell what the kernel is still doing with the urbs; leak them rather than
+            // risk it writing into freed memory
+            self.transfers.drain(..).filter_map(|e| e.slot).for_each(mem::forget);
+        }
+    }
+}
+
+/// [mio](https://github.com/tokio-rs/mio) integration.
 ///
-/// This trait allows `AsyncDevice` instances to partake in `mio` event loops.
-/// `mio` integration (and dependency) can be turned off by disabling the
-/// `mio` feature at the crate level.  This feature is enabled by default.
+/// This trait allows `AsyncDevice` instances to partake in `mio` event loops.  `mio`
+/// integration (and dependency) is enabled by the `mio` feature at the crate level.
 ///
-/// `Device`s become `Writeable` when `Transfer`s are available to be `reap()`ed.
+/// `AsyncDevice`s become writable when `Transfer`s are available to be `reap()`ed.  Register
+/// with `Interest::WRITABLE`.  Since `mio` is edge triggered, keep calling `reap_nowait()` until
+/// it returns `WouldBlock` after each event.
 #[cfg(feature="mio")]
-impl<R> Evented for AsyncDevice<R>
-    where R: DerefMut,
-          R::Target: Transfer
-{
-    fn register(&self,
-                selector: &mut Selector,
-                token: Token,
-                interest: EventSet,
-                opts: PollOpt)
-                -> io::Result<()> {
-        println!("register {:?} {:?} {:?}",
-                 EventedFd(&self.as_raw_fd()),
-                 interest,
-                 opts);
-        EventedFd(&self.as_raw_fd()).register(selector, token, interest, opts)
-    }
-
-    fn reregister(&self,
-                  selector: &mut Selector,
-                  token: Token,
-                  interest: EventSet,
-                  opts: PollOpt)
-                  -> io::Result<()> {
-        println!("reregister");
-        EventedFd(&self.as_raw_fd()).reregister(selector, token, interest, opts)
-    }
-
-    fn deregister(&self, selector: &mut Selector) -> io::Result<()> {
-        println!("deregister");
-        EventedFd(&self.as_raw_fd()).deregister(selector)
+impl<R, U> Source for AsyncDevice<R, U> {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).deregister(registry)
     }
 }
@@ -0,0 +1,100 @@
+use super::*;
+use deviceinfo::{read_sysfs_string, write_sysfs_string};
+
+/// Which newly connected devices a bus authorizes, from `bus_authorized_default()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum AuthorizedDefault {
+    /// No device is usable until authorized with `DeviceInfo::set_authorized()`.
+    None,
+    All,
+    /// Only devices wired into the machine, as its firmware reports them, are authorized.
+    Internal,
+}
+
+/// Device authorization through sysfs, the kernel's mechanism for USB allow-listing.
+///
+/// An unauthorized device is enumerated, so its descriptors can be inspected, but it is not
+/// configured and no driver binds to it.  A USB guard sets its bus to authorize nothing by
+/// default with `set_bus_authorized_default()` and then authorizes each device it allows.
+/// Writing these attributes needs root.  They are not available for devices found without
+/// sysfs, which fail with `ErrorKind::Unsupported`.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// set_bus_authorized_default(1, AuthorizedDefault::None).unwrap();
+/// for info in deviceinfo_enumerate() {
+///     let allowed = matches!(info.device_descriptor(), Ok(d) if d.idVendor == 0xffff);
+///     if info.busnum().unwrap() == 1 && allowed != info.authorized().unwrap() {
+///         info.set_authorized(allowed).unwrap();
+///     }
+/// }
+/// ```
+impl DeviceInfo {
+    pub fn authorized(&self) -> Result<bool> {
+        read_sysfs_flag(self.sysfs_dirname()?, "authorized")
+    }
+
+    /// Authorize the device, letting the kernel configure it and drivers bind to it, or
+    /// deauthorize it, which unconfigures it and unbinds its drivers.
+    pub fn set_authorized(&self, authorized: bool) -> Result<()> {
+        write_sysfs_flag(self.sysfs_dirname()?, "authorized", authorized)
+    }
+
+    /// Whether interface `interface` of the active configuration is authorized.
+    pub fn interface_authorized(&self, interface: u8) -> Result<bool> {
+        read_sysfs_flag(&self.interface_dirname(interface)?, "authorized")
+    }
+
+    /// Authorize or deauthorize a single interface of the active configuration, allowing
+    /// drivers to bind to some functions of a composite device but not others.
+    pub fn set_interface_authorized(&self, interface: u8, authorized: bool) -> Result<()> {
+        write_sysfs_flag(&self.interface_dirname(interface)?, "authorized", authorized)
+    }
+}
+
+/// Which devices bus `busnum` authorizes as they are connected.
+pub fn bus_authorized_default(busnum: u32) -> Result<AuthorizedDefault> {
+    match read_sysfs_string(&format!("usb{}", busnum), "authorized_default")?.as_str() {
+        "0" => Ok(AuthorizedDefault::None),
+        "1" | "-1" => Ok(AuthorizedDefault::All),
+        "2" => Ok(AuthorizedDefault::Internal),
+        _ => Err(Error::new(ErrorKind::Other, "bad parse")),
+    }
+}
+
+/// Set which devices bus `busnum` authorizes from now on.  Devices already connected keep
+/// their authorization.
+pub fn set_bus_authorized_default(busnum: u32, default: AuthorizedDefault) -> Result<()> {
+    let value = match default {
+        AuthorizedDefault::None => "0",
+        AuthorizedDefault::All => "1",
+        AuthorizedDefault::Internal => "2",
+    };
+    write_sysfs_string(&format!("usb{}", busnum), "authorized_default", value)
+}
+
+/// Whether interfaces of devices on bus `busnum` start out authorized.
+pub fn bus_interface_authorized_default(busnum: u32) -> Result<bool> {
+    read_sysfs_flag(&format!("usb{}", busnum), "interface_authorized_default")
+}
+
+/// Set whether interfaces of devices on bus `busnum` start out authorized.  With `false`,
+/// each interface must be authorized with `DeviceInfo::set_interface_authorized()`.
+pub fn set_bus_interface_authorized_default(busnum: u32, authorized: bool) -> Result<()> {
+    write_sysfs_flag(&format!("usb{}", busnum), "interface_authorized_default", authorized)
+}
+
+fn read_sysfs_flag(dirname: &str, attr: &str) -> Result<bool> {
+    match read_sysfs_string(dirname, attr)?.as_str() {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(Error::new(ErrorKind::Other, "bad parse")),
+    }
+}
+
+fn write_sysfs_flag(dirname: &str, attr: &str, value: bool) -> Result<()> {
+    write_sysfs_string(dirname, attr, if value { "1" } else { "0" })
+}
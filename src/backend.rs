@@ -0,0 +1,29 @@
+use super::*;
+use devfs::{c_int, c_void, ioctl_num_type};
+
+/// Replacement for the usbfs ioctl layer beneath a `Device`.
+///
+/// Every operation of `Device`, `AsyncDevice`, and the types built on them is ultimately an
+/// ioctl on the usbfs file.  A device created with `Device::with_backend()` hands those ioctls
+/// to its backend instead of the kernel, which lets driver code run against a simulated
+/// device; see `MockBackend`, available with the `mock` feature.
+///
+/// Requests and their arguments are those of `ioctl(2)` on a usbfs file, as described in
+/// `<linux/usbdevice_fs.h>`.  Operations that read sysfs rather than issuing an ioctl, such as
+/// `Device::claim()` and `DeviceInfo::from_fd()`, still need a real device.
+///
+/// # Safety
+/// Reaped urbs are trusted: `USBDEVFS_REAPURB` and `USBDEVFS_REAPURBNDELAY` store a
+/// `*mut Urb` through `arg`, and on success it must be a pointer passed to
+/// `USBDEVFS_SUBMITURB` on this backend and not reaped since, whose urb the backend has
+/// finished with.  The reaper dereferences it, and frees the transfer holding it, on the
+/// strength of this.
+pub unsafe trait Backend: Send + Sync {
+    /// Perform usbfs ioctl `request` with argument `arg`, returning the ioctl's result.
+    ///
+    /// # Safety
+    /// `arg` points to the argument struct for `request`, for example a `Urb` for
+    /// `USBDEVFS_SUBMITURB`, which must be valid as for the corresponding ioctl.  Submitted urbs
+    /// stay in use, and must be handed back by a reap request, until they complete.
+    unsafe fn ioctl(&self, request: ioctl_num_type, arg: *mut c_void) -> nix::Result<c_int>;
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::*;
+
+/// Throughput benchmark for bulk and isochronous endpoints.
+///
+/// A queue of `depth` transfers of `transfer_size` bytes is kept in flight on an
+/// `AsyncDevice` for `duration`, resubmitting each transfer as soon as it is reaped.  The
+/// resulting `BenchReport` gives the sustained data rate along with error counts, making it
+/// easy to check firmware against its expected bandwidth.
+///
+/// Defaults are 16KiB transfers, a queue depth of 8, and a duration of 5 seconds.
+///
+/// The transfers are queued on the device's own file, which is borrowed mutably for the run
+/// so nothing else reaps from its completion queue.  Handles from `Device::try_clone()` share
+/// that queue, and must not have transfers in flight meanwhile.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use usbfs::*;
+///
+/// let mut device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// device.claim_interface(0).unwrap();
+/// let report = Bench::new(0x81)
+///     .transfer_size(64 * 1024)
+///     .depth(16)
+///     .duration(Duration::from_secs(10))
+///     .bulk_in(&mut device)
+///     .unwrap();
+/// println!("{}", report);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Bench {
+    endpoint: u8,
+    transfer_size: usize,
+    depth: usize,
+    duration: Duration,
+}
+
+/// Outcome of a `Bench` run.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct BenchReport {
+    /// Bytes transferred by successful transfers, or isochronous packets.
+    pub bytes: u64,
+    /// Transfers reaped.
+    pub transfers: u64,
+    /// Transfers that failed as a whole.
+    pub errors: u64,
+    /// Isochronous packets reaped.
+    pub packets: u64,
+    /// Isochronous packets that failed, ie. were lost.
+    pub packet_errors: u64,
+    pub elapsed: Duration,
+}
+
+impl BenchReport {
+    /// Sustained throughput in megabytes (10^6 bytes) per second.
+    pub fn mb_per_sec(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.bytes as f64 / 1e6 / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bytes in {:.3}s ({:.2} MB/s), {} transfers, {} failed",
+               self.bytes, self.elapsed.as_secs_f64(), self.mb_per_sec(), self.transfers, self.errors)?;
+        if self.packets > 0 {
+            write!(f, ", {} of {} packets lost", self.packet_errors, self.packets)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Bench {
+    fn default() -> Bench {
+        Bench {
+            endpoint: 0,
+            transfer_size: 16 * 1024,
+            depth: 8,
+            duration: Duration::from_secs(5),
+        }
+    }
+}
+
+// Buffer of a benchmark iso transfer: equally sized packets.
+#[derive(Debug)]
+struct BenchIsoBuf {
+    buf: Vec<u8>,
+    packet_length: usize,
+}
+
+impl AsMut<[u8]> for BenchIsoBuf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl IsoBuffer for BenchIsoBuf {
+    fn packet_length(&self) -> usize {
+        self.packet_length
+    }
+}
+
+// Most packets in a single benchmark iso transfer.
+const BENCH_ISO_PACKETS: usize = 128;
+
+impl Bench {
+    /// Benchmark `endpoint`.  The direction bit is set or cleared by the method running the
+    /// benchmark.
+    pub fn new(endpoint: u8) -> Bench {
+        Bench { endpoint, ..Default::default() }
+    }
+
+    /// Bytes per transfer.
+    pub fn transfer_size(mut self, transfer_size: usize) -> Bench {
+        self.transfer_size = transfer_size;
+        self
+    }
+
+    /// Number of transfers kept in flight.
+    pub fn depth(mut self, depth: usize) -> Bench {
+        self.depth = depth;
+        self
+    }
+
+    /// How long to run for.
+    pub fn duration(mut self, duration: Duration) -> Bench {
+        self.duration = duration;
+        self
+    }
+
+    /// Measure bulk IN throughput.  The interface of the endpoint must already be claimed.
+    pub fn bulk_in(&self, device: &mut Device) -> Result<BenchReport> {
+        self.run_bulk(device, self.endpoint | 0x80)
+    }
+
+    /// Measure bulk OUT throughput, sending zeros.  The interface of the endpoint must already
+    /// be claimed.
+    pub fn bulk_out(&self, device: &mut Device) -> Result<BenchReport> {
+        self.run_bulk(device, self.endpoint & 0x7f)
+    }
+
+    /// Measure isochronous IN throughput and packet loss, with transfers of `packet_length`
+    /// byte packets.  `transfer_size` is rounded down to whole packets, at most 128 of them.
+    /// The interface of the endpoint must already be claimed and set to an alternate setting
+    /// with bandwidth.
+    pub fn iso_in(&self, device: &mut Device, packet_length: usize) -> Result<BenchReport> {
+        if packet_length == 0 || packet_length > self.transfer_size {
+            return Err(Error::new(ErrorKind::InvalidParam, "packet length must be between 1 and the transfer size"));
+        }
+        let packets = std::cmp::min(self.transfer_size / packet_length, BENCH_ISO_PACKETS);
+        let endpoint = self.endpoint | 0x80;
+        self.run(device, || {
+            Box::new(IsoBufTransfer::<_, BENCH_ISO_PACKETS>::isochronous(endpoint, UrbFlags::URB_ISO_ASAP, BenchIsoBuf {
+                buf: vec![0; packets * packet_length],
+                packet_length,
+            }))
+        }, |report, xfer, result| {
+            // -EXDEV reports that only some packets completed
+            if !matches!(result, TransferResult::Completed{..} | TransferResult::Other(libc::EXDEV)) {
+                report.errors += 1;
+            }
+            for (packet, data) in xfer.packets_mut() {
+                report.packets += 1;
+                match packet.status {
+                    0 => report.bytes += data.len() as u64,
+                    _ => report.packet_errors += 1,
+                }
+            }
+        })
+    }
+
+    fn run_bulk(&self, device: &mut Device, endpoint: u8) -> Result<BenchReport> {
+        self.run(device, || {
+            Box::new(BulkTransferMut::new(endpoint, UrbFlags::empty(), vec![0u8; self.transfer_size]))
+        }, |report, _xfer, result| match result {
+            TransferResult::Completed{len} => report.bytes += len as u64,
+            _ => report.errors += 1,
+        })
+    }
+
+    // Keep `depth` transfers made by `make` in flight for `duration`, tallying each reaped
+    // transfer with `tally`.
+    fn run<R, M, T>(&self, device: &mut Device, make: M, mut tally: T) -> Result<BenchReport>
+        where R: StableDeref,
+              R::Target: Transfer,
+              M: Fn() -> R,
+              T: FnMut(&mut BenchReport, &mut R::Target, TransferResult)
+    {
+        if self.depth == 0 {
+            return Err(Error::new(ErrorKind::InvalidParam, "queue depth must be at least 1"));
+        }
+        // dropped, and its transfers reaped, before the borrow of `device` ends
+        let mut queue: AsyncDevice<R> = unsafe { device.lend() }.into();
+        let mut report = BenchReport::default();
+
+        let start = Instant::now();
+        for _ in 0..self.depth {
+            queue.submit(make())?;
+        }
+        let deadline = start + self.duration;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::ZERO {
+                break;
+            }
+            let (_slot, mut xfer, result) = match queue.reap_timeout(remaining) {
+                Err(ref err) if err.kind() == ErrorKind::Timeout => break,
+                reaped => reaped?,
+            };
+            report.transfers += 1;
+            tally(&mut report, unsafe { xfer.stable_mut() }, result);
+            if result == TransferResult::NoDevice {
+                return Err(Error::new(ErrorKind::Disconnected, "device disconnected during benchmark"));
+            }
+            queue.submit(xfer)?;
+        }
+        report.elapsed = start.elapsed();
+        Ok(report)
+    }
+}
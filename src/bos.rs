@@ -0,0 +1,144 @@
+use std::fs;
+use std::io::Read;
+
+use super::*;
+use descriptors::{le16, le32, uuid, split_descriptors, bad_descriptor};
+use deviceinfo::SYSFS_DEVICE_PATH;
+
+/// Parsed Binary Object Store descriptor, listing the device capabilities of a USB 2.1 or
+/// later device.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let info = deviceinfo_find(0xffff, 3).unwrap();
+/// if let Some(bos) = info.bos().unwrap() {
+///     println!("LPM: {}", bos.lpm_supported());
+///     if let Some(DeviceCapability::SuperSpeed{wSpeedsSupported, ..}) = bos.superspeed() {
+///         println!("speeds {:04x}", wSpeedsSupported);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct BosDescriptor {
+    pub capabilities: Vec<DeviceCapability>,
+}
+
+/// A device capability descriptor from the `BosDescriptor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum DeviceCapability {
+    /// USB 2.0 extension (type `0x02`).  Bit 1 of `bmAttributes` indicates link power
+    /// management support.
+    Usb2Extension { bmAttributes: u32 },
+    /// SuperSpeed USB (type `0x03`).
+    SuperSpeed {
+        bmAttributes: u8,
+        wSpeedsSupported: u16,
+        bFunctionalitySupport: u8,
+        bU1DevExitLat: u8,
+        wU2DevExitLat: u16,
+    },
+    /// Container ID (type `0x04`), a UUID identifying the device across all of its
+    /// connections.
+    ContainerId { uuid: [u8; 16] },
+    /// Platform capability (type `0x05`), identified by a UUID, such as the Microsoft OS 2.0
+    /// descriptor set information.
+    Platform { uuid: [u8; 16], data: Vec<u8> },
+    /// Any other capability, with the bytes following its capability type.
+    Other { bDevCapabilityType: u8, data: Vec<u8> },
+}
+
+impl DeviceCapability {
+    fn parse(d: &[u8]) -> DeviceCapability {
+        match d[2] {
+            0x02 if d.len() >= 7 => DeviceCapability::Usb2Extension { bmAttributes: le32(d, 3) },
+            0x03 if d.len() >= 10 => DeviceCapability::SuperSpeed {
+                bmAttributes: d[3],
+                wSpeedsSupported: le16(d, 4),
+                bFunctionalitySupport: d[6],
+                bU1DevExitLat: d[7],
+                wU2DevExitLat: le16(d, 8),
+            },
+            0x04 if d.len() >= 20 => DeviceCapability::ContainerId { uuid: uuid(d, 4) },
+            0x05 if d.len() >= 20 => DeviceCapability::Platform {
+                uuid: uuid(d, 4),
+                data: d[20..].to_vec(),
+            },
+            cap_type => DeviceCapability::Other { bDevCapabilityType: cap_type, data: d[3..].to_vec() },
+        }
+    }
+}
+
+impl BosDescriptor {
+    /// Parse a BOS descriptor and the device capability descriptors following it.  Bytes
+    /// beyond `wTotalLength` are ignored.
+    pub fn parse(buf: &[u8]) -> Result<BosDescriptor> {
+        if buf.len() < 5 || buf[1] != DescriptorType::Bos as u8 {
+            return Err(bad_descriptor());
+        }
+        let total = le16(buf, 2) as usize;
+        if total < buf[0] as usize || total > buf.len() {
+            return Err(bad_descriptor());
+        }
+        let mut capabilities = Vec::new();
+        for d in split_descriptors(&buf[buf[0] as usize..total]) {
+            let d = d?;
+            if d[1] == DescriptorType::DeviceCapability as u8 && d.len() >= 3 {
+                capabilities.push(DeviceCapability::parse(d));
+            }
+        }
+        Ok(BosDescriptor{capabilities})
+    }
+
+    /// `true` if the device supports USB 2.0 link power management.
+    pub fn lpm_supported(&self) -> bool {
+        self.capabilities.iter().any(|cap| match *cap {
+            DeviceCapability::Usb2Extension{bmAttributes} => bmAttributes & 0x02 != 0,
+            _ => false,
+        })
+    }
+
+    /// The SuperSpeed USB capability, present on SuperSpeed capable devices.
+    pub fn superspeed(&self) -> Option<&DeviceCapability> {
+        self.capabilities.iter().find(|cap| matches!(cap, DeviceCapability::SuperSpeed{..}))
+    }
+
+    /// The container ID of the device, if it has one.
+    pub fn container_id(&self) -> Option<[u8; 16]> {
+        self.capabilities.iter().find_map(|cap| match *cap {
+            DeviceCapability::ContainerId{uuid} => Some(uuid),
+            _ => None,
+        })
+    }
+}
+
+impl DeviceInfo {
+    /// The BOS descriptor of the device, as cached by the kernel, or `None` if the device
+    /// doesn't have one.  No request is sent to the device.
+    pub fn bos(&self) -> Result<Option<BosDescriptor>> {
+        let mut buf = Vec::new();
+        match fs::File::open(format!("{}/{}/bos_descriptors", SYSFS_DEVICE_PATH, self.dirname())) {
+            Ok(mut f) => f.read_to_end(&mut buf)?,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        BosDescriptor::parse(&buf).map(Some)
+    }
+}
+
+impl Device {
+    /// Read the BOS descriptor from the device.  Devices older than USB 2.1 stall the request,
+    /// giving `ErrorKind::Stall`.
+    pub fn bos(&self, timeout_ms: u32) -> Result<BosDescriptor> {
+        let mut header = [0u8; 5];
+        if self.get_descriptor(DescriptorType::Bos as u8, 0, 0, &mut header, timeout_ms)? < 4 {
+            return Err(bad_descriptor());
+        }
+        let mut buf = vec![0u8; le16(&header, 2) as usize];
+        let len = self.get_descriptor(DescriptorType::Bos as u8, 0, 0, &mut buf, timeout_ms)?;
+        BosDescriptor::parse(&buf[..len])
+    }
+}
@@ -0,0 +1,172 @@
+use std::alloc::Layout;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::*;
+use alignedbuf::HeapBuf;
+
+/// A fixed set of reusable transfer buffers.
+///
+/// `BufferPool` allocates `count` buffers of `buf_size` bytes up front, either from the heap
+/// with a chosen alignment or, with `BufferPool::mmap()`, from usbfs itself like `MmapBuffer`.
+/// `get()` hands out a `PooledBuffer`, which implements `AsRef<[u8]>` and `AsMut<[u8]>` and so
+/// can be the buffer of any transfer type.  Dropping a `PooledBuffer`, typically along with the
+/// reaped transfer that owns it, returns it to the pool, so streaming code allocates nothing
+/// once running.
+///
+/// Pools are cheap to clone; clones share the same buffers.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let pool = BufferPool::mmap(&device, 16384, 8).unwrap();
+/// let mut asyncdevice: AsyncDevice<Box<BulkTransferMut<PooledBuffer>>> = device.into();
+/// while let Some(buf) = pool.get() {
+///     asyncdevice.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), buf))).unwrap();
+/// }
+/// loop {
+///     let (_slot, xfer, result) = asyncdevice.reap_wait().unwrap();
+///     println!("{:?}", result);
+///     drop(xfer); // back to the pool
+///     let buf = pool.get().unwrap();
+///     asyncdevice.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), buf))).unwrap();
+/// }
+/// ```
+#[derive(Clone)]
+pub struct BufferPool {
+    shared: Arc<PoolShared>,
+}
+
+struct PoolShared {
+    free: Mutex<Vec<Storage>>,
+    buf_size: usize,
+    count: usize,
+}
+
+impl BufferPool {
+    /// Allocate `count` zeroed heap buffers of `buf_size` bytes, each aligned to `align` bytes.
+    /// `align` must be a power of two; the page size suits DMA best.
+    pub fn new(buf_size: usize, align: usize, count: usize) -> Result<BufferPool> {
+        if buf_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidParam, "zero length buffer"));
+        }
+        let layout = Layout::from_size_align(buf_size, align)
+            .map_err(|_| Error::new(ErrorKind::InvalidParam, "alignment must be a power of two"))?;
+        let free = (0..count)
+            .map(|_| Storage::Heap(HeapBuf::new(layout)))
+            .collect();
+        Ok(BufferPool::with_storage(free, buf_size))
+    }
+
+    /// Allocate `count` buffers of `buf_size` bytes from `device`, as with `MmapBuffer::new()`.
+    /// They are only zero-copy for transfers submitted to that device.
+    pub fn mmap(device: &Device, buf_size: usize, count: usize) -> Result<BufferPool> {
+        let free = (0..count)
+            .map(|_| MmapBuffer::new(device, buf_size).map(Storage::Mmap))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(BufferPool::with_storage(free, buf_size))
+    }
+
+    /// Take a buffer from the pool, or `None` if all are in use.  The buffer's length is the
+    /// pool's `buf_size()`; its contents are whatever was left by its last user.
+    pub fn get(&self) -> Option<PooledBuffer> {
+        let storage = self.shared.lock().pop()?;
+        Some(PooledBuffer{storage: Some(storage), len: self.shared.buf_size, pool: self.shared.clone()})
+    }
+
+    pub fn buf_size(&self) -> usize {
+        self.shared.buf_size
+    }
+
+    /// Total number of buffers, in use or not.
+    pub fn count(&self) -> usize {
+        self.shared.count
+    }
+
+    /// Number of buffers not in use.
+    pub fn available(&self) -> usize {
+        self.shared.lock().len()
+    }
+
+    fn with_storage(free: Vec<Storage>, buf_size: usize) -> BufferPool {
+        let count = free.len();
+        BufferPool{shared: Arc::new(PoolShared{free: Mutex::new(free), buf_size, count})}
+    }
+}
+
+impl PoolShared {
+    fn lock(&self) -> MutexGuard<'_, Vec<Storage>> {
+        self.free.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Buffer taken from a `BufferPool`, returned to it on drop.
+pub struct PooledBuffer {
+    storage: Option<Storage>,
+    len: usize,
+    pool: Arc<PoolShared>,
+}
+
+impl PooledBuffer {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Size of the underlying allocation, the pool's `buf_size()`.
+    pub fn capacity(&self) -> usize {
+        self.pool.buf_size
+    }
+
+    /// Shorten or restore the length seen through `AsRef` and `AsMut`, eg. to send less than a
+    /// full buffer.  Panics if `len` exceeds `capacity()`.
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.capacity(), "length exceeds buffer capacity");
+        self.len = len;
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.storage.as_ref().unwrap().as_slice()[..self.len]
+    }
+}
+
+impl AsMut<[u8]> for PooledBuffer {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.storage.as_mut().unwrap().as_mut_slice()[..self.len]
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(storage) = self.storage.take() {
+            self.pool.lock().push(storage);
+        }
+    }
+}
+
+enum Storage {
+    Heap(HeapBuf),
+    Mmap(MmapBuffer),
+}
+
+impl Storage {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Storage::Heap(buf) => buf.as_slice(),
+            Storage::Mmap(buf) => buf.as_ref(),
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        match self {
+            Storage::Heap(buf) => buf.as_mut_slice(),
+            Storage::Mmap(buf) => buf.as_mut(),
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+#[cfg(feature="tokio")]
+use std::pin::Pin;
+#[cfg(feature="tokio")]
+use std::task::{Context, Poll};
+#[cfg(feature="tokio")]
+use futures_core::Stream;
+#[cfg(feature="tokio")]
+use tokio::io::unix::AsyncFd;
+
+use super::*;
+
+type ReaderTransfer = Box<StdBufTransfer<Vec<u8>>>;
+
+/// Streaming reads from a bulk IN endpoint.
+///
+/// `BulkReader` keeps `depth` bulk IN transfers of `transfer_size` bytes queued on one
+/// endpoint, resubmitting each one when the next read finds its data consumed, so the device
+/// can always send.  The data is presented as a contiguous byte stream through
+/// `std::io::Read`, or chunk by chunk with `next_chunk()`.
+///
+/// Protocols that delimit messages with short packets can read whole messages with
+/// `read_message()`.  For that `transfer_size` must be a multiple of the endpoint's maximum
+/// packet size, so that a transfer only ends short where the device sent a short packet.
+///
+/// A failed transfer is not resubmitted and its error is returned; the queue depth drops by
+/// one.  Once no transfers remain `read()` reports end of file.  With the `tokio` feature,
+/// `into_stream()` gives a `Stream` of chunks.
+///
+/// # Examples
+/// ```no_run
+/// use std::io::{BufRead, BufReader};
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// device.claim_interface(0).unwrap();
+/// let reader = BulkReader::new(device, 0x81, 16 * 1024, 4).unwrap();
+/// for line in BufReader::new(reader).lines() {
+///     println!("{}", line.unwrap());
+/// }
+/// ```
+pub struct BulkReader {
+    device: AsyncDevice<ReaderTransfer>,
+    depth: usize,
+    // reaped transfer being consumed: transfer, data length, read position
+    current: Option<(ReaderTransfer, usize, usize)>,
+    bytes_read: u64,
+}
+
+impl BulkReader {
+    /// Read from bulk IN `endpoint` with `depth` transfers of `transfer_size` bytes each.  The
+    /// direction bit of `endpoint` is set automatically.
+    pub fn new(device: Device, endpoint: u8, transfer_size: usize, depth: usize) -> Result<Self> {
+        if transfer_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidParam, "transfer size must be non-zero"));
+        }
+        let mut reader = BulkReader{device: device.into(), depth: 0, current: None, bytes_read: 0};
+        for _ in 0..depth {
+            reader.device.submit(Box::new(StdBufTransfer::bulk(endpoint | 0x80,
+                                                               UrbFlags::empty(),
+                                                               vec![0; transfer_size])))?;
+            reader.depth += 1;
+        }
+        Ok(reader)
+    }
+
+    /// Number of transfers queued, including one whose data is being consumed.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Bytes received from the device so far, whether consumed yet or not.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Wait for data and return all of it up to the end of the next transfer.  Fails with
+    /// `ErrorKind::NotFound` once no transfers remain.
+    pub fn next_chunk(&mut self) -> Result<Vec<u8>> {
+        self.chunk(true)
+    }
+
+    /// Like `next_chunk()`, but fails with `ErrorKind::WouldBlock` instead of waiting.
+    /// For use with event loops, which should wait for the file descriptor to become writable.
+    pub fn next_chunk_nowait(&mut self) -> Result<Vec<u8>> {
+        self.chunk(false)
+    }
+
+    /// Wait for data up to and including the next short or zero-length packet, which marks the
+    /// end of a message.  Data already partly consumed by `read()` counts towards the message.
+    /// Fails with `ErrorKind::NotFound` once no transfers remain.
+    pub fn read_message(&mut self) -> Result<Vec<u8>> {
+        let mut message = Vec::new();
+        loop {
+            self.fill(true)?;
+            let (xfer, len, pos) = self.current.as_mut().unwrap();
+            message.extend_from_slice(&xfer.buf[*pos..*len]);
+            *pos = *len;
+            if *len < xfer.buf.len() {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Stop reading, returning the underlying `AsyncDevice`.  Unconsumed data is discarded;
+    /// transfers still in flight stay in the device and are returned by its `reap_*()` methods.
+    pub fn into_inner(self) -> AsyncDevice<ReaderTransfer> {
+        self.device
+    }
+
+    /// Register the reader with the current tokio runtime, yielding each chunk as
+    /// `next_chunk()` would.  Available with the `tokio` feature.
+    #[cfg(feature="tokio")]
+    pub fn into_stream(self) -> io::Result<BulkReaderStream> {
+        Ok(BulkReaderStream{inner: AsyncFd::new(self)?})
+    }
+
+    fn chunk(&mut self, wait: bool) -> Result<Vec<u8>> {
+        self.fill(wait)?;
+        let (xfer, len, pos) = self.current.as_mut().unwrap();
+        let chunk = xfer.buf[*pos..*len].to_vec();
+        *pos = *len;
+        Ok(chunk)
+    }
+
+    fn read_buf(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.fill(true) {
+                Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+                other => other?,
+            }
+            let (xfer, len, pos) = self.current.as_mut().unwrap();
+            let n = buf.len().min(*len - *pos);
+            buf[..n].copy_from_slice(&xfer.buf[*pos..*pos + n]);
+            *pos += n;
+            // a zero-length transfer must not read as end of file
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+
+    // Resubmit `current` if its data has been consumed.
+    fn recycle(&mut self) -> Result<()> {
+        match self.current.take() {
+            Some((xfer, len, pos)) if pos == len => {
+                if let Err((err, _)) = self.device.submit_give_back_on_fail(xfer) {
+                    self.depth -= 1;
+                    return Err(err);
+                }
+            }
+            current => self.current = current,
+        }
+        Ok(())
+    }
+
+    // Make `current` a transfer with unconsumed data, or a zero-length transfer.
+    fn fill(&mut self, wait: bool) -> Result<()> {
+        self.recycle()?;
+        if self.current.is_some() {
+            return Ok(());
+        }
+
+        if self.depth == 0 {
+            return Err(Error::new(ErrorKind::NotFound, "no transfers queued"));
+        }
+        let (_slot, xfer, result) = match wait {
+            true => self.device.reap_wait()?,
+            false => self.device.reap_nowait()?,
+        };
+        match result.into_io_result() {
+            Ok(len) => {
+                self.bytes_read += len as u64;
+                self.current = Some((xfer, len, 0));
+                Ok(())
+            }
+            Err(err) => {
+                self.depth -= 1;
+                Err(err.into())
+            }
+        }
+    }
+
+    // Whether data can be consumed without reaping.
+    #[cfg(feature="tokio")]
+    fn has_buffered(&self) -> bool {
+        matches!(self.current, Some((_, len, pos)) if pos < len)
+    }
+}
+
+impl Read for BulkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_buf(buf).map_err(io::Error::from)
+    }
+}
+
+impl AsRawFd for BulkReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}
+
+/// A `BulkReader` registered with the tokio reactor, from `BulkReader::into_stream()`.
+///
+/// Yields chunks of received data.  The stream ends once no transfers remain.
+#[cfg(feature="tokio")]
+pub struct BulkReaderStream {
+    inner: AsyncFd<BulkReader>,
+}
+
+#[cfg(feature="tokio")]
+impl BulkReaderStream {
+    pub fn get_ref(&self) -> &BulkReader {
+        self.inner.get_ref()
+    }
+
+    /// Recover the wrapped `BulkReader`, deregistering it from the reactor.
+    pub fn into_inner(self) -> BulkReader {
+        self.inner.into_inner()
+    }
+}
+
+#[cfg(feature="tokio")]
+impl Stream for BulkReaderStream {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let reader = this.inner.get_mut();
+        if reader.has_buffered() {
+            return Poll::Ready(Some(reader.next_chunk_nowait().map_err(io::Error::from)));
+        }
+        // a consumed transfer must be back in the queue before waiting on the device
+        if let Err(err) = reader.recycle() {
+            return Poll::Ready(Some(Err(err.into())));
+        }
+        if reader.depth() == 0 {
+            return Poll::Ready(None);
+        }
+        loop {
+            let mut guard = match this.inner.poll_write_ready_mut(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(err)) => return Poll::Ready(Some(Err(err))),
+                Poll::Pending => return Poll::Pending,
+            };
+            match guard.try_io(|fd| fd.get_mut().next_chunk_nowait().map_err(io::Error::from)) {
+                Ok(result) => return Poll::Ready(Some(result)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use super::*;
+
+type WriterTransfer = Box<StdBufTransfer<Vec<u8>>>;
+
+/// Streaming writes to a bulk OUT endpoint.
+///
+/// `BulkWriter` accepts a continuous byte stream through `std::io::Write`, gathers it into
+/// transfers of `transfer_size` bytes, and keeps up to `depth` of them in flight so the device
+/// is never left waiting on the host.  The transfer buffers are allocated once and reused as
+/// each transfer completes.  A write blocks only when all `depth` transfers are in flight.
+///
+/// `flush()` sends any partly filled buffer and waits for every transfer to complete.  Data
+/// still buffered when the writer is dropped is lost, so flush before dropping.  After an error
+/// some of the data written may not have reached the device.
+///
+/// # Examples
+/// ```no_run
+/// use std::io::Write;
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// device.claim_interface(0).unwrap();
+/// let image = std::fs::read("image.bin").unwrap();
+/// let mut writer = BulkWriter::new(device, 0x02, 64 * 1024, 4).unwrap();
+/// writer.write_all(&image).unwrap();
+/// writer.flush().unwrap();
+/// println!("{:.2} MB/s", writer.mb_per_sec());
+/// ```
+pub struct BulkWriter {
+    device: AsyncDevice<WriterTransfer>,
+    transfer_size: usize,
+    // transfers not in flight, and the one being filled
+    idle: Vec<WriterTransfer>,
+    filling: Option<WriterTransfer>,
+    bytes_written: u64,
+    started: Option<Instant>,
+}
+
+impl BulkWriter {
+    /// Write to bulk OUT `endpoint` with `depth` transfers of `transfer_size` bytes each.  The
+    /// direction bit of `endpoint` is cleared automatically.
+    pub fn new(device: Device, endpoint: u8, transfer_size: usize, depth: usize) -> Result<Self> {
+        if transfer_size == 0 || depth == 0 {
+            return Err(Error::new(ErrorKind::InvalidParam, "transfer size and depth must be non-zero"));
+        }
+        let idle = (0..depth)
+            .map(|_| Box::new(StdBufTransfer::bulk(endpoint & 0x7f,
+                                                   UrbFlags::empty(),
+                                                   Vec::with_capacity(transfer_size))))
+            .collect();
+        Ok(BulkWriter {
+            device: device.into(),
+            transfer_size,
+            idle,
+            filling: None,
+            bytes_written: 0,
+            started: None,
+        })
+    }
+
+    /// Number of transfers in flight.
+    pub fn pending_count(&self) -> usize {
+        self.device.pending_count()
+    }
+
+    /// Bytes accepted by the device so far.  Data still buffered or in flight is not counted.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Time since the first transfer was submitted.
+    pub fn elapsed(&self) -> Duration {
+        self.started.map(|t| t.elapsed()).unwrap_or_default()
+    }
+
+    /// Sustained throughput in megabytes (10^6 bytes) per second: `bytes_written()` over
+    /// `elapsed()`.
+    pub fn mb_per_sec(&self) -> f64 {
+        match self.elapsed().as_secs_f64() {
+            secs if secs > 0.0 => self.bytes_written as f64 / 1e6 / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// Stop writing, returning the underlying `AsyncDevice`.  Buffered data that hasn't been
+    /// submitted is discarded; transfers still in flight stay in the device and are returned by
+    /// its `reap_*()` methods.
+    pub fn into_inner(self) -> AsyncDevice<WriterTransfer> {
+        self.device
+    }
+
+    fn write_buf(&mut self, data: &[u8]) -> Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let mut xfer = match self.filling.take() {
+            Some(xfer) => xfer,
+            None => self.idle_transfer()?,
+        };
+        let n = data.len().min(self.transfer_size - xfer.buf.len());
+        xfer.buf.extend_from_slice(&data[..n]);
+        if xfer.buf.len() == self.transfer_size {
+            self.submit(xfer)?;
+        } else {
+            self.filling = Some(xfer);
+        }
+        Ok(n)
+    }
+
+    fn flush_buf(&mut self) -> Result<()> {
+        if let Some(xfer) = self.filling.take() {
+            if xfer.buf.is_empty() {
+                self.idle.push(xfer);
+            } else {
+                self.submit(xfer)?;
+            }
+        }
+
+        // wait for everything, reporting the first failure
+        let mut status = Ok(());
+        while self.device.pending_count() > 0 {
+            let (_slot, xfer, result) = self.device.reap_wait()?;
+            let reaped = self.recycle(xfer, result);
+            if status.is_ok() {
+                status = reaped;
+            }
+        }
+        status
+    }
+
+    // An empty transfer to fill, waiting for one to complete if all are in flight.
+    fn idle_transfer(&mut self) -> Result<WriterTransfer> {
+        if let Some(xfer) = self.idle.pop() {
+            return Ok(xfer);
+        }
+        let (_slot, xfer, result) = self.device.reap_wait()?;
+        self.recycle(xfer, result)?;
+        Ok(self.idle.pop().unwrap())
+    }
+
+    // Account for a reaped transfer and return it to the idle list.
+    fn recycle(&mut self, mut xfer: WriterTransfer, result: TransferResult) -> Result<()> {
+        xfer.buf.clear();
+        self.idle.push(xfer);
+        self.bytes_written += result.into_io_result()? as u64;
+        Ok(())
+    }
+
+    fn submit(&mut self, xfer: WriterTransfer) -> Result<()> {
+        self.started.get_or_insert_with(Instant::now);
+        match self.device.submit_give_back_on_fail(xfer) {
+            Ok(_slot) => Ok(()),
+            Err((err, mut xfer)) => {
+                xfer.buf.clear();
+                self.idle.push(xfer);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Write for BulkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_buf(buf).map_err(io::Error::from)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf().map_err(io::Error::from)
+    }
+}
@@ -0,0 +1,618 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::slice;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::*;
+use devfs::{c_int, c_void, ioctl_num_type, BulkTransfer, CtrlTransfer};
+
+// pcapng block types, see https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 1;
+const ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+// The binary usbmon header, with room for isochronous packet descriptors, which Wireshark
+// decodes as "USB packets with Linux header and padding".
+const LINKTYPE_USB_LINUX_MMAPPED: u16 = 220;
+pub(crate) const HEADER_LEN: usize = 64;
+pub(crate) const ISO_DESC_LEN: usize = 16;
+
+/// Whether a `CaptureEvent` is a transfer starting or finishing, usbmon's `S`, `C`, and `E`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CaptureEventKind {
+    Submit,
+    Complete,
+    /// The transfer could not be submitted; `status` tells why.
+    Error,
+}
+
+/// One isochronous packet of a `CaptureEvent`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CaptureIsoPacket {
+    pub status: i32,
+    /// Offset of the packet in the transfer's data.
+    pub offset: u32,
+    pub length: u32,
+}
+
+/// A transfer being submitted or completed, as recorded by `Recorder` and read back by
+/// `Replayer`.
+///
+/// The fields mirror the binary header of the kernel's usbmon, so captures can also come from
+/// Wireshark or `tcpdump` sniffing a `usbmonN` interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureEvent {
+    /// Identifies the transfer; its submission and completion have the same id.
+    pub id: u64,
+    pub kind: CaptureEventKind,
+    pub transfer_type: UrbType,
+    /// Endpoint address including its direction bit.  Control transfers have endpoint 0x80 or
+    /// 0, following the direction of their setup packet.
+    pub endpoint: u8,
+    pub busnum: u16,
+    pub devnum: u8,
+    /// Time since the Unix epoch.
+    pub timestamp: Duration,
+    /// Zero or a negated errno.  Submissions have `-EINPROGRESS`.
+    pub status: i32,
+    /// Length requested at submission, or transferred at completion, excluding any setup
+    /// packet.
+    pub length: u32,
+    /// The setup packet, on submission of a control transfer.
+    pub setup: Option<[u8; 8]>,
+    /// Error count and packets of an isochronous transfer.
+    pub error_count: i32,
+    pub iso_packets: Vec<CaptureIsoPacket>,
+    pub start_frame: i32,
+    pub flags: u32,
+    /// Data going to the device on submission, or coming from it on completion.  Empty if
+    /// there is none, or it was not captured.
+    pub data: Vec<u8>,
+}
+
+impl CaptureEvent {
+    /// Whether this is the submission of a transfer that sends something to the device: any
+    /// control request, or OUT data on another endpoint.
+    pub fn is_host_to_device(&self) -> bool {
+        self.kind == CaptureEventKind::Submit
+            && (self.transfer_type == UrbType::Control || self.endpoint & 0x80 == 0)
+    }
+
+    /// Issue this event's transfer again on `device`, if `is_host_to_device()`.  Control
+    /// requests are sent with their recorded setup packet and OUT data, and IN data is
+    /// discarded; bulk and interrupt OUT data is sent synchronously.  Isochronous transfers
+    /// are not replayed.  Returns whether the transfer was issued.
+    pub fn replay(&self, device: &Device, timeout_ms: u32) -> Result<bool> {
+        if !self.is_host_to_device() {
+            return Ok(false);
+        }
+        match (self.transfer_type, self.setup) {
+            (UrbType::Control, Some(setup)) => {
+                // wLength bounds the buffer to 64KiB; OUT data must all have been captured
+                let length = le16(&setup, 6);
+                let mut data = match setup[0] & 0x80 {
+                    0 if self.data.len() < length as usize => {
+                        return Err(Error::new(ErrorKind::InvalidParam, "control request data missing from capture"));
+                    }
+                    0 => self.data[..length as usize].to_vec(),
+                    _ => vec![0; length as usize],
+                };
+                let mut xfer = CtrlTransfer {
+                    bmRequestType: setup[0],
+                    bRequest: setup[1],
+                    wValue: le16(&setup, 2),
+                    wIndex: le16(&setup, 4),
+                    wLength: length,
+                    timeout: timeout_ms,
+                    data: data.as_mut_ptr(),
+                };
+                unsafe { devfs::nix_result_to_result(devfs::control(device, &mut xfer))? };
+            }
+            (UrbType::Bulk, _) | (UrbType::Interrupt, _) => {
+                device.bulk_transfer_out(self.endpoint, &self.data, timeout_ms)?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.iso_packets.len() * ISO_DESC_LEN + self.data.len());
+        out.extend_from_slice(&self.id.to_ne_bytes());
+        out.push(match self.kind {
+            CaptureEventKind::Submit => b'S',
+            CaptureEventKind::Complete => b'C',
+            CaptureEventKind::Error => b'E',
+        });
+        out.push(self.transfer_type as u8);
+        out.push(self.endpoint);
+        out.push(self.devnum);
+        out.extend_from_slice(&self.busnum.to_ne_bytes());
+        out.push(if self.setup.is_some() { 0 } else { b'-' });
+        out.push(match (self.data.is_empty(), self.endpoint & 0x80) {
+            (false, _) => 0,
+            (true, 0) => b'>',
+            (true, _) => b'<',
+        });
+        out.extend_from_slice(&(self.timestamp.as_secs() as i64).to_ne_bytes());
+        out.extend_from_slice(&(self.timestamp.subsec_micros() as i32).to_ne_bytes());
+        out.extend_from_slice(&self.status.to_ne_bytes());
+        out.extend_from_slice(&self.length.to_ne_bytes());
+        out.extend_from_slice(&(self.data.len() as u32).to_ne_bytes());
+        match self.setup {
+            Some(setup) => out.extend_from_slice(&setup),
+            None => {
+                out.extend_from_slice(&self.error_count.to_ne_bytes());
+                out.extend_from_slice(&(self.iso_packets.len() as i32).to_ne_bytes());
+            }
+        }
+        out.extend_from_slice(&0i32.to_ne_bytes()); // interval
+        out.extend_from_slice(&self.start_frame.to_ne_bytes());
+        out.extend_from_slice(&self.flags.to_ne_bytes());
+        out.extend_from_slice(&(self.iso_packets.len() as u32).to_ne_bytes());
+        for packet in &self.iso_packets {
+            out.extend_from_slice(&packet.status.to_ne_bytes());
+            out.extend_from_slice(&packet.offset.to_ne_bytes());
+            out.extend_from_slice(&packet.length.to_ne_bytes());
+            out.extend_from_slice(&0u32.to_ne_bytes());
+        }
+        out.extend_from_slice(&self.data);
+        out
+    }
+
+    // Parse a usbmon packet with the 64 byte header.
+    pub(crate) fn parse(buf: &[u8]) -> Result<CaptureEvent> {
+        if buf.len() < HEADER_LEN {
+            return Err(Error::new(ErrorKind::InvalidParam, "short usbmon header"));
+        }
+        let u32_at = |i: usize| u32::from_ne_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+        let kind = match buf[8] {
+            b'S' => CaptureEventKind::Submit,
+            b'C' => CaptureEventKind::Complete,
+            b'E' => CaptureEventKind::Error,
+            _ => return Err(Error::new(ErrorKind::InvalidParam, "bad usbmon event type")),
+        };
+        let transfer_type = UrbType::from_raw(buf[9]).unwrap_or(UrbType::Bulk);
+        let mut ts_sec = [0; 8];
+        ts_sec.copy_from_slice(&buf[16..24]);
+        let ndesc = u32_at(60) as usize;
+        let len_cap = u32_at(36) as usize;
+        // counts too big for the packet can't be trusted to add up without overflowing
+        let data_start = match ndesc.checked_mul(ISO_DESC_LEN).and_then(|descs| descs.checked_add(HEADER_LEN)) {
+            Some(start) if buf.len() >= start && buf.len() - start >= len_cap => start,
+            _ => return Err(Error::new(ErrorKind::InvalidParam, "short usbmon packet")),
+        };
+        let mut setup = [0; 8];
+        setup.copy_from_slice(&buf[40..48]);
+        let iso_packets = (0..ndesc).map(|i| {
+            let at = HEADER_LEN + i * ISO_DESC_LEN;
+            CaptureIsoPacket { status: u32_at(at) as i32, offset: u32_at(at + 4), length: u32_at(at + 8) }
+        }).collect();
+        Ok(CaptureEvent {
+            id: u64::from_ne_bytes([buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7]]),
+            kind,
+            transfer_type,
+            endpoint: buf[10],
+            devnum: buf[11],
+            busnum: u16::from_ne_bytes([buf[12], buf[13]]),
+            timestamp: Duration::new(i64::from_ne_bytes(ts_sec).max(0) as u64, 0)
+                + Duration::from_micros(u32_at(24) as u64),
+            status: u32_at(28) as i32,
+            length: u32_at(32),
+            setup: if buf[14] == 0 { Some(setup) } else { None },
+            error_count: if buf[14] == 0 { 0 } else { u32_at(40) as i32 },
+            iso_packets,
+            start_frame: u32_at(52) as i32,
+            flags: u32_at(56),
+            data: buf[data_start..data_start + len_cap].to_vec(),
+        })
+    }
+}
+
+/// Records every transfer of a device to a [pcapng](https://pcapng.com/) capture.
+///
+/// A `Recorder` is a `Backend` standing between a device and its usbfs file.  Devices made by
+/// `device()` send their ioctls through it, so synchronous control and bulk transfers, and
+/// urbs submitted and reaped by `AsyncDevice` and everything built on it, are all captured
+/// with their setup packets, data, status, and timing.  Packets have the binary usbmon
+/// header, so the capture opens in Wireshark like one taken from a `usbmonN` interface, and
+/// can be played back to a device with `Replayer`.  This helps when working out the protocol
+/// of a device from its vendor's software, or when checking that a driver still talks to its
+/// device as it used to.
+///
+/// Writing to the capture is best effort: a failed write stops recording without disturbing
+/// the device, and is reported by `flush()`.
+///
+/// # Examples
+/// ```no_run
+/// use std::fs::File;
+/// use usbfs::*;
+///
+/// let info = deviceinfo_find(0xffff, 3).unwrap();
+/// let recorder = Recorder::new(Device::new(&info).unwrap(), File::create("dev.pcapng").unwrap()).unwrap();
+/// let device = recorder.device().unwrap();
+/// device.claim_interface(0).unwrap();
+/// device.bulk_transfer_out(1, b"hello", 1000).unwrap();
+/// recorder.flush().unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Recorder(Arc<RecorderShared>);
+
+struct RecorderShared {
+    device: Device,
+    busnum: u16,
+    devnum: u8,
+    sink: Mutex<Sink>,
+}
+
+struct Sink {
+    out: Box<dyn Write + Send>,
+    error: Option<io::Error>,
+}
+
+impl Sink {
+    fn write_block(&mut self, block_type: u32, body: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        let padding = (4 - body.len() % 4) % 4;
+        let total = (12 + body.len() + padding) as u32;
+        let mut block = Vec::with_capacity(total as usize);
+        block.extend_from_slice(&block_type.to_ne_bytes());
+        block.extend_from_slice(&total.to_ne_bytes());
+        block.extend_from_slice(body);
+        block.resize(block.len() + padding, 0);
+        block.extend_from_slice(&total.to_ne_bytes());
+        if let Err(err) = self.out.write_all(&block) {
+            self.error = Some(err);
+        }
+    }
+
+    fn write_event(&mut self, event: &CaptureEvent) {
+        let packet = event.encode();
+        let micros = event.timestamp.as_micros() as u64;
+        let mut body = Vec::with_capacity(20 + packet.len());
+        body.extend_from_slice(&0u32.to_ne_bytes()); // interface
+        body.extend_from_slice(&((micros >> 32) as u32).to_ne_bytes());
+        body.extend_from_slice(&(micros as u32).to_ne_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_ne_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_ne_bytes());
+        body.extend_from_slice(&packet);
+        self.write_block(ENHANCED_PACKET, &body);
+    }
+}
+
+impl Recorder {
+    /// Record transfers of `device` to `out`, starting with the capture's header.  The device
+    /// should not be used directly from here on, or its transfers will be missed.
+    pub fn new<W: Write + Send + 'static>(device: Device, out: W) -> Result<Recorder> {
+        let (busnum, devnum) = deviceinfo::busdev_from_fd(&device).unwrap_or((0, 0));
+        let mut sink = Sink { out: Box::new(out), error: None };
+
+        let mut section = Vec::new();
+        section.extend_from_slice(&BYTE_ORDER_MAGIC.to_ne_bytes());
+        section.extend_from_slice(&1u16.to_ne_bytes());
+        section.extend_from_slice(&0u16.to_ne_bytes());
+        section.extend_from_slice(&(-1i64).to_ne_bytes()); // section length unknown
+        sink.write_block(SECTION_HEADER, &section);
+
+        let mut interface = Vec::new();
+        interface.extend_from_slice(&LINKTYPE_USB_LINUX_MMAPPED.to_ne_bytes());
+        interface.extend_from_slice(&0u16.to_ne_bytes());
+        interface.extend_from_slice(&0u32.to_ne_bytes()); // no snapshot length
+        sink.write_block(INTERFACE_DESCRIPTION, &interface);
+
+        if let Some(err) = sink.error.take() {
+            return Err(err.into());
+        }
+        Ok(Recorder(Arc::new(RecorderShared {
+            device,
+            busnum: busnum as u16,
+            devnum: devnum as u8,
+            sink: Mutex::new(sink),
+        })))
+    }
+
+    /// A `Device` whose transfers are recorded.  Any number may be made.
+    pub fn device(&self) -> Result<Device> {
+        let fd = self.0.device.try_clone()?.into_raw_fd();
+        Ok(Device::with_backend(unsafe { File::from_raw_fd(fd) }, Arc::new(self.clone())))
+    }
+
+    /// Flush the capture, reporting the error that stopped recording, if there was one.
+    pub fn flush(&self) -> Result<()> {
+        let mut sink = self.lock();
+        if let Some(ref err) = sink.error {
+            return Err(Error::new(ErrorKind::Other, &format!("recording stopped: {}", err)));
+        }
+        sink.out.flush()?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Sink> {
+        self.0.sink.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn event(&self, id: u64, kind: CaptureEventKind, transfer_type: UrbType, endpoint: u8) -> CaptureEvent {
+        CaptureEvent {
+            id,
+            kind,
+            transfer_type,
+            endpoint,
+            busnum: self.0.busnum,
+            devnum: self.0.devnum,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default(),
+            status: 0,
+            length: 0,
+            setup: None,
+            error_count: 0,
+            iso_packets: Vec::new(),
+            start_frame: 0,
+            flags: 0,
+            data: Vec::new(),
+        }
+    }
+
+    // Submission of an urb, read before it is handed to the device.
+    unsafe fn urb_submit_event(&self, urb: *const Urb) -> CaptureEvent {
+        let u = &*urb;
+        let buffer = buffer(u.buffer, u.buffer_length);
+        let mut event = self.urb_event(urb, CaptureEventKind::Submit);
+        event.status = -libc::EINPROGRESS;
+        if u.urbtype == UrbType::Control as u8 && buffer.len() >= 8 {
+            let mut setup = [0; 8];
+            setup.copy_from_slice(&buffer[..8]);
+            event.endpoint = setup[0] & 0x80;
+            event.length = le16(&setup, 6) as u32;
+            if setup[0] & 0x80 == 0 {
+                event.data = buffer[8..].iter().take(event.length as usize).cloned().collect();
+            }
+            event.setup = Some(setup);
+        } else {
+            event.length = buffer.len() as u32;
+            if u.endpoint & 0x80 == 0 {
+                event.data = buffer.to_vec();
+            }
+        }
+        event
+    }
+
+    // Completion of a reaped urb.
+    unsafe fn urb_complete_event(&self, urb: *const Urb) -> CaptureEvent {
+        let u = &*urb;
+        let buffer = buffer(u.buffer, u.buffer_length);
+        let actual = u.actual_length.max(0) as usize;
+        let mut event = self.urb_event(urb, CaptureEventKind::Complete);
+        event.status = u.status;
+        event.length = actual as u32;
+        if u.urbtype == UrbType::Control as u8 && buffer.len() >= 8 {
+            event.endpoint = buffer[0] & 0x80;
+            if event.endpoint != 0 {
+                event.data = buffer[8..].iter().take(actual).cloned().collect();
+            }
+        } else if u.endpoint & 0x80 != 0 {
+            // isochronous packets sit at their offsets in the whole buffer
+            event.data = match u.urbtype == UrbType::Iso as u8 {
+                true => buffer.to_vec(),
+                false => buffer[..actual.min(buffer.len())].to_vec(),
+            };
+        }
+        event
+    }
+
+    unsafe fn urb_event(&self, urb: *const Urb, kind: CaptureEventKind) -> CaptureEvent {
+        let u = &*urb;
+        let transfer_type = UrbType::from_raw(u.urbtype).unwrap_or(UrbType::Bulk);
+        let mut event = self.event(urb as u64, kind, transfer_type, u.endpoint);
+        event.flags = u.flags.bits();
+        if transfer_type == UrbType::Iso {
+            let descs = devfs::iso_frame_desc(urb);
+            let mut offset = 0;
+            for desc in descs {
+                let (status, length) = match kind {
+                    CaptureEventKind::Complete => (desc.status, desc.actual_length),
+                    _ => (0, desc.length),
+                };
+                event.iso_packets.push(CaptureIsoPacket { status, offset, length: length as u32 });
+                offset += desc.length as u32;
+            }
+            event.start_frame = u.start_frame;
+            event.error_count = u.error_count;
+        }
+        event
+    }
+
+    // A synchronous transfer, recorded as a submission and a completion around the ioctl.
+    unsafe fn sync_transfer(&self, request: ioctl_num_type, arg: *mut c_void, mut submit: CaptureEvent,
+                            data: *mut u8) -> nix::Result<c_int> {
+        let is_in = submit.endpoint & 0x80 != 0;
+        if !is_in {
+            submit.data = buffer(data, submit.length as i32).to_vec();
+        }
+        submit.status = -libc::EINPROGRESS;
+        self.lock().write_event(&submit);
+        let result = self.0.device.ioctl(request, arg);
+        let mut complete = self.event(submit.id, CaptureEventKind::Complete, submit.transfer_type, submit.endpoint);
+        match result {
+            Ok(len) => {
+                complete.length = len as u32;
+                if is_in {
+                    complete.data = buffer(data, len).to_vec();
+                }
+            }
+            Err(errno) => complete.status = -(errno as i32),
+        }
+        self.lock().write_event(&complete);
+        result
+    }
+}
+
+unsafe impl Backend for Recorder {
+    unsafe fn ioctl(&self, request: ioctl_num_type, arg: *mut c_void) -> nix::Result<c_int> {
+        match request {
+            devfs::CONTROL => {
+                let xfer = &*(arg as *const CtrlTransfer);
+                let mut event = self.event(arg as u64, CaptureEventKind::Submit, UrbType::Control,
+                                           xfer.bmRequestType & 0x80);
+                let mut setup = [xfer.bmRequestType, xfer.bRequest, 0, 0, 0, 0, 0, 0];
+                setup[2..4].copy_from_slice(&xfer.wValue.to_le_bytes());
+                setup[4..6].copy_from_slice(&xfer.wIndex.to_le_bytes());
+                setup[6..8].copy_from_slice(&xfer.wLength.to_le_bytes());
+                event.setup = Some(setup);
+                event.length = xfer.wLength as u32;
+                self.sync_transfer(request, arg, event, xfer.data)
+            }
+            devfs::BULK => {
+                let xfer = &*(arg as *const BulkTransfer);
+                let mut event = self.event(arg as u64, CaptureEventKind::Submit, UrbType::Bulk, xfer.ep as u8);
+                event.length = xfer.len;
+                self.sync_transfer(request, arg, event, xfer.data)
+            }
+            devfs::SUBMITURB => {
+                let mut event = self.urb_submit_event(arg as *const Urb);
+                // hold the capture until the submission is written, so that a reap on another
+                // thread can't record the completion first
+                let mut sink = self.lock();
+                let result = self.0.device.ioctl(request, arg);
+                if let Err(errno) = result {
+                    event.kind = CaptureEventKind::Error;
+                    event.status = -(errno as i32);
+                }
+                sink.write_event(&event);
+                result
+            }
+            devfs::REAPURB | devfs::REAPURBNDELAY => {
+                let result = self.0.device.ioctl(request, arg);
+                if result.is_ok() {
+                    let event = self.urb_complete_event(*(arg as *const *const Urb));
+                    self.lock().write_event(&event);
+                }
+                result
+            }
+            _ => self.0.device.ioctl(request, arg),
+        }
+    }
+}
+
+// A transfer's buffer, or nothing for a null pointer.
+unsafe fn buffer<'a>(data: *mut u8, len: i32) -> &'a [u8] {
+    match data.is_null() || len <= 0 {
+        true => &[],
+        false => slice::from_raw_parts(data, len as usize),
+    }
+}
+
+fn le16(buf: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([buf[i], buf[i + 1]])
+}
+
+/// Reads back the captures written by `Recorder`, or by Wireshark or `tcpdump` sniffing a
+/// `usbmonN` interface, and plays them to a device.
+///
+/// Replaying sends what the host sent: every control request and all bulk and interrupt OUT
+/// data, in order, as `CaptureEvent::replay()` does.  A capture of a vendor's configuration
+/// tool can then be applied without the tool, or a recorded session repeated in a regression
+/// test.  Only captures in pcapng format are read, and only packets with the 64 byte usbmon
+/// header (`LINKTYPE_USB_LINUX_MMAPPED`); packets from other interfaces are skipped.
+///
+/// # Examples
+/// ```no_run
+/// use std::fs::File;
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// device.claim_interface(0).unwrap();
+/// let mut replayer = Replayer::new(File::open("dev.pcapng").unwrap()).unwrap();
+/// let count = replayer.replay(&device, 1000).unwrap();
+/// println!("replayed {} transfers", count);
+/// ```
+pub struct Replayer<R> {
+    reader: R,
+    // link type of each interface described so far in the current section
+    linktypes: Vec<u16>,
+}
+
+impl<R: Read> Replayer<R> {
+    /// Start reading a capture, checking its section header.
+    pub fn new(mut reader: R) -> Result<Replayer<R>> {
+        match read_block(&mut reader)? {
+            Some((SECTION_HEADER, _)) => Ok(Replayer { reader, linktypes: Vec::new() }),
+            _ => Err(Error::new(ErrorKind::InvalidParam, "not a pcapng capture")),
+        }
+    }
+
+    /// The next event in the capture, or `None` at its end.
+    pub fn next_event(&mut self) -> Result<Option<CaptureEvent>> {
+        while let Some((block_type, body)) = read_block(&mut self.reader)? {
+            match block_type {
+                SECTION_HEADER => self.linktypes.clear(),
+                INTERFACE_DESCRIPTION if body.len() >= 2 => {
+                    self.linktypes.push(u16::from_ne_bytes([body[0], body[1]]));
+                }
+                ENHANCED_PACKET if body.len() >= 20 => {
+                    let u32_at = |i: usize| u32::from_ne_bytes([body[i], body[i + 1], body[i + 2], body[i + 3]]);
+                    let interface = u32_at(0) as usize;
+                    let caplen = u32_at(12) as usize;
+                    if self.linktypes.get(interface) != Some(&LINKTYPE_USB_LINUX_MMAPPED) {
+                        continue;
+                    }
+                    if body.len() < 20 + caplen {
+                        return Err(Error::new(ErrorKind::InvalidParam, "short packet block"));
+                    }
+                    return CaptureEvent::parse(&body[20..20 + caplen]).map(Some);
+                }
+                _ => (),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Replay the rest of the capture to `device`, returning how many transfers were issued.
+    /// Stops at the first transfer that fails.
+    pub fn replay(&mut self, device: &Device, timeout_ms: u32) -> Result<usize> {
+        let mut count = 0;
+        while let Some(event) = self.next_event()? {
+            if event.replay(device, timeout_ms)? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+// Read a pcapng block, returning its type and body, or `None` at the end of the capture.
+fn read_block<R: Read>(reader: &mut R) -> Result<Option<(u32, Vec<u8>)>> {
+    let mut header = [0u8; 8];
+    match reader.read_exact(&mut header[..4]) {
+        Ok(()) => (),
+        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    reader.read_exact(&mut header[4..])?;
+    let block_type = u32::from_ne_bytes([header[0], header[1], header[2], header[3]]);
+    let total = u32::from_ne_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let mut body = Vec::new();
+    if block_type == SECTION_HEADER {
+        // the length can only be trusted once the byte order is known
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if u32::from_ne_bytes(magic) != BYTE_ORDER_MAGIC {
+            return Err(Error::new(ErrorKind::Unsupported, "capture has foreign byte order"));
+        }
+        body.extend_from_slice(&magic);
+    }
+    if total < 12 + body.len() || !total.is_multiple_of(4) {
+        return Err(Error::new(ErrorKind::InvalidParam, "bad pcapng block length"));
+    }
+    // read rather than allocate what the length claims, which a damaged capture could put
+    // far beyond its end
+    let rest = (total - 8 - body.len()) as u64;
+    if reader.take(rest).read_to_end(&mut body)? as u64 != rest {
+        return Err(Error::new(ErrorKind::InvalidParam, "truncated pcapng block"));
+    }
+    body.truncate(total - 12);
+    Ok(Some((block_type, body)))
+}
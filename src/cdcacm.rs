@@ -0,0 +1,215 @@
+use std::io::{self, Read, Write};
+
+use super::*;
+
+/// Stop bits of a `LineCoding`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StopBits {
+    One = 0,
+    OnePointFive = 1,
+    Two = 2,
+}
+
+/// Parity of a `LineCoding`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Parity {
+    None = 0,
+    Odd = 1,
+    Even = 2,
+    Mark = 3,
+    Space = 4,
+}
+
+/// Serial line settings of a CDC-ACM function, as sent with SET_LINE_CODING.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LineCoding {
+    pub baud_rate: u32,
+    pub stop_bits: StopBits,
+    pub parity: Parity,
+    /// 5, 6, 7, 8, or 16.
+    pub data_bits: u8,
+}
+
+impl Default for LineCoding {
+    /// 115200 baud, 8N1.
+    fn default() -> LineCoding {
+        LineCoding { baud_rate: 115200, stop_bits: StopBits::One, parity: Parity::None, data_bits: 8 }
+    }
+}
+
+impl LineCoding {
+    fn to_bytes(self) -> [u8; 7] {
+        let b = self.baud_rate.to_le_bytes();
+        [b[0], b[1], b[2], b[3], self.stop_bits as u8, self.parity as u8, self.data_bits]
+    }
+
+    fn from_bytes(d: &[u8]) -> Option<LineCoding> {
+        if d.len() < 7 {
+            return None;
+        }
+        let stop_bits = match d[4] {
+            0 => StopBits::One,
+            1 => StopBits::OnePointFive,
+            2 => StopBits::Two,
+            _ => return None,
+        };
+        let parity = match d[5] {
+            0 => Parity::None,
+            1 => Parity::Odd,
+            2 => Parity::Even,
+            3 => Parity::Mark,
+            4 => Parity::Space,
+            _ => return None,
+        };
+        Some(LineCoding {
+            baud_rate: u32::from_le_bytes([d[0], d[1], d[2], d[3]]),
+            stop_bits,
+            parity,
+            data_bits: d[6],
+        })
+    }
+}
+
+// CDC PSTN class requests
+const SET_LINE_CODING: u8 = 0x20;
+const GET_LINE_CODING: u8 = 0x21;
+const SET_CONTROL_LINE_STATE: u8 = 0x22;
+const SEND_BREAK: u8 = 0x23;
+
+/// A CDC-ACM serial port: the communications interface, which takes the line settings, and
+/// the data interface, whose bulk endpoints carry the data.  Available with the `cdc` feature.
+///
+/// Both interfaces are claimed by `CdcAcm::open()` and released on drop.  Data is moved with
+/// the `Read` and `Write` implementations, each call being one synchronous bulk transfer.  A
+/// transfer that doesn't complete within the timeout fails with `io::ErrorKind::TimedOut`.
+///
+/// # Examples
+/// ```no_run
+/// use std::io::{BufRead, BufReader, Write};
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let mut port = CdcAcm::open(&device, 0).unwrap();
+/// port.set_line_coding(LineCoding { baud_rate: 9600, ..Default::default() }).unwrap();
+/// port.set_control_line_state(true, true).unwrap();
+/// port.write_all(b"*IDN?\n").unwrap();
+/// for line in BufReader::new(port).lines() {
+///     println!("{}", line.unwrap());
+/// }
+/// ```
+pub struct CdcAcm<'a> {
+    control: Interface<'a>,
+    data: Interface<'a>,
+    ep_in: u8,
+    ep_out: u8,
+    timeout_ms: u32,
+}
+
+impl<'a> CdcAcm<'a> {
+    /// Claim communications interface `control_interface` and its data interface.  The data
+    /// interface is taken from the union functional descriptor, or is the following interface
+    /// if there is none.  A data interface without a bulk IN and a bulk OUT endpoint gives
+    /// `ErrorKind::NotFound`.
+    ///
+    /// Kernel drivers bound to the interfaces, usually `cdc_acm`, must be detached first.
+    pub fn open(device: &'a Device, control_interface: u8) -> Result<CdcAcm<'a>> {
+        let control = device.claim(control_interface)?;
+        let data_interface = control.descriptor().cdc_functional_descriptors()
+            .filter_map(|func| match func {
+                CdcFunctional::Union { subordinate_interfaces, .. } => subordinate_interfaces.first().copied(),
+                _ => None,
+            })
+            .next()
+            .unwrap_or(control_interface + 1);
+        let mut data = device.claim(data_interface)?;
+
+        // some functions only enable their endpoints in a later alternate setting
+        let bulk_pair = |intf: &InterfaceDescriptor| {
+            let find = |is_in: bool| intf.endpoints.iter()
+                .find(|ep| ep.transfer_type() == UrbType::Bulk && ep.is_in() == is_in)
+                .map(|ep| ep.bEndpointAddress);
+            Some((intf.bAlternateSetting, find(true)?, find(false)?))
+        };
+        let (altsetting, ep_in, ep_out) = data.altsettings().iter().filter_map(bulk_pair).next()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no bulk endpoints on CDC data interface"))?;
+        if altsetting != data.altsetting() {
+            data.set_altsetting(altsetting)?;
+        }
+
+        Ok(CdcAcm { control, data, ep_in, ep_out, timeout_ms: 1000 })
+    }
+
+    pub fn control_interface(&self) -> &Interface<'a> {
+        &self.control
+    }
+
+    pub fn data_interface(&self) -> &Interface<'a> {
+        &self.data
+    }
+
+    /// Timeout of each read, write, and control request; 0 waits forever.  Defaults to 1000ms.
+    pub fn set_timeout(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    pub fn timeout(&self) -> u32 {
+        self.timeout_ms
+    }
+
+    pub fn set_line_coding(&self, coding: LineCoding) -> Result<()> {
+        self.request(SET_LINE_CODING, 0)
+            .data_out(&coding.to_bytes())
+            .send(self.control.device())
+            .map(|_| ())
+    }
+
+    pub fn get_line_coding(&self) -> Result<LineCoding> {
+        let data = self.request(GET_LINE_CODING, 0)
+            .data_in(7)
+            .send(self.control.device())?;
+        LineCoding::from_bytes(&data)
+            .ok_or_else(|| Error::new(ErrorKind::Other, "malformed GET_LINE_CODING response"))
+    }
+
+    /// Set the DTR and RTS signals.  Many functions only send data while DTR is asserted.
+    pub fn set_control_line_state(&self, dtr: bool, rts: bool) -> Result<()> {
+        self.request(SET_CONTROL_LINE_STATE, (dtr as u16) | (rts as u16) << 1)
+            .send(self.control.device())
+            .map(|_| ())
+    }
+
+    /// Send a break for `duration_ms`.  0xffff holds the break until it is ended by a
+    /// `duration_ms` of 0.
+    pub fn send_break(&self, duration_ms: u16) -> Result<()> {
+        self.request(SEND_BREAK, duration_ms)
+            .send(self.control.device())
+            .map(|_| ())
+    }
+
+    // A class request to the communications interface.
+    fn request(&self, request: u8, value: u16) -> ControlRequest {
+        ControlRequest::new()
+            .request_type(SetupType::Class)
+            .recipient(SetupRecipient::Interface)
+            .request(request)
+            .value(value)
+            .index(self.control.number() as u16)
+            .timeout(self.timeout_ms)
+    }
+}
+
+impl<'a> Read for CdcAcm<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.device().bulk_transfer_in(self.ep_in, buf, self.timeout_ms).map(|n| n as usize).map_err(io::Error::from)
+    }
+}
+
+impl<'a> Write for CdcAcm<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.device().bulk_transfer_out(self.ep_out, buf, self.timeout_ms).map(|n| n as usize).map_err(io::Error::from)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
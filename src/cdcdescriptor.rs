@@ -0,0 +1,83 @@
+use super::*;
+use descriptors::{le16, le32};
+
+/// CDC functional descriptor, found among the `extra` descriptors of communications class
+/// interfaces.
+///
+/// Available with the `cdc` feature.
+///
+/// # Examples
+/// Find the data interface belonging to a CDC-ACM control interface:
+///
+/// ```no_run
+/// use usbfs::*;
+///
+/// let config = &deviceinfo_find(0xffff, 3).unwrap().configurations().unwrap()[0];
+/// for intf in config.interfaces.iter() {
+///     for func in intf.cdc_functional_descriptors() {
+///         if let CdcFunctional::Union{control_interface, subordinate_interfaces} = func {
+///             println!("control {} data {:?}", control_interface, subordinate_interfaces);
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum CdcFunctional {
+    /// Header functional descriptor (subtype `0x00`).
+    Header { bcdCDC: u16 },
+    /// Call management functional descriptor (subtype `0x01`).
+    CallManagement { bmCapabilities: u8, bDataInterface: u8 },
+    /// Abstract control management functional descriptor (subtype `0x02`).
+    AbstractControlManagement { bmCapabilities: u8 },
+    /// Union functional descriptor (subtype `0x06`).
+    Union { control_interface: u8, subordinate_interfaces: Vec<u8> },
+    /// Ethernet networking functional descriptor (subtype `0x0f`).
+    EthernetNetworking {
+        iMACAddress: u8,
+        bmEthernetStatistics: u32,
+        wMaxSegmentSize: u16,
+        wNumberMCFilters: u16,
+        bNumberPowerFilters: u8,
+    },
+    /// Any other functional descriptor, with the bytes following its subtype.
+    Other { subtype: u8, data: Vec<u8> },
+}
+
+impl CdcFunctional {
+    /// Parse a raw functional descriptor.  `None` if `raw` is not a `CS_INTERFACE` descriptor,
+    /// or is too short for its subtype; subtypes not parsed here are returned as `Other`.
+    pub fn parse(raw: &RawDescriptor) -> Option<CdcFunctional> {
+        let d = &raw.0;
+        if d.len() < 3 || d[1] != CS_INTERFACE {
+            return None;
+        }
+        let func = match (d[2], d.len()) {
+            (0x00, 5..) => CdcFunctional::Header { bcdCDC: le16(d, 3) },
+            (0x01, 5..) => CdcFunctional::CallManagement { bmCapabilities: d[3], bDataInterface: d[4] },
+            (0x02, 4..) => CdcFunctional::AbstractControlManagement { bmCapabilities: d[3] },
+            (0x06, 4..) => CdcFunctional::Union {
+                control_interface: d[3],
+                subordinate_interfaces: d[4..].to_vec(),
+            },
+            (0x0f, 13..) => CdcFunctional::EthernetNetworking {
+                iMACAddress: d[3],
+                bmEthernetStatistics: le32(d, 4),
+                wMaxSegmentSize: le16(d, 8),
+                wNumberMCFilters: le16(d, 10),
+                bNumberPowerFilters: d[12],
+            },
+            (0x00 | 0x01 | 0x02 | 0x06 | 0x0f, _) => return None,
+            (subtype, _) => CdcFunctional::Other { subtype, data: d[3..].to_vec() },
+        };
+        Some(func)
+    }
+}
+
+impl InterfaceDescriptor {
+    /// CDC functional descriptors of a communications class interface.  Available with the
+    /// `cdc` feature.
+    pub fn cdc_functional_descriptors(&self) -> impl Iterator<Item=CdcFunctional> + '_ {
+        self.extra.iter().filter_map(CdcFunctional::parse)
+    }
+}
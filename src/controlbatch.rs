@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use super::*;
+
+type BatchTransfer = Box<ControlTransferMut<Vec<u8>>>;
+
+/// Reply to one request of a `ControlBatch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlReply {
+    /// The setup packet as sent.
+    pub setup: Setup,
+    pub result: TransferResult,
+    /// Data received by an IN request.  Empty for OUT requests.
+    pub data: Vec<u8>,
+}
+
+/// A sequence of control requests submitted together to an `AsyncDevice`, such as the dozens
+/// of register writes a device needs at initialization.
+///
+/// Every request is queued on endpoint 0 at once, so the device works through them without
+/// waiting on the host between requests, and `run()` returns one `ControlReply` per request,
+/// in the order they were added.  A batch can be run any number of times.
+///
+/// In all-or-nothing mode the requests are instead sent one at a time, and `run()` stops at
+/// the first that fails, returning its error; no request after it reaches the device.
+/// Requests that succeeded before it are not undone.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let mut device: AsyncDevice<Box<ControlTransferMut<Vec<u8>>>> = device.into();
+/// let write_reg = Setup::new(SetupDirection::HostToDevice, SetupType::Vendor,
+///                            SetupRecipient::Device, 0x01, 0, 0, 0);
+/// let read_id = Setup::new(SetupDirection::DeviceToHost, SetupType::Vendor,
+///                          SetupRecipient::Device, 0x02, 0, 0, 4);
+/// let mut batch = ControlBatch::new().all_or_nothing(true);
+/// for (reg, value) in [(0x10, 0x01), (0x11, 0x80), (0x12, 0x3f)] {
+///     batch.write(Setup { wIndex: reg, ..write_reg }, &[value]);
+/// }
+/// batch.read(read_id);
+/// let replies = batch.run(&mut device, Duration::from_secs(1)).unwrap();
+/// println!("id {:02x?}", replies[3].data);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ControlBatch {
+    // setup packets and the buffers they are sent with, setup room included
+    requests: Vec<(Setup, Vec<u8>)>,
+    all_or_nothing: bool,
+}
+
+impl ControlBatch {
+    /// Create an empty batch, which runs every request regardless of failures.
+    pub fn new() -> ControlBatch {
+        Default::default()
+    }
+
+    /// Whether to send requests one at a time and stop at the first failure.
+    pub fn all_or_nothing(mut self, all_or_nothing: bool) -> ControlBatch {
+        self.all_or_nothing = all_or_nothing;
+        self
+    }
+
+    /// Add an OUT request sending `data`.  The direction of `setup` is set to host to device
+    /// and its `wLength` to the length of `data`.
+    pub fn write(&mut self, mut setup: Setup, data: &[u8]) -> &mut ControlBatch {
+        setup.bmRequestType &= !(SetupDirection::DeviceToHost as u8);
+        let mut buf = vec![0; 8];
+        buf.extend_from_slice(data);
+        self.requests.push((setup, buf));
+        self
+    }
+
+    /// Add an IN request reading up to `wLength` bytes.  The direction of `setup` is set to
+    /// device to host.
+    pub fn read(&mut self, mut setup: Setup) -> &mut ControlBatch {
+        setup.bmRequestType |= SetupDirection::DeviceToHost as u8;
+        self.requests.push((setup, vec![0; 8 + setup.wLength as usize]));
+        self
+    }
+
+    /// Number of requests in the batch.
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Submit the requests to `device` and wait for all of them, allowing `timeout` for each
+    /// to complete.
+    ///
+    /// `device` must have no transfers in flight, or this fails with
+    /// `ErrorKind::InvalidParam`.  If submission fails, or no request completes within
+    /// `timeout`, the requests still in flight are cancelled and the error returned.
+    pub fn run<U: Default>(&self, device: &mut AsyncDevice<BatchTransfer, U>, timeout: Duration) -> Result<Vec<ControlReply>> {
+        if device.pending_count() != 0 {
+            return Err(Error::new(ErrorKind::InvalidParam, "device has transfers in flight"));
+        }
+        let depth = if self.all_or_nothing { 1 } else { self.requests.len() };
+        let mut slots: Vec<SlotId> = Vec::with_capacity(self.requests.len());
+        let mut replies: Vec<Option<ControlReply>> = vec![None; self.requests.len()];
+        let mut done = 0;
+        while done < self.requests.len() {
+            while slots.len() < self.requests.len() && slots.len() - done < depth {
+                let (setup, ref buf) = self.requests[slots.len()];
+                let xfer = ControlTransferMut::from_setup(setup, buf.clone());
+                match device.submit(Box::new(xfer)) {
+                    Ok(slot) => slots.push(slot),
+                    Err(err) => return Err(abandon(device, err)),
+                }
+            }
+            let (slot, xfer, result) = match device.reap_timeout(timeout) {
+                Ok(reaped) => reaped,
+                Err(err) => return Err(abandon(device, err)),
+            };
+            let index = match slots.iter().position(|&s| s == slot) {
+                Some(index) => index,
+                None => continue,
+            };
+            done += 1;
+            if self.all_or_nothing && !result.is_ok() {
+                let kind = Error::from(result.into_io_result().unwrap_err()).kind();
+                return Err(Error::new(kind, &format!("control request {} failed: {}", index, result)));
+            }
+            replies[index] = Some(reply(*xfer, result));
+        }
+        Ok(replies.into_iter().map(Option::unwrap).collect())
+    }
+}
+
+// The reply to a reaped request.
+fn reply(xfer: ControlTransferMut<Vec<u8>>, result: TransferResult) -> ControlReply {
+    let setup = *xfer.setup();
+    let mut data = xfer.buf;
+    match (setup.bmRequestType & SetupDirection::DeviceToHost as u8, result) {
+        (0, _) => data.clear(),
+        (_, TransferResult::Completed { len }) => {
+            data.drain(..8);
+            data.truncate(len);
+        }
+        _ => data.clear(),
+    }
+    ControlReply { setup, result, data }
+}
+
+// Cancel and reap whatever is still in flight after `err`, returning `err`.
+fn abandon<U>(device: &mut AsyncDevice<BatchTransfer, U>, err: Error) -> Error {
+    device.cancel_all();
+    let _ = device.drain();
+    err
+}
@@ -0,0 +1,199 @@
+use super::*;
+
+/// Builder for control requests, sent synchronously with `send()` or queued on an
+/// `AsyncDevice` with `submit()`.
+///
+/// Fields not set keep their defaults: a standard OUT request to the device, with `bRequest`,
+/// `wValue`, and `wIndex` of 0, no data stage, and a 1000ms timeout.  `data_in()` and
+/// `data_out()` set the direction along with the data stage.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let data = ControlRequest::new()
+///     .request_type(SetupType::Vendor)
+///     .recipient(SetupRecipient::Interface)
+///     .request(0x01)
+///     .index(2)
+///     .data_in(64)
+///     .send(&device)
+///     .unwrap();
+///
+/// ControlRequest::new()
+///     .request_type(SetupType::Vendor)
+///     .request(0x02)
+///     .value(0x8000)
+///     .data_out(&data)
+///     .send(&device)
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ControlRequest {
+    direction: SetupDirection,
+    setuptype: SetupType,
+    recipient: SetupRecipient,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+    data: Vec<u8>,
+    // set by data_out() given more than a control transfer can carry
+    oversized: bool,
+    timeout_ms: u32,
+}
+
+impl Default for ControlRequest {
+    fn default() -> ControlRequest {
+        ControlRequest {
+            direction: SetupDirection::HostToDevice,
+            setuptype: SetupType::Standard,
+            recipient: SetupRecipient::Device,
+            request: 0,
+            value: 0,
+            index: 0,
+            length: 0,
+            data: Vec::new(),
+            oversized: false,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+impl ControlRequest {
+    pub fn new() -> ControlRequest {
+        Default::default()
+    }
+
+    pub fn direction(mut self, direction: SetupDirection) -> ControlRequest {
+        self.direction = direction;
+        self
+    }
+
+    pub fn request_type(mut self, setuptype: SetupType) -> ControlRequest {
+        self.setuptype = setuptype;
+        self
+    }
+
+    pub fn recipient(mut self, recipient: SetupRecipient) -> ControlRequest {
+        self.recipient = recipient;
+        self
+    }
+
+    /// `bRequest` of the setup packet.
+    pub fn request(mut self, request: u8) -> ControlRequest {
+        self.request = request;
+        self
+    }
+
+    /// `wValue` of the setup packet.
+    pub fn value(mut self, value: u16) -> ControlRequest {
+        self.value = value;
+        self
+    }
+
+    /// `wIndex` of the setup packet.
+    pub fn index(mut self, index: u16) -> ControlRequest {
+        self.index = index;
+        self
+    }
+
+    /// Timeout of `send()`.  Submitted requests have no timeout.
+    pub fn timeout(mut self, timeout_ms: u32) -> ControlRequest {
+        self.timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Make this an IN request reading up to `length` bytes.
+    pub fn data_in(mut self, length: u16) -> ControlRequest {
+        self.direction = SetupDirection::DeviceToHost;
+        self.length = length;
+        self.data.clear();
+        self.oversized = false;
+        self
+    }
+
+    /// Make this an OUT request sending `data`.  Data longer than a control transfer can carry
+    /// (65535 bytes) makes `send()`, `transfer()`, and `submit()` fail with
+    /// `ErrorKind::InvalidParam`.
+    pub fn data_out(mut self, data: &[u8]) -> ControlRequest {
+        self.direction = SetupDirection::HostToDevice;
+        self.oversized = data.len() > u16::MAX as usize;
+        match self.oversized {
+            true => {
+                self.length = 0;
+                self.data.clear();
+            }
+            false => {
+                self.length = data.len() as u16;
+                self.data = data.to_vec();
+            }
+        }
+        self
+    }
+
+    fn check(&self) -> Result<()> {
+        match self.oversized {
+            true => Err(Error::new(ErrorKind::InvalidParam, "control transfer data too long")),
+            false => Ok(()),
+        }
+    }
+
+    /// Perform the request synchronously.  IN requests return the data received, OUT requests
+    /// an empty `Vec`.
+    pub fn send(&self, device: &Device) -> Result<Vec<u8>> {
+        self.check()?;
+        match self.direction {
+            SetupDirection::DeviceToHost => {
+                let mut buf = vec![0u8; self.length as usize];
+                let len = device.control_transfer_in(self.setuptype,
+                                                     self.recipient,
+                                                     self.request,
+                                                     self.value,
+                                                     self.index,
+                                                     Some(&mut buf),
+                                                     self.timeout_ms)?;
+                buf.truncate(len as usize);
+                Ok(buf)
+            }
+            SetupDirection::HostToDevice => {
+                device.control_transfer_out(self.setuptype,
+                                            self.recipient,
+                                            self.request,
+                                            self.value,
+                                            self.index,
+                                            Some(&self.data),
+                                            self.timeout_ms)?;
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Build an async control transfer for the request.  The buffer holds the setup packet
+    /// followed by the data stage.
+    pub fn transfer(&self, flags: UrbFlags) -> Result<StdBufTransfer<Vec<u8>>> {
+        self.check()?;
+        let mut buf = vec![0u8; 8 + self.length as usize];
+        buf[8..8 + self.data.len()].copy_from_slice(&self.data);
+        Ok(StdBufTransfer::control(self.direction,
+                                   self.setuptype,
+                                   self.recipient,
+                                   self.request,
+                                   self.value,
+                                   self.index,
+                                   flags,
+                                   buf))
+    }
+
+    /// Submit the request to `device` as an async control transfer (see `transfer()`).  The
+    /// transfer type `R` of the device must be constructible from it, eg.
+    /// `Box<StdBufTransfer<Vec<u8>>>`.  Devices holding a `DynTransfer` should submit
+    /// `Box::new(request.transfer(flags)?)` instead.
+    pub fn submit<R>(&self, device: &mut AsyncDevice<R>) -> Result<SlotId>
+        where R: StableDeref + From<StdBufTransfer<Vec<u8>>>,
+              R::Target: Transfer
+    {
+        device.submit(R::from(self.transfer(UrbFlags::empty())?))
+    }
+}
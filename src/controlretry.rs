@@ -0,0 +1,98 @@
+use std::thread;
+use std::time::Duration;
+
+use super::*;
+
+/// How `Device::control_transfer_retry()` handles stalled control requests.
+///
+/// Plenty of firmware occasionally stalls a perfectly good request, typically while it is busy.
+/// A stalled request is retried up to `retries` times, sleeping `backoff` before the first retry
+/// and twice as long before each following one.  A stall of endpoint 0 ends with the next setup
+/// packet, so the retried request itself is what clears it; there is no halt to clear as for
+/// other endpoints.
+///
+/// The default policy retries 3 times starting with a 10ms backoff.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let policy = RetryPolicy::new().retries(5).backoff(Duration::from_millis(50));
+/// let mut buf = [0u8; 16];
+/// let len = device.control_transfer_retry(SetupDirection::DeviceToHost,
+///                                         SetupType::Vendor,
+///                                         SetupRecipient::Device,
+///                                         3, 0, 0,
+///                                         Some(&mut buf),
+///                                         1000,
+///                                         &policy).unwrap();
+/// println!("{:?}", &buf[..len as usize]);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Create the default policy.
+    pub fn new() -> RetryPolicy {
+        Default::default()
+    }
+
+    /// Retry a stalled request at most `retries` times.  0 disables retrying.
+    pub fn retries(mut self, retries: u32) -> RetryPolicy {
+        self.retries = retries;
+        self
+    }
+
+    /// Sleep `backoff` before the first retry, doubling for each further retry.
+    pub fn backoff(mut self, backoff: Duration) -> RetryPolicy {
+        self.backoff = backoff;
+        self
+    }
+}
+
+impl Device {
+    /// Perform a synchronous control transfer like `control_transfer()`, retrying according to
+    /// `policy` if the device stalls the request.
+    ///
+    /// Only stalls are retried; other errors are returned immediately.  If the request still
+    /// stalls after the last retry, that stall is returned.
+    pub fn control_transfer_retry(&self,
+                                  setupdirection: SetupDirection,
+                                  setuptype: SetupType,
+                                  setuprecipient: SetupRecipient,
+                                  bRequest: u8,
+                                  wValue: u16,
+                                  wIndex: u16,
+                                  mut odata: Option<&mut [u8]>,
+                                  timeout_ms: u32,
+                                  policy: &RetryPolicy)
+                                  -> Result<i32> {
+        let mut backoff = policy.backoff;
+        let mut retries = policy.retries;
+        loop {
+            match self.control_transfer(setupdirection, setuptype, setuprecipient,
+                                        bRequest, wValue, wIndex,
+                                        odata.as_deref_mut(), timeout_ms) {
+                Err(ref err) if err.kind() == ErrorKind::Stall && retries > 0 => (),
+                result => return result,
+            }
+            retries -= 1;
+            thread::sleep(backoff);
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use std::fs;
+use std::io::Read;
+
+use super::*;
+
+/// Descriptor type of class-specific interface descriptors, used by the communications, audio,
+/// and video classes among others.
+pub const CS_INTERFACE: u8 = 0x24;
+/// Descriptor type of class-specific endpoint descriptors.
+pub const CS_ENDPOINT: u8 = 0x25;
+
+/// A descriptor kept as raw bytes, including its `bLength` and `bDescriptorType` header.
+///
+/// Class-specific and otherwise unknown descriptors are retained this way by
+/// `ConfigDescriptor::parse()`, attached to the interface or endpoint they follow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct RawDescriptor(pub Vec<u8>);
+
+impl RawDescriptor {
+    pub fn descriptor_type(&self) -> u8 {
+        self.0[1]
+    }
+
+    /// The descriptor without its 2 byte header.
+    pub fn body(&self) -> &[u8] {
+        &self.0[2..]
+    }
+}
+
+/// Parsed configuration descriptor, along with its interfaces and endpoints.
+///
+/// Multi-byte fields are in native endian.  Alternate settings are listed as separate
+/// `InterfaceDescriptor`s sharing a `bInterfaceNumber`.
+///
+/// # Examples
+/// Find the bulk endpoints of interface 0:
+///
+/// ```no_run
+/// use usbfs::*;
+///
+/// let config = &deviceinfo_find(0xffff, 3).unwrap().configurations().unwrap()[0];
+/// for intf in config.interfaces.iter().filter(|i| i.bInterfaceNumber == 0) {
+///     for ep in intf.endpoints.iter().filter(|e| e.transfer_type() == UrbType::Bulk) {
+///         println!("alt {} endpoint {:02x}", intf.bAlternateSetting, ep.bEndpointAddress);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct ConfigDescriptor {
+    pub wTotalLength: u16,
+    pub bNumInterfaces: u8,
+    pub bConfigurationValue: u8,
+    pub iConfiguration: u8,
+    pub bmAttributes: u8,
+    pub bMaxPower: u8,
+    pub interfaces: Vec<InterfaceDescriptor>,
+    /// Descriptors preceding the first interface, such as interface associations; see
+    /// `associations()`.
+    pub extra: Vec<RawDescriptor>,
+}
+
+/// Parsed interface descriptor.  See `ConfigDescriptor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct InterfaceDescriptor {
+    pub bInterfaceNumber: u8,
+    pub bAlternateSetting: u8,
+    pub bNumEndpoints: u8,
+    pub bInterfaceClass: u8,
+    pub bInterfaceSubClass: u8,
+    pub bInterfaceProtocol: u8,
+    pub iInterface: u8,
+    pub endpoints: Vec<EndpointDescriptor>,
+    /// Class-specific descriptors between the interface descriptor and its first endpoint.
+    pub extra: Vec<RawDescriptor>,
+}
+
+/// Parsed endpoint descriptor.  See `ConfigDescriptor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct EndpointDescriptor {
+    pub bEndpointAddress: u8,
+    pub bmAttributes: u8,
+    pub wMaxPacketSize: u16,
+    pub bInterval: u8,
+    /// Descriptors following the endpoint descriptor, such as SuperSpeed endpoint companions
+    /// or class-specific endpoint descriptors.
+    pub extra: Vec<RawDescriptor>,
+}
+
+impl EndpointDescriptor {
+    pub fn transfer_type(&self) -> UrbType {
+        match self.bmAttributes & 0x03 {
+            0 => UrbType::Control,
+            1 => UrbType::Iso,
+            2 => UrbType::Bulk,
+            _ => UrbType::Interrupt,
+        }
+    }
+
+    /// `true` for IN endpoints.
+    pub fn is_in(&self) -> bool {
+        self.bEndpointAddress & 0x80 != 0
+    }
+
+    /// Maximum packet size in bytes, without the high bandwidth multiplier bits.
+    pub fn max_packet_size(&self) -> usize {
+        (self.wMaxPacketSize & 0x7ff) as usize
+    }
+}
+
+impl ConfigDescriptor {
+    /// Parse a configuration descriptor and everything following it, as returned by
+    /// `GET_DESCRIPTOR(CONFIGURATION)`.  Bytes beyond `wTotalLength` are ignored.
+    pub fn parse(buf: &[u8]) -> Result<ConfigDescriptor> {
+        let mut descriptors = split_descriptors(buf);
+        let header = match descriptors.next() {
+            Some(Ok(d)) if d.len() >= 9 && d[1] == DescriptorType::Configuration as u8 => d,
+            Some(Err(err)) => return Err(err),
+            _ => return Err(bad_descriptor()),
+        };
+        let total = le16(header, 2) as usize;
+        if total < header.len() || total > buf.len() {
+            return Err(bad_descriptor());
+        }
+        let mut config = ConfigDescriptor {
+            wTotalLength: total as u16,
+            bNumInterfaces: header[4],
+            bConfigurationValue: header[5],
+            iConfiguration: header[6],
+            bmAttributes: header[7],
+            bMaxPower: header[8],
+            interfaces: Vec::new(),
+            extra: Vec::new(),
+        };
+
+        for d in split_descriptors(&buf[header.len()..total]) {
+            let d = d?;
+            if d[1] == DescriptorType::Interface as u8 && d.len() >= 9 {
+                config.interfaces.push(InterfaceDescriptor {
+                    bInterfaceNumber: d[2],
+                    bAlternateSetting: d[3],
+                    bNumEndpoints: d[4],
+                    bInterfaceClass: d[5],
+                    bInterfaceSubClass: d[6],
+                    bInterfaceProtocol: d[7],
+                    iInterface: d[8],
+                    endpoints: Vec::new(),
+                    extra: Vec::new(),
+                });
+                continue;
+            }
+            let intf = match config.interfaces.last_mut() {
+                Some(intf) => intf,
+                None => {
+                    config.extra.push(RawDescriptor(d.to_vec()));
+                    continue;
+                }
+            };
+            if d[1] == DescriptorType::Endpoint as u8 && d.len() >= 7 {
+                intf.endpoints.push(EndpointDescriptor {
+                    bEndpointAddress: d[2],
+                    bmAttributes: d[3],
+                    wMaxPacketSize: le16(d, 4),
+                    bInterval: d[6],
+                    extra: Vec::new(),
+                });
+            } else {
+                match intf.endpoints.last_mut() {
+                    Some(ep) => ep.extra.push(RawDescriptor(d.to_vec())),
+                    None => intf.extra.push(RawDescriptor(d.to_vec())),
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Alternate settings of interface `number`.
+    pub fn interface(&self, number: u8) -> impl Iterator<Item=&InterfaceDescriptor> + '_ {
+        self.interfaces.iter().filter(move |i| i.bInterfaceNumber == number)
+    }
+}
+
+impl DeviceInfo {
+    /// All configuration descriptors of the device, as cached by the kernel.  No request is
+    /// sent to the device.
+    pub fn configurations(&self) -> Result<Vec<ConfigDescriptor>> {
+        let mut buf = Vec::new();
+        fs::File::open(self.descriptors_path())?.read_to_end(&mut buf)?;
+
+        // the device descriptor comes first, followed by each configuration in turn
+        let mut configs = Vec::new();
+        let mut rest = match buf.first() {
+            Some(&len) if len as usize <= buf.len() => &buf[len as usize..],
+            _ => return Err(bad_descriptor()),
+        };
+        while rest.len() >= 4 {
+            let config = ConfigDescriptor::parse(rest)?;
+            rest = &rest[config.wTotalLength as usize..];
+            configs.push(config);
+        }
+        Ok(configs)
+    }
+}
+
+/// Descriptors of a device, as read from the device itself by
+/// `Device::read_descriptors_via_control()`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct DeviceDescriptors {
+    pub device: DeviceDescriptor,
+    pub configurations: Vec<ConfigDescriptor>,
+}
+
+impl Device {
+    /// Read the device descriptor and every configuration descriptor with `GET_DESCRIPTOR`
+    /// requests, for where sysfs is unavailable, as in containers that are given
+    /// `/dev/bus/usb` but not `/sys/bus/usb`.  Elsewhere `DeviceInfo::device_descriptor()` and
+    /// `DeviceInfo::configurations()` are cheaper, since they don't disturb the device.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// let device = Device::new_from_busdev(1, 4).unwrap();
+    /// let descriptors = device.read_descriptors_via_control(1000).unwrap();
+    /// for config in &descriptors.configurations {
+    ///     println!("configuration {}: {} interfaces", config.bConfigurationValue, config.bNumInterfaces);
+    /// }
+    /// ```
+    pub fn read_descriptors_via_control(&self, timeout_ms: u32) -> Result<DeviceDescriptors> {
+        let device = self.get_device_descriptor(timeout_ms)?;
+        let configurations = (0..device.bNumConfigurations)
+            .map(|index| self.get_config_descriptor(index, timeout_ms))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(DeviceDescriptors { device, configurations })
+    }
+
+    // Descriptor of the active configuration, or `None` if the device is unconfigured.  Read
+    // from sysfs where possible, otherwise from the device.
+    pub(crate) fn active_config_descriptor(&self) -> Result<Option<ConfigDescriptor>> {
+        let (value, configs) = match DeviceInfo::from_fd(self) {
+            Ok(info) => (info.configuration_value()?, info.configurations()?),
+            Err(_) => {
+                let value = self.get_configuration(CONTROL_TIMEOUT_MS)? as u32;
+                (value, self.read_descriptors_via_control(CONTROL_TIMEOUT_MS)?.configurations)
+            }
+        };
+        Ok(configs.into_iter().find(|c| c.bConfigurationValue as u32 == value))
+    }
+
+    /// Read configuration descriptor `index` (not `bConfigurationValue`) from the device.
+    pub fn get_config_descriptor(&self, index: u8, timeout_ms: u32) -> Result<ConfigDescriptor> {
+        let mut header = [0u8; 9];
+        if self.get_descriptor(DescriptorType::Configuration as u8, index, 0, &mut header, timeout_ms)? < 4 {
+            return Err(bad_descriptor());
+        }
+        let mut buf = vec![0u8; le16(&header, 2) as usize];
+        let len = self.get_descriptor(DescriptorType::Configuration as u8, index, 0, &mut buf, timeout_ms)?;
+        ConfigDescriptor::parse(&buf[..len])
+    }
+}
+
+// Timeout of requests the crate sends on its own account, where the caller gives none, such
+// as descriptor and configuration lookups on devices found without sysfs.
+pub(crate) const CONTROL_TIMEOUT_MS: u32 = 1000;
+
+// Split a run of descriptors at their bLength.
+pub(crate) fn split_descriptors(mut buf: &[u8]) -> impl Iterator<Item=Result<&[u8]>> {
+    std::iter::from_fn(move || {
+        if buf.is_empty() {
+            return None;
+        }
+        let len = buf[0] as usize;
+        if len < 2 || len > buf.len() {
+            buf = &[];
+            return Some(Err(bad_descriptor()));
+        }
+        let (d, rest) = buf.split_at(len);
+        buf = rest;
+        Some(Ok(d))
+    })
+}
+
+pub(crate) fn le16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+pub(crate) fn le32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+// A 16 byte UUID or GUID, kept in wire order.
+pub(crate) fn uuid(buf: &[u8], offset: usize) -> [u8; 16] {
+    let mut uuid = [0u8; 16];
+    uuid.copy_from_slice(&buf[offset..offset + 16]);
+    uuid
+}
+
+pub(crate) fn bad_descriptor() -> Error {
+    Error::new(ErrorKind::Other, "malformed descriptor")
+}
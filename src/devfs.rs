@@ -1,7 +1,12 @@
 
 use std::mem::size_of;
-pub use nix::libc::{c_uint, c_int};
+use std::{fmt, ptr, slice};
+pub use nix::libc::{c_uint, c_int, c_void};
+pub use nix::sys::ioctl::ioctl_num_type;
 use std::io;
+use std::time::Duration;
+
+use super::Device;
 use nix;
 
 #[derive(Debug, Copy, Clone)]
@@ -28,10 +33,37 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Features supported by the running kernel and the host controller, as reported by
+    /// `Device::capabilities()`.
+    #[repr(C)]
+    pub struct Capabilities: u32 {
+        const ZERO_PACKET           = 0x01;
+        const BULK_CONTINUATION     = 0x02;
+        const NO_PACKET_SIZE_LIM    = 0x04;
+        const BULK_SCATTER_GATHER   = 0x08;
+        const REAP_AFTER_DISCONNECT = 0x10;
+        const MMAP                  = 0x20;
+        const DROP_PRIVILEGES       = 0x40;
+    }
+}
+
+bitflags! {
+    /// Conditions for `Device::disconnect_claim()`.
+    #[repr(C)]
+    pub struct DisconnectClaimFlags: u32 {
+        /// Disconnect and claim only if the bound driver matches the given driver name.
+        const IF_DRIVER     = 0x01;
+        /// Disconnect and claim unless the bound driver matches the given driver name.
+        const EXCEPT_DRIVER = 0x02;
+    }
+}
+
 /// The [type of transfer](http://www.beyondlogic.org/usbnutshell/usb4.shtml).
 ///
 /// Isochronous transfers not implemented (yet),
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
 pub enum UrbType {
     Iso = 0,
     Interrupt = 1,
@@ -39,6 +71,40 @@ pub enum UrbType {
     Bulk = 3,
 }
 
+impl UrbType {
+    // Decode the `urbtype` field of an `Urb`.
+    pub(crate) fn from_raw(urbtype: u8) -> Option<UrbType> {
+        match urbtype {
+            0 => Some(UrbType::Iso),
+            1 => Some(UrbType::Interrupt),
+            2 => Some(UrbType::Control),
+            3 => Some(UrbType::Bulk),
+            _ => None,
+        }
+    }
+}
+
+/// `number_of_packets` of isochronous urbs, or `stream_id` of bulk urbs on a stream, which
+/// share storage in the kernel's `struct usbdevfs_urb`.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub union UrbPacketsOrStream {
+    pub number_of_packets: i32,
+    pub stream_id: u32,
+}
+
+impl fmt::Debug for UrbPacketsOrStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // both members are plain integers, so either can be read
+        write!(f, "{}", unsafe { self.number_of_packets })
+    }
+}
+
+/// `struct usbdevfs_urb`.
+///
+/// Isochronous urbs are followed in memory by their `IsoPacketDesc`s, the kernel's
+/// `iso_frame_desc[]` flexible array, so transfers place an array of them right after the
+/// urb in a `#[repr(C)]` struct.
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct Urb {
@@ -50,35 +116,46 @@ pub struct Urb {
     pub buffer_length: i32, // assigned upon submit
     pub actual_length: i32, // reap result
     pub start_frame: i32,
-    pub number_of_packets: i32,
+    pub packets_or_stream: UrbPacketsOrStream,
     pub error_count: i32, // reap result
     pub signr: u32, // signal to be sent on completion, or 0 if none should be sent.
-    pub usercontext: usize, /* assigned upon submit
-                             * struct usbdevfs_iso_packet_desc iso_frame_desc[0];
-                             *
-                             * union {
-                             *  int number_of_packets;  /* Only used for isoc urbs */
-                             *  unsigned int stream_id; /* Only used with bulk streams */
-                             * }; */
+    pub usercontext: usize, // assigned upon submit
 }
 
+// The buffer pointer refers to memory owned by the enclosing transfer, which moves between
+// threads along with the Urb.
+unsafe impl Send for Urb {}
+
 impl Urb {
     pub fn new(urbtype: UrbType, endpoint: u8, flags: UrbFlags) -> Urb {
         Urb {
             urbtype: urbtype as u8,
             endpoint,
-            status: -22, // -EINVAL, in case status is read before urb is used.
             flags,
-            buffer: 0 as *mut u8,
-            buffer_length: 0,
-            actual_length: 0,
-            start_frame: 0,
-            number_of_packets: 0,
-            error_count: 0,
-            signr: 0,
-            usercontext: 0,
+            ..Urb::default()
         }
     }
+
+    /// Number of isochronous packets.  Shares storage with `stream_id()`.
+    pub fn number_of_packets(&self) -> i32 {
+        unsafe { self.packets_or_stream.number_of_packets }
+    }
+
+    pub fn set_number_of_packets(&mut self, number_of_packets: i32) {
+        self.packets_or_stream.number_of_packets = number_of_packets;
+    }
+
+    /// Bulk stream this urb is submitted on.  Shares storage with `number_of_packets()`, which
+    /// is only meaningful for isochronous urbs.
+    pub fn stream_id(&self) -> u32 {
+        unsafe { self.packets_or_stream.stream_id }
+    }
+
+    /// Submit this (bulk) urb on the given stream.  Streams must first be allocated with
+    /// `Device::alloc_streams()`.
+    pub fn set_stream_id(&mut self, stream_id: u32) {
+        self.packets_or_stream.stream_id = stream_id;
+    }
 }
 
 impl Default for Urb {
@@ -88,11 +165,11 @@ impl Default for Urb {
             endpoint: 0,
             status: -22, // -EINVAL, in case status is read before urb is used.
             flags: UrbFlags::empty(),
-            buffer: 0 as *mut u8,
+            buffer: ptr::null_mut(),
             buffer_length: 0,
             actual_length: 0,
             start_frame: 0,
-            number_of_packets: 0,
+            packets_or_stream: UrbPacketsOrStream { number_of_packets: 0 },
             error_count: 0,
             signr: 0,
             usercontext: 0,
@@ -100,19 +177,35 @@ impl Default for Urb {
     }
 }
 
+// The packet descriptors following isochronous urb `urb`.  `urb` must point into a transfer
+// with room for `number_of_packets()` of them.
+pub(crate) unsafe fn iso_frame_desc<'a>(urb: *const Urb) -> &'a [IsoPacketDesc] {
+    slice::from_raw_parts(urb.add(1) as *const IsoPacketDesc, (*urb).number_of_packets().max(0) as usize)
+}
+
+#[cfg(feature="mock")]
+pub(crate) unsafe fn iso_frame_desc_mut<'a>(urb: *mut Urb) -> &'a mut [IsoPacketDesc] {
+    slice::from_raw_parts_mut(urb.add(1) as *mut IsoPacketDesc, (*urb).number_of_packets().max(0) as usize)
+}
+
 // Remaining elements of linux usbfs that have not been implemented in this crate.
 // These are left here as a reminder of things that can yet be implemented.
 
-// FIXME: shouldn't be pub
-// #[allow(non_snake_case)]
-// #[derive(Debug)]
-// #[repr(C)]
-// pub struct bulktransfer {
-//     pub ep      :u32,
-//     pub len     :u32,
-//     pub timeout :u32, // in milliseconds
-//     pub data    :*mut u8,
-// }
+// struct usbdevfs_bulktransfer {
+//  unsigned int ep;
+//  unsigned int len;
+//  unsigned int timeout; /* in milliseconds */
+//  void __user *data;
+// };
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct BulkTransfer {
+    pub ep: c_uint,
+    pub len: c_uint,
+    pub timeout: c_uint, // in milliseconds
+    pub data: *mut u8,
+}
 
 // struct usbdevfs_setinterface {
 //  unsigned int interface;
@@ -131,19 +224,71 @@ pub struct SetInterface {
 //  void __user *context;
 // };
 
+#[derive(Debug)]
+#[repr(C)]
+pub struct DisconnectSignal {
+    pub signr: c_uint,
+    pub context: usize,
+}
+
 // #define USBDEVFS_MAXDRIVERNAME 255
+pub const MAXDRIVERNAME: usize = 255;
 
 // struct usbdevfs_getdriver {
 //  unsigned int interface;
 //  char driver[USBDEVFS_MAXDRIVERNAME + 1];
 // };
 
+#[repr(C)]
+pub struct GetDriver {
+    pub interface: c_uint,
+    pub driver: [u8; MAXDRIVERNAME + 1],
+}
+
 // struct usbdevfs_connectinfo {
 //  unsigned int devnum;
 //  unsigned char slow;
 // };
 
-#[derive(Debug, Copy, Clone)]
+/// Connection information returned by `Device::connect_info()`.
+#[derive(Debug, Copy, Clone, Default)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+#[repr(C)]
+pub struct ConnectInfo {
+    /// Device number on the bus.
+    pub devnum: c_uint,
+    /// Non-zero if the device is a low speed device.
+    pub slow: u8,
+}
+
+// struct usbdevfs_ioctl {
+//  int ifno;       /* interface 0..N ; negative numbers reserved */
+//  int ioctl_code; /* MUST encode size + direction of data so the
+//           * macros in <asm/ioctl.h> give correct values */
+//  void __user *data;  /* param buffer (in, or out) */
+// };
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct IoctlRequest {
+    pub ifno: c_int,
+    pub ioctl_code: c_int,
+    pub data: *mut u8,
+}
+
+// struct usbdevfs_hub_portinfo {
+//  char nports;        /* number of downstream ports in this hub */
+//  char port [127];    /* e.g. port 3 connects to device 27 */
+// };
+
+#[repr(C)]
+pub struct HubPortInfo {
+    pub nports: u8,
+    pub port: [u8; 127],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
 #[repr(C)]
 pub struct IsoPacketDesc {
     pub length: i32, // kernel header uses unsigned int, but use i32 instead for consistency with urb.
@@ -204,74 +349,170 @@ impl Default for IsoPacketDesc {
 //  char driver[USBDEVFS_MAXDRIVERNAME + 1];
 // };
 
+#[repr(C)]
+pub struct DisconnectClaim {
+    pub interface: c_uint,
+    pub flags: DisconnectClaimFlags,
+    pub driver: [u8; MAXDRIVERNAME + 1],
+}
+
 // struct usbdevfs_streams {
 //  unsigned int num_streams; /* Not used by USBDEVFS_FREE_STREAMS */
 //  unsigned int num_eps;
 //  unsigned char eps[0];
 // };
 
+#[derive(Debug)]
+#[repr(C)]
+pub struct Streams {
+    pub num_streams: c_uint,
+    pub num_eps: c_uint,
+    // eps follow
+}
+
+/// Owned, correctly aligned storage for a variable length `Streams` struct.
+pub struct StreamsBuf(Vec<c_uint>);
+
+impl StreamsBuf {
+    pub fn new(num_streams: u32, eps: &[u8]) -> StreamsBuf {
+        let header_words = size_of::<Streams>() / size_of::<c_uint>();
+        let eps_words = eps.len().div_ceil(size_of::<c_uint>());
+        let mut words = vec![0 as c_uint; header_words + eps_words];
+        words[0] = num_streams as c_uint;
+        words[1] = eps.len() as c_uint;
+
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, words.len() * size_of::<c_uint>())
+        };
+        bytes[size_of::<Streams>()..][..eps.len()].copy_from_slice(eps);
+
+        StreamsBuf(words)
+    }
+
+    pub fn as_ptr(&self) -> *const Streams {
+        self.0.as_ptr() as *const Streams
+    }
+}
+
 
 // Sigh, usbfs ioctls have incorrect inversion of read and write.
-// This doesn't matter at all from C, but nix crate applies const/mut to
-// wrappers.
+// This doesn't matter at all from C, but the wrappers apply const/mut
+// according to what the kernel actually does with the argument.
+
+// The `*32` variants of the urb, control, bulk, and ioctl requests are the same requests on the
+// structs as laid out by 32-bit processes, which a 64-bit kernel translates.  The structs here
+// follow the pointer width of the target, so a 32-bit build computes those codes from
+// `size_of` by itself and works on both 32-bit and 64-bit kernels; see `layout`.
+
+// Define the request code of a usbfs ioctl along with a wrapper issuing it on a `Device`, which
+// passes it on to its `Backend` or the kernel.
+macro_rules! usbfs_ioctl {
+    ($name:ident, $code:ident, $request:expr, $ptr:ty) => {
+        pub const $code: ioctl_num_type = $request;
+        pub unsafe fn $name(device: &Device, data: $ptr) -> nix::Result<c_int> {
+            device.ioctl($code, data as *mut c_void)
+        }
+    };
+    ($name:ident, $code:ident, $request:expr) => {
+        pub const $code: ioctl_num_type = $request;
+        pub unsafe fn $name(device: &Device) -> nix::Result<c_int> {
+            device.ioctl($code, ptr::null_mut())
+        }
+    };
+}
 
 
 // #define USBDEVFS_CONTROL           _IOWR('U', 0, struct usbdevfs_ctrltransfer)
-ioctl_readwrite!(control, b'U', 0, CtrlTransfer);
+usbfs_ioctl!(control, CONTROL, request_code_readwrite!(b'U', 0, size_of::<CtrlTransfer>()), *mut CtrlTransfer);
 
 // #define USBDEVFS_CONTROL32           _IOWR('U', 0, struct usbdevfs_ctrltransfer32)
 // #define USBDEVFS_BULK              _IOWR('U', 2, struct usbdevfs_bulktransfer)
+usbfs_ioctl!(bulk, BULK, request_code_readwrite!(b'U', 2, size_of::<BulkTransfer>()), *mut BulkTransfer);
+
 // #define USBDEVFS_BULK32              _IOWR('U', 2, struct usbdevfs_bulktransfer32)
 // #define USBDEVFS_RESETEP           _IOR('U', 3, unsigned int)
+usbfs_ioctl!(resetep, RESETEP, request_code_read!(b'U', 3, size_of::<c_uint>()), *const c_uint);
 
 // #define USBDEVFS_SETINTERFACE      _IOR('U', 4, struct usbdevfs_setinterface)
-ioctl_write_ptr_bad!(setinterface, request_code_read!('U', 4, size_of::<SetInterface>()), SetInterface);
+usbfs_ioctl!(setinterface, SETINTERFACE, request_code_read!('U', 4, size_of::<SetInterface>()), *const SetInterface);
 
 // #define USBDEVFS_SETCONFIGURATION  _IOR('U', 5, unsigned int)
+usbfs_ioctl!(setconfiguration, SETCONFIGURATION, request_code_read!(b'U', 5, size_of::<c_uint>()), *const c_uint);
+
 // #define USBDEVFS_GETDRIVER         _IOW('U', 8, struct usbdevfs_getdriver)
+usbfs_ioctl!(getdriver, GETDRIVER, request_code_write!(b'U', 8, size_of::<GetDriver>()), *mut GetDriver);
 
 // #define USBDEVFS_SUBMITURB         _IOR('U', 10, struct usbdevfs_urb)
-ioctl_write_ptr_bad!(submiturb, request_code_read!(b'U', 10, size_of::<Urb>()), Urb);
+usbfs_ioctl!(submiturb, SUBMITURB, request_code_read!(b'U', 10, size_of::<Urb>()), *const Urb);
 
 // #define USBDEVFS_SUBMITURB32       _IOR('U', 10, struct usbdevfs_urb32)
 // #define USBDEVFS_DISCARDURB        _IO('U', 11)
-//pub const DISCARDURB_IOCTL: libc::c_ulong = io!(b'U', 11) as libc::c_ulong;
-// ioctl!(none discardurb with b'U', 11; Urb);  // doesn't work due to defective ioctl def (discardurb actually does take a param)
+// Defined without a parameter, but discardurb actually does take the address of the urb.
+usbfs_ioctl!(discardurb, DISCARDURB, request_code_none!(b'U', 11), *const Urb);
 
 
 // #define USBDEVFS_REAPURB           _IOW('U', 12, void *)
-ioctl_read_bad!(reapurb, request_code_write!(b'U', 12, size_of::<*mut Urb>()), *mut Urb);
+usbfs_ioctl!(reapurb, REAPURB, request_code_write!(b'U', 12, size_of::<*mut Urb>()), *mut *mut Urb);
 
 // #define USBDEVFS_REAPURB32         _IOW('U', 12, __u32)
 
 // #define USBDEVFS_REAPURBNDELAY     _IOW('U', 13, void *)
-ioctl_read_bad!(reapurbndelay, request_code_write!(b'U', 13, size_of::<*mut Urb>()), *mut Urb);
+usbfs_ioctl!(reapurbndelay, REAPURBNDELAY, request_code_write!(b'U', 13, size_of::<*mut Urb>()), *mut *mut Urb);
+
+// Reaps fail with ENODEV as soon as the device is marked gone, which can be before the kernel
+// has killed its urbs.  On kernels with REAP_AFTER_DISCONNECT those urbs still come back, so
+// whoever waits for them polls at this interval.
+pub const DISCONNECT_REAP_INTERVAL: Duration = Duration::from_millis(1);
 
 // #define USBDEVFS_REAPURBNDELAY32   _IOW('U', 13, __u32)
 // #define USBDEVFS_DISCSIGNAL        _IOR('U', 14, struct usbdevfs_disconnectsignal)
+usbfs_ioctl!(discsignal, DISCSIGNAL, request_code_read!(b'U', 14, size_of::<DisconnectSignal>()), *const DisconnectSignal);
+
 // #define USBDEVFS_DISCSIGNAL32      _IOR('U', 14, struct usbdevfs_disconnectsignal32)
 
 // #define USBDEVFS_CLAIMINTERFACE    _IOR('U', 15, unsigned int)
 //ioctl_write_bad!(claiminterface, request_code_read!('U', 15, sizeof::<c_uint>()), c_uint);
-ioctl_write_ptr_bad!(claiminterface, request_code_read!('U', 15, size_of::<c_uint>()), c_uint);
+usbfs_ioctl!(claiminterface, CLAIMINTERFACE, request_code_read!('U', 15, size_of::<c_uint>()), *const c_uint);
 
 // #define USBDEVFS_RELEASEINTERFACE  _IOR('U', 16, unsigned int)
+usbfs_ioctl!(releaseinterface, RELEASEINTERFACE, request_code_read!(b'U', 16, size_of::<c_uint>()), *const c_uint);
+
 // #define USBDEVFS_CONNECTINFO       _IOW('U', 17, struct usbdevfs_connectinfo)
+usbfs_ioctl!(connectinfo, CONNECTINFO, request_code_write!(b'U', 17, size_of::<ConnectInfo>()), *mut ConnectInfo);
+
 // #define USBDEVFS_IOCTL             _IOWR('U', 18, struct usbdevfs_ioctl)
+usbfs_ioctl!(usbioctl, IOCTL, request_code_readwrite!(b'U', 18, size_of::<IoctlRequest>()), *mut IoctlRequest);
+
 // #define USBDEVFS_IOCTL32           _IOWR('U', 18, struct usbdevfs_ioctl32)
 // #define USBDEVFS_HUB_PORTINFO      _IOR('U', 19, struct usbdevfs_hub_portinfo)
+// Handled by the hub driver, so this is not issued directly but passed through usbioctl.
+pub const HUB_PORTINFO: c_int = request_code_read!(b'U', 19, size_of::<HubPortInfo>()) as c_int;
+
 // #define USBDEVFS_RESET             _IO('U', 20)
 // #define USBDEVFS_CLEAR_HALT        _IOR('U', 21, unsigned int)
+usbfs_ioctl!(clear_halt, CLEAR_HALT, request_code_read!(b'U', 21, size_of::<c_uint>()), *const c_uint);
+
 // #define USBDEVFS_DISCONNECT        _IO('U', 22)
 // #define USBDEVFS_CONNECT           _IO('U', 23)
 // #define USBDEVFS_CLAIM_PORT        _IOR('U', 24, unsigned int)
 // #define USBDEVFS_RELEASE_PORT      _IOR('U', 25, unsigned int)
 // #define USBDEVFS_GET_CAPABILITIES  _IOR('U', 26, __u32)
+usbfs_ioctl!(get_capabilities, GET_CAPABILITIES, request_code_read!(b'U', 26, size_of::<u32>()), *mut u32);
+
 // #define USBDEVFS_DISCONNECT_CLAIM  _IOR('U', 27, struct usbdevfs_disconnect_claim)
+usbfs_ioctl!(disconnect_claim, DISCONNECT_CLAIM, request_code_read!(b'U', 27, size_of::<DisconnectClaim>()), *const DisconnectClaim);
+
 // #define USBDEVFS_ALLOC_STREAMS     _IOR('U', 28, struct usbdevfs_streams)
+usbfs_ioctl!(alloc_streams, ALLOC_STREAMS, request_code_read!(b'U', 28, size_of::<Streams>()), *const Streams);
+
 // #define USBDEVFS_FREE_STREAMS      _IOR('U', 29, struct usbdevfs_streams)
+usbfs_ioctl!(free_streams, FREE_STREAMS, request_code_read!(b'U', 29, size_of::<Streams>()), *const Streams);
+
 // #define USBDEVFS_DROP_PRIVILEGES   _IOW('U', 30, __u32)
 
+// #define USBDEVFS_GET_SPEED         _IO('U', 31)
+usbfs_ioctl!(get_speed, GET_SPEED, request_code_none!(b'U', 31));
+
 fn nix_err_to_io_err(err: nix::Error) -> io::Error {
     io::Error::from(err)
 }
@@ -279,3 +520,7 @@ fn nix_err_to_io_err(err: nix::Error) -> io::Error {
 pub fn nix_result_to_io_result<T>(res: nix::Result<T>) -> io::Result<T> {
     res.map_err(nix_err_to_io_err)
 }
+
+pub fn nix_result_to_result<T>(res: nix::Result<T>) -> super::Result<T> {
+    res.map_err(super::Error::from)
+}
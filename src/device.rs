@@ -1,8 +1,12 @@
 
 
-use std::{io, fs, fmt};
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::fs;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::fs::File;
+use std::mem::ManuallyDrop;
+use std::sync::Arc;
+
+use nix::errno::Errno;
 
 
 //use nix;
@@ -11,18 +15,86 @@ use std::fs::File;
 //use usbtypes::devfs::*;
 //use deviceinfo::*;
 
+#[cfg(feature="mio")]
+use std::io;
+#[cfg(feature="mio")]
+use mio::{Token, Interest, Registry};
+#[cfg(feature="mio")]
+use mio::event::Source;
+#[cfg(feature="mio")]
+use mio::unix::SourceFd;
+
 use super::*;
+use logging::LogName;
 
 
 /// Perform synchronous USB operations
 ///
 /// This struct wraps a usbfs device for performing synchronous USB operations.  If all you need is
 /// control transfer access to your USB hardware, this may be all you need.
-pub struct Device(pub File);
+///
+/// A device normally issues its ioctls on the usbfs file; `Device::with_backend()` routes them
+/// to a `Backend` instead, such as a `MockBackend` for testing without hardware.
+pub struct Device {
+    file: ManuallyDrop<File>,
+    backend: Option<Arc<dyn Backend>>,
+    // a loan of another device's file, see `lend()`, which isn't ours to close
+    lent: bool,
+}
 
 impl AsRawFd for Device {
     fn as_raw_fd(&self) -> RawFd {
-        self.0.as_raw_fd()
+        self.file.as_raw_fd()
+    }
+}
+
+/// Adopt an already-open usbfs file descriptor, for example one received over a Unix socket
+/// from a privileged helper.  Use `DeviceInfo::from_fd()` to recover the matching `DeviceInfo`.
+impl FromRawFd for Device {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Device::from(File::from_raw_fd(fd))
+    }
+}
+
+impl IntoRawFd for Device {
+    fn into_raw_fd(mut self) -> RawFd {
+        // the caller takes over closing the file
+        self.lent = true;
+        self.file.as_raw_fd()
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        if !self.lent {
+            unsafe { ManuallyDrop::drop(&mut self.file) }
+        }
+    }
+}
+
+/// Adopt an open usbfs device file.
+impl From<File> for Device {
+    fn from(file: File) -> Device {
+        Device { file: ManuallyDrop::new(file), backend: None, lent: false }
+    }
+}
+
+/// [mio](https://github.com/tokio-rs/mio) integration, available with the `mio` feature.
+///
+/// The device becomes writable when an URB submitted on its file descriptor is ready to be
+/// reaped.  See the `AsyncDevice` implementation.
+#[cfg(feature="mio")]
+impl Source for Device {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.as_raw_fd()).deregister(registry)
     }
 }
 
@@ -30,18 +102,20 @@ impl AsRawFd for Device {
 impl Device {
     /// Create new Device given a DeviceInfo struct.
     ///
+    /// `Device::open_diagnose()` does the same, explaining failures such as missing
+    /// permissions.
+    ///
     /// # Examples
     /// Find and open a specific device by idVendor and idProduct.
     ///
-    /// ```
+    /// ```no_run
     /// use usbfs::*;
     ///
     /// fn main() {
-    ///     let device_info = deviceinfo_collection()
-    ///                       .into_iter()
+    ///     let device_info = deviceinfo_enumerate()
     ///                       .find(is_my_device)
     ///                       .unwrap();
-    ///     let device = Device::new(device_info);
+    ///     let device = Device::new(&device_info);
     ///     // ...
     /// }
     ///
@@ -52,19 +126,81 @@ impl Device {
     ///     }
     /// }
     /// ```
-    pub fn new(device: &DeviceInfo) -> io::Result<Self> {
+    pub fn new(device: &DeviceInfo) -> Result<Self> {
         Self::new_from_busdev(device.busnum()?, device.devnum()?)
     }
 
-    pub fn new_from_busdev(busnum: u32, devnum: u32) -> io::Result<Self> {
+    pub fn new_from_busdev(busnum: u32, devnum: u32) -> Result<Self> {
         let mut openopts = fs::OpenOptions::new();
         openopts.read(true).write(true);
 
-        // pick first available path for device
-        openopts.open(fmt::format(format_args!("/dev/bus/usb/{:03}/{:03}", busnum, devnum)))
-            .or_else(|_|openopts.open(fmt::format(format_args!("/dev/usbdev{}.{}", busnum, devnum))))
-            .or_else(|_|openopts.open(fmt::format(format_args!("/proc/bus/usb/{:03}/{:03}", busnum, devnum))))
-        .map(|f| Device(f))
+        // pick first available path for device, reporting why the preferred one failed
+        let [bus_usb, usbdev, proc_bus_usb] = deviceinfo::devnode_paths(busnum, devnum);
+        let result = openopts.open(bus_usb)
+            .or_else(|err| openopts.open(usbdev)
+                     .or_else(|_|openopts.open(proc_bus_usb))
+                     .map_err(|_| err))
+        .map(Device::from)
+        .map_err(Error::from);
+        match result {
+            Ok(_) => log_debug!("{:03}:{:03} opened", busnum, devnum),
+            Err(ref err) => log_debug!("{:03}:{:03} open failed: {}", busnum, devnum, err),
+        }
+        result
+    }
+
+    /// Create a device whose ioctls go to `backend`.  `file` stands in for the usbfs file:
+    /// it is what `as_raw_fd()` returns and what event loops poll, so it should become writable
+    /// when the backend has a completed urb ready to be reaped, as a usbfs file does.
+    pub fn with_backend(file: File, backend: Arc<dyn Backend>) -> Device {
+        Device { file: ManuallyDrop::new(file), backend: Some(backend), lent: false }
+    }
+
+    /// Bus number of the device, recovered from its device node.  Devices not opened from a
+    /// usbfs node, such as those with a mock backend, give `ErrorKind::InvalidParam`.
+    pub fn busnum(&self) -> Result<u32> {
+        deviceinfo::busdev_from_fd(self).map(|(busnum, _)| busnum)
+    }
+
+    /// Device number (address) of the device on its bus, recovered from its device node.
+    pub fn devnum(&self) -> Result<u32> {
+        deviceinfo::busdev_from_fd(self).map(|(_, devnum)| devnum)
+    }
+
+    /// Open the device's node again, as a new handle with none of this one's claimed
+    /// interfaces or pending transfers, eg. to start afresh after a reset.  The device must
+    /// have kept its address; one that re-enumerated is found again with `DeviceId`.
+    pub fn reopen(&self) -> Result<Device> {
+        let (busnum, devnum) = deviceinfo::busdev_from_fd(self)?;
+        Device::new_from_busdev(busnum, devnum)
+    }
+
+    /// The usbfs file the device was opened from, or the stand-in given to `with_backend()`.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Another handle on the same device, sharing its backend if it has one.
+    pub fn try_clone(&self) -> Result<Device> {
+        Ok(Device { file: ManuallyDrop::new(self.file.try_clone()?), backend: self.backend.clone(), lent: false })
+    }
+
+    // A handle on this device's own file descriptor, rather than a duplicate, that leaves it
+    // open when dropped.  Used to run an `AsyncDevice` on the device for the length of a call
+    // holding `&mut self`, so nothing else can submit or reap on it meanwhile.
+    //
+    // Safety: the loan must be dropped before `self`.
+    pub(crate) unsafe fn lend(&self) -> Device {
+        let file = File::from_raw_fd(self.file.as_raw_fd());
+        Device { file: ManuallyDrop::new(file), backend: self.backend.clone(), lent: true }
+    }
+
+    // Issue a usbfs ioctl on the device or its backend.
+    pub(crate) unsafe fn ioctl(&self, request: devfs::ioctl_num_type, arg: *mut devfs::c_void) -> nix::Result<devfs::c_int> {
+        match self.backend {
+            Some(ref backend) => backend.ioctl(request, arg),
+            None => Errno::result(libc::ioctl(self.file.as_raw_fd(), request, arg)),
+        }
     }
 
     /// Perform a single synchronous control transfer.  Do not write a Setup packet to
@@ -72,6 +208,8 @@ impl Device {
     /// no exchange beyond the Setup packet is needed.
     ///
     /// The number of bytes transferred to/from `data` is returned as the `Ok` result.
+    ///
+    /// `ControlRequest` builds the same request without the long argument list.
     pub fn control_transfer(&self,
                             setupdirection: SetupDirection,
                             setuptype: SetupType,
@@ -81,7 +219,7 @@ impl Device {
                             wIndex: u16,
                             odata: Option<&mut [u8]>,
                             timeout_ms: u32)
-                            -> io::Result<i32> {
+                            -> Result<i32> {
 
         let (data, wLength) = match odata {
             Some(mref) => (mref.as_mut_ptr(), mref.len() as u16),
@@ -99,7 +237,7 @@ impl Device {
             data,
         };
 
-        unsafe { devfs::nix_result_to_io_result(devfs::control(self.as_raw_fd(), &mut xfer)) }
+        unsafe { devfs::nix_result_to_result(devfs::control(self, &mut xfer)) }
     }
 
     pub fn control_transfer_in(&self,
@@ -110,7 +248,7 @@ impl Device {
                             wIndex: u16,
                             odata: Option<&mut [u8]>,
                             timeout_ms: u32)
-                            -> io::Result<i32> {
+                            -> Result<i32> {
 
         let (data, wLength) = match odata {
             Some(mref) => (mref.as_mut_ptr(), mref.len() as u16),
@@ -127,7 +265,7 @@ impl Device {
             data,
         };
 
-        unsafe { devfs::nix_result_to_io_result(devfs::control(self.as_raw_fd(), &mut xfer)) }
+        unsafe { devfs::nix_result_to_result(devfs::control(self, &mut xfer)) }
     }
 
     pub fn control_transfer_out(&self,
@@ -138,7 +276,7 @@ impl Device {
                             wIndex: u16,
                             odata: Option<& [u8]>,
                             timeout_ms: u32)
-                            -> io::Result<i32> {
+                            -> Result<i32> {
 
         let (data, wLength) = match odata {
             Some(mref) => (mref.as_ptr(), mref.len() as u16),
@@ -156,23 +294,216 @@ impl Device {
             data: data as *mut u8,
         };
 
-        unsafe { devfs::nix_result_to_io_result(devfs::control(self.as_raw_fd(), &mut xfer)) }
+        unsafe { devfs::nix_result_to_result(devfs::control(self, &mut xfer)) }
     }
 
 
+    /// Perform a single synchronous bulk IN transfer on `endpoint`.  The direction bit of
+    /// `endpoint` is set automatically.
+    ///
+    /// The number of bytes received into `data` is returned as the `Ok` result.
+    pub fn bulk_transfer_in(&self,
+                            endpoint: u8,
+                            data: &mut [u8],
+                            timeout_ms: u32)
+                            -> Result<i32> {
+
+        let mut xfer = devfs::BulkTransfer {
+            ep: (endpoint | 0x80) as devfs::c_uint,
+            len: data.len() as devfs::c_uint,
+            timeout: timeout_ms as devfs::c_uint,
+            data: data.as_mut_ptr(),
+        };
 
-    pub fn claim_interface(&self, interface: u16) -> io::Result<()> {
+        unsafe { devfs::nix_result_to_result(devfs::bulk(self, &mut xfer)) }
+    }
+
+    /// Perform a single synchronous bulk OUT transfer on `endpoint`.  The direction bit of
+    /// `endpoint` is cleared automatically.
+    ///
+    /// The number of bytes sent from `data` is returned as the `Ok` result.
+    pub fn bulk_transfer_out(&self,
+                             endpoint: u8,
+                             data: &[u8],
+                             timeout_ms: u32)
+                             -> Result<i32> {
+
+        let mut xfer = devfs::BulkTransfer {
+            ep: (endpoint & 0x7f) as devfs::c_uint,
+            len: data.len() as devfs::c_uint,
+            timeout: timeout_ms as devfs::c_uint,
+            data: data.as_ptr() as *mut u8,
+        };
+
+        unsafe { devfs::nix_result_to_result(devfs::bulk(self, &mut xfer)) }
+    }
+
+    pub fn claim_interface(&self, interface: u16) -> Result<()> {
         let i: devfs::c_uint = interface as devfs::c_uint;
-        unsafe { devfs::nix_result_to_io_result(devfs::claiminterface(self.as_raw_fd(), &i).map(|_|())) }
+        let result = unsafe { devfs::nix_result_to_result(devfs::claiminterface(self, &i).map(|_|())) };
+        match result {
+            Ok(()) => log_debug!("{} claimed interface {}", LogName(self), interface),
+            Err(ref err) => log_debug!("{} claim of interface {} failed: {}", LogName(self), interface, err),
+        }
+        result
+    }
+
+    pub fn release_interface(&self, interface: u16) -> Result<()> {
+        let i: devfs::c_uint = interface as devfs::c_uint;
+        let result = unsafe { devfs::nix_result_to_result(devfs::releaseinterface(self, &i).map(|_|())) };
+        match result {
+            Ok(()) => log_debug!("{} released interface {}", LogName(self), interface),
+            Err(ref err) => log_debug!("{} release of interface {} failed: {}", LogName(self), interface, err),
+        }
+        result
+    }
+
+    /// Arrange for signal `signr` (eg. `libc::SIGUSR1`) to be sent to this process when the
+    /// device is disconnected.  `context` is delivered in the `si_addr` field of the signal's
+    /// `siginfo_t`, allowing a handler to tell devices apart.  A `signr` of 0 disables the
+    /// notification.
+    ///
+    /// Installing a suitable signal handler is the caller's responsibility.
+    pub fn disconnect_signal(&self, signr: i32, context: usize) -> Result<()> {
+        let data = devfs::DisconnectSignal {
+            signr: signr as devfs::c_uint,
+            context,
+        };
+        unsafe { devfs::nix_result_to_result(devfs::discsignal(self, &data)).map(|_|()) }
+    }
+
+    /// `false` once the device has been unplugged or otherwise disconnected from this handle.
+    ///
+    /// usbfs fails every request on a disconnected device with `ErrorKind::Disconnected`; this
+    /// checks with a harmless one.
+    pub fn is_connected(&self) -> bool {
+        match self.connect_info() {
+            Err(ref err) => err.kind() != ErrorKind::Disconnected,
+            Ok(_) => true,
+        }
+    }
+
+    /// Query the device number and whether the device is low speed.
+    pub fn connect_info(&self) -> Result<ConnectInfo> {
+        let mut info = ConnectInfo::default();
+        unsafe { devfs::nix_result_to_result(devfs::connectinfo(self, &mut info))? };
+        Ok(info)
+    }
+
+    /// For hubs, query the device number attached to each downstream port.
+    ///
+    /// Element `n` of the `Ok` result is the device number attached to port `n+1`, or 0 if the
+    /// port is empty.  Fails if the device is not a hub bound to the kernel hub driver.
+    pub fn hub_port_info(&self) -> Result<Vec<u8>> {
+        let mut info = devfs::HubPortInfo{nports: 0, port: [0; 127]};
+        let mut req = devfs::IoctlRequest {
+            ifno: 0,
+            ioctl_code: devfs::HUB_PORTINFO,
+            data: &mut info as *mut devfs::HubPortInfo as *mut u8,
+        };
+
+        unsafe { devfs::nix_result_to_result(devfs::usbioctl(self, &mut req))? };
+        let nports = std::cmp::min(info.nports as usize, info.port.len());
+        Ok(info.port[..nports].to_vec())
+    }
+
+    /// Clear a halt (stall) condition on `endpoint` by sending a `CLEAR_FEATURE(ENDPOINT_HALT)`
+    /// request to the device, and reset the host side data toggle.  Include the direction bit
+    /// in `endpoint`.
+    pub fn clear_halt(&self, endpoint: u8) -> Result<()> {
+        let ep: devfs::c_uint = endpoint as devfs::c_uint;
+        unsafe { devfs::nix_result_to_result(devfs::clear_halt(self, &ep).map(|_|())) }
+    }
+
+    /// Reset the data toggle/sequence state of `endpoint`.  Unlike clearing a halt, no request
+    /// is sent to the device; only the host side state is reset.
+    pub fn reset_endpoint(&self, endpoint: u8) -> Result<()> {
+        let ep: devfs::c_uint = endpoint as devfs::c_uint;
+        unsafe { devfs::nix_result_to_result(devfs::resetep(self, &ep).map(|_|())) }
+    }
+
+    /// Allocate `num_streams` USB 3.0 bulk streams on each of the given bulk `endpoints`.
+    ///
+    /// The `Ok` result is the number of streams actually allocated, which may be fewer than
+    /// requested.  Stream ids `1..=n` can then be assigned to bulk urbs with `Urb::set_stream_id()`.
+    pub fn alloc_streams(&self, num_streams: u32, endpoints: &[u8]) -> Result<u32> {
+        let streams = devfs::StreamsBuf::new(num_streams, endpoints);
+        unsafe { devfs::nix_result_to_result(devfs::alloc_streams(self, streams.as_ptr())).map(|n| n as u32) }
+    }
+
+    /// Release the bulk streams previously allocated on `endpoints`.
+    pub fn free_streams(&self, endpoints: &[u8]) -> Result<()> {
+        let streams = devfs::StreamsBuf::new(0, endpoints);
+        unsafe { devfs::nix_result_to_result(devfs::free_streams(self, streams.as_ptr())).map(|_|()) }
+    }
+
+    /// Query the usbfs capabilities of the running kernel for this device.
+    ///
+    /// Unknown capability bits reported by newer kernels are dropped.
+    pub fn capabilities(&self) -> Result<Capabilities> {
+        let mut caps: u32 = 0;
+        unsafe { devfs::nix_result_to_result(devfs::get_capabilities(self, &mut caps))? };
+        Ok(Capabilities::from_bits_truncate(caps))
+    }
+
+    /// Select the active configuration by its `bConfigurationValue`.  A value of 0 puts the
+    /// device in the unconfigured state.
+    pub fn set_configuration(&self, config: u32) -> Result<()> {
+        let c: devfs::c_uint = config as devfs::c_uint;
+        unsafe { devfs::nix_result_to_result(devfs::setconfiguration(self, &c).map(|_|())) }
+    }
+
+    /// Name of the kernel driver bound to `interface`, or `None` if no driver is bound.
+    pub fn kernel_driver(&self, interface: u32) -> Result<Option<String>> {
+        let mut data = devfs::GetDriver {
+            interface: interface as devfs::c_uint,
+            driver: [0; devfs::MAXDRIVERNAME + 1],
+        };
+
+        match unsafe { devfs::getdriver(self, &mut data) } {
+            Ok(_) => (),
+            Err(nix::errno::Errno::ENODATA) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+
+        // kernel should terminate the name, but don't rely on it
+        let len = data.driver.iter().position(|&c| c == 0).unwrap_or(data.driver.len());
+        Ok(Some(String::from_utf8_lossy(&data.driver[..len]).into_owned()))
+    }
+
+    /// Atomically disconnect any kernel driver bound to `interface` and claim it.
+    ///
+    /// `flags` restricts the operation based on the name of the bound driver: with
+    /// `DisconnectClaimFlags::IF_DRIVER` the interface is only taken over from `driver_name`,
+    /// and with `DisconnectClaimFlags::EXCEPT_DRIVER` it is taken over from any driver except
+    /// `driver_name`.  With empty flags `driver_name` is ignored.
+    pub fn disconnect_claim(&self,
+                            interface: u32,
+                            flags: DisconnectClaimFlags,
+                            driver_name: &str)
+                            -> Result<()> {
+        let name = driver_name.as_bytes();
+        if name.len() > devfs::MAXDRIVERNAME || name.contains(&0) {
+            return Err(Error::new(ErrorKind::InvalidParam, "invalid driver name"));
+        }
+
+        let mut data = devfs::DisconnectClaim {
+            interface: interface as devfs::c_uint,
+            flags,
+            driver: [0; devfs::MAXDRIVERNAME + 1],
+        };
+        data.driver[..name.len()].copy_from_slice(name);
+
+        unsafe { devfs::nix_result_to_result(devfs::disconnect_claim(self, &data)).map(|_|()) }
     }
 
-    pub fn set_interface(&self, interface: u32, altsetting: u32) -> io::Result<()> {
+    pub fn set_interface(&self, interface: u32, altsetting: u32) -> Result<()> {
         unsafe {
             let data = devfs::SetInterface{
                 interface: interface as devfs::c_uint,
                 altsetting: altsetting as devfs::c_uint,
             };
-            devfs::nix_result_to_io_result(devfs::setinterface(self.as_raw_fd(), &data)).map(|_|())
+            devfs::nix_result_to_result(devfs::setinterface(self, &data)).map(|_|())
         }
     }
 }
@@ -0,0 +1,130 @@
+
+use super::*;
+
+/// Criteria for selecting USB devices during enumeration.
+///
+/// Each criterion is optional; a device matches when it satisfies all criteria that have been
+/// set.  An empty filter matches every device.
+///
+/// # Examples
+/// Open the first device with a given vendor and product on bus 1:
+///
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device_info = DeviceFilter::new()
+///                   .vendor(0xffff)
+///                   .product(3)
+///                   .bus(1)
+///                   .find()
+///                   .unwrap();
+/// let device = Device::new(&device_info).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    vendor: Option<u16>,
+    product: Option<u16>,
+    class: Option<u8>,
+    serial: Option<String>,
+    bus: Option<u32>,
+    port_path: Option<String>,
+}
+
+impl DeviceFilter {
+    /// Create a filter that matches every device.
+    pub fn new() -> DeviceFilter {
+        Default::default()
+    }
+
+    /// Match devices with the given `idVendor`.
+    pub fn vendor(mut self, vendor: u16) -> DeviceFilter {
+        self.vendor = Some(vendor);
+        self
+    }
+
+    /// Match devices with the given `idProduct`.
+    pub fn product(mut self, product: u16) -> DeviceFilter {
+        self.product = Some(product);
+        self
+    }
+
+    /// Match devices with the given `bDeviceClass`.
+    pub fn class(mut self, class: u8) -> DeviceFilter {
+        self.class = Some(class);
+        self
+    }
+
+    /// Match devices with the given serial number string.
+    pub fn serial(mut self, serial: &str) -> DeviceFilter {
+        self.serial = Some(serial.to_string());
+        self
+    }
+
+    /// Match devices on the given bus number.
+    pub fn bus(mut self, bus: u32) -> DeviceFilter {
+        self.bus = Some(bus);
+        self
+    }
+
+    /// Match the device attached at the given port path, eg. `"1-1.4.2"`.
+    pub fn port_path(mut self, port_path: &str) -> DeviceFilter {
+        self.port_path = Some(port_path.to_string());
+        self
+    }
+
+    /// Test whether `di` satisfies this filter.  Devices whose metadata can't be read do not
+    /// match.
+    pub fn matches(&self, di: &DeviceInfo) -> bool {
+        self.try_matches(di).unwrap_or(false)
+    }
+
+    fn try_matches(&self, di: &DeviceInfo) -> Result<bool> {
+        if let Some(ref port_path) = self.port_path {
+            if di.port_path() != port_path.as_str() {
+                return Ok(false);
+            }
+        }
+        if let Some(bus) = self.bus {
+            if di.busnum()? != bus {
+                return Ok(false);
+            }
+        }
+        if self.vendor.is_some() || self.product.is_some() || self.class.is_some() {
+            let descr = di.device_descriptor()?;
+            if self.vendor.is_some_and(|v| v != descr.idVendor)
+                || self.product.is_some_and(|p| p != descr.idProduct)
+                || self.class.is_some_and(|c| c != descr.bDeviceClass) {
+                return Ok(false);
+            }
+        }
+        if let Some(ref serial) = self.serial {
+            if di.serial()?.as_deref() != Some(serial.as_str()) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Enumerate all devices that satisfy this filter.
+    pub fn enumerate(&self) -> impl Iterator<Item=DeviceInfo> + '_ {
+        deviceinfo_enumerate().filter(move |di| self.matches(di))
+    }
+
+    /// Find the first device that satisfies this filter.
+    pub fn find(&self) -> Option<DeviceInfo> {
+        self.enumerate().next()
+    }
+}
+
+/// Find the first device with the given `idVendor` and `idProduct`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// ```
+pub fn deviceinfo_find(vendor: u16, product: u16) -> Option<DeviceInfo> {
+    DeviceFilter::new().vendor(vendor).product(product).find()
+}
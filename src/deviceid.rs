@@ -0,0 +1,113 @@
+use std::{fmt, str};
+
+use super::*;
+
+/// Identity of a physical device that survives replugging and reboots, unlike bus and device
+/// numbers, which change every time the device enumerates.
+///
+/// A device is identified by its idVendor and idProduct together with its serial number or,
+/// for devices without one, the port it is plugged into.  Devices with a serial number are
+/// found again on any port; the recorded port only breaks ties between identical devices.
+///
+/// The `Display` and `FromStr` implementations convert to and from a string like
+/// `1-1.4:0525:a4a0:SERIAL`, suitable for configuration files.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let id = deviceinfo_find(0xffff, 3).unwrap().id().unwrap();
+/// let saved = id.to_string();
+///
+/// // ... after a replug or reboot
+/// let id: DeviceId = saved.parse().unwrap();
+/// let device = Device::open_by_id(&id).unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct DeviceId {
+    /// Port path of the device, as `DeviceInfo::port_path()`.
+    pub port_path: String,
+    pub vendor: u16,
+    pub product: u16,
+    /// Serial number string, if the device has one.
+    pub serial: Option<String>,
+}
+
+impl DeviceInfo {
+    /// Persistent identity of the device.
+    pub fn id(&self) -> Result<DeviceId> {
+        let descr = self.device_descriptor()?;
+        Ok(DeviceId {
+            port_path: self.port_path().to_string(),
+            vendor: descr.idVendor,
+            product: descr.idProduct,
+            serial: self.serial()?,
+        })
+    }
+}
+
+impl DeviceId {
+    /// Whether `di` is the device identified, ignoring the port of devices with a serial
+    /// number.
+    pub fn matches(&self, di: &DeviceInfo) -> bool {
+        self.filter().matches(di)
+    }
+
+    /// Find the device currently present with this identity.
+    pub fn find(&self) -> Option<DeviceInfo> {
+        let mut candidates: Vec<DeviceInfo> = self.filter().enumerate().collect();
+        match candidates.iter().position(|di| di.port_path() == self.port_path) {
+            Some(i) => Some(candidates.swap_remove(i)),
+            None => candidates.into_iter().next(),
+        }
+    }
+
+    fn filter(&self) -> DeviceFilter {
+        let filter = DeviceFilter::new().vendor(self.vendor).product(self.product);
+        match self.serial {
+            Some(ref serial) => filter.serial(serial),
+            None => filter.port_path(&self.port_path),
+        }
+    }
+}
+
+impl Device {
+    /// Open the device identified by `id`, wherever it now is.  Fails with
+    /// `ErrorKind::NotFound` if the device isn't present.
+    pub fn open_by_id(id: &DeviceId) -> Result<Device> {
+        match id.find() {
+            Some(info) => Device::new(&info),
+            None => Err(Error::new(ErrorKind::NotFound, "no device with this identity")),
+        }
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{:04x}:{:04x}", self.port_path, self.vendor, self.product)?;
+        match self.serial {
+            Some(ref serial) => write!(f, ":{}", serial),
+            None => Ok(()),
+        }
+    }
+}
+
+impl str::FromStr for DeviceId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DeviceId> {
+        let bad = || Error::new(ErrorKind::InvalidParam, "malformed device identity");
+        // port paths never contain ':', while serial numbers may
+        let mut fields = s.splitn(4, ':');
+        let port_path = fields.next().filter(|p| !p.is_empty()).ok_or_else(bad)?;
+        let vendor = fields.next().and_then(|v| u16::from_str_radix(v, 16).ok()).ok_or_else(bad)?;
+        let product = fields.next().and_then(|p| u16::from_str_radix(p, 16).ok()).ok_or_else(bad)?;
+        Ok(DeviceId {
+            port_path: port_path.to_string(),
+            vendor,
+            product,
+            serial: fields.next().map(|s| s.to_string()),
+        })
+    }
+}
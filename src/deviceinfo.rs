@@ -1,76 +1,378 @@
 
 use std;
-use std::{io, fs, fmt, mem, slice};
+use std::{fs, fmt};
 use std::io::Read;
 //use std::vec::Vec;
 use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::os::unix::io::AsRawFd;
+
+use nix::sys::stat;
 
 //use super::usbtypes::*;
 use super::*;
+use descriptors::CONTROL_TIMEOUT_MS;
+
+pub(crate) const SYSFS_DEVICE_PATH: &str = "/sys/bus/usb/devices";
+const SYSFS_CHAR_DEV_PATH: &str = "/sys/dev/char";
+const DEVNODE_PATH: &str = "/dev/bus/usb";
 
-const SYSFS_DEVICE_PATH: &'static str = "/sys/bus/usb/devices";
+// Character device major number of usbfs device nodes.
+const USB_DEVICE_MAJOR: u64 = 189;
 
 
 /// Provides metadata about a specific USB device.
 ///
 /// All information is collected from the linux `sysfs` directory.
-/// See the function deviceinfo_collection()
-#[derive(Debug)]
+/// See the function deviceinfo_enumerate()
+///
+/// Where sysfs is unavailable, as in containers given only `/dev/bus/usb`, devices are found
+/// through their device nodes instead.  Descriptors are then read from the node, and
+/// strings, speed, and the active configuration are fetched by opening the device, which
+/// needs write access to the node.  Such devices have no known topology: their port path is
+/// their device node's name, like `usbdev1.4`, and they have no parent.
+#[derive(Debug, Clone)]
 pub struct DeviceInfo {
     dir: OsString,
+    // bus and device number of devices found without sysfs
+    node: Option<(u32, u32)>,
 }
 
 impl DeviceInfo {
-    /// Something about device_descriptor.
-    pub fn device_descriptor(&self) -> io::Result<DeviceDescriptor<NativeEndian>> {
-        let mut descr: DeviceDescriptor<BusEndian> = unsafe { mem::MaybeUninit::uninit().assume_init() };
-        let filename = fmt::format(format_args!("{}/{}/descriptors",
-                                                SYSFS_DEVICE_PATH,
-                                                self.dir.to_str().unwrap()));
-        let buf: &mut [u8] = unsafe {
-            slice::from_raw_parts_mut(&mut descr as *mut DeviceDescriptor<BusEndian> as *mut u8,
-                                      mem::size_of::<DeviceDescriptor<BusEndian>>())
+    /// Recover the `DeviceInfo` of an already-open usbfs device node, such as a `Device` or a
+    /// file descriptor passed in from a privileged helper process.
+    ///
+    /// The device is identified from the major/minor numbers of the file descriptor, so no
+    /// access to `/dev` is required.  The returned `DeviceInfo` still relies on `sysfs` for
+    /// its metadata.
+    pub fn from_fd<F: AsRawFd>(f: &F) -> Result<DeviceInfo> {
+        let minor = usbfs_minor(f)?;
+
+        // /sys/dev/char/<major>:<minor> links to the device's sysfs directory
+        let link = match fs::read_link(format!("{}/{}:{}", SYSFS_CHAR_DEV_PATH, USB_DEVICE_MAJOR, minor)) {
+            Ok(link) => link,
+            Err(_) if !sysfs_available() => {
+                let (busnum, devnum) = busdev_from_minor(minor);
+                return Ok(DeviceInfo::from_node(busnum, devnum));
+            }
+            Err(err) => return Err(err.into()),
         };
-        fs::File::open(filename)?.read_exact(buf)?;
-        Ok(descr.into())
+        link.file_name()
+            .map(|dir| DeviceInfo{dir: dir.to_os_string(), node: None})
+            .ok_or_else(|| Error::new(ErrorKind::Other, "bad sysfs link"))
+    }
+
+    /// Device descriptor of the device, as cached by the kernel.  No request is sent to the
+    /// device.
+    pub fn device_descriptor(&self) -> Result<DeviceDescriptor> {
+        let mut buf = [0u8; DeviceDescriptor::SIZE];
+        fs::File::open(self.descriptors_path())?.read_exact(&mut buf)?;
+        DeviceDescriptor::parse(&buf)
+    }
+    /// Serial number string of the device, or `None` if the device doesn't have one.
+    pub fn serial(&self) -> Result<Option<String>> {
+        match self.node {
+            Some(_) => self.string_via_control(|d| d.iSerialNumber),
+            None => read_sysfs_optional_string(self.dirname(), "serial"),
+        }
+    }
+
+    /// Product string of the device, or `None` if the device doesn't have one.
+    pub fn product(&self) -> Result<Option<String>> {
+        match self.node {
+            Some(_) => self.string_via_control(|d| d.iProduct),
+            None => read_sysfs_optional_string(self.dirname(), "product"),
+        }
+    }
+
+    /// Manufacturer string of the device, or `None` if the device doesn't have one.
+    pub fn manufacturer(&self) -> Result<Option<String>> {
+        match self.node {
+            Some(_) => self.string_via_control(|d| d.iManufacturer),
+            None => read_sysfs_optional_string(self.dirname(), "manufacturer"),
+        }
+    }
+
+    /// Snapshot of the device's metadata.
+    pub fn summary(&self) -> Result<DeviceSummary> {
+        Ok(DeviceSummary {
+            busnum: self.busnum()?,
+            devnum: self.devnum()?,
+            port_path: self.port_path().to_string(),
+            speed: self.speed().ok(),
+            descriptor: self.device_descriptor()?,
+            manufacturer: self.manufacturer()?,
+            product: self.product()?,
+            serial: self.serial()?,
+        })
+    }
+    pub fn busnum(&self) -> Result<u32> {
+        match self.node {
+            Some((busnum, _)) => Ok(busnum),
+            None => read_sysfs_num(self.dir.to_str().unwrap(), "busnum"),
+        }
+    }
+    pub fn devnum(&self) -> Result<u32> {
+        match self.node {
+            Some((_, devnum)) => Ok(devnum),
+            None => read_sysfs_num(self.dir.to_str().unwrap(), "devnum"),
+        }
     }
-    pub fn busnum(&self) -> io::Result<u32> {
-        read_sysfs_num(self.dir.to_str().unwrap(), "busnum")
+
+    /// The device's directory in sysfs, eg. `/sys/bus/usb/devices/1-1.4`.  Devices found
+    /// without sysfs give a path that doesn't exist.
+    pub fn sysfs_path(&self) -> PathBuf {
+        Path::new(SYSFS_DEVICE_PATH).join(&self.dir)
+    }
+
+    /// The device's node in `/dev/bus/usb`, eg. `/dev/bus/usb/001/004`.
+    pub fn devnode(&self) -> Result<PathBuf> {
+        let [bus_usb, ..] = devnode_paths(self.busnum()?, self.devnum()?);
+        Ok(bus_usb)
+    }
+
+    /// Device node that `Device::new()` opens, eg. `/dev/bus/usb/001/004`, so tools can report
+    /// which node needs permissions.  Where no node exists, this is the path udev would create.
+    pub fn devnode_path(&self) -> Result<PathBuf> {
+        let paths = devnode_paths(self.busnum()?, self.devnum()?);
+        Ok(paths.iter().find(|p| p.exists()).unwrap_or(&paths[0]).clone())
     }
-    pub fn devnum(&self) -> io::Result<u32> {
-        read_sysfs_num(self.dir.to_str().unwrap(), "devnum")
+
+    /// Negotiated speed of the device.
+    pub fn speed(&self) -> Result<Speed> {
+        match self.node {
+            Some(_) => Device::new(self)?.speed(),
+            None => Speed::from_sysfs(&read_sysfs_string(self.dirname(), "speed")?),
+        }
+    }
+
+    /// Physical location of the device as a sysfs port path, eg. `"1-1.4.2"` for a device on
+    /// port 2 of a hub on port 4 of a hub on port 1 of bus 1.  Root hubs are named `"usbN"`.
+    pub fn port_path(&self) -> &str {
+        self.dirname()
+    }
+
+    /// The hub this device is attached to, or `None` for root hubs.
+    pub fn parent(&self) -> Option<DeviceInfo> {
+        let path = self.port_path();
+        if path.starts_with("usb") {
+            return None;
+        }
+        match path.rfind('.') {
+            Some(i) => Some(DeviceInfo::from_dirname(&path[..i])),
+            None => {
+                // attached directly to the root hub of this bus
+                let bus = &path[..path.find('-')?];
+                Some(DeviceInfo::from_dirname(&format!("usb{}", bus)))
+            }
+        }
+    }
+
+    /// Devices attached to this device, if it is a hub.
+    pub fn children(&self) -> impl Iterator<Item=DeviceInfo> + '_ {
+        deviceinfo_enumerate()
+            .filter(move |di| di.parent().is_some_and(|p| p.port_path() == self.port_path()))
+    }
+
+    /// `bConfigurationValue` of the active configuration, or `None` if the device is
+    /// unconfigured, as the kernel keeps it in sysfs.  Unlike `Device::get_configuration()`
+    /// this sends no request to the device, so it can't disturb the drivers bound to it.
+    /// Devices found without sysfs give `ErrorKind::Unsupported`.
+    pub fn active_configuration(&self) -> Result<Option<u8>> {
+        match read_sysfs_string(self.sysfs_dirname()?, "bConfigurationValue")?.trim() {
+            "" | "0" => Ok(None),
+            value => value.parse().map(Some).map_err(|_| Error::new(ErrorKind::Other, "bad parse")),
+        }
+    }
+
+    /// Current alternate setting of interface `interface` of the active configuration, from
+    /// sysfs, like `active_configuration()`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// let info = deviceinfo_find(0xffff, 3).unwrap();
+    /// if let Some(value) = info.active_configuration().unwrap() {
+    ///     let config = info.configurations().unwrap().into_iter().find(|c| c.bConfigurationValue == value).unwrap();
+    ///     for number in 0..config.bNumInterfaces {
+    ///         println!("interface {}: altsetting {}", number, info.interface_altsetting(number).unwrap());
+    ///     }
+    /// }
+    /// ```
+    pub fn interface_altsetting(&self, interface: u8) -> Result<u8> {
+        read_sysfs_num(&self.interface_dirname(interface)?, "bAlternateSetting")
+    }
+
+    // sysfs directory of an interface of the active configuration, eg. `1-1.4:1.0`
+    pub(crate) fn interface_dirname(&self, interface: u8) -> Result<String> {
+        let dirname = self.sysfs_dirname()?;
+        match self.active_configuration()? {
+            Some(value) => Ok(format!("{}:{}.{}", dirname, value, interface)),
+            None => Err(Error::new(ErrorKind::NotFound, "device is unconfigured")),
+        }
+    }
+
+    // bConfigurationValue of the active configuration
+    pub(crate) fn configuration_value(&self) -> Result<u32> {
+        match self.node {
+            Some(_) => Ok(Device::new(self)?.get_configuration(CONTROL_TIMEOUT_MS)? as u32),
+            None => read_sysfs_num(self.dirname(), "bConfigurationValue"),
+        }
+    }
+
+    pub(crate) fn from_dirname(dirname: &str) -> DeviceInfo {
+        DeviceInfo{dir: OsString::from(dirname), node: None}
+    }
+
+    fn from_node(busnum: u32, devnum: u32) -> DeviceInfo {
+        DeviceInfo{dir: OsString::from(format!("usbdev{}.{}", busnum, devnum)), node: Some((busnum, devnum))}
     }
+
+    // File holding the device descriptor followed by the configuration descriptors: the
+    // sysfs `descriptors` attribute, or failing sysfs the device node, whose reads give the
+    // same.
+    pub(crate) fn descriptors_path(&self) -> PathBuf {
+        match self.node {
+            Some((busnum, devnum)) => devnode_paths(busnum, devnum)[0].clone(),
+            None => self.sysfs_path().join("descriptors"),
+        }
+    }
+
+    // String descriptor `index(descriptor)`, read from the device in its first language.
+    fn string_via_control<F: Fn(&DeviceDescriptor) -> u8>(&self, index: F) -> Result<Option<String>> {
+        let index = index(&self.device_descriptor()?);
+        if index == 0 {
+            return Ok(None);
+        }
+        let device = Device::new(self)?;
+        let language = device.get_languages(CONTROL_TIMEOUT_MS)?.first().copied().unwrap_or(0x0409);
+        device.get_string_descriptor(index, language, CONTROL_TIMEOUT_MS).map(Some)
+    }
+
+    // name of the device's sysfs directory
+    pub(crate) fn dirname(&self) -> &str {
+        self.dir.to_str().unwrap()
+    }
+
+    // name of the device's sysfs directory, for attributes that only sysfs provides
+    pub(crate) fn sysfs_dirname(&self) -> Result<&str> {
+        match self.node {
+            Some(_) => Err(Error::new(ErrorKind::Unsupported, "device found without sysfs")),
+            None => Ok(self.dirname()),
+        }
+    }
+}
+
+// Minor number of usbfs device node `f`.
+fn usbfs_minor<F: AsRawFd>(f: &F) -> Result<u64> {
+    let st = devfs::nix_result_to_result(stat::fstat(f.as_raw_fd()))?;
+    let is_chr = (st.st_mode & stat::SFlag::S_IFMT.bits()) == stat::SFlag::S_IFCHR.bits();
+    if !is_chr || stat::major(st.st_rdev) != USB_DEVICE_MAJOR {
+        return Err(Error::new(ErrorKind::InvalidParam, "not a usbfs device node"));
+    }
+    Ok(stat::minor(st.st_rdev))
+}
+
+// Bus and device number of a usbfs device node with minor number `minor`; minor numbers
+// count 128 devices per bus.
+fn busdev_from_minor(minor: u64) -> (u32, u32) {
+    (minor as u32 / 128 + 1, minor as u32 % 128 + 1)
+}
+
+// Bus and device number of usbfs device node `f`.
+pub(crate) fn busdev_from_fd<F: AsRawFd>(f: &F) -> Result<(u32, u32)> {
+    usbfs_minor(f).map(busdev_from_minor)
+}
+
+// Whether sysfs lists USB devices here.
+fn sysfs_available() -> bool {
+    Path::new(SYSFS_DEVICE_PATH).is_dir()
+}
+
+// Candidate device nodes of a device, in order of preference.
+pub(crate) fn devnode_paths(busnum: u32, devnum: u32) -> [PathBuf; 3] {
+    [
+        PathBuf::from(format!("/dev/bus/usb/{:03}/{:03}", busnum, devnum)),
+        PathBuf::from(format!("/dev/usbdev{}.{}", busnum, devnum)),
+        PathBuf::from(format!("/proc/bus/usb/{:03}/{:03}", busnum, devnum)),
+    ]
 }
 
+/// Metadata of a device, collected by `DeviceInfo::summary()` for inventories and logs.  With
+/// the `serde` feature it can be serialized, eg. to JSON.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct DeviceSummary {
+    pub busnum: u32,
+    pub devnum: u32,
+    pub port_path: String,
+    /// Negotiated speed, if the kernel reports one the crate knows.
+    pub speed: Option<Speed>,
+    pub descriptor: DeviceDescriptor,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial: Option<String>,
+}
 
-fn read_sysfs_num<T: std::str::FromStr>(dirname: &str, attr: &str) -> io::Result<T> {
+/// Summary in the style of `lsusb`, eg. `Bus 001 Device 004: ID 0525:a4a0 Gadget Zero`.  Devices
+/// that have gone away show as their port path.
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.busnum(), self.devnum(), self.device_descriptor()) {
+            (Ok(busnum), Ok(devnum), Ok(descr)) => {
+                write!(f, "Bus {:03} Device {:03}: ID {:04x}:{:04x}", busnum, devnum, descr.idVendor, descr.idProduct)?;
+                match self.product() {
+                    Ok(Some(product)) => write!(f, " {}", product),
+                    _ => Ok(()),
+                }
+            }
+            _ => write!(f, "{} (not present)", self.port_path()),
+        }
+    }
+}
+
+pub(crate) fn read_sysfs_string(dirname: &str, attr: &str) -> Result<String> {
     let filename = fmt::format(format_args!("{}/{}/{}", SYSFS_DEVICE_PATH, dirname, attr));
     let mut buf = String::new();
-    fs::File::open(filename)?.read_to_string(&mut buf).unwrap();
-    buf.trim().parse().map_err(|_| io::Error::new(io::ErrorKind::Other, "bad parse"))
+    fs::File::open(filename)?.read_to_string(&mut buf)?;
+    Ok(buf.trim_end_matches('\n').to_string())
 }
 
+pub(crate) fn write_sysfs_string(dirname: &str, attr: &str, value: &str) -> Result<()> {
+    let filename = fmt::format(format_args!("{}/{}/{}", SYSFS_DEVICE_PATH, dirname, attr));
+    fs::write(filename, value)?;
+    Ok(())
+}
 
-// Someday return just an iterator instead of a collection.
-// Rust needs to support return type inference first.
- pub fn deviceinfo_enumerate() -> impl Iterator<Item=DeviceInfo> {
-     fs::read_dir(SYSFS_DEVICE_PATH)
-     .into_iter().flat_map(|x|x)  // produce empty iterator if read_dir failed
-     .filter_map(|x| x.ok()) // discard erroneous dir entries
-     .map(|x| x.file_name())
-     .filter(is_device_dirname) //discard non-device filnames
-     .map(|x| DeviceInfo{dir:x})
- }
+// Like read_sysfs_string(), for attributes only some devices have.
+fn read_sysfs_optional_string(dirname: &str, attr: &str) -> Result<Option<String>> {
+    match read_sysfs_string(dirname, attr) {
+        Ok(value) => Ok(Some(value)),
+        Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn read_sysfs_num<T: std::str::FromStr>(dirname: &str, attr: &str) -> Result<T> {
+    let filename = fmt::format(format_args!("{}/{}/{}", SYSFS_DEVICE_PATH, dirname, attr));
+    let mut buf = String::new();
+    fs::File::open(filename)?.read_to_string(&mut buf)?;
+    buf.trim().parse().map_err(|_| Error::new(ErrorKind::Other, "bad parse"))
+}
 
 
-/// Provide collection of `DeviceInfo` instances representing
+/// Provide an iterator of `DeviceInfo` instances representing
 /// all USB devices on the host.
 ///
+/// Devices are listed from sysfs or, where sysfs is unavailable, from `/dev/bus/usb` as
+/// `deviceinfo_enumerate_devnodes()` does.  Root hubs are left out either way.
+///
 /// # Examples
 ///
 /// Show Device Descriptors for all USB devices:
 ///
-/// ```
+/// ```no_run
 /// use usbfs::*;
 /// for di in deviceinfo_enumerate() {
 ///     let desc = di.device_descriptor().unwrap();
@@ -80,10 +382,10 @@ fn read_sysfs_num<T: std::str::FromStr>(dirname: &str, attr: &str) -> io::Result
 ///
 /// Find a specific device:
 ///
-/// ```
-/// use usbfs::*
-/// fn main() {}
-///     let mydev_info = deviceinfo_enumerate().find(is_my_device).unwrap()
+/// ```no_run
+/// use usbfs::*;
+/// fn main() {
+///     let mydev_info = deviceinfo_enumerate().find(is_my_device).unwrap();
 ///     // ...
 /// }
 ///
@@ -95,17 +397,39 @@ fn read_sysfs_num<T: std::str::FromStr>(dirname: &str, attr: &str) -> io::Result
 ///     }
 /// }
 /// ```
-///
+pub fn deviceinfo_enumerate() -> impl Iterator<Item=DeviceInfo> {
+    let devnodes = match sysfs_available() {
+        true => Vec::new(),
+        false => deviceinfo_enumerate_devnodes().collect(),
+    };
+    fs::read_dir(SYSFS_DEVICE_PATH)
+    .into_iter().flatten()  // produce empty iterator if read_dir failed
+    .filter_map(|x| x.ok()) // discard erroneous dir entries
+    .map(|x| x.file_name())
+    .filter(is_device_dirname) //discard non-device filnames
+    .map(|x| DeviceInfo{dir:x, node: None})
+    .chain(devnodes)
+}
 
-//pub fn deviceinfo_collection() -> Vec<DeviceInfo> {
-//    fs::read_dir(SYSFS_DEVICE_PATH)
-//        .into_iter().flat_map(|x| x)     // produce empty iterator if read_dir failed
-//        .filter_map(|x| x.ok())         // discard erroneous dir entries
-//        .map(|x| x.file_name())         // take just the filename
-//        .filter(is_device_dirname)      // discard non-device filenames
-//        .map(|x| DeviceInfo { dir: x })     // to DeviceInfo struct
-//        .collect()
-//}
+/// Like `deviceinfo_enumerate()`, but find devices by walking `/dev/bus/usb/BBB/DDD` rather
+/// than sysfs.  See `DeviceInfo` for what works without sysfs.
+pub fn deviceinfo_enumerate_devnodes() -> impl Iterator<Item=DeviceInfo> {
+    let number = |entry: &fs::DirEntry| entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok());
+    let mut nodes: Vec<(u32, u32)> = fs::read_dir(DEVNODE_PATH)
+        .into_iter().flatten()
+        .filter_map(|bus| bus.ok())
+        .filter_map(|bus| Some((number(&bus)?, bus.path())))
+        .flat_map(|(busnum, path)| {
+            fs::read_dir(path).into_iter().flatten()
+                .filter_map(|dev| dev.ok())
+                .filter_map(move |dev| Some((busnum, number(&dev)?)))
+        })
+        // the root hub of each bus has address 1
+        .filter(|&(_, devnum)| devnum != 1)
+        .collect();
+    nodes.sort();
+    nodes.into_iter().map(|(busnum, devnum)| DeviceInfo::from_node(busnum, devnum))
+}
 
 fn is_device_dirname(dirname: &OsString) -> bool {
     match dirname.to_str() {
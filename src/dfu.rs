@@ -0,0 +1,444 @@
+use std::thread;
+use std::time::Duration;
+
+use super::*;
+use descriptors::le16;
+
+/// Descriptor type of the DFU functional descriptor.
+pub const DFU_FUNCTIONAL_DESCRIPTOR_TYPE: u8 = 0x21;
+
+/// DFU functional descriptor, found among the `extra` descriptors of DFU interfaces.
+///
+/// Available with the `dfu` feature.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct DfuFunctional {
+    pub bmAttributes: u8,
+    /// Time the device waits for a reset after DFU_DETACH, in ms.
+    pub wDetachTimeOut: u16,
+    /// Largest block moved by DFU_DNLOAD and DFU_UPLOAD.
+    pub wTransferSize: u16,
+    /// 0x0100 for descriptors from DFU 1.0, which lack the field.
+    pub bcdDFUVersion: u16,
+}
+
+impl DfuFunctional {
+    /// Parse a raw functional descriptor.  `None` if `raw` is not a DFU functional
+    /// descriptor or is too short.
+    pub fn parse(raw: &RawDescriptor) -> Option<DfuFunctional> {
+        let d = &raw.0;
+        if d.len() < 7 || d[1] != DFU_FUNCTIONAL_DESCRIPTOR_TYPE {
+            return None;
+        }
+        Some(DfuFunctional {
+            bmAttributes: d[2],
+            wDetachTimeOut: le16(d, 3),
+            wTransferSize: le16(d, 5),
+            bcdDFUVersion: if d.len() >= 9 { le16(d, 7) } else { 0x0100 },
+        })
+    }
+
+    pub fn can_download(&self) -> bool {
+        self.bmAttributes & 0x01 != 0
+    }
+
+    pub fn can_upload(&self) -> bool {
+        self.bmAttributes & 0x02 != 0
+    }
+
+    /// Whether the device still answers requests after manifesting new firmware, rather than
+    /// waiting to be reset.
+    pub fn manifestation_tolerant(&self) -> bool {
+        self.bmAttributes & 0x04 != 0
+    }
+
+    /// Whether the device detaches and re-enumerates by itself after DFU_DETACH, rather than
+    /// waiting for the host to reset it.
+    pub fn will_detach(&self) -> bool {
+        self.bmAttributes & 0x08 != 0
+    }
+}
+
+impl InterfaceDescriptor {
+    /// DFU functional descriptor of a DFU interface (class 0xfe, subclass 0x01), either the
+    /// run-time interface of an application or the interface of a device in DFU mode.
+    /// Available with the `dfu` feature.
+    pub fn dfu_functional_descriptor(&self) -> Option<DfuFunctional> {
+        if self.bInterfaceClass != 0xfe || self.bInterfaceSubClass != 0x01 {
+            return None;
+        }
+        self.extra.iter().filter_map(DfuFunctional::parse).next()
+    }
+}
+
+/// State of a DFU device, as reported by DFU_GETSTATUS and DFU_GETSTATE.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum DfuState {
+    AppIdle = 0,
+    AppDetach = 1,
+    DfuIdle = 2,
+    DfuDnloadSync = 3,
+    DfuDnbusy = 4,
+    DfuDnloadIdle = 5,
+    DfuManifestSync = 6,
+    DfuManifest = 7,
+    DfuManifestWaitReset = 8,
+    DfuUploadIdle = 9,
+    DfuError = 10,
+}
+
+impl DfuState {
+    fn from_u8(state: u8) -> Option<DfuState> {
+        let state = match state {
+            0 => DfuState::AppIdle,
+            1 => DfuState::AppDetach,
+            2 => DfuState::DfuIdle,
+            3 => DfuState::DfuDnloadSync,
+            4 => DfuState::DfuDnbusy,
+            5 => DfuState::DfuDnloadIdle,
+            6 => DfuState::DfuManifestSync,
+            7 => DfuState::DfuManifest,
+            8 => DfuState::DfuManifestWaitReset,
+            9 => DfuState::DfuUploadIdle,
+            10 => DfuState::DfuError,
+            _ => return None,
+        };
+        Some(state)
+    }
+}
+
+/// Response to DFU_GETSTATUS.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct DfuStatus {
+    /// Result of the last request; 0 is OK.  See `status_name()`.
+    pub status: u8,
+    /// Time to wait before the next DFU_GETSTATUS, in ms.
+    pub poll_timeout: u32,
+    pub state: DfuState,
+    /// Index of a string descriptor describing the status, or 0.
+    pub string_index: u8,
+}
+
+impl DfuStatus {
+    pub fn is_ok(&self) -> bool {
+        self.status == 0
+    }
+
+    /// Name of the status code as given by the DFU specification, eg. "errVERIFY".
+    pub fn status_name(&self) -> &'static str {
+        match self.status {
+            0x00 => "OK",
+            0x01 => "errTARGET",
+            0x02 => "errFILE",
+            0x03 => "errWRITE",
+            0x04 => "errERASE",
+            0x05 => "errCHECK_ERASED",
+            0x06 => "errPROG",
+            0x07 => "errVERIFY",
+            0x08 => "errADDRESS",
+            0x09 => "errNOTDONE",
+            0x0a => "errFIRMWARE",
+            0x0b => "errVENDOR",
+            0x0c => "errUSBR",
+            0x0d => "errPOR",
+            0x0e => "errUNKNOWN",
+            0x0f => "errSTALLEDPKT",
+            _ => "[unknown]",
+        }
+    }
+
+    fn parse(d: &[u8]) -> Result<DfuStatus> {
+        let bad = || Error::new(ErrorKind::Other, "malformed DFU_GETSTATUS response");
+        if d.len() < 6 {
+            return Err(bad());
+        }
+        Ok(DfuStatus {
+            status: d[0],
+            poll_timeout: u32::from_le_bytes([d[1], d[2], d[3], 0]),
+            state: DfuState::from_u8(d[4]).ok_or_else(bad)?,
+            string_index: d[5],
+        })
+    }
+}
+
+// DFU class requests
+const DFU_DETACH: u8 = 0x00;
+const DFU_DNLOAD: u8 = 0x01;
+const DFU_UPLOAD: u8 = 0x02;
+const DFU_GETSTATUS: u8 = 0x03;
+const DFU_CLRSTATUS: u8 = 0x04;
+const DFU_GETSTATE: u8 = 0x05;
+const DFU_ABORT: u8 = 0x06;
+
+/// A claimed DFU interface.  Available with the `dfu` feature.
+///
+/// The individual DFU requests are available, but most tools need only `detach()` to switch
+/// an application into DFU mode, and `download()` and `upload()`, which move a whole firmware
+/// image while stepping the device through the DFU state machine.  Failures reported by the
+/// device through DFU_GETSTATUS give `ErrorKind::Other` naming the status code.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let file = std::fs::read("firmware.dfu").unwrap();
+/// let (suffix, firmware) = DfuSuffix::parse(&file).unwrap();
+///
+/// let info = deviceinfo_find(0xffff, 3).unwrap();
+/// assert!(suffix.matches(&info.device_descriptor().unwrap()));
+/// let device = Device::new(&info).unwrap();
+/// let dfu = Dfu::new(device.claim(0).unwrap()).unwrap();
+/// dfu.download(firmware).unwrap();
+/// ```
+pub struct Dfu<'a> {
+    intf: Interface<'a>,
+    functional: DfuFunctional,
+    timeout_ms: u32,
+}
+
+impl<'a> Dfu<'a> {
+    /// Wrap claimed interface `intf`.  Interfaces without a DFU functional descriptor give
+    /// `ErrorKind::InvalidParam`.
+    pub fn new(intf: Interface<'a>) -> Result<Dfu<'a>> {
+        let functional = intf.descriptor().dfu_functional_descriptor()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidParam, "not a DFU interface"))?;
+        Ok(Dfu { intf, functional, timeout_ms: 5000 })
+    }
+
+    pub fn interface(&self) -> &Interface<'a> {
+        &self.intf
+    }
+
+    pub fn functional_descriptor(&self) -> &DfuFunctional {
+        &self.functional
+    }
+
+    /// Timeout of each request; 0 waits forever.  Defaults to 5000ms, since devices may be
+    /// slow to answer while erasing.
+    pub fn set_timeout(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    /// Ask an application to switch to DFU mode.  Unless the device `will_detach()`, the host
+    /// must reset it within `wDetachTimeOut`, after which it enumerates as a DFU device.
+    pub fn detach(&self) -> Result<()> {
+        self.request(DFU_DETACH, self.functional.wDetachTimeOut)
+            .send(self.intf.device())
+            .map(|_| ())
+    }
+
+    /// Send block `block` of a download.  A zero length block ends the download.
+    pub fn dnload(&self, block: u16, data: &[u8]) -> Result<()> {
+        self.request(DFU_DNLOAD, block)
+            .data_out(data)
+            .send(self.intf.device())
+            .map(|_| ())
+    }
+
+    /// Fetch block `block` of an upload, of up to `length` bytes.  A short block ends the
+    /// upload.
+    pub fn upload_block(&self, block: u16, length: u16) -> Result<Vec<u8>> {
+        self.request(DFU_UPLOAD, block)
+            .data_in(length)
+            .send(self.intf.device())
+    }
+
+    pub fn get_status(&self) -> Result<DfuStatus> {
+        let data = self.request(DFU_GETSTATUS, 0)
+            .data_in(6)
+            .send(self.intf.device())?;
+        DfuStatus::parse(&data)
+    }
+
+    /// Leave the `DfuError` state.
+    pub fn clear_status(&self) -> Result<()> {
+        self.request(DFU_CLRSTATUS, 0)
+            .send(self.intf.device())
+            .map(|_| ())
+    }
+
+    /// The device's state, without the state transitions DFU_GETSTATUS causes.
+    pub fn get_state(&self) -> Result<DfuState> {
+        let data = self.request(DFU_GETSTATE, 0)
+            .data_in(1)
+            .send(self.intf.device())?;
+        data.first().and_then(|&s| DfuState::from_u8(s))
+            .ok_or_else(|| Error::new(ErrorKind::Other, "malformed DFU_GETSTATE response"))
+    }
+
+    /// Abandon a download or upload, returning to `DfuIdle`.
+    pub fn abort(&self) -> Result<()> {
+        self.request(DFU_ABORT, 0)
+            .send(self.intf.device())
+            .map(|_| ())
+    }
+
+    /// Bring the device to `DfuIdle` from wherever a previous session left it.  Devices still
+    /// running their application give `ErrorKind::InvalidParam`; `detach()` them first.
+    pub fn reset_state(&self) -> Result<()> {
+        match self.get_status()?.state {
+            DfuState::DfuIdle => return Ok(()),
+            DfuState::AppIdle | DfuState::AppDetach => {
+                return Err(Error::new(ErrorKind::InvalidParam, "device is not in DFU mode"));
+            }
+            DfuState::DfuError => self.clear_status()?,
+            _ => self.abort()?,
+        }
+        match self.get_state()? {
+            DfuState::DfuIdle => Ok(()),
+            _ => Err(Error::new(ErrorKind::Other, "DFU device did not return to dfuIDLE")),
+        }
+    }
+
+    /// Download `firmware` in blocks of `wTransferSize`, then have the device manifest it.
+    /// Devices that aren't `manifestation_tolerant()` stop answering when done and need to be
+    /// reset to run the new firmware.
+    pub fn download(&self, firmware: &[u8]) -> Result<()> {
+        let size = self.transfer_size()?;
+        self.reset_state()?;
+        let mut block: u16 = 0;
+        for chunk in firmware.chunks(size) {
+            self.dnload(block, chunk)?;
+            self.expect_state(self.poll()?, DfuState::DfuDnloadIdle)?;
+            block = block.wrapping_add(1);
+        }
+        self.dnload(block, &[])?;
+        match self.poll() {
+            Ok(status) if status.state == DfuState::DfuManifestWaitReset => Ok(()),
+            Ok(status) => self.expect_state(status, DfuState::DfuIdle),
+            // the device may reset itself as soon as manifestation ends
+            Err(ref err) if !self.functional.manifestation_tolerant()
+                && matches!(err.kind(), ErrorKind::Disconnected | ErrorKind::Stall | ErrorKind::Timeout) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Upload the device's firmware in blocks of `wTransferSize`.  `max_length` bounds the
+    /// upload for devices that don't end it with a short block.
+    pub fn upload(&self, max_length: usize) -> Result<Vec<u8>> {
+        let size = self.transfer_size()?;
+        self.reset_state()?;
+        let mut firmware = Vec::new();
+        let mut block: u16 = 0;
+        while firmware.len() < max_length {
+            let length = size.min(max_length - firmware.len());
+            let data = self.upload_block(block, length as u16)?;
+            let short = data.len() < length;
+            firmware.extend_from_slice(&data);
+            if short {
+                return Ok(firmware);
+            }
+            block = block.wrapping_add(1);
+        }
+        self.abort()?;
+        Ok(firmware)
+    }
+
+    fn transfer_size(&self) -> Result<usize> {
+        match self.functional.wTransferSize {
+            0 => Err(Error::new(ErrorKind::InvalidParam, "DFU descriptor gives a transfer size of 0")),
+            size => Ok(size as usize),
+        }
+    }
+
+    // Poll DFU_GETSTATUS until the device is no longer busy, honouring its poll timeout.
+    fn poll(&self) -> Result<DfuStatus> {
+        loop {
+            let status = self.get_status()?;
+            if !status.is_ok() {
+                return Err(Error::new(ErrorKind::Other, &format!("DFU device reports {}", status.status_name())));
+            }
+            match status.state {
+                DfuState::DfuDnloadSync | DfuState::DfuDnbusy | DfuState::DfuManifestSync | DfuState::DfuManifest => {
+                    thread::sleep(Duration::from_millis(status.poll_timeout as u64));
+                }
+                _ => return Ok(status),
+            }
+        }
+    }
+
+    fn expect_state(&self, status: DfuStatus, state: DfuState) -> Result<()> {
+        match status.state == state {
+            true => Ok(()),
+            false => Err(Error::new(ErrorKind::Other, &format!("unexpected DFU state {:?}", status.state))),
+        }
+    }
+
+    // A class request to the interface.
+    fn request(&self, request: u8, value: u16) -> ControlRequest {
+        ControlRequest::new()
+            .request_type(SetupType::Class)
+            .recipient(SetupRecipient::Interface)
+            .request(request)
+            .value(value)
+            .index(self.intf.number() as u16)
+            .timeout(self.timeout_ms)
+    }
+}
+
+/// Suffix of a DFU file, identifying the device the firmware is meant for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct DfuSuffix {
+    /// 0xffff matches any device.
+    pub bcdDevice: u16,
+    /// 0xffff matches any device.
+    pub idProduct: u16,
+    /// 0xffff matches any device.
+    pub idVendor: u16,
+    pub bcdDFU: u16,
+    pub bLength: u8,
+    pub dwCRC: u32,
+}
+
+impl DfuSuffix {
+    /// Split a DFU file into its suffix and the firmware it carries, verifying the suffix's
+    /// signature and CRC.  Files without a valid suffix give `ErrorKind::InvalidParam`.
+    pub fn parse(file: &[u8]) -> Result<(DfuSuffix, &[u8])> {
+        let bad = |msg| Err(Error::new(ErrorKind::InvalidParam, msg));
+        if file.len() < 16 {
+            return bad("file too short for a DFU suffix");
+        }
+        let s = &file[file.len() - 16..];
+        if &s[8..11] != b"UFD" {
+            return bad("no DFU suffix signature");
+        }
+        let suffix = DfuSuffix {
+            bcdDevice: le16(s, 0),
+            idProduct: le16(s, 2),
+            idVendor: le16(s, 4),
+            bcdDFU: le16(s, 6),
+            bLength: s[11],
+            dwCRC: u32::from_le_bytes([s[12], s[13], s[14], s[15]]),
+        };
+        if (suffix.bLength as usize) < 16 || suffix.bLength as usize > file.len() {
+            return bad("bad DFU suffix length");
+        }
+        if dfu_crc(&file[..file.len() - 4]) != suffix.dwCRC {
+            return bad("DFU file CRC mismatch");
+        }
+        Ok((suffix, &file[..file.len() - suffix.bLength as usize]))
+    }
+
+    /// Whether the firmware is meant for a device with descriptor `descriptor`.
+    pub fn matches(&self, descriptor: &DeviceDescriptor) -> bool {
+        let field = |suffix: u16, device: u16| suffix == 0xffff || suffix == device;
+        field(self.idVendor, descriptor.idVendor)
+            && field(self.idProduct, descriptor.idProduct)
+            && field(self.bcdDevice, descriptor.bcdDevice)
+    }
+}
+
+// CRC-32 as used by DFU suffixes: the usual reflected polynomial, without the final inversion.
+fn dfu_crc(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
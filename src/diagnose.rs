@@ -0,0 +1,166 @@
+use std::{error, fmt, fs};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use nix::unistd;
+
+use super::*;
+
+/// Why a device couldn't be opened, as worked out by `Device::open_diagnose()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OpenFailure {
+    /// There is no device node: the device is gone, or `/dev/bus/usb` isn't available, as in
+    /// containers that haven't been given it.
+    NoDeviceNode,
+    /// The permissions of the device node exclude this process.
+    Permissions {
+        /// Permission bits of the node.
+        mode: u32,
+        /// Owner of the node.
+        uid: u32,
+        /// Group of the node.
+        gid: u32,
+    },
+    /// The permissions of the device node allow access, but it was denied anyway, eg. by the
+    /// device cgroup of a container or by a security module.
+    Denied,
+    /// Anything else; see `OpenDiagnosis::error()`.
+    Other,
+}
+
+/// Explanation of a failure to open a device, returned by `Device::open_diagnose()`.
+///
+/// The `Display` implementation gives a message fit for users, including a udev rule to
+/// install when the node's permissions are the problem.  Converts into `Error`, so `?` works
+/// in functions returning `usbfs::Result`.
+#[derive(Debug)]
+pub struct OpenDiagnosis {
+    error: Error,
+    devnode: Option<PathBuf>,
+    cause: OpenFailure,
+    ids: Option<(u16, u16)>,
+}
+
+impl Device {
+    /// Open a device like `Device::new()`, but on failure work out why.  This is mostly
+    /// useful for permission problems, which are common on fresh systems where no udev rule
+    /// grants access to the device.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// let info = deviceinfo_find(0xffff, 3).unwrap();
+    /// let device = match Device::open_diagnose(&info) {
+    ///     Ok(device) => device,
+    ///     Err(diagnosis) => {
+    ///         eprintln!("{}", diagnosis);
+    ///         std::process::exit(1);
+    ///     }
+    /// };
+    /// ```
+    pub fn open_diagnose(info: &DeviceInfo) -> Result<Device, OpenDiagnosis> {
+        match Device::new(info) {
+            Ok(device) => Ok(device),
+            Err(error) => Err(OpenDiagnosis::new(info, error)),
+        }
+    }
+}
+
+impl OpenDiagnosis {
+    fn new(info: &DeviceInfo, error: Error) -> OpenDiagnosis {
+        let devnode = info.devnode_path().ok();
+        let cause = match devnode.as_ref().map(fs::metadata) {
+            None | Some(Err(_)) => OpenFailure::NoDeviceNode,
+            Some(Ok(ref md)) if error.kind() == ErrorKind::PermissionDenied => {
+                match may_read_write(md.mode(), md.uid(), md.gid()) {
+                    true => OpenFailure::Denied,
+                    false => OpenFailure::Permissions { mode: md.mode() & 0o7777, uid: md.uid(), gid: md.gid() },
+                }
+            }
+            Some(Ok(_)) => OpenFailure::Other,
+        };
+        let ids = info.device_descriptor().ok().map(|d| (d.idVendor, d.idProduct));
+        OpenDiagnosis { error, devnode, cause, ids }
+    }
+
+    /// The error `Device::new()` failed with.
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    /// The device node that couldn't be opened, or `None` if the device has left sysfs too.
+    pub fn devnode(&self) -> Option<&Path> {
+        self.devnode.as_deref()
+    }
+
+    pub fn cause(&self) -> OpenFailure {
+        self.cause
+    }
+
+    /// A udev rule granting access to devices with this device's idVendor and idProduct, to
+    /// be installed as eg. `/etc/udev/rules.d/70-mydevice.rules`.  The rule gives the user
+    /// logged in at the seat access and opens the node to its group.  `None` if the device
+    /// descriptor couldn't be read.
+    pub fn udev_rule(&self) -> Option<String> {
+        self.ids.map(|(vendor, product)| {
+            format!("SUBSYSTEM==\"usb\", ATTR{{idVendor}}==\"{:04x}\", ATTR{{idProduct}}==\"{:04x}\", MODE=\"0660\", TAG+=\"uaccess\"",
+                    vendor, product)
+        })
+    }
+}
+
+// Whether permission bits `mode` of a node owned by `uid`:`gid` let this process open it for
+// reading and writing.
+fn may_read_write(mode: u32, uid: u32, gid: u32) -> bool {
+    let euid = unistd::geteuid();
+    if euid.is_root() {
+        return true;
+    }
+    let in_group = unistd::getegid().as_raw() == gid
+        || unistd::getgroups().is_ok_and(|groups| groups.iter().any(|g| g.as_raw() == gid));
+    let bits = if euid.as_raw() == uid {
+        mode >> 6
+    } else if in_group {
+        mode >> 3
+    } else {
+        mode
+    };
+    bits & 0o6 == 0o6
+}
+
+impl fmt::Display for OpenDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let devnode = self.devnode.as_ref().map_or("<unknown>".into(), |p| p.display().to_string());
+        match self.cause {
+            OpenFailure::NoDeviceNode => {
+                write!(f, "{} does not exist; the device was unplugged, or /dev/bus/usb is not available here", devnode)
+            }
+            OpenFailure::Permissions { mode, uid, gid } => {
+                write!(f, "no permission to open {} (mode {:04o}, owner {}, group {}) as user {}",
+                       devnode, mode, uid, gid, unistd::geteuid())?;
+                match self.udev_rule() {
+                    Some(rule) => write!(f, "; run as root or install a udev rule such as:\n{}", rule),
+                    None => write!(f, "; run as root or install a udev rule for the device"),
+                }
+            }
+            OpenFailure::Denied => {
+                write!(f, "access to {} denied despite its permissions, eg. by a container or security policy: {}",
+                       devnode, self.error)
+            }
+            OpenFailure::Other => write!(f, "can't open {}: {}", devnode, self.error),
+        }
+    }
+}
+
+impl error::Error for OpenDiagnosis {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<OpenDiagnosis> for Error {
+    fn from(diagnosis: OpenDiagnosis) -> Error {
+        diagnosis.error
+    }
+}
@@ -0,0 +1,91 @@
+use std::any::Any;
+use std::fmt;
+
+use super::*;
+
+/// A `Transfer` of any type, for `AsyncDevice`s that mix transfer types.
+///
+/// `AsyncDevice<R>` holds a single transfer type `R`.  To queue, say, control and isochronous
+/// transfers on the same device, use `AsyncDevice<DynTransfer>` and box each transfer.  Reaped
+/// transfers are downcast back to their concrete type, or identified with `is()`.
+///
+/// `AnyTransfer` is implemented for every `'static + Send` transfer type.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let mut device: AsyncDevice<DynTransfer> =
+///     AsyncDevice::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+///
+/// device.submit(Box::new(StdBufTransfer::control(SetupDirection::DeviceToHost,
+///                                                SetupType::Vendor,
+///                                                SetupRecipient::Device,
+///                                                0, 0, 0,
+///                                                UrbFlags::empty(),
+///                                                vec![0u8; 8 + 64]))).unwrap();
+/// device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0u8; 512]))).unwrap();
+///
+/// for _ in 0..2 {
+///     let (_slot, xfer, result) = device.reap_wait().unwrap();
+///     match xfer.downcast::<BulkTransferMut<Vec<u8>>>() {
+///         Ok(bulk) => println!("bulk {:?}: {:?}", result, &bulk.buf[..]),
+///         Err(xfer) => {
+///             let control = xfer.downcast::<StdBufTransfer<Vec<u8>>>().unwrap();
+///             println!("control {:?}: {:?}", result, &control.buf[8..]);
+///         }
+///     }
+/// }
+/// ```
+pub trait AnyTransfer: Transfer + Send {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send>;
+}
+
+/// Boxed transfer of any type.  See `AnyTransfer`.
+pub type DynTransfer = Box<dyn AnyTransfer>;
+
+impl<T: Transfer + Send + 'static> AnyTransfer for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        self
+    }
+}
+
+impl dyn AnyTransfer {
+    /// `true` if the transfer is a `T`.
+    pub fn is<T: AnyTransfer + 'static>(&self) -> bool {
+        self.as_any().is::<T>()
+    }
+
+    pub fn downcast_ref<T: AnyTransfer + 'static>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    pub fn downcast_mut<T: AnyTransfer + 'static>(&mut self) -> Option<&mut T> {
+        self.as_any_mut().downcast_mut()
+    }
+
+    /// Recover the concrete transfer, or get the box back unchanged if it isn't a `T`.
+    pub fn downcast<T: AnyTransfer + 'static>(self: Box<Self>) -> Result<Box<T>, Box<dyn AnyTransfer>> {
+        if self.is::<T>() {
+            Ok(self.into_any().downcast().unwrap())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Debug for dyn AnyTransfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AnyTransfer { .. }")
+    }
+}
@@ -0,0 +1,92 @@
+use std::io::{self, Read, Write};
+
+use super::*;
+
+/// `std::io::Read` adapter for a bulk or interrupt IN endpoint.
+///
+/// Each `read()` performs one synchronous transfer with the `USBDEVFS_BULK` ioctl, which the
+/// kernel also accepts for interrupt endpoints.  A transfer that doesn't complete within the
+/// timeout fails with `io::ErrorKind::TimedOut`.
+///
+/// Reads end at packet boundaries, so a read may return fewer bytes than requested even though
+/// more data is on its way.  Wrap the reader in a `BufReader` or use `read_exact()` to consume
+/// a byte stream.
+///
+/// # Examples
+/// ```no_run
+/// use std::io::{BufRead, BufReader};
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// device.claim_interface(0).unwrap();
+/// let reader = BufReader::new(device.endpoint_reader(0x81, 1000));
+/// for line in reader.lines() {
+///     println!("{}", line.unwrap());
+/// }
+/// ```
+pub struct EndpointReader<'a> {
+    device: &'a Device,
+    endpoint: u8,
+    timeout_ms: u32,
+}
+
+/// `std::io::Write` adapter for a bulk or interrupt OUT endpoint.
+///
+/// Each `write()` performs one synchronous transfer with the `USBDEVFS_BULK` ioctl.  A transfer
+/// that doesn't complete within the timeout fails with `io::ErrorKind::TimedOut`.  `flush()`
+/// does nothing since writes are not buffered.
+pub struct EndpointWriter<'a> {
+    device: &'a Device,
+    endpoint: u8,
+    timeout_ms: u32,
+}
+
+impl Device {
+    /// Create a reader for IN `endpoint`.  The direction bit of `endpoint` is set automatically.
+    /// `timeout_ms` applies to each transfer; 0 waits forever.
+    pub fn endpoint_reader(&self, endpoint: u8, timeout_ms: u32) -> EndpointReader<'_> {
+        EndpointReader{device: self, endpoint: endpoint | 0x80, timeout_ms}
+    }
+
+    /// Create a writer for OUT `endpoint`.  The direction bit of `endpoint` is cleared
+    /// automatically.  `timeout_ms` applies to each transfer; 0 waits forever.
+    pub fn endpoint_writer(&self, endpoint: u8, timeout_ms: u32) -> EndpointWriter<'_> {
+        EndpointWriter{device: self, endpoint: endpoint & 0x7f, timeout_ms}
+    }
+}
+
+impl<'a> EndpointReader<'a> {
+    pub fn endpoint(&self) -> u8 {
+        self.endpoint
+    }
+
+    pub fn set_timeout(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+}
+
+impl<'a> EndpointWriter<'a> {
+    pub fn endpoint(&self) -> u8 {
+        self.endpoint
+    }
+
+    pub fn set_timeout(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+}
+
+impl<'a> Read for EndpointReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.device.bulk_transfer_in(self.endpoint, buf, self.timeout_ms).map(|n| n as usize).map_err(io::Error::from)
+    }
+}
+
+impl<'a> Write for EndpointWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.device.bulk_transfer_out(self.endpoint, buf, self.timeout_ms).map(|n| n as usize).map_err(io::Error::from)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
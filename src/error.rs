@@ -0,0 +1,156 @@
+use std::{error, fmt, io, result};
+
+use libc;
+use nix;
+
+/// Result type of usbfs operations.
+pub type Result<T, E = Error> = result::Result<T, E>;
+
+/// USB-level classification of an `Error`.
+///
+/// Different failures call for different recovery: a stalled endpoint can be cleared and the
+/// request retried, while a disconnected device has to be reopened once it comes back.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The device has been unplugged or otherwise disconnected (`ENODEV`, `ESHUTDOWN`).  The
+    /// `Device` is useless from now on; reopen the device once it comes back.
+    Disconnected,
+    /// The interface or device is claimed by someone else (`EBUSY`).
+    Busy,
+    /// Insufficient permissions to open or use the device (`EACCES`, `EPERM`).
+    PermissionDenied,
+    /// The endpoint stalled, or the device rejected a control request (`EPIPE`).
+    Stall,
+    /// The device sent more data than requested (`EOVERFLOW`).
+    Overflow,
+    /// The operation timed out (`ETIMEDOUT`).
+    Timeout,
+    /// An argument was rejected, eg. a nonexistent endpoint or interface (`EINVAL`).
+    InvalidParam,
+    /// The device, file, or transfer was not found (`ENOENT`).
+    NotFound,
+    /// Nothing is ready yet; try again later (`EAGAIN`).
+    WouldBlock,
+    /// Interrupted by a signal (`EINTR`).
+    Interrupted,
+    /// Not supported by the kernel or host controller (`ENOTTY`, `ENOSYS`).
+    Unsupported,
+    /// Anything else.
+    Other,
+}
+
+/// Error type of usbfs operations.
+///
+/// Wraps the underlying `io::Error`, which keeps the original errno available through
+/// `raw_os_error()`, and classifies it into an `ErrorKind`.  Converts to and from `io::Error`,
+/// so `?` works in either direction.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// match device.claim_interface(0) {
+///     Ok(()) => (),
+///     Err(ref err) if err.kind() == ErrorKind::Busy => println!("interface in use"),
+///     Err(err) => panic!("claim failed: {}", err),
+/// }
+/// ```
+pub struct Error {
+    kind: ErrorKind,
+    inner: io::Error,
+}
+
+impl Error {
+    /// Create an error that didn't come from the OS.
+    pub fn new(kind: ErrorKind, msg: &str) -> Error {
+        let io_kind = match kind {
+            ErrorKind::PermissionDenied => io::ErrorKind::PermissionDenied,
+            ErrorKind::Timeout => io::ErrorKind::TimedOut,
+            ErrorKind::InvalidParam => io::ErrorKind::InvalidInput,
+            ErrorKind::NotFound => io::ErrorKind::NotFound,
+            ErrorKind::WouldBlock => io::ErrorKind::WouldBlock,
+            ErrorKind::Interrupted => io::ErrorKind::Interrupted,
+            ErrorKind::Unsupported => io::ErrorKind::Unsupported,
+            _ => io::ErrorKind::Other,
+        };
+        Error{kind, inner: io::Error::new(io_kind, msg)}
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The original errno, if the error came from the OS.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.inner.raw_os_error()
+    }
+
+    /// The underlying `io::Error`.
+    pub fn io_error(&self) -> &io::Error {
+        &self.inner
+    }
+}
+
+fn kind_of(err: &io::Error) -> ErrorKind {
+    match err.raw_os_error() {
+        Some(libc::ENODEV) | Some(libc::ESHUTDOWN) => ErrorKind::Disconnected,
+        Some(libc::EBUSY) => ErrorKind::Busy,
+        Some(libc::EACCES) | Some(libc::EPERM) => ErrorKind::PermissionDenied,
+        Some(libc::EPIPE) => ErrorKind::Stall,
+        Some(libc::EOVERFLOW) => ErrorKind::Overflow,
+        Some(libc::ETIMEDOUT) => ErrorKind::Timeout,
+        Some(libc::EINVAL) => ErrorKind::InvalidParam,
+        Some(libc::ENOENT) => ErrorKind::NotFound,
+        Some(libc::EAGAIN) => ErrorKind::WouldBlock,
+        Some(libc::EINTR) => ErrorKind::Interrupted,
+        Some(libc::ENOTTY) | Some(libc::ENOSYS) => ErrorKind::Unsupported,
+        Some(_) => ErrorKind::Other,
+        None => match err.kind() {
+            io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            io::ErrorKind::InvalidInput => ErrorKind::InvalidParam,
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            io::ErrorKind::WouldBlock => ErrorKind::WouldBlock,
+            io::ErrorKind::Interrupted => ErrorKind::Interrupted,
+            io::ErrorKind::Unsupported => ErrorKind::Unsupported,
+            _ => ErrorKind::Other,
+        },
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error{kind: kind_of(&err), inner: err}
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(err: nix::Error) -> Error {
+        Error::from(io::Error::from(err))
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        err.inner
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Error").field("kind", &self.kind).field("inner", &self.inner).finish()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.inner.source()
+    }
+}
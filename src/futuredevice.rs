@@ -0,0 +1,207 @@
+use std::io;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+use async_io::Async;
+
+use super::*;
+
+/// `std::future` adapter for `AsyncDevice`.
+///
+/// `FutureDevice` submits transfers and returns futures that resolve once the transfer has been
+/// reaped.  The usbfs file descriptor is registered with the [`async-io`](https://docs.rs/async-io)
+/// reactor, so the futures can be awaited from any executor.  Many transfers may be in flight at
+/// once; completions are reaped by whichever future is polled and handed to their owners.
+///
+/// This adapter is available with the `async` feature.
+///
+/// # Examples
+/// ```no_run,edition2018
+/// # use usbfs::*;
+/// # async fn example() -> std::io::Result<()> {
+/// let device = FutureDevice::new(AsyncDevice::new(&deviceinfo_find(0xffff, 3).unwrap())?)?;
+/// let xfer = Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0u8; 512]));
+/// let (xfer, result) = device.submit_async(xfer).await?;
+/// if let TransferResult::Completed{len} = result {
+///     println!("{:?}", &xfer.buf[..len]);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct FutureDevice<R> {
+    reactor: Async<OwnedFd>,  // duplicate of the device descriptor, registered with async-io
+    shared: Mutex<Shared<R>>,
+}
+
+// Futures are identified by tickets rather than slots, since a slot can be reused as soon as
+// its transfer is reaped, possibly before the future has collected the result.
+struct Shared<R> {
+    device: AsyncDevice<R>,
+    next_ticket: u64,
+    tickets: HashMap<SlotId, u64>,  // in-flight slot -> ticket
+    completed: HashMap<u64, (R, TransferResult)>,
+    wakers: HashMap<u64, Waker>,
+    abandoned: HashSet<u64>,  // futures dropped before completion
+    driver: Option<u64>,      // future currently registered with the reactor
+}
+
+impl<R> FutureDevice<R>
+    where R: StableDeref,
+          R::Target: Transfer
+{
+    /// Wrap `device` for use with futures.
+    pub fn new(device: AsyncDevice<R>) -> io::Result<Self> {
+        let fd = devfs::nix_result_to_io_result(nix::unistd::dup(device.as_raw_fd()))?;
+        let reactor = Async::new(unsafe { OwnedFd::from_raw_fd(fd) })?;
+        Ok(FutureDevice {
+            reactor,
+            shared: Mutex::new(Shared {
+                device,
+                next_ticket: 0,
+                tickets: HashMap::new(),
+                completed: HashMap::new(),
+                wakers: HashMap::new(),
+                abandoned: HashSet::new(),
+                driver: None,
+            }),
+        })
+    }
+
+    /// Submit a transfer and return a future that resolves to the transfer and its result once
+    /// it has completed.
+    ///
+    /// The transfer is submitted immediately, before the future is first polled.  If the future
+    /// is dropped before completion, the transfer is dropped when it is eventually reaped.
+    pub fn submit_async(&self, transfer: R) -> Completion<'_, R> {
+        let mut shared = self.shared.lock().unwrap();
+        let ticket = shared.device.submit(transfer).map(|slot| {
+            let ticket = shared.next_ticket;
+            shared.next_ticket += 1;
+            shared.tickets.insert(slot, ticket);
+            ticket
+        }).map_err(io::Error::from);
+        Completion{device: self, ticket: Some(ticket)}
+    }
+
+    /// Recover the wrapped `AsyncDevice`.  Transfers still in flight stay in it and are returned
+    /// by its `reap_*()` methods.
+    pub fn into_inner(self) -> AsyncDevice<R> {
+        self.shared.into_inner().unwrap().device
+    }
+
+    fn poll_ticket(&self, ticket: u64, cx: &mut Context) -> Poll<io::Result<(R, TransferResult)>> {
+        loop {
+            let mut shared = self.shared.lock().unwrap();
+            shared.reap_available()?;
+
+            if let Some(done) = shared.completed.remove(&ticket) {
+                shared.release(ticket);
+                return Poll::Ready(Ok(done));
+            }
+
+            shared.wakers.insert(ticket, cx.waker().clone());
+            match shared.driver {
+                Some(driver) if driver != ticket => return Poll::Pending,
+                _ => shared.driver = Some(ticket),
+            }
+            drop(shared);
+
+            match self.reactor.poll_writable(cx) {
+                Poll::Ready(Ok(())) => continue,  // completions may be available
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<R> Shared<R>
+    where R: StableDeref,
+          R::Target: Transfer
+{
+    // Reap everything that has completed and wake the owners.
+    fn reap_available(&mut self) -> io::Result<()> {
+        loop {
+            match self.device.reap_nowait() {
+                Ok((slot, transfer, result)) => {
+                    let ticket = match self.tickets.remove(&slot) {
+                        Some(ticket) => ticket,
+                        None => continue,  // submitted before the device was wrapped
+                    };
+                    if self.abandoned.remove(&ticket) {
+                        continue;
+                    }
+                    self.completed.insert(ticket, (transfer, result));
+                    if let Some(waker) = self.wakers.remove(&ticket) {
+                        waker.wake();
+                    }
+                    // its future may not be polled again for a while, eg. after losing a
+                    // `select!`, so another has to take over the reactor
+                    self.release(ticket);
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => {
+                    // everybody is going to see this error
+                    self.wakers.drain().for_each(|(_, w)| w.wake());
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+}
+
+impl<R> Shared<R> {
+    // Forget about `ticket`.  If its future was registered with the reactor, hand that job to
+    // the remaining futures.
+    fn release(&mut self, ticket: u64) {
+        self.wakers.remove(&ticket);
+        if self.driver == Some(ticket) {
+            self.driver = None;
+            self.wakers.values().for_each(|w| w.wake_by_ref());
+        }
+    }
+}
+
+/// Future returned by `FutureDevice::submit_async()`.
+pub struct Completion<'a, R: 'a> {
+    device: &'a FutureDevice<R>,
+    ticket: Option<io::Result<u64>>,
+}
+
+impl<'a, R> Future for Completion<'a, R>
+    where R: StableDeref,
+          R::Target: Transfer
+{
+    type Output = io::Result<(R, TransferResult)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let ticket = match this.ticket.take() {
+            Some(Ok(ticket)) => ticket,
+            Some(Err(err)) => return Poll::Ready(Err(err)),
+            None => panic!("Completion polled after completion"),
+        };
+        let poll = this.device.poll_ticket(ticket, cx);
+        if poll.is_pending() {
+            this.ticket = Some(Ok(ticket));
+        }
+        poll
+    }
+}
+
+impl<'a, R> Drop for Completion<'a, R> {
+    fn drop(&mut self) {
+        if let Some(Ok(ticket)) = self.ticket {
+            let mut shared = self.device.shared.lock().unwrap();
+            // drop the transfer now if it already completed, otherwise when it is reaped
+            if shared.completed.remove(&ticket).is_none() {
+                shared.abandoned.insert(ticket);
+            }
+            shared.release(ticket);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use super::*;
+
+/// Report type of HID GET_REPORT and SET_REPORT requests.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HidReportType {
+    Input = 1,
+    Output = 2,
+    Feature = 3,
+}
+
+/// Protocol of HID boot interfaces, selected with `HidInterface::set_protocol()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HidProtocol {
+    /// The simplified, fixed report format of boot keyboards and mice.
+    Boot = 0,
+    /// The format given by the report descriptor.
+    Report = 1,
+}
+
+// HID class requests
+const GET_REPORT: u8 = 0x01;
+const GET_IDLE: u8 = 0x02;
+const GET_PROTOCOL: u8 = 0x03;
+const SET_REPORT: u8 = 0x09;
+const SET_IDLE: u8 = 0x0a;
+const SET_PROTOCOL: u8 = 0x0b;
+
+/// A claimed HID interface, with helpers for the HID class requests.  Available with the
+/// `hid` feature.
+///
+/// Reports sent by the device on its interrupt IN endpoint are read with `input_stream()`;
+/// reports can also be requested and sent over the control pipe with `get_report()` and
+/// `set_report()`.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let hid = HidInterface::new(device.claim(0).unwrap()).unwrap();
+/// let report_descriptor = hid.report_descriptor(1000).unwrap();
+/// hid.set_idle(0, 0, 1000).unwrap();
+/// for report in hid.input_stream(4).unwrap() {
+///     println!("{:?}", report.unwrap());
+/// }
+/// ```
+pub struct HidInterface<'a> {
+    intf: Interface<'a>,
+    descriptor: HidDescriptor,
+}
+
+impl<'a> HidInterface<'a> {
+    /// Wrap claimed interface `intf`.  Interfaces without a HID descriptor in their current
+    /// alternate setting give `ErrorKind::InvalidParam`.
+    pub fn new(intf: Interface<'a>) -> Result<HidInterface<'a>> {
+        let descriptor = intf.descriptor().hid_descriptor()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidParam, "not a HID interface"))?;
+        Ok(HidInterface { intf, descriptor })
+    }
+
+    pub fn interface(&self) -> &Interface<'a> {
+        &self.intf
+    }
+
+    /// Release the interface from the HID helpers.
+    pub fn into_inner(self) -> Interface<'a> {
+        self.intf
+    }
+
+    pub fn hid_descriptor(&self) -> &HidDescriptor {
+        &self.descriptor
+    }
+
+    /// Fetch the report descriptor, which describes the layout of the interface's reports.
+    pub fn report_descriptor(&self, timeout_ms: u32) -> Result<Vec<u8>> {
+        let length = self.descriptor.report_descriptor_length()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "HID descriptor lists no report descriptor"))?;
+        self.intf.device().get_hid_report_descriptor(self.intf.number() as u16, length, timeout_ms)
+    }
+
+    /// Request a report of up to `length` bytes with GET_REPORT.  `report_id` is 0 for
+    /// interfaces that don't use report IDs.
+    pub fn get_report(&self, report_type: HidReportType, report_id: u8, length: u16, timeout_ms: u32) -> Result<Vec<u8>> {
+        self.request(GET_REPORT, (report_type as u16) << 8 | report_id as u16, timeout_ms)
+            .data_in(length)
+            .send(self.intf.device())
+    }
+
+    /// Send a report with SET_REPORT.  Where report IDs are used, `data` starts with the ID
+    /// as well.
+    pub fn set_report(&self, report_type: HidReportType, report_id: u8, data: &[u8], timeout_ms: u32) -> Result<()> {
+        self.request(SET_REPORT, (report_type as u16) << 8 | report_id as u16, timeout_ms)
+            .data_out(data)
+            .send(self.intf.device())
+            .map(|_| ())
+    }
+
+    /// Idle rate of input report `report_id`, in units of 4ms; 0 means reports are only sent
+    /// when they change.
+    pub fn get_idle(&self, report_id: u8, timeout_ms: u32) -> Result<u8> {
+        let data = self.request(GET_IDLE, report_id as u16, timeout_ms)
+            .data_in(1)
+            .send(self.intf.device())?;
+        data.first().copied().ok_or_else(|| Error::new(ErrorKind::Other, "short GET_IDLE response"))
+    }
+
+    /// Set the idle rate of input report `report_id`, or of all reports if it is 0, in units
+    /// of 4ms.  A `duration` of 0 silences the endpoint until a report changes, which is what
+    /// most hosts want.
+    pub fn set_idle(&self, duration: u8, report_id: u8, timeout_ms: u32) -> Result<()> {
+        self.request(SET_IDLE, (duration as u16) << 8 | report_id as u16, timeout_ms)
+            .send(self.intf.device())
+            .map(|_| ())
+    }
+
+    /// Protocol in use by a boot interface.
+    pub fn get_protocol(&self, timeout_ms: u32) -> Result<HidProtocol> {
+        let data = self.request(GET_PROTOCOL, 0, timeout_ms)
+            .data_in(1)
+            .send(self.intf.device())?;
+        match data.first() {
+            Some(0) => Ok(HidProtocol::Boot),
+            Some(_) => Ok(HidProtocol::Report),
+            None => Err(Error::new(ErrorKind::Other, "short GET_PROTOCOL response")),
+        }
+    }
+
+    /// Switch a boot interface between the boot and report protocols.
+    pub fn set_protocol(&self, protocol: HidProtocol, timeout_ms: u32) -> Result<()> {
+        self.request(SET_PROTOCOL, protocol as u16, timeout_ms)
+            .send(self.intf.device())
+            .map(|_| ())
+    }
+
+    /// Start reading input reports from the interrupt IN endpoint, with `depth` transfers
+    /// queued.  The stream works on its own handle on the device, so it can outlive this
+    /// `HidInterface`, but the interface must stay claimed while it is used.
+    ///
+    /// The handle is a duplicate of the device's file descriptor, and shares the file's
+    /// completion queue with every other handle on it.  The device must be otherwise idle
+    /// while the stream runs: any transfer in flight elsewhere on the file, including on
+    /// another stream, could be reaped by the wrong side, which then fails with
+    /// `ErrorKind::Other`.  `InterruptStream::new()` takes a handle of the caller's choosing
+    /// instead.
+    pub fn input_stream(&self, depth: usize) -> Result<InterruptStream> {
+        let ep = self.intf.endpoints()
+            .find(|ep| ep.transfer_type() == UrbType::Interrupt && ep.is_in())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no interrupt IN endpoint"))?;
+        InterruptStream::new(self.intf.device().try_clone()?, ep.bEndpointAddress, ep.max_packet_size(), depth)
+    }
+
+    // A class request to the interface.
+    fn request(&self, request: u8, value: u16, timeout_ms: u32) -> ControlRequest {
+        ControlRequest::new()
+            .request_type(SetupType::Class)
+            .recipient(SetupRecipient::Interface)
+            .request(request)
+            .value(value)
+            .index(self.intf.number() as u16)
+            .timeout(timeout_ms)
+    }
+}
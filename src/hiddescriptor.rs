@@ -0,0 +1,84 @@
+use super::*;
+use descriptors::le16;
+
+/// HID class descriptor (type `0x21`), found among the `extra` descriptors of HID interfaces.
+///
+/// Available with the `hid` feature.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let config = &deviceinfo_find(0xffff, 3).unwrap().configurations().unwrap()[0];
+/// for intf in config.interfaces.iter() {
+///     if let Some(hid) = intf.hid_descriptor() {
+///         println!("interface {}: report descriptor is {:?} bytes",
+///                  intf.bInterfaceNumber, hid.report_descriptor_length());
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct HidDescriptor {
+    pub bcdHID: u16,
+    pub bCountryCode: u8,
+    /// `(bDescriptorType, wDescriptorLength)` of each class descriptor the interface provides,
+    /// starting with its report descriptor.
+    pub descriptors: Vec<(u8, u16)>,
+}
+
+/// Descriptor type of the HID descriptor.
+pub const HID_DESCRIPTOR_TYPE: u8 = 0x21;
+/// Descriptor type of HID report descriptors.
+pub const HID_REPORT_DESCRIPTOR_TYPE: u8 = 0x22;
+
+impl HidDescriptor {
+    /// Parse a raw HID descriptor.  `None` if `raw` is not a well formed HID descriptor.
+    pub fn parse(raw: &RawDescriptor) -> Option<HidDescriptor> {
+        let d = &raw.0;
+        if d.len() < 6 || d[1] != HID_DESCRIPTOR_TYPE {
+            return None;
+        }
+        let count = d[5] as usize;
+        if d.len() < 6 + 3 * count {
+            return None;
+        }
+        Some(HidDescriptor {
+            bcdHID: le16(d, 2),
+            bCountryCode: d[4],
+            descriptors: (0..count).map(|i| (d[6 + 3 * i], le16(d, 7 + 3 * i))).collect(),
+        })
+    }
+
+    /// Length of the report descriptor, which is needed to fetch it with
+    /// `Device::get_hid_report_descriptor()`.
+    pub fn report_descriptor_length(&self) -> Option<u16> {
+        self.descriptors.iter()
+            .find(|&&(t, _)| t == HID_REPORT_DESCRIPTOR_TYPE)
+            .map(|&(_, len)| len)
+    }
+}
+
+impl InterfaceDescriptor {
+    /// The HID descriptor of a HID interface.  Available with the `hid` feature.
+    pub fn hid_descriptor(&self) -> Option<HidDescriptor> {
+        self.extra.iter().find_map(HidDescriptor::parse)
+    }
+}
+
+impl Device {
+    /// Read the report descriptor of HID interface `interface`.  `length` is given by
+    /// `HidDescriptor::report_descriptor_length()`.  Available with the `hid` feature.
+    pub fn get_hid_report_descriptor(&self, interface: u16, length: u16, timeout_ms: u32) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; length as usize];
+        let len = self.control_transfer_in(SetupType::Standard,
+                                           SetupRecipient::Interface,
+                                           StandardRequest::GetDescriptor as u8,
+                                           (HID_REPORT_DESCRIPTOR_TYPE as u16) << 8,
+                                           interface,
+                                           Some(&mut buf),
+                                           timeout_ms)?;
+        buf.truncate(len as usize);
+        Ok(buf)
+    }
+}
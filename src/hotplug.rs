@@ -0,0 +1,348 @@
+use std::{io, fs};
+use std::io::Read;
+use std::collections::{HashMap, VecDeque};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::socket::{self, AddressFamily, NetlinkAddr, SockFlag, SockProtocol, SockType};
+use nix::sys::inotify::{Inotify, InitFlags, AddWatchFlags, WatchDescriptor};
+
+use super::*;
+
+/// Kind of change reported by a `HotplugEvent`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HotplugAction {
+    /// Device arrived.
+    Add,
+    /// Device left.
+    Remove,
+    /// A driver was bound to the device.
+    Bind,
+    /// A driver was unbound from the device.
+    Unbind,
+}
+
+/// A USB device arrival, departure, or driver change.
+#[derive(Debug, Clone)]
+pub struct HotplugEvent {
+    pub action: HotplugAction,
+    pub busnum: u32,
+    pub devnum: u32,
+    /// `idVendor` of the device, if known to the monitor backend.
+    pub vendor: Option<u16>,
+    /// `idProduct` of the device, if known to the monitor backend.
+    pub product: Option<u16>,
+    /// Name of the device's sysfs directory (eg. `"1-1.4"`), if known to the monitor backend.
+    pub sysname: Option<String>,
+}
+
+impl HotplugEvent {
+    /// `DeviceInfo` for the device this event refers to.  Not available for backends that don't
+    /// report the sysfs name, and of little use after a `Remove` event.
+    pub fn device_info(&self) -> Option<DeviceInfo> {
+        self.sysname.as_ref().map(|name| DeviceInfo::from_dirname(name))
+    }
+}
+
+// multicast group of kernel (as opposed to udev) uevents
+const UEVENT_GROUP_KERNEL: u32 = 1;
+
+const UEVENT_BUFFER_SIZE: usize = 8192;
+
+const DEV_BUS_USB_PATH: &str = "/dev/bus/usb";
+
+/// Monitor USB device hotplug events.
+///
+/// `HotplugMonitor` reports `HotplugEvent`s for USB devices using one of two backends:
+/// * The kernel's uevent netlink socket.  This reports all `HotplugAction`s along with the
+///   device's vendor, product, and sysfs name.
+/// * An inotify watch on `/dev/bus/usb`, for systems such as containers where uevents are not
+///   delivered.  This only reports `Add` and `Remove`, and no sysfs name.  Vendor and product
+///   are read from the device node when permissions allow.
+///
+/// It implements `AsRawFd` so that it can share an external poll loop with `AsyncDevice`; the
+/// file descriptor becomes *readable* when events are pending.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let mut monitor = HotplugMonitor::new().unwrap().filter(0xffff, 3);
+/// loop {
+///     let event = monitor.event_wait().unwrap();
+///     println!("{:?}", event);
+/// }
+/// ```
+pub struct HotplugMonitor {
+    source: MonitorSource,
+    filter: Option<(u16, u16)>,
+}
+
+enum MonitorSource {
+    Netlink(OwnedFd),
+    Inotify(InotifyWatcher),
+}
+
+impl AsRawFd for HotplugMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        match self.source {
+            MonitorSource::Netlink(ref fd) => fd.as_raw_fd(),
+            MonitorSource::Inotify(ref watcher) => watcher.fd.as_raw_fd(),
+        }
+    }
+}
+
+impl HotplugMonitor {
+    /// Open a monitor using the netlink backend, falling back to inotify if netlink is
+    /// unavailable.
+    pub fn new() -> io::Result<HotplugMonitor> {
+        Self::new_netlink().or_else(|_| Self::new_inotify())
+    }
+
+    /// Open a monitor using a uevent netlink socket.
+    pub fn new_netlink() -> io::Result<HotplugMonitor> {
+        let fd = devfs::nix_result_to_io_result(socket::socket(AddressFamily::Netlink,
+                                                               SockType::Datagram,
+                                                               SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+                                                               SockProtocol::NetlinkKObjectUEvent))?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let addr = NetlinkAddr::new(0, UEVENT_GROUP_KERNEL);
+        devfs::nix_result_to_io_result(socket::bind(fd.as_raw_fd(), &addr))?;
+        Ok(HotplugMonitor{source: MonitorSource::Netlink(fd), filter: None})
+    }
+
+    /// Open a monitor using inotify on `/dev/bus/usb`.
+    pub fn new_inotify() -> io::Result<HotplugMonitor> {
+        Ok(HotplugMonitor{source: MonitorSource::Inotify(InotifyWatcher::new()?), filter: None})
+    }
+
+    /// Only report events for devices with the given `idVendor` and `idProduct`.
+    pub fn filter(mut self, vendor: u16, product: u16) -> HotplugMonitor {
+        self.filter = Some((vendor, product));
+        self
+    }
+
+    /// Collect the next pending event.
+    ///
+    /// If no event is pending the error kind will be `io::ErrorKind::WouldBlock`.
+    pub fn event_nowait(&mut self) -> io::Result<HotplugEvent> {
+        loop {
+            let event = match self.source {
+                MonitorSource::Netlink(ref fd) => netlink_event(fd.as_raw_fd())?,
+                MonitorSource::Inotify(ref mut watcher) => watcher.event()?,
+            };
+            if let Some(event) = event {
+                if self.accepts(&event) {
+                    return Ok(event);
+                }
+            }
+        }
+    }
+
+    /// Wait for the next event.
+    pub fn event_wait(&mut self) -> io::Result<HotplugEvent> {
+        loop {
+            match self.event_nowait() {
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    let mut fds = [PollFd::new(self.as_raw_fd(), PollFlags::POLLIN)];
+                    devfs::nix_result_to_io_result(poll(&mut fds, -1))?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn accepts(&self, event: &HotplugEvent) -> bool {
+        match self.filter {
+            Some((vendor, product)) => event.vendor == Some(vendor) && event.product == Some(product),
+            None => true,
+        }
+    }
+}
+
+// Receive one uevent.  Returns None for messages that aren't about USB devices.
+fn netlink_event(fd: RawFd) -> io::Result<Option<HotplugEvent>> {
+    let mut buf = [0u8; UEVENT_BUFFER_SIZE];
+    let (len, addr) = devfs::nix_result_to_io_result(socket::recvfrom::<NetlinkAddr>(fd, &mut buf))?;
+
+    // only trust messages from the kernel
+    if addr.map(|a| a.pid()) != Some(0) {
+        return Ok(None);
+    }
+    Ok(HotplugEvent::parse_uevent(&buf[..len]))
+}
+
+impl HotplugEvent {
+    /// Parse a kernel uevent message of the form `"action@devpath\0KEY=value\0KEY=value\0..."`,
+    /// as received on a `NETLINK_KOBJECT_UEVENT` socket.  `None` for anything other than a USB
+    /// device.
+    pub fn parse_uevent(msg: &[u8]) -> Option<HotplugEvent> {
+        let mut action = None;
+        let mut subsystem = None;
+        let mut devtype = None;
+        let mut devpath = None;
+        let mut product = None;
+        let mut busnum = None;
+        let mut devnum = None;
+
+        for field in msg.split(|&c| c == 0).skip(1) {
+            let field = match std::str::from_utf8(field) {
+                Ok(f) => f,
+                Err(_) => continue,
+            };
+            let (key, value) = match field.find('=') {
+                Some(i) => (&field[..i], &field[i + 1..]),
+                None => continue,
+            };
+            match key {
+                "ACTION" => action = Some(value),
+                "SUBSYSTEM" => subsystem = Some(value),
+                "DEVTYPE" => devtype = Some(value),
+                "DEVPATH" => devpath = Some(value),
+                "PRODUCT" => product = Some(value),
+                "BUSNUM" => busnum = value.parse().ok(),
+                "DEVNUM" => devnum = value.parse().ok(),
+                _ => (),
+            }
+        }
+
+        if subsystem != Some("usb") || devtype != Some("usb_device") {
+            return None;
+        }
+
+        let action = match action? {
+            "add" => HotplugAction::Add,
+            "remove" => HotplugAction::Remove,
+            "bind" => HotplugAction::Bind,
+            "unbind" => HotplugAction::Unbind,
+            _ => return None,
+        };
+
+        // PRODUCT is "idVendor/idProduct/bcdDevice" in hex
+        let mut ids = product.unwrap_or("").split('/').map(|x| u16::from_str_radix(x, 16).ok());
+        let vendor = ids.next().and_then(|x| x);
+        let product = ids.next().and_then(|x| x);
+
+        Some(HotplugEvent {
+            action,
+            busnum: busnum?,
+            devnum: devnum?,
+            vendor,
+            product,
+            sysname: devpath.and_then(|p| p.rsplit('/').next()).map(|x| x.to_string()),
+        })
+    }
+}
+
+// Watches /dev/bus/usb for bus directories, and each bus directory for device nodes.
+struct InotifyWatcher {
+    inotify: Inotify,
+    fd: OwnedFd, // owns the inotify descriptor, which nix's Inotify does not close
+    root: WatchDescriptor,
+    buses: HashMap<WatchDescriptor, u32>,
+    ids: HashMap<(u32, u32), (u16, u16)>, // vendor/product of known devices, for Remove events
+    pending: VecDeque<HotplugEvent>,
+}
+
+impl InotifyWatcher {
+    fn new() -> io::Result<InotifyWatcher> {
+        let inotify = devfs::nix_result_to_io_result(
+            Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC))?;
+        let fd = unsafe { OwnedFd::from_raw_fd(inotify.as_raw_fd()) };
+        let root = devfs::nix_result_to_io_result(
+            inotify.add_watch(DEV_BUS_USB_PATH, AddWatchFlags::IN_CREATE | AddWatchFlags::IN_DELETE))?;
+
+        let mut watcher = InotifyWatcher {
+            inotify,
+            fd,
+            root,
+            buses: HashMap::new(),
+            ids: HashMap::new(),
+            pending: VecDeque::new(),
+        };
+
+        // watch existing buses and learn the ids of devices already present
+        for entry in fs::read_dir(DEV_BUS_USB_PATH)?.filter_map(|x| x.ok()) {
+            if let Some(busnum) = parse_node_name(&entry.file_name()) {
+                watcher.watch_bus(busnum)?;
+                for dev in fs::read_dir(entry.path())?.filter_map(|x| x.ok()) {
+                    if let Some(devnum) = parse_node_name(&dev.file_name()) {
+                        if let Some(ids) = read_node_ids(busnum, devnum) {
+                            watcher.ids.insert((busnum, devnum), ids);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(watcher)
+    }
+
+    fn watch_bus(&mut self, busnum: u32) -> io::Result<()> {
+        let path = format!("{}/{:03}", DEV_BUS_USB_PATH, busnum);
+        let wd = devfs::nix_result_to_io_result(
+            self.inotify.add_watch(path.as_str(), AddWatchFlags::IN_CREATE | AddWatchFlags::IN_DELETE))?;
+        self.buses.insert(wd, busnum);
+        Ok(())
+    }
+
+    // Produce the next event, or None if an inotify event didn't translate into one.
+    fn event(&mut self) -> io::Result<Option<HotplugEvent>> {
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(Some(event));
+        }
+
+        for ievent in devfs::nix_result_to_io_result(self.inotify.read_events())? {
+            let num = match ievent.name.as_ref().and_then(parse_node_name) {
+                Some(num) => num,
+                None => continue,
+            };
+            let created = ievent.mask.contains(AddWatchFlags::IN_CREATE);
+
+            if ievent.wd == self.root {
+                if created {
+                    self.watch_bus(num)?;
+                }
+                continue;
+            }
+
+            let busnum = match self.buses.get(&ievent.wd) {
+                Some(&busnum) => busnum,
+                None => continue,
+            };
+            let (action, ids) = if created {
+                // node permissions may not be settled yet, in which case ids are unknown
+                let ids = read_node_ids(busnum, num);
+                if let Some(ids) = ids {
+                    self.ids.insert((busnum, num), ids);
+                }
+                (HotplugAction::Add, ids)
+            } else {
+                (HotplugAction::Remove, self.ids.remove(&(busnum, num)))
+            };
+
+            self.pending.push_back(HotplugEvent {
+                action,
+                busnum,
+                devnum: num,
+                vendor: ids.map(|x| x.0),
+                product: ids.map(|x| x.1),
+                sysname: None,
+            });
+        }
+
+        Ok(self.pending.pop_front())
+    }
+}
+
+// Bus directories and device nodes are named with 3 digit numbers.
+fn parse_node_name(name: &std::ffi::OsString) -> Option<u32> {
+    name.to_str().and_then(|x| x.parse().ok())
+}
+
+// Read idVendor/idProduct from the device descriptor at the start of a usbfs device node.
+fn read_node_ids(busnum: u32, devnum: u32) -> Option<(u16, u16)> {
+    let mut buf = [0u8; 12];
+    let path = format!("{}/{:03}/{:03}", DEV_BUS_USB_PATH, busnum, devnum);
+    fs::File::open(path).and_then(|mut f| f.read_exact(&mut buf)).ok()?;
+    Some((u16::from_le_bytes([buf[8], buf[9]]), u16::from_le_bytes([buf[10], buf[11]])))
+}
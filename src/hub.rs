@@ -0,0 +1,179 @@
+use std::thread;
+use std::time::Duration;
+
+use super::*;
+
+// Hub class descriptor types and port feature selectors, USB 2.0 section 11.24 and
+// USB 3.2 section 10.16.
+const HUB_DESCRIPTOR: u8 = 0x29;
+const SS_HUB_DESCRIPTOR: u8 = 0x2a;
+const PORT_RESET: u16 = 4;
+const PORT_POWER: u16 = 8;
+
+/// How a hub switches power to its downstream ports, from `wHubCharacteristics`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PowerSwitching {
+    /// All ports are switched together; a port only loses power once every port is off.
+    Ganged,
+    PerPort,
+    /// Ports are always powered.  Many hubs claim otherwise.
+    None,
+}
+
+/// Status of a hub's downstream port, from `Hub::port_status()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PortStatus {
+    /// `wPortStatus`, whose bits differ between USB 2.0 and SuperSpeed hubs.
+    pub status: u16,
+    /// `wPortChange`.
+    pub change: u16,
+    superspeed: bool,
+}
+
+impl PortStatus {
+    pub fn connected(&self) -> bool {
+        self.status & 0x0001 != 0
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.status & 0x0002 != 0
+    }
+
+    pub fn over_current(&self) -> bool {
+        self.status & 0x0008 != 0
+    }
+
+    /// The port is being reset.
+    pub fn resetting(&self) -> bool {
+        self.status & 0x0010 != 0
+    }
+
+    pub fn powered(&self) -> bool {
+        match self.superspeed {
+            true => self.status & 0x0200 != 0,
+            false => self.status & 0x0100 != 0,
+        }
+    }
+}
+
+/// Hub class requests to a hub's downstream ports, for switching port power and resetting
+/// ports.
+///
+/// Test rigs can power-cycle the device on a port to recover it from a wedged state, or to
+/// exercise its enumeration.  Ports are numbered from 1.  Requests are sent through the hub's
+/// default control endpoint, so the kernel hub driver can stay bound; it notices the device
+/// disappearing and enumerates it again once powered.  Hubs often implement power
+/// switching loosely, see `power_switching()`.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use usbfs::*;
+///
+/// let device = deviceinfo_find(0xffff, 3).unwrap();
+/// let hub = Hub::new(Device::new(&device.parent().unwrap()).unwrap(), 1000).unwrap();
+/// let port = device.port_path().rsplit(['-', '.']).next().unwrap().parse().unwrap();
+/// hub.power_cycle(port, Duration::from_secs(2), 1000).unwrap();
+/// ```
+pub struct Hub {
+    device: Device,
+    num_ports: u8,
+    characteristics: u16,
+    superspeed: bool,
+}
+
+impl Hub {
+    /// Wrap an open hub, reading its hub descriptor.  Fails with `ErrorKind::InvalidParam` if
+    /// the device doesn't return one.
+    pub fn new(device: Device, timeout_ms: u32) -> Result<Hub> {
+        let superspeed = matches!(device.speed(), Ok(speed) if speed >= Speed::Super);
+        let desc_type = if superspeed { SS_HUB_DESCRIPTOR } else { HUB_DESCRIPTOR };
+        let mut buf = [0u8; 12];
+        let len = device.control_transfer_in(SetupType::Class, SetupRecipient::Device,
+                                             StandardRequest::GetDescriptor as u8,
+                                             (desc_type as u16) << 8, 0, Some(&mut buf), timeout_ms)?;
+        if len < 5 || buf[1] != desc_type {
+            return Err(Error::new(ErrorKind::InvalidParam, "not a hub"));
+        }
+        Ok(Hub {
+            device,
+            num_ports: buf[2],
+            characteristics: u16::from_le_bytes([buf[3], buf[4]]),
+            superspeed,
+        })
+    }
+
+    pub fn num_ports(&self) -> u8 {
+        self.num_ports
+    }
+
+    pub fn power_switching(&self) -> PowerSwitching {
+        match self.characteristics & 0x03 {
+            0 => PowerSwitching::Ganged,
+            1 => PowerSwitching::PerPort,
+            _ => PowerSwitching::None,
+        }
+    }
+
+    pub fn port_status(&self, port: u8, timeout_ms: u32) -> Result<PortStatus> {
+        self.check_port(port)?;
+        let mut buf = [0u8; 4];
+        let len = self.device.control_transfer_in(SetupType::Class, SetupRecipient::Other,
+                                                  StandardRequest::GetStatus as u8,
+                                                  0, port as u16, Some(&mut buf), timeout_ms)?;
+        if len < 4 {
+            return Err(Error::new(ErrorKind::Other, "short port status"));
+        }
+        Ok(PortStatus {
+            status: u16::from_le_bytes([buf[0], buf[1]]),
+            change: u16::from_le_bytes([buf[2], buf[3]]),
+            superspeed: self.superspeed,
+        })
+    }
+
+    /// Switch power to `port` on or off.
+    pub fn set_port_power(&self, port: u8, on: bool, timeout_ms: u32) -> Result<()> {
+        self.port_feature(port, PORT_POWER, on, timeout_ms)
+    }
+
+    /// Reset the device on `port`, as at enumeration.
+    pub fn reset_port(&self, port: u8, timeout_ms: u32) -> Result<()> {
+        self.port_feature(port, PORT_RESET, true, timeout_ms)
+    }
+
+    /// Switch `port` off, wait `off_time`, and switch it back on.  Allow time for the device
+    /// to discharge; a second or two is usually enough.
+    pub fn power_cycle(&self, port: u8, off_time: Duration, timeout_ms: u32) -> Result<()> {
+        self.set_port_power(port, false, timeout_ms)?;
+        thread::sleep(off_time);
+        self.set_port_power(port, true, timeout_ms)
+    }
+
+    /// Device number attached to each port, as `Device::hub_port_info()`.
+    pub fn attached(&self) -> Result<Vec<u8>> {
+        self.device.hub_port_info()
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    pub fn into_inner(self) -> Device {
+        self.device
+    }
+
+    fn port_feature(&self, port: u8, feature: u16, set: bool, timeout_ms: u32) -> Result<()> {
+        self.check_port(port)?;
+        let request = if set { StandardRequest::SetFeature } else { StandardRequest::ClearFeature };
+        self.device.control_transfer_out(SetupType::Class, SetupRecipient::Other, request as u8,
+                                         feature, port as u16, None, timeout_ms)?;
+        Ok(())
+    }
+
+    fn check_port(&self, port: u8) -> Result<()> {
+        match port {
+            1.. if port <= self.num_ports => Ok(()),
+            _ => Err(Error::new(ErrorKind::InvalidParam, "no such port")),
+        }
+    }
+}
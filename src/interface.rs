@@ -0,0 +1,189 @@
+use super::*;
+use descriptors::CONTROL_TIMEOUT_MS;
+
+/// A claimed interface of a `Device`, returned by `Device::claim()`.
+///
+/// The interface knows the descriptors of all its alternate settings, so endpoints can be
+/// looked up and transfers constructed without going back to the configuration descriptor.
+/// The interface is released when the handle is dropped.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let mut intf = device.claim(0).unwrap();
+/// intf.set_altsetting(1).unwrap();
+/// for ep in intf.endpoints() {
+///     println!("endpoint {:02x} {:?}", ep.bEndpointAddress, ep.transfer_type());
+/// }
+/// let xfer = intf.transfer(0x81, UrbFlags::empty(), vec![0u8; 512]).unwrap();
+/// ```
+pub struct Interface<'a> {
+    device: &'a Device,
+    number: u8,
+    altsetting: u8,
+    altsettings: Vec<InterfaceDescriptor>,
+}
+
+impl Device {
+    /// Claim interface `interface` of the active configuration, returning a handle that
+    /// releases it when dropped.  The interface starts out in the alternate setting it was
+    /// left in, as reported by sysfs, or by `GET_INTERFACE` without sysfs.
+    ///
+    /// Descriptors are read from the kernel's cache, so no request is sent to the device,
+    /// unless sysfs is unavailable and they must be fetched with `GET_DESCRIPTOR`.  An
+    /// interface missing from the active configuration gives `ErrorKind::NotFound`.
+    pub fn claim(&self, interface: u8) -> Result<Interface<'_>> {
+        self.claim_in(self.active_config_descriptor()?.as_ref(), interface, false)
+    }
+
+    /// Claim every interface of the active configuration, returning their handles in
+    /// interface order, which release them when dropped.  With `detach`, kernel drivers bound
+    /// to the interfaces are disconnected first; they are not rebound on release.  If any
+    /// claim fails, those already made are released and the error returned.
+    ///
+    /// For tools that take over the whole device, such as firmware updaters and test rigs.
+    /// A device that is not configured gives `ErrorKind::NotFound`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+    /// let interfaces = device.claim_all(true).unwrap();
+    /// // ... update firmware ...
+    /// drop(interfaces);
+    /// ```
+    pub fn claim_all(&self, detach: bool) -> Result<Vec<Interface<'_>>> {
+        let config = self.active_config_descriptor()?
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "device not configured"))?;
+        let mut numbers: Vec<u8> = config.interfaces.iter().map(|i| i.bInterfaceNumber).collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+        numbers.into_iter().map(|number| self.claim_in(Some(&config), number, detach)).collect()
+    }
+
+    // Claim `interface` of `config`, the active configuration, disconnecting any kernel
+    // driver first with `detach`.
+    pub(crate) fn claim_in(&self, config: Option<&ConfigDescriptor>, interface: u8, detach: bool) -> Result<Interface<'_>> {
+        let altsettings: Vec<InterfaceDescriptor> = config
+            .map(|c| c.interface(interface).cloned().collect())
+            .unwrap_or_default();
+        if altsettings.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, "no such interface in active configuration"));
+        }
+        match detach {
+            true => self.disconnect_claim(interface as u32, DisconnectClaimFlags::empty(), "")?,
+            false => self.claim_interface(interface as u16)?,
+        }
+        // devices that stall GET_INTERFACE, or report a setting they don't describe, are
+        // taken to be in setting 0
+        let altsetting = self.current_altsetting(interface)
+            .ok()
+            .filter(|&a| altsettings.iter().any(|i| i.bAlternateSetting == a))
+            .unwrap_or(0);
+        Ok(Interface {
+            device: self,
+            number: interface,
+            altsetting,
+            altsettings,
+        })
+    }
+
+    // Alternate setting `interface` is in, from sysfs if possible.
+    fn current_altsetting(&self, interface: u8) -> Result<u8> {
+        match DeviceInfo::from_fd(self).and_then(|info| info.interface_altsetting(interface)) {
+            Ok(altsetting) => Ok(altsetting),
+            Err(_) => self.get_interface(interface as u16, CONTROL_TIMEOUT_MS),
+        }
+    }
+}
+
+impl<'a> Interface<'a> {
+    pub fn device(&self) -> &'a Device {
+        self.device
+    }
+
+    /// `bInterfaceNumber` of the interface.
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    /// The current alternate setting, as found when claimed or last selected with
+    /// `set_altsetting()`.
+    pub fn altsetting(&self) -> u8 {
+        self.altsetting
+    }
+
+    /// Descriptors of every alternate setting of the interface.
+    pub fn altsettings(&self) -> &[InterfaceDescriptor] {
+        &self.altsettings
+    }
+
+    /// Descriptor of the current alternate setting.
+    pub fn descriptor(&self) -> &InterfaceDescriptor {
+        self.altsettings.iter()
+            .find(|i| i.bAlternateSetting == self.altsetting)
+            .unwrap_or(&self.altsettings[0])
+    }
+
+    /// Select alternate setting `altsetting`.  Settings not described by the interface give
+    /// `ErrorKind::InvalidParam` without a request being sent.
+    pub fn set_altsetting(&mut self, altsetting: u8) -> Result<()> {
+        if !self.altsettings.iter().any(|i| i.bAlternateSetting == altsetting) {
+            return Err(Error::new(ErrorKind::InvalidParam, "no such alternate setting"));
+        }
+        self.device.set_interface(self.number as u32, altsetting as u32)?;
+        self.altsetting = altsetting;
+        Ok(())
+    }
+
+    /// Endpoints of the current alternate setting.
+    pub fn endpoints(&self) -> impl Iterator<Item=&EndpointDescriptor> + '_ {
+        self.descriptor().endpoints.iter()
+    }
+
+    /// Endpoint `address` (including its direction bit) of the current alternate setting.
+    pub fn endpoint(&self, address: u8) -> Option<&EndpointDescriptor> {
+        self.endpoints().find(|e| e.bEndpointAddress == address)
+    }
+
+    /// Construct a transfer on endpoint `address` of the current alternate setting, with the
+    /// bulk, interrupt, or isochronous type the endpoint descriptor calls for.  Endpoints not
+    /// in the current alternate setting give `ErrorKind::InvalidParam`.
+    pub fn transfer<B: Buffer>(&self, address: u8, flags: UrbFlags, buf: B) -> Result<StdBufTransfer<B>> {
+        let ep = self.endpoint(address)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidParam, "endpoint not in current alternate setting"))?;
+        match ep.transfer_type() {
+            UrbType::Bulk => Ok(StdBufTransfer::bulk(address, flags, buf)),
+            UrbType::Interrupt => Ok(StdBufTransfer::interrupt(address, flags, buf)),
+            UrbType::Iso => Ok(StdBufTransfer::isochronous(address, flags, buf)),
+            UrbType::Control => Err(Error::new(ErrorKind::InvalidParam, "control endpoints take control transfers")),
+        }
+    }
+
+    /// Construct an isochronous transfer of `N` packets on endpoint `address` of the current
+    /// alternate setting.  Endpoints that are not isochronous, and buffers whose
+    /// `packet_length()` exceeds what the endpoint can move per interval at the device's speed,
+    /// give `ErrorKind::InvalidParam`.
+    pub fn iso_transfer<B: IsoBuffer, const N: usize>(&self, address: u8, flags: UrbFlags, buf: B) -> Result<IsoBufTransfer<B,N>> {
+        let ep = match self.endpoint(address) {
+            Some(ep) if ep.transfer_type() == UrbType::Iso => ep,
+            _ => return Err(Error::new(ErrorKind::InvalidParam, "not an isochronous endpoint of the current alternate setting")),
+        };
+        // kernels too old to report the speed get no check
+        if let Ok(speed) = self.device.speed() {
+            if buf.packet_length() > ep.max_bytes_per_interval(speed) {
+                return Err(Error::new(ErrorKind::InvalidParam, "packet length exceeds endpoint bandwidth"));
+            }
+        }
+        Ok(IsoBufTransfer::isochronous(address, flags, buf))
+    }
+}
+
+impl<'a> Drop for Interface<'a> {
+    fn drop(&mut self) {
+        let _ = self.device.release_interface(self.number as u16);
+    }
+}
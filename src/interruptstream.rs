@@ -0,0 +1,113 @@
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use super::*;
+
+/// Continuous interrupt IN polling.
+///
+/// `InterruptStream` keeps a number of interrupt IN transfers queued on one endpoint and
+/// resubmits each one as soon as its report has been consumed, so the host controller polls the
+/// endpoint at every interval without gaps.  This is the usual way to read HID-style devices.
+///
+/// Reports can be consumed in place with `next_report()`, or as owned `Vec`s by iterating.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// device.claim_interface(0).unwrap();
+/// let stream = InterruptStream::new(device, 0x81, 64, 4).unwrap();
+/// for report in stream {
+///     println!("{:?}", report.unwrap());
+/// }
+/// ```
+pub struct InterruptStream {
+    device: AsyncDevice<Box<StdBufTransfer<Vec<u8>>>>,
+    depth: usize,
+}
+
+impl InterruptStream {
+    /// Start polling interrupt IN `endpoint` with `depth` transfers of `report_size` bytes each.
+    /// The direction bit of `endpoint` is set automatically.
+    pub fn new(device: Device, endpoint: u8, report_size: usize, depth: usize) -> Result<Self> {
+        let mut stream = InterruptStream{device: device.into(), depth: 0};
+        for _ in 0..depth {
+            stream.device.submit(Box::new(StdBufTransfer::interrupt(endpoint | 0x80,
+                                                                    UrbFlags::empty(),
+                                                                    vec![0; report_size])))?;
+            stream.depth += 1;
+        }
+        Ok(stream)
+    }
+
+    /// Number of transfers in flight.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Wait for the next report, pass it to `f`, and resubmit its transfer.
+    ///
+    /// If the transfer failed (eg. the endpoint stalled or the device was unplugged) it is not
+    /// resubmitted, `f` is not called, and the error is returned; the queue depth drops by one.
+    pub fn next_report<F, T>(&mut self, f: F) -> Result<T>
+        where F: FnOnce(&[u8]) -> T
+    {
+        self.process(true, f)
+    }
+
+    /// Like `next_report()`, but fails with `ErrorKind::WouldBlock` instead of waiting.
+    /// For use with event loops, which should wait for the file descriptor to become writable.
+    pub fn next_report_nowait<F, T>(&mut self, f: F) -> Result<T>
+        where F: FnOnce(&[u8]) -> T
+    {
+        self.process(false, f)
+    }
+
+    /// Stop polling, returning the underlying `AsyncDevice`.  Transfers still in flight stay in
+    /// it and are returned by its `reap_*()` methods.
+    pub fn into_inner(self) -> AsyncDevice<Box<StdBufTransfer<Vec<u8>>>> {
+        self.device
+    }
+
+    fn process<F, T>(&mut self, wait: bool, f: F) -> Result<T>
+        where F: FnOnce(&[u8]) -> T
+    {
+        let (_slot, xfer, result) = match wait {
+            true => self.device.reap_wait()?,
+            false => self.device.reap_nowait()?,
+        };
+        let len = match result.into_io_result() {
+            Ok(len) => len,
+            Err(err) => {
+                self.depth -= 1;
+                return Err(err.into());
+            }
+        };
+
+        let value = f(&xfer.buf[..len]);
+
+        if let Err((err, _)) = self.device.submit_give_back_on_fail(xfer) {
+            self.depth -= 1;
+            return Err(err);
+        }
+        Ok(value)
+    }
+}
+
+/// Yields owned copies of each report.  Iteration ends once no transfers remain in flight.
+impl Iterator for InterruptStream {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.depth {
+            0 => None,
+            _ => Some(self.next_report(|report| report.to_vec())),
+        }
+    }
+}
+
+impl AsRawFd for InterruptStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}
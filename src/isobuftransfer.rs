@@ -6,18 +6,30 @@
 use super::*;
 
 use std::fmt::Debug;
+use std::mem;
 
+/// Buffer for an `IsoBufTransfer`.
+///
+/// The buffer is divided into packets laid out back to back.  By default every packet is
+/// `packet_length()` bytes.  Buffers with packets of differing sizes, such as UVC payloads,
+/// also override `packet_lengths()`.
 pub trait IsoBuffer: AsMut<[u8]> {
+    /// Length of each packet, or the maximum packet length if `packet_lengths()` is overridden.
     fn packet_length(&self) -> usize;
-//    const PACKET_LENGTH: usize; // maximum size of each iso packet
-//    fn packet_lengths(&self) -> impl Iterator<Item=usize>;
+
+    /// Length of each packet in order.  The number of packets is limited by this iterator, the
+    /// size of the buffer, and the number of packet descriptors in the transfer, whichever is
+    /// smallest.  The default repeats `packet_length()`.
+    fn packet_lengths(&self) -> impl Iterator<Item=usize> {
+        std::iter::repeat(self.packet_length())
+    }
 }
 
 
 //////////////////////////////////////////////////////////////////////////////
-///
-/// StdBufTransfer
-///
+//
+// StdBufTransfer
+//
 
 #[derive(Debug)]
 #[repr(C)]
@@ -40,10 +52,7 @@ unsafe impl<B: IsoBuffer+Debug, const N: usize> Transfer for IsoBufTransfer<B, N
         let mut tot_length = self.buf.as_mut().len();
         let mut tot_packets = 0;
 
-        // leave this as iterator for now in case IsoBuffer ever gets packet_lengths() back.
-        let length = self.buf.packet_length();
-
-        for packet in &mut self.iso_packets {
+        for (packet, length) in self.iso_packets.iter_mut().zip(self.buf.packet_lengths()) {
             if 0==tot_length { break; }
             let limited_length = std::cmp::min(tot_length, length);
             packet.length = limited_length as i32;
@@ -54,7 +63,7 @@ unsafe impl<B: IsoBuffer+Debug, const N: usize> Transfer for IsoBufTransfer<B, N
         }
 
         self.urb.buffer = self.buf.as_mut().as_mut_ptr();
-        self.urb.number_of_packets = tot_packets;
+        self.urb.set_number_of_packets(tot_packets);
 
         &mut self.urb
     }
@@ -80,7 +89,96 @@ impl<B,const N: usize> IsoBufTransfer<B,N> {
         &self.urb
     }
 
+    /// Schedule the transfer to start at frame `frame` (microframe on high speed and faster
+    /// devices) rather than as soon as possible.  This clears `URB_ISO_ASAP`; if the frame has
+    /// already passed or is too far ahead, the submission fails or the packets complete with
+    /// an error status, depending on the host controller.
+    ///
+    /// Lining up streams on several endpoints would be a matter of submitting each at the same
+    /// frame, but usbfs has no way to read the host controller's current frame number, and does
+    /// not report the frame an `URB_ISO_ASAP` transfer was given either.  The frame has to be
+    /// learned some other way, eg. from the device, or the start is a guess.
+    pub fn set_start_frame(&mut self, frame: u32) {
+        self.urb.flags.remove(UrbFlags::URB_ISO_ASAP);
+        self.urb.start_frame = frame as i32;
+    }
+
+    /// The start frame of the transfer as submitted: the frame requested with
+    /// `set_start_frame()`, or whatever was in the urb for `URB_ISO_ASAP` transfers.  usbfs does
+    /// not copy the scheduled frame back when the transfer is reaped, so this does not change.
+    pub fn start_frame(&self) -> u32 {
+        self.urb.start_frame as u32
+    }
+
     pub fn status(&self) -> &[IsoPacketDesc] {
-        &self.iso_packets[..(self.urb.number_of_packets as usize)]
+        &self.iso_packets[..(self.urb.number_of_packets() as usize)]
+    }
+
+    /// Number of packets of the reaped transfer that failed, as counted by the kernel.
+    pub fn error_count(&self) -> u32 {
+        self.urb.error_count.max(0) as u32
+    }
+
+    /// Failed packets of the reaped transfer, counted by status.
+    pub fn error_summary(&self) -> IsoErrorSummary {
+        IsoErrorSummary::from_packets(self.status())
+    }
+
+    /// Consume the reaped transfer, returning its buffer and a copy of its outcome, including
+    /// the packet descriptors.
+    pub fn into_result(self) -> (B, TransferOutcome) {
+        let outcome = TransferOutcome::from_urb(&self.urb, self.status());
+        (self.buf, outcome)
+    }
+}
+
+impl<B: AsRef<[u8]>, const N: usize> IsoBufTransfer<B,N> {
+    /// Descriptor and received data of each packet of a reaped transfer, the data clipped to
+    /// the packet's `actual_length`.
+    ///
+    /// Packet data is laid out back to back in the buffer according to the requested packet
+    /// lengths, whatever the actual lengths were, so each slice starts where its packet was
+    /// placed.  Check `status` before trusting the data of a packet.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// # fn f(xfer: &IsoBufTransfer<Vec<u8>, 8>) {
+    /// for (packet, data) in xfer.packets().filter(|(packet, _)| packet.status == 0) {
+    ///     println!("{} of {} bytes: {:?}", data.len(), packet.length, data);
+    /// }
+    /// # }
+    /// ```
+    pub fn packets(&self) -> impl Iterator<Item=(IsoPacketDesc, &[u8])> {
+        let packets = &self.iso_packets[..(self.urb.number_of_packets() as usize)];
+        let mut rest: &[u8] = self.buf.as_ref();
+        packets.iter().map(move |packet| {
+            let (data, tail) = rest.split_at(std::cmp::min(packet.length as usize, rest.len()));
+            rest = tail;
+            let actual = std::cmp::min(packet.actual_length as usize, data.len());
+            (*packet, &data[..actual])
+        })
+    }
+}
+
+impl<B: AsMut<[u8]>, const N: usize> IsoBufTransfer<B,N> {
+    /// Mutable version of `packets()`.
+    pub fn packets_mut(&mut self) -> impl Iterator<Item=(IsoPacketDesc, &mut [u8])> {
+        let packets = &self.iso_packets[..(self.urb.number_of_packets() as usize)];
+        let mut rest: &mut [u8] = self.buf.as_mut();
+        packets.iter().map(move |packet| {
+            let len = std::cmp::min(packet.length as usize, rest.len());
+            let (data, tail) = mem::take(&mut rest).split_at_mut(len);
+            rest = tail;
+            let actual = std::cmp::min(packet.actual_length as usize, data.len());
+            (*packet, &mut data[..actual])
+        })
+    }
+
+    // Result and received data of each packet.
+    pub(crate) fn packet_results(&mut self) -> impl Iterator<Item=(TransferResult, &[u8])> {
+        self.packets_mut()
+            .map(|(packet, data)| (TransferResult::from_status(packet.status, packet.actual_length), &*data))
     }
 }
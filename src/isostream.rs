@@ -0,0 +1,137 @@
+use std::fmt::Debug;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use super::*;
+
+/// Continuous isochronous IN streaming.
+///
+/// `IsoStream` keeps a fixed number of `IsoBufTransfer`s in flight on one endpoint.  Each call
+/// to `next_transfer()` or `next_packets()` reaps the oldest completed transfer, hands its
+/// contents to a closure, and immediately resubmits it, so the queue depth stays constant
+/// without any bookkeeping by the caller.
+///
+/// The queue depth is the number of buffers given to `new()`.  Deeper queues tolerate more
+/// scheduling latency in the application at the cost of buffering delay.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// #[derive(Debug)]
+/// struct Frames(Vec<u8>);
+///
+/// impl AsMut<[u8]> for Frames {
+///     fn as_mut(&mut self) -> &mut [u8] { &mut self.0 }
+/// }
+///
+/// impl IsoBuffer for Frames {
+///     fn packet_length(&self) -> usize { 192 }
+/// }
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let mut stream: IsoStream<Frames, 8> =
+///     IsoStream::new(device, 0x81, UrbFlags::URB_ISO_ASAP,
+///                    (0..4).map(|_| Frames(vec![0; 8 * 192]))).unwrap();
+/// loop {
+///     stream.next_packets(|result, data| {
+///         if result.is_ok() {
+///             println!("{} bytes", data.len());
+///         }
+///     }).unwrap();
+/// }
+/// ```
+pub struct IsoStream<B, const N: usize> {
+    device: AsyncDevice<Box<IsoBufTransfer<B, N>>>,
+    depth: usize,
+}
+
+impl<B: IsoBuffer + Debug, const N: usize> IsoStream<B, N> {
+    /// Start streaming from isochronous IN `endpoint`, submitting one transfer per buffer in
+    /// `bufs`.  The direction bit of `endpoint` is set automatically.
+    pub fn new<I>(device: Device, endpoint: u8, flags: UrbFlags, bufs: I) -> Result<Self>
+        where I: IntoIterator<Item=B>
+    {
+        let mut stream = IsoStream{device: device.into(), depth: 0};
+        for buf in bufs {
+            stream.device.submit(Box::new(IsoBufTransfer::isochronous(endpoint | 0x80, flags, buf)))?;
+            stream.depth += 1;
+        }
+        Ok(stream)
+    }
+
+    /// Start streaming with `depth` default-initialized buffers.
+    pub fn with_depth(device: Device, endpoint: u8, flags: UrbFlags, depth: usize) -> Result<Self>
+        where B: Default
+    {
+        Self::new(device, endpoint, flags, (0..depth).map(|_| B::default()))
+    }
+
+    /// Number of transfers in flight.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Wait for the next transfer to complete, pass it to `f`, and resubmit it.
+    ///
+    /// Per-packet failures are left for `f` to inspect.  If the transfer as a whole failed (eg.
+    /// the device was unplugged) it is not resubmitted, `f` is not called, and the error is
+    /// returned; the queue depth drops by one.
+    pub fn next_transfer<F, T>(&mut self, f: F) -> Result<T>
+        where F: FnOnce(&mut IsoBufTransfer<B, N>) -> T
+    {
+        self.process(true, f)
+    }
+
+    /// Like `next_transfer()`, but fails with `ErrorKind::WouldBlock` instead of waiting.
+    /// For use with event loops, which should wait for the file descriptor to become writable.
+    pub fn next_transfer_nowait<F, T>(&mut self, f: F) -> Result<T>
+        where F: FnOnce(&mut IsoBufTransfer<B, N>) -> T
+    {
+        self.process(false, f)
+    }
+
+    /// Wait for the next transfer to complete and call `f` with the result and received data
+    /// of each of its packets, then resubmit it.
+    pub fn next_packets<F>(&mut self, mut f: F) -> Result<()>
+        where F: FnMut(TransferResult, &[u8])
+    {
+        self.next_transfer(|xfer| xfer.packet_results().for_each(|(result, data)| f(result, data)))
+    }
+
+    /// Stop streaming, returning the underlying `AsyncDevice`.  Transfers still in flight stay
+    /// in it and are returned by its `reap_*()` methods.
+    pub fn into_inner(self) -> AsyncDevice<Box<IsoBufTransfer<B, N>>> {
+        self.device
+    }
+
+    fn process<F, T>(&mut self, wait: bool, f: F) -> Result<T>
+        where F: FnOnce(&mut IsoBufTransfer<B, N>) -> T
+    {
+        let (_slot, mut xfer, result) = match wait {
+            true => self.device.reap_wait()?,
+            false => self.device.reap_nowait()?,
+        };
+        match result {
+            // -EXDEV reports that only some packets completed
+            TransferResult::Completed{..} | TransferResult::Other(libc::EXDEV) => (),
+            result => {
+                self.depth -= 1;
+                return Err(result.into_io_result().unwrap_err().into());
+            }
+        }
+
+        let value = f(&mut xfer);
+
+        if let Err((err, _)) = self.device.submit_give_back_on_fail(xfer) {
+            self.depth -= 1;
+            return Err(err);
+        }
+        Ok(value)
+    }
+}
+
+impl<B, const N: usize> AsRawFd for IsoStream<B, N> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}
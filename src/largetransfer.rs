@@ -0,0 +1,233 @@
+use std::{io, cmp, mem, ptr, thread};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use nix::poll::{poll, PollFd, PollFlags};
+
+use super::*;
+
+// Largest URB accepted by kernels without Capabilities::NO_PACKET_SIZE_LIM.
+const MAX_URB_BUFFER_SIZE: usize = 16384;
+
+// Largest URB made with it.  `buffer_length` is an i32, and a power of two keeps every URB but
+// the last a whole number of packets.
+const MAX_URB_LENGTH: usize = 1 << 30;
+
+/// Synchronous bulk transfers of arbitrary size.
+///
+/// These methods split the buffer into as many URBs as the running kernel requires, submit
+/// them, and wait for them to complete.  When the kernel supports
+/// `Capabilities::BULK_CONTINUATION`, all URBs of an IN transfer are queued at once and a short
+/// packet ends the transfer early; otherwise IN URBs are submitted one at a time.
+///
+/// URBs are submitted and reaped on the device's file descriptor directly, so these methods
+/// must not be used while asynchronous transfers are in flight on the same file descriptor.
+/// Should they reap an URB of someone else's, they fail with `ErrorKind::Other`, after waiting
+/// for their own URBs to finish; the other URB is lost to its owner.
+///
+/// The buffer is only handed back once the kernel is done with every URB, including after a
+/// disconnect.  Should reaping fail for good, the process is aborted rather than return early.
+impl Device {
+    /// Read up to `data.len()` bytes from bulk `endpoint`.  The direction bit of `endpoint` is
+    /// set automatically.
+    ///
+    /// The `Ok` result is the total number of bytes received, which is less than `data.len()` if
+    /// the device ended the transfer with a short packet.  `timeout_ms` applies to the whole
+    /// transfer; 0 waits forever.
+    pub fn bulk_transfer_in_large(&self, endpoint: u8, data: &mut [u8], timeout_ms: u32) -> Result<usize> {
+        self.bulk_large(endpoint | 0x80, data.as_mut_ptr(), data.len(), timeout_ms)
+    }
+
+    /// Write all of `data` to bulk `endpoint`.  The direction bit of `endpoint` is cleared
+    /// automatically.
+    ///
+    /// The `Ok` result is the total number of bytes sent.  `timeout_ms` applies to the whole
+    /// transfer; 0 waits forever.
+    pub fn bulk_transfer_out_large(&self, endpoint: u8, data: &[u8], timeout_ms: u32) -> Result<usize> {
+        self.bulk_large(endpoint & 0x7f, data.as_ptr() as *mut u8, data.len(), timeout_ms)
+    }
+
+    fn bulk_large(&self, endpoint: u8, buf: *mut u8, len: usize, timeout_ms: u32) -> Result<usize> {
+        let caps = self.capabilities()?;
+        let is_in = 0 != endpoint & 0x80;
+        let deadline = match timeout_ms {
+            0 => None,
+            t => Some(Instant::now() + Duration::from_millis(t as u64)),
+        };
+
+        let chunk_size = if caps.contains(Capabilities::NO_PACKET_SIZE_LIM) {
+            len.clamp(1, MAX_URB_LENGTH)
+        } else {
+            MAX_URB_BUFFER_SIZE
+        };
+
+        // Without continuation support a short IN packet can't cancel queued URBs, so
+        // IN URBs are run one at a time.
+        let continuation = caps.contains(Capabilities::BULK_CONTINUATION);
+        let batch_size = if is_in && !continuation { chunk_size } else { cmp::max(len, 1) };
+
+        let mut total = 0;
+        let mut offset = 0;
+        loop {
+            let batch_len = cmp::min(batch_size, len - offset);
+            let (actual, short) = self.bulk_batch(endpoint,
+                                                  unsafe { buf.add(offset) },
+                                                  batch_len,
+                                                  chunk_size,
+                                                  is_in && continuation,
+                                                  caps.contains(Capabilities::REAP_AFTER_DISCONNECT),
+                                                  deadline)?;
+            total += actual;
+            offset += batch_len;
+            if short || offset >= len {
+                return Ok(total);
+            }
+        }
+    }
+
+    // Submit one batch of URBs covering `len` bytes at `buf` and wait for all of them.
+    // Returns the number of bytes transferred and whether the batch ended with a short packet.
+    fn bulk_batch(&self,
+                  endpoint: u8,
+                  buf: *mut u8,
+                  len: usize,
+                  chunk_size: usize,
+                  continuation: bool,
+                  reap_after_disconnect: bool,
+                  deadline: Option<Instant>)
+                  -> Result<(usize, bool)> {
+        let is_in = 0 != endpoint & 0x80;
+        let nchunks = cmp::max(1, len.div_ceil(chunk_size));
+
+        // urbs must not move while submitted; the Vec is never resized after this point
+        let mut urbs: Vec<Urb> = (0..nchunks).map(|i| {
+            let mut flags = UrbFlags::empty();
+            if is_in && i + 1 < nchunks {
+                flags |= UrbFlags::URB_SHORT_NOT_OK;
+            }
+            if continuation && i > 0 {
+                flags |= UrbFlags::URB_BULK_CONTINUATION;
+            }
+            let offset = i * chunk_size;
+            Urb {
+                buffer: unsafe { buf.add(offset) },
+                buffer_length: cmp::min(chunk_size, len - offset) as i32,
+                ..Urb::new(UrbType::Bulk, endpoint, flags)
+            }
+        }).collect();
+
+        let mut submitted = 0;
+        let mut result = Ok(());
+        for urb in urbs.iter_mut() {
+            result = unsafe { devfs::nix_result_to_result(devfs::submiturb(self, urb)).map(|_|()) };
+            if result.is_err() {
+                break;
+            }
+            submitted += 1;
+        }
+
+        // collect everything that was submitted, cancelling on failure or timeout
+        let mut outstanding = submitted;
+        let mut cancelled = result.is_err();
+        let mut discarded = false;
+        let mut disconnected = false;
+        while outstanding > 0 {
+            // nothing may return early from here on, or the kernel could write into freed urbs
+            if !cancelled {
+                match self.wait_writable(deadline) {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        result = Err(Error::new(ErrorKind::Timeout, "bulk transfer timed out"));
+                        cancelled = true;
+                    }
+                    Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        result = Err(err);
+                        cancelled = true;
+                    }
+                }
+            }
+            if cancelled && !discarded && !disconnected {
+                for urb in urbs[..submitted].iter_mut() {
+                    // fails harmlessly for urbs that have already completed
+                    let _ = unsafe { devfs::discardurb(self, urb) };
+                }
+                discarded = true;
+            }
+
+            if disconnected {
+                thread::sleep(devfs::DISCONNECT_REAP_INTERVAL);
+            }
+            let mut urbp: *mut Urb = ptr::null_mut();
+            let reaped = if cancelled && !disconnected {
+                unsafe { devfs::nix_result_to_result(devfs::reapurb(self, &mut urbp)) }
+            } else {
+                unsafe { devfs::nix_result_to_result(devfs::reapurbndelay(self, &mut urbp)) }
+            };
+            match reaped {
+                Ok(_) if urbs[..submitted].iter().any(|urb| ptr::eq(urb, urbp)) => outstanding -= 1,
+                Ok(_) => {
+                    // not ours; ours may still be in flight, so keep going until they are reaped
+                    result = Err(Error::new(ErrorKind::Other, "reaped an URB not submitted by this transfer"));
+                    cancelled = true;
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::Interrupted => (),
+                Err(err) if err.kind() == ErrorKind::Disconnected => {
+                    // the kernel kills our urbs along with the device, but may not have yet
+                    if !reap_after_disconnect {
+                        // and never hands them back, nor touches them again
+                        return Err(err);
+                    }
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                    cancelled = true;
+                    disconnected = true;
+                }
+                Err(err) => {
+                    // can't tell what the kernel is still doing with our urbs, and the buffer
+                    // can't be kept from the caller; stop before it can be written once freed
+                    log_debug!("{} urbs of a bulk transfer can't be reaped: {}", outstanding, err);
+                    mem::forget(urbs);
+                    std::process::abort();
+                }
+            }
+        }
+        result?;
+
+        // tally results in order, stopping at the first short packet
+        let mut total = 0;
+        for urb in &urbs {
+            match urb.status {
+                0 => {
+                    total += urb.actual_length as usize;
+                    if is_in && (urb.actual_length as usize) < urb.buffer_length as usize {
+                        return Ok((total, true));
+                    }
+                }
+                status if status == -libc::EREMOTEIO => {
+                    // short packet on a URB_SHORT_NOT_OK urb; remaining urbs were cancelled
+                    return Ok((total + urb.actual_length as usize, true));
+                }
+                status => return Err(io::Error::from_raw_os_error(-status).into()),
+            }
+        }
+        Ok((total, false))
+    }
+
+    // Wait for the file descriptor to become writable (ie. an urb is reapable).
+    // Returns false on timeout.
+    pub(crate) fn wait_writable(&self, deadline: Option<Instant>) -> Result<bool> {
+        let timeout = match deadline {
+            None => -1,
+            Some(deadline) => {
+                // rounded up, so as not to give up before the deadline
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                cmp::min(remaining.as_micros().div_ceil(1000), i32::MAX as u128) as i32
+            }
+        };
+        let mut fds = [PollFd::new(self.as_raw_fd(), PollFlags::POLLOUT)];
+        let n = devfs::nix_result_to_result(poll(&mut fds, timeout))?;
+        Ok(n > 0)
+    }
+}
@@ -0,0 +1,90 @@
+// Compile-time checks of the structs passed to usbfs ioctls against the kernel's
+// include/uapi/linux/usbdevice_fs.h, so a layout mistake fails the build instead of corrupting
+// ioctls.  x86_64 and aarch64 share one layout, and 32-bit arm (like every 32-bit target
+// without 8 byte fields) another.  The request codes embed the struct sizes, so they are
+// checked too.
+
+use std::mem::{self, align_of, size_of};
+
+use devfs::*;
+
+macro_rules! assert_layout {
+    ($t:ty, $size:expr, { $($field:ident: $offset:expr),* }) => {
+        const _: () = assert!(size_of::<$t>() == $size);
+        $(const _: () = assert!(mem::offset_of!($t, $field) == $offset);)*
+    };
+}
+
+// Layouts that don't depend on the pointer width.
+assert_layout!(IsoPacketDesc, 12, { length: 0, actual_length: 4, status: 8 });
+assert_layout!(SetInterface, 8, { interface: 0, altsetting: 4 });
+assert_layout!(GetDriver, 260, { interface: 0, driver: 4 });
+assert_layout!(ConnectInfo, 8, { devnum: 0, slow: 4 });
+assert_layout!(HubPortInfo, 128, { nports: 0, port: 1 });
+assert_layout!(DisconnectClaim, 264, { interface: 0, flags: 4, driver: 8 });
+assert_layout!(Streams, 8, { num_streams: 0, num_eps: 4 });
+assert_layout!(UrbFlags, 4, {});
+assert_layout!(DisconnectClaimFlags, 4, {});
+
+// iso_frame_desc[] follows the urb without padding
+const _: () = assert!(size_of::<Urb>().is_multiple_of(align_of::<IsoPacketDesc>()));
+
+#[cfg(target_pointer_width="64")]
+mod lp64 {
+    use super::*;
+
+    assert_layout!(CtrlTransfer, 24, {
+        bmRequestType: 0, bRequest: 1, wValue: 2, wIndex: 4, wLength: 6, timeout: 8, data: 16
+    });
+    assert_layout!(BulkTransfer, 24, { ep: 0, len: 4, timeout: 8, data: 16 });
+    assert_layout!(DisconnectSignal, 16, { signr: 0, context: 8 });
+    assert_layout!(IoctlRequest, 16, { ifno: 0, ioctl_code: 4, data: 8 });
+    assert_layout!(Urb, 56, {
+        urbtype: 0, endpoint: 1, status: 4, flags: 8, buffer: 16, buffer_length: 24,
+        actual_length: 28, start_frame: 32, packets_or_stream: 36, error_count: 40, signr: 44,
+        usercontext: 48
+    });
+
+    const _: () = assert!(CONTROL as u32 == 0xc018_5500);
+    const _: () = assert!(BULK as u32 == 0xc018_5502);
+    const _: () = assert!(SUBMITURB as u32 == 0x8038_550a);
+    const _: () = assert!(REAPURB as u32 == 0x4008_550c);
+    const _: () = assert!(REAPURBNDELAY as u32 == 0x4008_550d);
+    const _: () = assert!(DISCSIGNAL as u32 == 0x8010_550e);
+    const _: () = assert!(IOCTL as u32 == 0xc010_5512);
+}
+
+// These are the codes the kernel knows as USBDEVFS_CONTROL32, USBDEVFS_SUBMITURB32, and so on,
+// which a 64-bit kernel accepts from 32-bit processes.
+#[cfg(target_pointer_width="32")]
+mod ilp32 {
+    use super::*;
+
+    assert_layout!(CtrlTransfer, 16, {
+        bmRequestType: 0, bRequest: 1, wValue: 2, wIndex: 4, wLength: 6, timeout: 8, data: 12
+    });
+    assert_layout!(BulkTransfer, 16, { ep: 0, len: 4, timeout: 8, data: 12 });
+    assert_layout!(DisconnectSignal, 8, { signr: 0, context: 4 });
+    assert_layout!(IoctlRequest, 12, { ifno: 0, ioctl_code: 4, data: 8 });
+    assert_layout!(Urb, 44, {
+        urbtype: 0, endpoint: 1, status: 4, flags: 8, buffer: 12, buffer_length: 16,
+        actual_length: 20, start_frame: 24, packets_or_stream: 28, error_count: 32, signr: 36,
+        usercontext: 40
+    });
+
+    const _: () = assert!(CONTROL as u32 == 0xc010_5500);
+    const _: () = assert!(BULK as u32 == 0xc010_5502);
+    const _: () = assert!(SUBMITURB as u32 == 0x802c_550a);
+    const _: () = assert!(REAPURB as u32 == 0x4004_550c);
+    const _: () = assert!(REAPURBNDELAY as u32 == 0x4004_550d);
+    const _: () = assert!(DISCSIGNAL as u32 == 0x8008_550e);
+    const _: () = assert!(IOCTL as u32 == 0xc00c_5512);
+}
+
+// Request codes that don't depend on the pointer width.
+const _: () = assert!(SETINTERFACE as u32 == 0x8008_5504);
+const _: () = assert!(CLAIMINTERFACE as u32 == 0x8004_550f);
+const _: () = assert!(GETDRIVER as u32 == 0x4104_5508);
+const _: () = assert!(CONNECTINFO as u32 == 0x4008_5511);
+const _: () = assert!(DISCONNECT_CLAIM as u32 == 0x8108_551b);
+const _: () = assert!(HUB_PORTINFO as u32 == 0x8080_5513);
@@ -3,8 +3,39 @@
 //!
 //! # Features
 //! * Access to synchronous and asynchronous usbfs functions.
-//! * Enumeration of USB devices using sysfs.
-//! * The `usbfs::Device` type has the `mio::Evented` trait and can partake in [`mio`](https://github.com/carllerche/mio) event loops.
+//! * Enumeration of USB devices using sysfs, or `/dev/bus/usb` where sysfs is unavailable.
+//! * Streaming on bulk endpoints with `BulkReader` and `BulkWriter`, which keep a queue of
+//!   transfers in flight behind `std::io::Read` and `Write` interfaces.
+//! * Parsing of configuration descriptors, including the interface associations that group
+//!   the interfaces of composite devices into functions.  Class-specific descriptors of HID,
+//!   CDC, audio, and video interfaces can be parsed with the `hid`, `cdc`, `uac`, and `uvc`
+//!   features.
+//!   The `hid` feature also adds `HidInterface`, with helpers for HID class requests and
+//!   input report streams.
+//! * CDC-ACM serial functions can be opened as a `std::io` stream with `CdcAcm`, available with
+//!   the `cdc` feature.
+//! * Cameras can be streamed from without the kernel's `uvcvideo` driver using `UvcStreaming`
+//!   and `UvcFrameAssembler`, available with the `uvc` feature.
+//! * Audio can be played and captured with `UacStreaming` and `UacStream`, including feedback
+//!   from asynchronous sinks, available with the `uac` feature.
+//! * Firmware can be updated over DFU with `Dfu`, and DFU files checked with `DfuSuffix`,
+//!   available with the `dfu` feature.
+//! * Test and measurement instruments can be driven with `Usbtmc`, available with the `usbtmc`
+//!   feature.
+//! * The `usbfs::Device` and `usbfs::AsyncDevice` types implement `mio::event::Source` and can partake in [`mio`](https://github.com/tokio-rs/mio) event loops.
+//! * Devices, hotplug monitors, and timers can be waited on together without any event loop
+//!   crate using `Reactor`, available with the `reactor` feature.
+//! * Descriptors, device summaries, and transfer results implement `serde`'s `Serialize` and
+//!   `Deserialize` with the `serde` feature.
+//! * Opening devices, claiming interfaces, and every transfer submitted and reaped are logged
+//!   through the [`log`](https://github.com/rust-lang/log) crate with the `log` feature.
+//! * Transfers can be recorded to a pcapng capture that opens in Wireshark with `Recorder`,
+//!   and captures played back to a device with `Replayer`.  Traffic on a whole bus can be
+//!   watched through the kernel's usbmon with `Usbmon`.
+//! * Code built on this crate can be tested without hardware by running it over a scripted
+//!   `MockBackend`, available with the `mock` feature.
+//! * Runs on 32-bit and 64-bit targets, including 32-bit userland on a 64-bit kernel such as
+//!   ARM32 systems with 64-bit kernels, where the kernel's compat ioctls are used.
 //! * Is written entirely in Rust.  The only external requirement is support for usbfs in the kernel.
 //!
 //! # Differences from `libusb`
@@ -15,7 +46,7 @@
 //! * This crate requires no initialization, no `context` structure, and has less coupling.
 //! * This crate externalizes event loop support.  The `AsyncDevice` has traits
 //!   [`AsRawFd`](https://doc.rust-lang.org/std/os/unix/io/trait.AsRawFd.html) and
-//!   [`mio::Evented`](https://github.com/carllerche/mio) to facilitate integration into external
+//!   [`mio::event::Source`](https://github.com/tokio-rs/mio) to facilitate integration into external
 //!   event loops.
 //!
 //! Note that `libusb` has a [Rust wrapper](https://github.com/dcuddeback/libusb-rs).
@@ -24,24 +55,24 @@
 //!
 //! A basic synchronous transfer:
 //!
-//! ```
+//! ```no_run
 //! use usbfs::*;
 //! fn main() {
 //!     // find my device
-//!     let device_info = deviceinfo_collection().into_iter().find(is_my_device).unwrap();
+//!     let device_info = deviceinfo_enumerate().find(is_my_device).unwrap();
 //!
 //!     // open my device
 //!     let device = Device::new(&device_info).unwrap();
 //!
 //!     // read some data from my device with a control transfer
-//!     let mut unique_id:[u8;16] = unsafe{std::mem::uninitialized()};
+//!     let mut unique_id:[u8;16] = [0;16];
 //!     device.control_transfer( SetupDirection::DeviceToHost,
 //!                              SetupType::Vendor,
 //!                              SetupRecipient::Interface,
 //!                              0, // request for USB device, gets HW serial number
 //!                              0, // value (ignored for this request)
 //!                              0, // index (ignored for this request)
-//!                              &mut unique_id,
+//!                              Some(&mut unique_id),
 //!                              1000).unwrap();
 //!
 //!     // do stuff with unique_id ...
@@ -58,6 +89,7 @@
 
 
 #![allow(non_snake_case)]
+#![allow(clippy::too_many_arguments)]
 
 extern crate libc;
 
@@ -70,24 +102,180 @@ extern crate bitflags;
 #[cfg(feature="mio")]
 extern crate mio;
 
+#[cfg(feature="async")]
+extern crate async_io;
+
+#[cfg(feature="tokio")]
+extern crate tokio;
+#[cfg(feature="tokio")]
+extern crate futures_core;
+#[cfg(feature="serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature="log")]
+extern crate log;
+
+#[macro_use]
+mod logging;
+
+mod error;
+pub use error::*;
+
 mod usbtypes;
 pub use usbtypes::*;
 
 mod devfs;
-pub use devfs::{UrbType, UrbFlags};
+pub use devfs::{UrbType, UrbFlags, Capabilities, DisconnectClaimFlags};
 //pub use devfs::UrbFlags; //::{URB_SHORT_NOT_OK, URB_ISO_ASAP, URB_BULK_CONTINUATION, URB_NO_FSBR,
                 //URB_ZERO_PACKET, URB_NO_INTERRUPT};
-pub use devfs::{Urb, IsoPacketDesc};
+pub use devfs::{Urb, UrbPacketsOrStream, IsoPacketDesc, ConnectInfo};
+
+mod layout;
 
 mod deviceinfo;
 pub use deviceinfo::*;
 
+mod devicefilter;
+pub use devicefilter::*;
+
+mod deviceid;
+pub use deviceid::*;
+
+mod hotplug;
+pub use hotplug::*;
+
 mod device;
 pub use device::*;
 
+mod backend;
+pub use backend::*;
+
+mod diagnose;
+pub use diagnose::*;
+
+mod capture;
+pub use capture::*;
+
+mod usbmon;
+pub use usbmon::*;
+
+#[cfg(feature="mock")]
+mod mockbackend;
+#[cfg(feature="mock")]
+pub use mockbackend::*;
+
+#[cfg(feature="reactor")]
+mod reactor;
+#[cfg(feature="reactor")]
+pub use reactor::*;
+
+mod speed;
+pub use speed::*;
+
+mod power;
+pub use power::*;
+
+mod authorize;
+pub use authorize::*;
+
+mod largetransfer;
+
+mod endpointio;
+pub use endpointio::*;
+
+mod controlretry;
+pub use controlretry::*;
+
+mod controlrequest;
+pub use controlrequest::*;
+
+mod controlbatch;
+pub use controlbatch::*;
+
+mod standard;
+pub use standard::*;
+
+mod hub;
+pub use hub::*;
+
+mod descriptors;
+pub use descriptors::{ConfigDescriptor, InterfaceDescriptor, EndpointDescriptor, RawDescriptor,
+                      CS_INTERFACE, CS_ENDPOINT};
+
+mod interface;
+pub use interface::*;
+
+mod association;
+pub use association::*;
+
+mod bos;
+pub use bos::*;
+
+mod msos;
+pub use msos::*;
+
+mod report;
+pub use report::*;
+
+#[cfg(feature="hid")]
+mod hiddescriptor;
+#[cfg(feature="hid")]
+pub use hiddescriptor::*;
+#[cfg(feature="hid")]
+mod hid;
+#[cfg(feature="hid")]
+pub use hid::*;
+
+#[cfg(feature="cdc")]
+mod cdcdescriptor;
+#[cfg(feature="cdc")]
+pub use cdcdescriptor::*;
+#[cfg(feature="cdc")]
+mod cdcacm;
+#[cfg(feature="cdc")]
+pub use cdcacm::*;
+
+#[cfg(feature="uac")]
+mod uacdescriptor;
+#[cfg(feature="uac")]
+pub use uacdescriptor::*;
+#[cfg(feature="uac")]
+mod uac;
+#[cfg(feature="uac")]
+pub use uac::*;
+
+#[cfg(feature="uvc")]
+mod uvcdescriptor;
+#[cfg(feature="uvc")]
+pub use uvcdescriptor::*;
+#[cfg(feature="uvc")]
+mod uvc;
+#[cfg(feature="uvc")]
+pub use uvc::*;
+#[cfg(feature="dfu")]
+mod dfu;
+#[cfg(feature="dfu")]
+pub use dfu::*;
+#[cfg(feature="usbtmc")]
+mod usbtmc;
+#[cfg(feature="usbtmc")]
+pub use usbtmc::*;
+
+mod transferresult;
+pub use transferresult::*;
+
 mod asyncdevice;
 pub use asyncdevice::*;
 
+mod dyntransfer;
+pub use dyntransfer::*;
+
+mod splitdevice;
+pub use splitdevice::*;
+
+mod transferscope;
+pub use transferscope::*;
+
 mod monotransfer;
 pub use monotransfer::*;
 
@@ -96,3 +284,40 @@ pub use stdbuftransfer::*;
 
 mod isobuftransfer;
 pub use isobuftransfer::*;
+
+mod isostream;
+pub use isostream::*;
+
+mod bench;
+pub use bench::*;
+
+mod interruptstream;
+pub use interruptstream::*;
+
+mod pacedinterrupt;
+pub use pacedinterrupt::*;
+
+mod bulkwriter;
+pub use bulkwriter::*;
+
+mod bulkreader;
+pub use bulkreader::*;
+
+mod mmapbuffer;
+pub use mmapbuffer::*;
+
+mod alignedbuf;
+pub use alignedbuf::AlignedBuf;
+
+mod bufferpool;
+pub use bufferpool::*;
+
+#[cfg(feature="async")]
+mod futuredevice;
+#[cfg(feature="async")]
+pub use futuredevice::*;
+
+#[cfg(feature="tokio")]
+mod tokiodevice;
+#[cfg(feature="tokio")]
+pub use tokiodevice::*;
@@ -0,0 +1,42 @@
+// Instrumentation through the `log` crate, under the `usbfs` target, with the `log` feature.
+// Opening, claiming, and failures are logged at debug level; every submit and reap at trace
+// level.  Without the feature the macros compile to nothing, though their arguments are still
+// type checked.  `tracing` subscribers pick these up through `tracing-log`.
+
+#[cfg(feature="log")]
+macro_rules! log_debug {
+    ($($arg:tt)+) => { ::log::debug!(target: "usbfs", $($arg)+) }
+}
+
+#[cfg(feature="log")]
+macro_rules! log_trace {
+    ($($arg:tt)+) => { ::log::trace!(target: "usbfs", $($arg)+) }
+}
+
+#[cfg(not(feature="log"))]
+macro_rules! log_debug {
+    ($($arg:tt)+) => { if false { let _ = format_args!($($arg)+); } }
+}
+
+#[cfg(not(feature="log"))]
+macro_rules! log_trace {
+    ($($arg:tt)+) => { if false { let _ = format_args!($($arg)+); } }
+}
+
+use std::fmt;
+use std::os::unix::io::AsRawFd;
+
+use super::*;
+
+// Identifies a device in log records: its bus and device number, or its file descriptor if it
+// wasn't opened from a usbfs node.
+pub(crate) struct LogName<'a>(pub(crate) &'a Device);
+
+impl<'a> fmt::Display for LogName<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match deviceinfo::busdev_from_fd(self.0) {
+            Ok((busnum, devnum)) => write!(f, "{:03}:{:03}", busnum, devnum),
+            Err(_) => write!(f, "fd {}", self.0.as_raw_fd()),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::{ptr, slice};
+use std::os::unix::io::AsRawFd;
+
+use nix::sys::mman::{mmap, munmap, ProtFlags, MapFlags};
+
+use super::*;
+
+/// Transfer buffer allocated by usbfs itself.
+///
+/// On kernels with `Capabilities::MMAP`, memory mapped from the usbfs file descriptor is
+/// handed to the host controller directly, avoiding a copy between user and kernel memory on
+/// every URB.  `MmapBuffer` implements `AsRef<[u8]>` and `AsMut<[u8]>` so it can be used as
+/// the buffer of any transfer type.
+///
+/// The buffer is only zero-copy when used with transfers submitted to the same device it was
+/// allocated from.  Where usbfs can't provide the memory, `new_or_heap()` falls back to an
+/// ordinary heap buffer, so code can use `MmapBuffer` unconditionally.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let buf = MmapBuffer::new(&device, 16384).unwrap();
+/// let xfer = Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), buf));
+/// let mut asyncdevice: AsyncDevice<_> = device.into();
+/// asyncdevice.submit(xfer).unwrap();
+/// ```
+pub struct MmapBuffer {
+    ptr: *mut u8,
+    len: usize,
+    // false for the heap fallback of `new_or_heap()`, which `ptr` then owns as a boxed slice
+    mapped: bool,
+}
+
+// The mapping is exclusively owned by the MmapBuffer.
+unsafe impl Send for MmapBuffer {}
+
+impl MmapBuffer {
+    /// Allocate a `len` byte buffer from `device`.  Fails with `ErrorKind::Unsupported` if
+    /// the kernel doesn't support usbfs mmap.
+    pub fn new(device: &Device, len: usize) -> Result<MmapBuffer> {
+        if len == 0 {
+            return Err(Error::new(ErrorKind::InvalidParam, "zero length buffer"));
+        }
+        if !device.capabilities()?.contains(Capabilities::MMAP) {
+            return Err(Error::new(ErrorKind::Unsupported, "usbfs mmap not supported"));
+        }
+        let ptr = unsafe {
+            devfs::nix_result_to_result(mmap(ptr::null_mut(),
+                                                len,
+                                                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                                                MapFlags::MAP_SHARED,
+                                                device.as_raw_fd(),
+                                                0))?
+        };
+        Ok(MmapBuffer{ptr: ptr as *mut u8, len, mapped: true})
+    }
+
+    /// Allocate a `len` byte buffer from `device` as with `new()`, or from the heap if the
+    /// kernel doesn't support usbfs mmap or the mapping fails, eg. because the memory usbfs
+    /// allows for transfer buffers is used up.  `is_mapped()` tells which it got.
+    pub fn new_or_heap(device: &Device, len: usize) -> Result<MmapBuffer> {
+        if len == 0 {
+            return Err(Error::new(ErrorKind::InvalidParam, "zero length buffer"));
+        }
+        let caps = match device.capabilities() {
+            Err(ref err) if err.kind() == ErrorKind::Unsupported => Capabilities::empty(),
+            caps => caps?,
+        };
+        if caps.contains(Capabilities::MMAP) {
+            if let Ok(buf) = MmapBuffer::new(device, len) {
+                return Ok(buf);
+            }
+        }
+        let ptr = Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8;
+        Ok(MmapBuffer{ptr, len, mapped: false})
+    }
+
+    /// Whether the buffer is memory mapped from usbfs, rather than the heap fallback of
+    /// `new_or_heap()`.
+    pub fn is_mapped(&self) -> bool {
+        self.mapped
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl AsRef<[u8]> for MmapBuffer {
+    fn as_ref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl AsMut<[u8]> for MmapBuffer {
+    fn as_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for MmapBuffer {
+    fn drop(&mut self) {
+        match self.mapped {
+            true => { let _ = unsafe { munmap(self.ptr as *mut _, self.len) }; }
+            false => drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(self.ptr, self.len)) }),
+        }
+    }
+}
@@ -0,0 +1,504 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::FromRawFd;
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex};
+
+use nix::errno::Errno;
+
+use super::*;
+use devfs::{c_int, c_uint, c_void, ioctl_num_type, BulkTransfer, CtrlTransfer, DisconnectClaim, SetInterface};
+
+/// Scripted outcome of a transfer on a `MockBackend`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockResponse {
+    /// Complete successfully.  IN transfers receive the data, failing with `EOVERFLOW` if it
+    /// doesn't fit; OUT transfers are accepted in full and ignore it.  Isochronous IN data
+    /// fills the packets in turn.
+    Complete(Vec<u8>),
+    /// Fail with an errno, eg. `libc::EPIPE` for a stall.
+    Fail(i32),
+}
+
+/// Operation recorded by a `MockBackend`, for checking what a driver did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockEvent {
+    /// A control transfer, synchronous or submitted, along with the data of its OUT stage.
+    Control {
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+        data: Vec<u8>,
+    },
+    /// A bulk, interrupt, or isochronous transfer of `length` bytes, along with the data of
+    /// OUT transfers.
+    Transfer {
+        endpoint: u8,
+        length: usize,
+        data: Vec<u8>,
+    },
+    ClaimInterface(u32),
+    ReleaseInterface(u32),
+    SetInterface {
+        interface: u32,
+        altsetting: u32,
+    },
+    SetConfiguration(u32),
+    ClearHalt(u8),
+    ResetEndpoint(u8),
+}
+
+/// A `Backend` simulating a device from scripted responses, available with the `mock`
+/// feature.
+///
+/// Responses are queued per endpoint with `push()`, with both directions of endpoint 0 sharing
+/// the control queue, and are consumed by transfers on that endpoint in order.  A submitted urb
+/// without a response stays pending until one is pushed, the urb is discarded, or the mock is
+/// `disconnect()`ed; a synchronous transfer without one fails with `ETIMEDOUT` straight away.
+/// Every transfer and interface operation is recorded, and `take_events()` hands the record
+/// back.  Other requests get plausible defaults: no kernel driver is bound, the speed is high
+/// speed unless changed with `set_speed()`, and requests the mock doesn't know fail with
+/// `ENOTTY`.
+///
+/// The device's file descriptor is an eventfd made writable while a completed urb awaits
+/// reaping, so `AsyncDevice` waits and event loops behave as they would on usbfs.
+///
+/// # Examples
+/// ```
+/// use usbfs::*;
+///
+/// let mock = MockBackend::new().unwrap();
+/// let device = mock.device().unwrap();
+///
+/// // a vendor request reading a serial number
+/// mock.push(0, MockResponse::Complete(vec![1, 2, 3, 4]));
+/// let serial = ControlRequest::new()
+///     .request_type(SetupType::Vendor)
+///     .request(0x01)
+///     .data_in(4)
+///     .send(&device)
+///     .unwrap();
+/// assert_eq!(serial, [1, 2, 3, 4]);
+///
+/// // a bulk read completes once its data arrives
+/// let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = device.into();
+/// device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 64]))).unwrap();
+/// mock.push(0x81, MockResponse::Complete(b"hello".to_vec()));
+/// let (_slot, xfer, result) = device.reap_wait().unwrap();
+/// assert_eq!(result, TransferResult::Completed{len: 5});
+/// assert_eq!(&xfer.buf[..5], b"hello");
+/// ```
+#[derive(Clone)]
+pub struct MockBackend(Arc<MockShared>);
+
+struct MockShared {
+    state: Mutex<MockState>,
+    completion: Condvar,
+    file: File,
+}
+
+struct MockState {
+    responses: HashMap<u8, VecDeque<MockResponse>>,
+    pending: Vec<UrbPtr>,
+    completed: VecDeque<UrbPtr>,
+    events: Vec<MockEvent>,
+    speed: Speed,
+    capabilities: Capabilities,
+    disconnected: bool,
+    ready: bool,
+}
+
+// A urb handed to the mock by a submit, owned by the submitter's transfer until reaped.
+struct UrbPtr(*mut Urb);
+
+unsafe impl Send for UrbPtr {}
+
+// Adding this to an eventfd's counter leaves it at its maximum, where it polls unwritable.
+const EVENTFD_FULL: u64 = u64::MAX - 1;
+
+// Both directions of the default control pipe share a queue.
+fn queue_key(endpoint: u8) -> u8 {
+    match endpoint & 0x7f {
+        0 => 0,
+        _ => endpoint,
+    }
+}
+
+// Play `response` to a transfer into or out of the `length` bytes at `buf`, giving the negated
+// errno and number of bytes transferred.
+unsafe fn play(response: MockResponse, is_in: bool, buf: *mut u8, length: usize) -> (i32, usize) {
+    match response {
+        MockResponse::Fail(errno) => (-errno, 0),
+        MockResponse::Complete(_) if !is_in => (0, length),
+        MockResponse::Complete(data) => {
+            let len = std::cmp::min(data.len(), length);
+            ptr::copy_nonoverlapping(data.as_ptr(), buf, len);
+            match data.len() > length {
+                true => (-libc::EOVERFLOW, len),
+                false => (0, len),
+            }
+        }
+    }
+}
+
+// Play `response` to a submitted urb, leaving it as the kernel would on completion.
+unsafe fn complete_urb(urb: *mut Urb, response: MockResponse) {
+    let urb = &mut *urb;
+    let is_in = urb.endpoint & 0x80 != 0;
+    let (status, len) = if urb.urbtype == UrbType::Control as u8 {
+        let setup = urb.buffer;
+        let length = (urb.buffer_length as usize).saturating_sub(8);
+        play(response, *setup & 0x80 != 0, setup.add(8), length)
+    } else if urb.urbtype == UrbType::Iso as u8 {
+        let packets = devfs::iso_frame_desc_mut(urb as *mut Urb);
+        let mut data = match response {
+            MockResponse::Complete(ref data) if is_in => &data[..],
+            _ => &[][..],
+        };
+        let (mut offset, mut total) = (0, 0);
+        urb.error_count = 0;
+        for packet in packets {
+            let length = packet.length as usize;
+            let (status, len) = match response {
+                MockResponse::Complete(_) if is_in => {
+                    let len = std::cmp::min(data.len(), length);
+                    let (head, rest) = data.split_at(len);
+                    data = rest;
+                    play(MockResponse::Complete(head.to_vec()), true, urb.buffer.add(offset), length)
+                }
+                ref response => play(response.clone(), is_in, urb.buffer.add(offset), length),
+            };
+            packet.status = status;
+            packet.actual_length = len as i32;
+            offset += length;
+            total += len;
+            if status != 0 {
+                urb.error_count += 1;
+            }
+        }
+        match response {
+            MockResponse::Fail(errno) => (-errno, 0),
+            MockResponse::Complete(_) => (0, total),
+        }
+    } else {
+        let (status, len) = play(response, is_in, urb.buffer, urb.buffer_length as usize);
+        match status == 0 && is_in && urb.flags.contains(UrbFlags::URB_SHORT_NOT_OK) && len < urb.buffer_length as usize {
+            true => (-libc::EREMOTEIO, len),
+            false => (status, len),
+        }
+    };
+    urb.status = status;
+    urb.actual_length = len as i32;
+}
+
+// What a submitted urb does, for the record.
+unsafe fn urb_event(urb: &Urb) -> MockEvent {
+    let buf = |offset: usize, len: usize| std::slice::from_raw_parts(urb.buffer.add(offset), len).to_vec();
+    if urb.urbtype == UrbType::Control as u8 {
+        let setup = buf(0, 8);
+        let length = descriptors::le16(&setup, 6);
+        MockEvent::Control {
+            request_type: setup[0],
+            request: setup[1],
+            value: descriptors::le16(&setup, 2),
+            index: descriptors::le16(&setup, 4),
+            length,
+            data: match setup[0] & 0x80 {
+                0 => buf(8, length as usize),
+                _ => Vec::new(),
+            },
+        }
+    } else {
+        // like usbfs, total the packets of isochronous urbs rather than trust buffer_length
+        let length = match urb.urbtype == UrbType::Iso as u8 {
+            true => devfs::iso_frame_desc(urb).iter().map(|packet| packet.length as usize).sum(),
+            false => urb.buffer_length as usize,
+        };
+        MockEvent::Transfer {
+            endpoint: urb.endpoint,
+            length,
+            data: match urb.endpoint & 0x80 {
+                0 => buf(0, length),
+                _ => Vec::new(),
+            },
+        }
+    }
+}
+
+impl MockBackend {
+    pub fn new() -> Result<MockBackend> {
+        let fd = devfs::nix_result_to_result(Errno::result(unsafe {
+            libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK)
+        }))?;
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(&EVENTFD_FULL.to_ne_bytes())?;
+        Ok(MockBackend(Arc::new(MockShared {
+            state: Mutex::new(MockState {
+                responses: HashMap::new(),
+                pending: Vec::new(),
+                completed: VecDeque::new(),
+                events: Vec::new(),
+                speed: Speed::High,
+                capabilities: Capabilities::ZERO_PACKET | Capabilities::BULK_CONTINUATION
+                    | Capabilities::NO_PACKET_SIZE_LIM | Capabilities::REAP_AFTER_DISCONNECT,
+                disconnected: false,
+                ready: false,
+            }),
+            completion: Condvar::new(),
+            file,
+        })))
+    }
+
+    /// A `Device` on the mock.  Any number may be made, all seeing the same simulated device.
+    pub fn device(&self) -> Result<Device> {
+        Ok(Device::with_backend(self.0.file.try_clone()?, Arc::new(self.clone())))
+    }
+
+    /// Script the outcome of the next transfer on `endpoint`, including its direction bit.
+    /// Completes the oldest pending urb on the endpoint, if there is one.
+    pub fn push(&self, endpoint: u8, response: MockResponse) {
+        let mut state = self.lock();
+        let key = queue_key(endpoint);
+        match state.pending.iter().position(|urb| unsafe { queue_key((*urb.0).endpoint) } == key) {
+            Some(i) => {
+                let urb = state.pending.remove(i);
+                unsafe { complete_urb(urb.0, response) };
+                self.complete(&mut state, urb);
+            }
+            None => state.responses.entry(key).or_default().push_back(response),
+        }
+    }
+
+    /// Speed reported for the device.
+    pub fn set_speed(&self, speed: Speed) {
+        self.lock().speed = speed;
+    }
+
+    /// Capabilities reported for the kernel.  Defaults to `ZERO_PACKET`, `BULK_CONTINUATION`,
+    /// `NO_PACKET_SIZE_LIM`, and `REAP_AFTER_DISCONNECT`.
+    pub fn set_capabilities(&self, capabilities: Capabilities) {
+        self.lock().capabilities = capabilities;
+    }
+
+    /// Number of submitted urbs still waiting for a response.
+    pub fn pending_count(&self) -> usize {
+        self.lock().pending.len()
+    }
+
+    /// Operations recorded since the last call, oldest first.
+    pub fn take_events(&self) -> Vec<MockEvent> {
+        std::mem::take(&mut self.lock().events)
+    }
+
+    /// Simulate unplugging the device.  Pending urbs complete with `ESHUTDOWN` and can still
+    /// be reaped, unless the capabilities lack `REAP_AFTER_DISCONNECT`; everything else fails
+    /// with `ENODEV`.
+    pub fn disconnect(&self) {
+        let mut state = self.lock();
+        state.disconnected = true;
+        let reapable = state.capabilities.contains(Capabilities::REAP_AFTER_DISCONNECT);
+        for urb in std::mem::take(&mut state.pending) {
+            // killed urbs that will never be reaped are never written back either
+            if reapable {
+                unsafe { complete_urb(urb.0, MockResponse::Fail(libc::ESHUTDOWN)) };
+                self.complete(&mut state, urb);
+            }
+        }
+        if !reapable {
+            state.completed.clear();
+        }
+        self.update_ready(&mut state);
+    }
+
+    /// Simulate the start of an unplug, before the kernel has killed the device's urbs:
+    /// everything fails with `ENODEV`, reaps included once no completed urb is left, but
+    /// pending urbs stay pending until `disconnect()` or a response completes them.
+    pub fn begin_disconnect(&self) {
+        let mut state = self.lock();
+        state.disconnected = true;
+        self.update_ready(&mut state);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.0.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn complete(&self, state: &mut MockState, urb: UrbPtr) {
+        state.completed.push_back(urb);
+        self.update_ready(state);
+        self.0.completion.notify_all();
+    }
+
+    // Make the eventfd writable exactly when reaping wouldn't block, as usbfs does.
+    fn update_ready(&self, state: &mut MockState) {
+        let ready = state.disconnected || !state.completed.is_empty();
+        if ready != state.ready {
+            let mut counter = [0u8; 8];
+            let _ = match ready {
+                true => (&self.0.file).read(&mut counter),
+                false => (&self.0.file).write(&EVENTFD_FULL.to_ne_bytes()),
+            };
+            state.ready = ready;
+        }
+    }
+
+    // Outcome of a synchronous transfer of `length` bytes at `buf`.
+    unsafe fn transfer(&self, state: &mut MockState, endpoint: u8, is_in: bool, buf: *mut u8, length: usize) -> nix::Result<c_int> {
+        let response = state.responses.get_mut(&queue_key(endpoint)).and_then(|queue| queue.pop_front());
+        match response.map(|response| play(response, is_in, buf, length)) {
+            Some((0, len)) => Ok(len as c_int),
+            Some((status, _)) => Err(Errno::from_i32(-status)),
+            None => Err(Errno::ETIMEDOUT),
+        }
+    }
+
+    unsafe fn reap(&self, arg: *mut c_void, wait: bool) -> nix::Result<c_int> {
+        let mut state = self.lock();
+        loop {
+            // older kernels refuse to reap from a disconnected device at all
+            if state.disconnected && !state.capabilities.contains(Capabilities::REAP_AFTER_DISCONNECT) {
+                return Err(Errno::ENODEV);
+            }
+            if let Some(urb) = state.completed.pop_front() {
+                *(arg as *mut *mut Urb) = urb.0;
+                self.update_ready(&mut state);
+                return Ok(0);
+            }
+            if state.disconnected {
+                return Err(Errno::ENODEV);
+            }
+            if !wait {
+                return Err(Errno::EAGAIN);
+            }
+            state = self.0.completion.wait(state).unwrap_or_else(|err| err.into_inner());
+        }
+    }
+}
+
+unsafe impl Backend for MockBackend {
+    unsafe fn ioctl(&self, request: ioctl_num_type, arg: *mut c_void) -> nix::Result<c_int> {
+        match request {
+            devfs::REAPURB => return self.reap(arg, true),
+            devfs::REAPURBNDELAY => return self.reap(arg, false),
+            _ => (),
+        }
+
+        let mut state = self.lock();
+        if state.disconnected {
+            return Err(Errno::ENODEV);
+        }
+        match request {
+            devfs::CONTROL => {
+                let xfer = &*(arg as *const CtrlTransfer);
+                let is_in = xfer.bmRequestType & 0x80 != 0;
+                let length = xfer.wLength as usize;
+                state.events.push(MockEvent::Control {
+                    request_type: xfer.bmRequestType,
+                    request: xfer.bRequest,
+                    value: xfer.wValue,
+                    index: xfer.wIndex,
+                    length: xfer.wLength,
+                    data: match is_in || length == 0 {
+                        true => Vec::new(),
+                        false => std::slice::from_raw_parts(xfer.data, length).to_vec(),
+                    },
+                });
+                self.transfer(&mut state, 0, is_in, xfer.data, length)
+            }
+            devfs::BULK => {
+                let xfer = &*(arg as *const BulkTransfer);
+                let endpoint = xfer.ep as u8;
+                let is_in = endpoint & 0x80 != 0;
+                let length = xfer.len as usize;
+                state.events.push(MockEvent::Transfer {
+                    endpoint,
+                    length,
+                    data: match is_in || length == 0 {
+                        true => Vec::new(),
+                        false => std::slice::from_raw_parts(xfer.data, length).to_vec(),
+                    },
+                });
+                self.transfer(&mut state, endpoint, is_in, xfer.data, length)
+            }
+            devfs::SUBMITURB => {
+                let urb = arg as *mut Urb;
+                // like the kernel, refuse control urbs without room for the setup packet
+                if (*urb).urbtype == UrbType::Control as u8 && (*urb).buffer_length < 8 {
+                    return Err(Errno::EINVAL);
+                }
+                state.events.push(urb_event(&*urb));
+                (*urb).status = -libc::EINPROGRESS;
+                let response = state.responses.get_mut(&queue_key((*urb).endpoint)).and_then(|queue| queue.pop_front());
+                match response {
+                    Some(response) => {
+                        complete_urb(urb, response);
+                        self.complete(&mut state, UrbPtr(urb));
+                    }
+                    None => state.pending.push(UrbPtr(urb)),
+                }
+                Ok(0)
+            }
+            devfs::DISCARDURB => {
+                match state.pending.iter().position(|urb| urb.0 == arg as *mut Urb) {
+                    Some(i) => {
+                        let urb = state.pending.remove(i);
+                        complete_urb(urb.0, MockResponse::Fail(libc::ENOENT));
+                        self.complete(&mut state, urb);
+                        Ok(0)
+                    }
+                    None => Err(Errno::EINVAL),
+                }
+            }
+            devfs::CLAIMINTERFACE => {
+                state.events.push(MockEvent::ClaimInterface(*(arg as *const c_uint)));
+                Ok(0)
+            }
+            devfs::DISCONNECT_CLAIM => {
+                state.events.push(MockEvent::ClaimInterface((*(arg as *const DisconnectClaim)).interface));
+                Ok(0)
+            }
+            devfs::RELEASEINTERFACE => {
+                state.events.push(MockEvent::ReleaseInterface(*(arg as *const c_uint)));
+                Ok(0)
+            }
+            devfs::SETINTERFACE => {
+                let data = &*(arg as *const SetInterface);
+                state.events.push(MockEvent::SetInterface { interface: data.interface, altsetting: data.altsetting });
+                Ok(0)
+            }
+            devfs::SETCONFIGURATION => {
+                state.events.push(MockEvent::SetConfiguration(*(arg as *const c_uint)));
+                Ok(0)
+            }
+            devfs::CLEAR_HALT => {
+                state.events.push(MockEvent::ClearHalt(*(arg as *const c_uint) as u8));
+                Ok(0)
+            }
+            devfs::RESETEP => {
+                state.events.push(MockEvent::ResetEndpoint(*(arg as *const c_uint) as u8));
+                Ok(0)
+            }
+            devfs::GETDRIVER => Err(Errno::ENODATA),
+            devfs::CONNECTINFO => {
+                *(arg as *mut ConnectInfo) = ConnectInfo { devnum: 1, slow: (state.speed == Speed::Low) as u8 };
+                Ok(0)
+            }
+            devfs::GET_CAPABILITIES => {
+                ptr::write_unaligned(arg as *mut u32, state.capabilities.bits());
+                Ok(0)
+            }
+            // enum usb_device_speed
+            devfs::GET_SPEED => Ok(match state.speed {
+                Speed::Low => 1,
+                Speed::Full => 2,
+                Speed::High => 3,
+                Speed::Super => 5,
+                Speed::SuperPlus => 6,
+            }),
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+}
@@ -1,19 +1,23 @@
 use super::*;
 
-use std::slice;
-
 //////////////////////////////////////////////////////////////////////////////
-///
-/// ControlTransferMut
-///
+//
+// ControlTransferMut
+//
 
 /// Control Transfer on mutable buffer.  IN and OUT transfers permitted.
-/// Will panic if buffer is smaller than 8 bytes when wire_urb() is called.
+/// The buffer must have room for the 8 byte setup packet; a shorter buffer fails on
+/// submission with `ErrorKind::InvalidParam`, or up front with `try_new()`.
 /// Note there is no immutable version of this struct because setup packet
 /// always needs to be written to the given buffer.
+///
+/// By default the whole buffer after the setup packet is the data stage, so `wLength` is the
+/// buffer length less 8.  `set_length()` limits the data stage to the start of the payload,
+/// so one buffer can serve requests of differing sizes.
 pub struct ControlTransferMut<B> {
   pub urb: Urb,
-  setup: Setup<BusEndian>,
+  setup: Setup,
+  length: Option<u16>,
   pub buf: B,
 }
 impl<B> ControlTransferMut<B> {
@@ -36,30 +40,127 @@ impl<B> ControlTransferMut<B> {
       value,
       index,
       0,  // wLength, set at wire_urb time
-    ).into();
+    );
 
+    let mut xfer = Self::from_setup(setup, buf);
+    xfer.urb.flags = flags;
+    xfer
+  }
+
+  /// Construct a transfer from a prepared setup packet, which can be kept and reused for
+  /// requests that differ only in `wValue` or `wIndex`.  `wLength` of `setup` is ignored;
+  /// it follows the buffer and `set_length()`, as with `new()`.
+  ///
+  /// # Examples
+  /// ```no_run
+  /// use usbfs::*;
+  ///
+  /// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+  /// let mut device: AsyncDevice<Box<ControlTransferMut<Vec<u8>>>> = device.into();
+  /// let read_register = Setup::new(SetupDirection::DeviceToHost, SetupType::Vendor,
+  ///                                SetupRecipient::Device, 0x05, 0, 0, 4);
+  /// let mut xfer = Box::new(ControlTransferMut::from_setup(read_register, vec![0; 8 + 4]));
+  /// for register in 0..1000 {
+  ///     xfer.set_index(register);
+  ///     device.submit(xfer).unwrap();
+  ///     let (_slot, reaped, _result) = device.reap_wait().unwrap();
+  ///     println!("{:04x}: {:02x?}", register, reaped.payload());
+  ///     xfer = reaped;
+  /// }
+  /// ```
+  pub fn from_setup(setup: Setup, buf: B) -> Self {
     let urb = Urb {
       urbtype: UrbType::Control as u8,
       endpoint: 0, // endpoint
-      flags,
       ..Urb::default()
     };
 
-    ControlTransferMut {urb, setup, buf}
+    ControlTransferMut {urb, setup, length: None, buf}
+  }
+
+  /// Like `new()`, but fails with `ErrorKind::InvalidParam` if `buf` can't hold the setup
+  /// packet plus at most 65535 bytes of payload.
+  pub fn try_new(
+    direction: SetupDirection,
+    stype: SetupType,
+    recipient: SetupRecipient,
+    request: u8,
+    value: u16,
+    index: u16,
+    flags: UrbFlags,
+    buf: B,
+  ) -> Result<Self>
+  where B: AsMut<[u8]>
+  {
+    let mut xfer = Self::try_from_setup(Setup::new(direction, stype, recipient, request, value, index, 0), buf)?;
+    xfer.urb.flags = flags;
+    Ok(xfer)
+  }
+
+  /// Like `from_setup()`, but fails with `ErrorKind::InvalidParam` if `buf` can't hold the
+  /// setup packet plus at most 65535 bytes of payload.
+  pub fn try_from_setup(setup: Setup, mut buf: B) -> Result<Self>
+  where B: AsMut<[u8]>
+  {
+    let len = buf.as_mut().len();
+    if len < 8 || len - 8 > u16::MAX as usize {
+      return Err(Error::new(ErrorKind::InvalidParam, "control buffer must be 8 to 65543 bytes"));
+    }
+    Ok(Self::from_setup(setup, buf))
+  }
+
+  /// Consume the reaped transfer, returning its buffer and a copy of its outcome.
+  pub fn into_result(self) -> (B, TransferOutcome) {
+    (self.buf, TransferOutcome::from_urb(&self.urb, &[]))
+  }
+
+  /// Set `wLength`, the size of the data stage: the number of bytes to request for IN
+  /// transfers, or the number of payload bytes to send for OUT transfers.  The rest of the
+  /// buffer is left alone.  `None` uses the whole payload, and a length beyond the end of the
+  /// buffer is cut short to fit.
+  pub fn set_length(&mut self, length: Option<u16>) {
+    self.length = length;
+  }
+
+  /// Builder form of `set_length()`.
+  pub fn with_length(mut self, length: u16) -> Self {
+    self.length = Some(length);
+    self
+  }
+
+  pub fn length(&self) -> Option<u16> {
+    self.length
   }
 
-  /// Access to portion of buffer after the setup packet (the payload).
+  /// The setup packet.  `wLength` is that of the last submission.
+  pub fn setup(&self) -> &Setup {
+    &self.setup
+  }
+
+  /// Set `wValue` for the next submission, leaving the rest of the request as it is.
+  pub fn set_value(&mut self, value: u16) {
+    self.setup.wValue = value;
+  }
+
+  /// Set `wIndex` for the next submission, leaving the rest of the request as it is.
+  pub fn set_index(&mut self, index: u16) {
+    self.setup.wIndex = index;
+  }
+
+  /// Access to portion of buffer after the setup packet (the payload).  Empty if the buffer
+  /// is too short to hold the setup packet.
   pub fn payload(&self) -> &[u8]
   where B: AsRef<[u8]>
   {
-    & self.buf.as_ref()[8..]
+    self.buf.as_ref().get(8..).unwrap_or(&[])
   }
 
-  /// Mutable access to portion of buffer after the setup packet (the payload).
+  /// Mutable access to portion of buffer after the setup packet (the payload).  Empty if the
+  /// buffer is too short to hold the setup packet.
   pub fn payload_mut(&mut self) -> &mut [u8]
   where B: AsMut<[u8]>
   {
-    &mut self.buf.as_mut()[8..]
+    self.buf.as_mut().get_mut(8..).unwrap_or(&mut [])
   }
 }
 
@@ -68,27 +169,52 @@ unsafe impl<B: AsMut<[u8]>> Transfer for ControlTransferMut<B> {
 
     let mbuf: &mut [u8] = self.buf.as_mut();
 
-    // write setup packet to buffer
-    if mbuf.len() < 8 {
-      panic!("buffer too short for setup packet, min size is 8 bytes");
-    }
-    self.setup.wLength = ((mbuf.len() - 8) as u16).to_le();
-    unsafe {
-      mbuf[0..8].copy_from_slice(slice::from_raw_parts(&self.setup as *const Setup<BusEndian> as *const u8, 8));
-      // mbuf[0..8].copy_from_slice(slice::from_raw_parts(&self.setup as *const u8, 8));
+    // write setup packet to buffer.  Without room for it the kernel rejects the urb.
+    let mut len = mbuf.len();
+    if len >= 8 {
+      let available = std::cmp::min(len - 8, u16::MAX as usize) as u16;
+      self.setup.wLength = self.length.map_or(available, |l| std::cmp::min(l, available));
+      mbuf[0..8].copy_from_slice(&self.setup.to_le_bytes());
+      len = 8 + self.setup.wLength as usize;
     }
 
-    // wire up the urb
-    self.urb.buffer = mbuf.as_mut_ptr() as *mut u8;
-    self.urb.buffer_length = mbuf.len() as i32;
+    // wire up the urb, covering only the setup packet and the data stage
+    self.urb.buffer = mbuf.as_mut_ptr();
+    self.urb.buffer_length = len as i32;
     &mut self.urb
   }
 }
 
+// Flag helpers shared by the bulk and interrupt transfers.
+macro_rules! packet_flag_helpers {
+  ($name:ident) => {
+    impl<B> $name<B> {
+      /// Terminate an OUT transfer whose length is a multiple of the maximum packet size with a
+      /// zero-length packet (`URB_ZERO_PACKET`), so the device can tell where it ends.
+      pub fn with_zlp(mut self) -> Self {
+        self.urb.flags.insert(UrbFlags::URB_ZERO_PACKET);
+        self
+      }
+
+      /// Fail an IN transfer that ends in a short packet (`URB_SHORT_NOT_OK`), reporting
+      /// `TransferResult::ShortPacket` instead of `Completed`.
+      pub fn with_short_not_ok(mut self) -> Self {
+        self.urb.flags.insert(UrbFlags::URB_SHORT_NOT_OK);
+        self
+      }
+    }
+  }
+}
+
+packet_flag_helpers!(BulkTransfer);
+packet_flag_helpers!(BulkTransferMut);
+packet_flag_helpers!(InterruptTransfer);
+packet_flag_helpers!(InterruptTransferMut);
+
 //////////////////////////////////////////////////////////////////////////////
-///
-/// BulkTransfer
-///
+//
+// BulkTransfer
+//
 
 /// Bulk Transfer on immutable buffer.  Only OUT transfers permitted.
 pub struct BulkTransfer<B> {
@@ -96,6 +222,13 @@ pub struct BulkTransfer<B> {
   pub buf: B,
 }
 impl<B> BulkTransfer<B> {
+  /// Consume the reaped transfer, returning its buffer and a copy of its outcome.
+  pub fn into_result(self) -> (B, TransferOutcome) {
+    (self.buf, TransferOutcome::from_urb(&self.urb, &[]))
+  }
+
+  /// # Panics
+  /// Panics if `endpoint` is an IN endpoint; see `try_new()`.
   pub fn new(endpoint: u8, flags: UrbFlags, buf: B) -> Self {
     assert!(0 == endpoint & 0x80, "can't IN xfer onto immutable buffer");
     BulkTransfer {
@@ -108,6 +241,14 @@ impl<B> BulkTransfer<B> {
       buf,
     }
   }
+
+  /// Like `new()`, but fails with `ErrorKind::InvalidParam` if `endpoint` is an IN endpoint.
+  pub fn try_new(endpoint: u8, flags: UrbFlags, buf: B) -> Result<Self> {
+    if endpoint & 0x80 != 0 {
+      return Err(Error::new(ErrorKind::InvalidParam, "can't IN xfer onto immutable buffer"));
+    }
+    Ok(Self::new(endpoint, flags, buf))
+  }
 }
 unsafe impl<B: AsRef<[u8]>> Transfer for BulkTransfer<B> {
   fn wire_urb(&mut self) -> &mut Urb {
@@ -123,6 +264,11 @@ pub struct BulkTransferMut<B> {
   pub buf: B,
 }
 impl<B> BulkTransferMut<B> {
+  /// Consume the reaped transfer, returning its buffer and a copy of its outcome.
+  pub fn into_result(self) -> (B, TransferOutcome) {
+    (self.buf, TransferOutcome::from_urb(&self.urb, &[]))
+  }
+
   pub fn new(endpoint: u8, flags: UrbFlags, buf: B) -> Self {
     BulkTransferMut {
       urb: Urb {
@@ -137,16 +283,16 @@ impl<B> BulkTransferMut<B> {
 }
 unsafe impl<B: AsMut<[u8]>> Transfer for BulkTransferMut<B> {
   fn wire_urb(&mut self) -> &mut Urb {
-    self.urb.buffer = self.buf.as_mut().as_mut_ptr() as *mut u8;
+    self.urb.buffer = self.buf.as_mut().as_mut_ptr();
     self.urb.buffer_length = self.buf.as_mut().len() as i32;
     &mut self.urb
   }
 }
 
 //////////////////////////////////////////////////////////////////////////////
-///
-/// InterruptTransfer
-///
+//
+// InterruptTransfer
+//
 
 /// Interrupt Transfer on immutable buffer.  Only OUT transfers permitted.
 pub struct InterruptTransfer<B> {
@@ -154,6 +300,13 @@ pub struct InterruptTransfer<B> {
   pub buf: B,
 }
 impl<B> InterruptTransfer<B> {
+  /// Consume the reaped transfer, returning its buffer and a copy of its outcome.
+  pub fn into_result(self) -> (B, TransferOutcome) {
+    (self.buf, TransferOutcome::from_urb(&self.urb, &[]))
+  }
+
+  /// # Panics
+  /// Panics if `endpoint` is an IN endpoint; see `try_new()`.
   pub fn new(endpoint: u8, flags: UrbFlags, buf: B) -> Self {
     assert!(0 == endpoint & 0x80, "not an OUT endpoint");
     InterruptTransfer {
@@ -166,6 +319,14 @@ impl<B> InterruptTransfer<B> {
       buf,
     }
   }
+
+  /// Like `new()`, but fails with `ErrorKind::InvalidParam` if `endpoint` is an IN endpoint.
+  pub fn try_new(endpoint: u8, flags: UrbFlags, buf: B) -> Result<Self> {
+    if endpoint & 0x80 != 0 {
+      return Err(Error::new(ErrorKind::InvalidParam, "not an OUT endpoint"));
+    }
+    Ok(Self::new(endpoint, flags, buf))
+  }
 }
 unsafe impl<B: AsRef<[u8]>> Transfer for InterruptTransfer<B> {
   fn wire_urb(&mut self) -> &mut Urb {
@@ -181,6 +342,11 @@ pub struct InterruptTransferMut<B> {
   pub buf: B,
 }
 impl<B> InterruptTransferMut<B> {
+  /// Consume the reaped transfer, returning its buffer and a copy of its outcome.
+  pub fn into_result(self) -> (B, TransferOutcome) {
+    (self.buf, TransferOutcome::from_urb(&self.urb, &[]))
+  }
+
   pub fn new(endpoint: u8, flags: UrbFlags, buf: B) -> Self {
     InterruptTransferMut {
       urb: Urb {
@@ -195,7 +361,7 @@ impl<B> InterruptTransferMut<B> {
 }
 unsafe impl<B: AsMut<[u8]>> Transfer for InterruptTransferMut<B> {
   fn wire_urb(&mut self) -> &mut Urb {
-    self.urb.buffer = self.buf.as_mut().as_mut_ptr() as *mut u8;
+    self.urb.buffer = self.buf.as_mut().as_mut_ptr();
     self.urb.buffer_length = self.buf.as_mut().len() as i32;
     &mut self.urb
   }
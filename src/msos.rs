@@ -0,0 +1,284 @@
+use super::*;
+use descriptors::{le16, le32, bad_descriptor};
+
+/// Compatible ID feature of a Microsoft OS descriptor, naming the Windows driver for an
+/// interface or function, eg. `"WINUSB"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct MsCompatId {
+    /// First interface of the function, or `None` for a device-wide MS OS 2.0 feature.
+    pub interface: Option<u8>,
+    pub compatible_id: String,
+    pub sub_compatible_id: String,
+}
+
+/// Registry property feature of a Microsoft OS descriptor, eg. `DeviceInterfaceGUIDs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct MsRegistryProperty {
+    /// Interface the property applies to, or `None` for device-wide properties.
+    pub interface: Option<u8>,
+    /// Windows registry type, eg. 1 for `REG_SZ` or 7 for `REG_MULTI_SZ`.
+    pub data_type: u32,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl MsRegistryProperty {
+    /// The value decoded as a string, for the string types `REG_SZ`, `REG_EXPAND_SZ`, and
+    /// `REG_MULTI_SZ`.  Trailing NULs are removed; those separating `REG_MULTI_SZ` entries are
+    /// kept.
+    pub fn string_value(&self) -> Option<String> {
+        match self.data_type {
+            1 | 2 | 7 => Some(utf16le(&self.data)),
+            _ => None,
+        }
+    }
+}
+
+/// Microsoft OS 2.0 platform capability, found in the `BosDescriptor` of devices supporting
+/// MS OS 2.0 descriptors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct MsOs20Info {
+    pub dwWindowsVersion: u32,
+    pub wMSOSDescriptorSetTotalLength: u16,
+    pub bMS_VendorCode: u8,
+    pub bAltEnumCode: u8,
+}
+
+/// Parsed Microsoft OS 2.0 descriptor set.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// if let Some(info) = device.bos(1000).ok().and_then(|bos| bos.ms_os_20()) {
+///     let set = device.get_ms_os_20_descriptor_set(&info, 1000).unwrap();
+///     for id in set.compat_ids {
+///         println!("interface {:?}: {}", id.interface, id.compatible_id);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct MsOs20DescriptorSet {
+    pub dwWindowsVersion: u32,
+    pub compat_ids: Vec<MsCompatId>,
+    pub properties: Vec<MsRegistryProperty>,
+}
+
+// {D8DD60DF-4589-4CC7-9CD2-659D9E648A9F} in its USB byte order
+const MS_OS_20_PLATFORM_UUID: [u8; 16] = [0xdf, 0x60, 0xdd, 0xd8, 0x89, 0x45, 0xc7, 0x4c,
+                                          0x9c, 0xd2, 0x65, 0x9d, 0x9e, 0x64, 0x8a, 0x9f];
+const MS_OS_10_STRING_INDEX: u8 = 0xee;
+const MS_OS_10_EXTENDED_COMPAT_ID: u16 = 4;
+const MS_OS_10_EXTENDED_PROPERTIES: u16 = 5;
+const MS_OS_20_DESCRIPTOR_INDEX: u16 = 7;
+
+impl BosDescriptor {
+    /// The Microsoft OS 2.0 platform capability, if the device has one.
+    pub fn ms_os_20(&self) -> Option<MsOs20Info> {
+        self.capabilities.iter().find_map(|cap| match *cap {
+            DeviceCapability::Platform{uuid, ref data} if uuid == MS_OS_20_PLATFORM_UUID && data.len() >= 8 => {
+                Some(MsOs20Info {
+                    dwWindowsVersion: le32(data, 0),
+                    wMSOSDescriptorSetTotalLength: le16(data, 4),
+                    bMS_VendorCode: data[6],
+                    bAltEnumCode: data[7],
+                })
+            }
+            _ => None,
+        })
+    }
+}
+
+impl MsOs20DescriptorSet {
+    /// Parse a descriptor set as returned by the device.
+    pub fn parse(buf: &[u8]) -> Result<MsOs20DescriptorSet> {
+        if buf.len() < 10 || le16(buf, 0) != 10 || le16(buf, 2) != 0 {
+            return Err(bad_descriptor());
+        }
+        let mut set = MsOs20DescriptorSet {
+            dwWindowsVersion: le32(buf, 4),
+            compat_ids: Vec::new(),
+            properties: Vec::new(),
+        };
+
+        // function subsets run for wSubsetLength bytes; everything else applies device-wide
+        let mut interface = None;
+        let mut function_end = 0;
+        let mut pos = 10;
+        while pos + 4 <= buf.len() {
+            let len = le16(buf, pos) as usize;
+            if len < 4 || pos + len > buf.len() {
+                return Err(bad_descriptor());
+            }
+            if pos >= function_end {
+                interface = None;
+            }
+            let d = &buf[pos..pos + len];
+            match le16(d, 2) {
+                0x02 if len >= 8 => {
+                    interface = Some(d[4]);
+                    function_end = pos + le16(d, 6) as usize;
+                }
+                0x03 if len >= 20 => set.compat_ids.push(MsCompatId {
+                    interface,
+                    compatible_id: ascii(&d[4..12]),
+                    sub_compatible_id: ascii(&d[12..20]),
+                }),
+                0x04 if len >= 10 => {
+                    let name_len = le16(d, 6) as usize;
+                    if len < 10 + name_len {
+                        return Err(bad_descriptor());
+                    }
+                    let data_len = le16(d, 8 + name_len) as usize;
+                    if len < 10 + name_len + data_len {
+                        return Err(bad_descriptor());
+                    }
+                    set.properties.push(MsRegistryProperty {
+                        interface,
+                        data_type: le16(d, 4) as u32,
+                        name: utf16le(&d[8..8 + name_len]),
+                        data: d[10 + name_len..10 + name_len + data_len].to_vec(),
+                    });
+                }
+                _ => (),  // configuration subsets and features without a typed equivalent
+            }
+            pos += len;
+        }
+        Ok(set)
+    }
+}
+
+/// Microsoft OS descriptor requests.
+///
+/// MS OS 1.0 devices are discovered with `get_ms_os_vendor_code()`, MS OS 2.0 devices with
+/// `BosDescriptor::ms_os_20()`.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// if let Some(vendor_code) = device.get_ms_os_vendor_code(1000).unwrap() {
+///     for id in device.get_ms_os_compat_ids(vendor_code, 1000).unwrap() {
+///         println!("interface {:?}: {}", id.interface, id.compatible_id);
+///     }
+///     for prop in device.get_ms_os_properties(vendor_code, 0, 1000).unwrap() {
+///         println!("{} = {:?}", prop.name, prop.string_value());
+///     }
+/// }
+/// ```
+impl Device {
+    /// Vendor code for MS OS 1.0 requests, from the `"MSFT100"` string descriptor at index
+    /// `0xee`.  `None` if the device doesn't provide one.
+    pub fn get_ms_os_vendor_code(&self, timeout_ms: u32) -> Result<Option<u8>> {
+        let mut buf = [0u8; 18];
+        let len = match self.get_descriptor(DescriptorType::String as u8, MS_OS_10_STRING_INDEX, 0, &mut buf, timeout_ms) {
+            Ok(len) => len,
+            Err(ref err) if err.kind() == ErrorKind::Stall => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if len < 18 || buf[1] != DescriptorType::String as u8 || utf16le(&buf[2..16]) != "MSFT100" {
+            return Ok(None);
+        }
+        Ok(Some(buf[16]))
+    }
+
+    /// MS OS 1.0 extended compat ID descriptor.
+    pub fn get_ms_os_compat_ids(&self, vendor_code: u8, timeout_ms: u32) -> Result<Vec<MsCompatId>> {
+        let buf = self.ms_os_request(SetupRecipient::Device, vendor_code, 0, MS_OS_10_EXTENDED_COMPAT_ID, timeout_ms)?;
+        if buf.len() < 16 {
+            return Err(bad_descriptor());
+        }
+        Ok(buf[16..].chunks_exact(24).take(buf[8] as usize).map(|f| MsCompatId {
+            interface: Some(f[0]),
+            compatible_id: ascii(&f[2..10]),
+            sub_compatible_id: ascii(&f[10..18]),
+        }).collect())
+    }
+
+    /// MS OS 1.0 extended properties descriptor of `interface`.
+    pub fn get_ms_os_properties(&self, vendor_code: u8, interface: u8, timeout_ms: u32) -> Result<Vec<MsRegistryProperty>> {
+        let buf = self.ms_os_request(SetupRecipient::Interface, vendor_code, (interface as u16) << 8,
+                                     MS_OS_10_EXTENDED_PROPERTIES, timeout_ms)?;
+        if buf.len() < 10 {
+            return Err(bad_descriptor());
+        }
+        let mut properties = Vec::new();
+        let mut pos = 10;
+        for _ in 0..le16(&buf, 8) {
+            if pos + 14 > buf.len() {
+                return Err(bad_descriptor());
+            }
+            let d = &buf[pos..];
+            let size = le32(d, 0) as usize;
+            let name_len = le16(d, 8) as usize;
+            if size < 14 + name_len || size > d.len() {
+                return Err(bad_descriptor());
+            }
+            let data_len = le32(d, 10 + name_len) as usize;
+            if size < 14 + name_len + data_len {
+                return Err(bad_descriptor());
+            }
+            properties.push(MsRegistryProperty {
+                interface: Some(interface),
+                data_type: le32(d, 4),
+                name: utf16le(&d[10..10 + name_len]),
+                data: d[14 + name_len..14 + name_len + data_len].to_vec(),
+            });
+            pos += size;
+        }
+        Ok(properties)
+    }
+
+    /// Read and parse the MS OS 2.0 descriptor set announced by `info`.
+    pub fn get_ms_os_20_descriptor_set(&self, info: &MsOs20Info, timeout_ms: u32) -> Result<MsOs20DescriptorSet> {
+        // too short to hold the set header
+        if info.wMSOSDescriptorSetTotalLength < 10 {
+            return Err(bad_descriptor());
+        }
+        let mut buf = vec![0u8; info.wMSOSDescriptorSetTotalLength as usize];
+        let len = self.control_transfer_in(SetupType::Vendor,
+                                           SetupRecipient::Device,
+                                           info.bMS_VendorCode,
+                                           0,
+                                           MS_OS_20_DESCRIPTOR_INDEX,
+                                           Some(&mut buf),
+                                           timeout_ms)?;
+        MsOs20DescriptorSet::parse(&buf[..len as usize])
+    }
+
+    // MS OS 1.0 feature descriptor request: read the length from the header, then the lot
+    fn ms_os_request(&self, recipient: SetupRecipient, vendor_code: u8, wValue: u16, wIndex: u16, timeout_ms: u32) -> Result<Vec<u8>> {
+        let mut header = [0u8; 16];
+        if self.control_transfer_in(SetupType::Vendor, recipient, vendor_code, wValue, wIndex,
+                                    Some(&mut header), timeout_ms)? < 4 {
+            return Err(bad_descriptor());
+        }
+        // a control transfer can't carry more than wLength allows
+        let total = le32(&header, 0);
+        if total > u16::MAX as u32 {
+            return Err(bad_descriptor());
+        }
+        let mut buf = vec![0u8; total as usize];
+        let len = self.control_transfer_in(SetupType::Vendor, recipient, vendor_code, wValue, wIndex,
+                                           Some(&mut buf), timeout_ms)?;
+        buf.truncate(len as usize);
+        Ok(buf)
+    }
+}
+
+// NUL padded ASCII
+fn ascii(bytes: &[u8]) -> String {
+    bytes.iter().take_while(|&&b| b != 0).map(|&b| b as char).collect()
+}
+
+// UTF-16LE without its terminating NULs
+fn utf16le(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+    String::from_utf16_lossy(&units).trim_end_matches('\0').to_string()
+}
@@ -0,0 +1,151 @@
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
+
+use super::*;
+
+type PacedTransfer = Box<StdBufTransfer<Vec<u8>>>;
+
+/// Interrupt IN polling paced by a timer.
+///
+/// `InterruptStream` keeps the endpoint busy, so the host controller polls it at every service
+/// interval.  `PacedInterruptStream` instead submits a single transfer once per `interval`,
+/// measured by a `timerfd`, and leaves the endpoint alone in between.  For devices that only
+/// need to be read at the rate their descriptor asks for, or slower, this keeps bus and host
+/// load down.  If a transfer is still in flight when the timer ticks, that tick is skipped.
+///
+/// Event loops should wait for the device file descriptor (`as_raw_fd()`) to become writable
+/// and for `timer_fd()` to become readable, calling `next_report_nowait()` on either.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let info = deviceinfo_find(0xffff, 3).unwrap();
+/// let config = &info.configurations().unwrap()[0];
+/// let ep = config.interfaces[0].endpoints.iter().find(|ep| ep.is_in()).unwrap();
+/// let device = Device::new(&info).unwrap();
+/// device.claim_interface(0).unwrap();
+/// let mut stream = PacedInterruptStream::from_descriptor(device, ep, info.speed().unwrap()).unwrap();
+/// loop {
+///     stream.next_report(|report| println!("{:?}", report)).unwrap();
+/// }
+/// ```
+pub struct PacedInterruptStream {
+    device: AsyncDevice<PacedTransfer>,
+    timer: TimerFd,
+    interval: Duration,
+    // the transfer while it waits for the next tick
+    idle: Option<PacedTransfer>,
+}
+
+impl PacedInterruptStream {
+    /// Read reports of up to `report_size` bytes from interrupt IN `endpoint` every
+    /// `interval`.  The first transfer is submitted straight away.  The direction bit of
+    /// `endpoint` is set automatically.
+    pub fn new(device: Device, endpoint: u8, report_size: usize, interval: Duration) -> Result<Self> {
+        if interval == Duration::ZERO {
+            return Err(Error::new(ErrorKind::InvalidParam, "zero interval"));
+        }
+        let timer = devfs::nix_result_to_result(TimerFd::new(ClockId::CLOCK_MONOTONIC,
+                                                             TimerFlags::TFD_NONBLOCK | TimerFlags::TFD_CLOEXEC))?;
+        devfs::nix_result_to_result(timer.set(Expiration::Interval(TimeSpec::from_duration(interval)),
+                                              TimerSetTimeFlags::empty()))?;
+        let mut stream = PacedInterruptStream{device: device.into(), timer, interval, idle: None};
+        stream.device.submit(Box::new(StdBufTransfer::interrupt(endpoint | 0x80,
+                                                                UrbFlags::empty(),
+                                                                vec![0; report_size])))?;
+        Ok(stream)
+    }
+
+    /// Read from the endpoint described by `ep` at its own service interval at `speed`, with
+    /// reports of its maximum packet size.
+    pub fn from_descriptor(device: Device, ep: &EndpointDescriptor, speed: Speed) -> Result<Self> {
+        PacedInterruptStream::new(device, ep.bEndpointAddress, ep.max_bytes_per_interval(speed),
+                                  ep.service_interval(speed))
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// File descriptor of the timer, readable when a transfer is due.
+    pub fn timer_fd(&self) -> RawFd {
+        self.timer.as_raw_fd()
+    }
+
+    /// Wait for the next report and pass it to `f`.  Its transfer is resubmitted at the next
+    /// tick of the timer.
+    ///
+    /// If the transfer failed (eg. the endpoint stalled or the device was unplugged) it is
+    /// dropped, `f` is not called, and the error is returned.  After that, this fails with
+    /// `ErrorKind::NotFound`.
+    pub fn next_report<F, T>(&mut self, f: F) -> Result<T>
+        where F: FnOnce(&[u8]) -> T
+    {
+        if self.idle.is_some() {
+            let mut fds = [PollFd::new(self.timer.as_raw_fd(), PollFlags::POLLIN)];
+            devfs::nix_result_to_result(poll(&mut fds, -1))?;
+            self.tick()?;
+        }
+        self.process(true, f)
+    }
+
+    /// Like `next_report()`, but fails with `ErrorKind::WouldBlock` instead of waiting.  Also
+    /// submits the transfer if the timer has ticked.
+    pub fn next_report_nowait<F, T>(&mut self, f: F) -> Result<T>
+        where F: FnOnce(&[u8]) -> T
+    {
+        self.tick()?;
+        self.process(false, f)
+    }
+
+    /// Stop polling, returning the underlying `AsyncDevice`.  A transfer still in flight stays
+    /// in it and is returned by its `reap_*()` methods.
+    pub fn into_inner(self) -> AsyncDevice<PacedTransfer> {
+        self.device
+    }
+
+    // Submit the idle transfer if the timer has ticked since it was reaped.
+    fn tick(&mut self) -> Result<()> {
+        if self.idle.is_none() {
+            return Ok(());
+        }
+        match devfs::nix_result_to_result(self.timer.wait()) {
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+            other => other?,
+        }
+        self.device.submit(self.idle.take().unwrap())?;
+        Ok(())
+    }
+
+    fn process<F, T>(&mut self, wait: bool, f: F) -> Result<T>
+        where F: FnOnce(&[u8]) -> T
+    {
+        if self.idle.is_some() {
+            return Err(Error::new(ErrorKind::WouldBlock, "transfer waiting for the timer"));
+        }
+        if self.device.pending_count() == 0 {
+            return Err(Error::new(ErrorKind::NotFound, "no transfer queued"));
+        }
+        let (_slot, xfer, result) = match wait {
+            true => self.device.reap_wait()?,
+            false => self.device.reap_nowait()?,
+        };
+        let len = result.into_io_result()?;
+        // ticks that passed while the transfer was in flight don't count
+        let _ = self.timer.wait();
+        let value = f(&xfer.buf[..len]);
+        self.idle = Some(xfer);
+        Ok(value)
+    }
+}
+
+impl AsRawFd for PacedInterruptStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}
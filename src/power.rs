@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use super::*;
+use deviceinfo::{read_sysfs_string, write_sysfs_string};
+
+/// Runtime power state of a device, from `DeviceInfo::runtime_status()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum RuntimeStatus {
+    Active,
+    Suspended,
+    Suspending,
+    Resuming,
+    /// Runtime power management failed and is disabled for the device until the status is
+    /// cleared.
+    Error,
+    /// Runtime power management is not enabled for the device.
+    Unsupported,
+}
+
+/// Power management through the sysfs `power/` attributes.
+///
+/// The kernel suspends a device by itself once it has been idle for the autosuspend delay,
+/// provided autosuspend is enabled and every driver bound to the device allows it; an open
+/// `Device` with claimed interfaces keeps it awake while transfers are in flight.  Changing
+/// these settings usually needs root.  They are not available for devices found without
+/// sysfs, which fail with `ErrorKind::Unsupported`.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use usbfs::*;
+///
+/// let info = deviceinfo_find(0xffff, 3).unwrap();
+/// info.set_autosuspend(Some(Duration::from_secs(2))).unwrap();
+/// println!("{:?}", info.runtime_status().unwrap());
+/// ```
+impl DeviceInfo {
+    /// Current runtime power state.
+    pub fn runtime_status(&self) -> Result<RuntimeStatus> {
+        match read_sysfs_string(self.sysfs_dirname()?, "power/runtime_status")?.as_str() {
+            "active" => Ok(RuntimeStatus::Active),
+            "suspended" => Ok(RuntimeStatus::Suspended),
+            "suspending" => Ok(RuntimeStatus::Suspending),
+            "resuming" => Ok(RuntimeStatus::Resuming),
+            "error" => Ok(RuntimeStatus::Error),
+            "unsupported" => Ok(RuntimeStatus::Unsupported),
+            _ => Err(Error::new(ErrorKind::Other, "unknown runtime status")),
+        }
+    }
+
+    /// The autosuspend delay if autosuspend is enabled, or `None` if the device is kept
+    /// awake.
+    pub fn autosuspend(&self) -> Result<Option<Duration>> {
+        let dirname = self.sysfs_dirname()?;
+        if read_sysfs_string(dirname, "power/control")? != "auto" {
+            return Ok(None);
+        }
+        // a negative delay also keeps the device awake
+        match read_sysfs_string(dirname, "power/autosuspend_delay_ms")?.parse::<i64>() {
+            Ok(ms) if ms >= 0 => Ok(Some(Duration::from_millis(ms as u64))),
+            Ok(_) => Ok(None),
+            Err(_) => Err(Error::new(ErrorKind::Other, "bad parse")),
+        }
+    }
+
+    /// Let the kernel suspend the device after it has been idle for `delay`, or with `None`
+    /// keep it awake, resuming it if it is suspended.
+    pub fn set_autosuspend(&self, delay: Option<Duration>) -> Result<()> {
+        let dirname = self.sysfs_dirname()?;
+        match delay {
+            Some(delay) => {
+                let ms = delay.as_millis().min(i32::MAX as u128);
+                write_sysfs_string(dirname, "power/autosuspend_delay_ms", &ms.to_string())?;
+                write_sysfs_string(dirname, "power/control", "auto")
+            }
+            None => write_sysfs_string(dirname, "power/control", "on"),
+        }
+    }
+
+    /// Suspend the device as soon as it is idle, by enabling autosuspend with no delay.  There
+    /// is no way to force a busy device into suspend.
+    pub fn suspend(&self) -> Result<()> {
+        self.set_autosuspend(Some(Duration::ZERO))
+    }
+
+    /// Resume the device and keep it awake, disabling autosuspend as `set_autosuspend(None)`
+    /// does.
+    pub fn resume(&self) -> Result<()> {
+        self.set_autosuspend(None)
+    }
+}
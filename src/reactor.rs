@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::Duration;
+
+use nix::sys::epoll::{epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp};
+use nix::sys::time::TimeSpec;
+use nix::sys::timerfd::{ClockId, Expiration, TimerFd, TimerFlags, TimerSetTimeFlags};
+
+use super::*;
+
+/// Identifies a registration with a `Reactor` in the events it reports.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Token(pub u64);
+
+/// Readiness reported by `Reactor::poll()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReactorEvent {
+    pub token: Token,
+    /// The file descriptor reported an error or hangup.  For a device this means it has been
+    /// disconnected; reap what remains and deregister it, or it stays ready for good.
+    pub hangup: bool,
+}
+
+/// A minimal epoll event loop for devices, hotplug monitors, and timers.  Available with the
+/// `reactor` feature.
+///
+/// For programs that want neither `mio` nor `tokio`, `Reactor` takes care of the details of
+/// waiting on usbfs: a device becomes *writable*, not readable, when it has completed transfers
+/// to reap, while a `HotplugMonitor` becomes readable.  Registrations are level triggered, so a
+/// device is reported by every `poll()` until all of its completed transfers have been reaped;
+/// a handler may reap one transfer or all of them.
+///
+/// Anything implementing `AsRawFd` on top of a usbfs file descriptor can be registered with
+/// `register_device()`: `Device`, `AsyncDevice`, `InterruptStream`, `BulkReader`, and so on.
+/// Timers are kept by the reactor and are acknowledged as they are reported.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use usbfs::*;
+///
+/// const DEVICE: Token = Token(0);
+/// const HOTPLUG: Token = Token(1);
+/// const TICK: Token = Token(2);
+///
+/// let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> =
+///     AsyncDevice::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let mut monitor = HotplugMonitor::new().unwrap();
+/// device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 512]))).unwrap();
+///
+/// let mut reactor = Reactor::new().unwrap();
+/// reactor.register_device(&device, DEVICE).unwrap();
+/// reactor.register_hotplug(&monitor, HOTPLUG).unwrap();
+/// reactor.add_timer(TICK, Duration::from_secs(1), true).unwrap();
+///
+/// let mut events = Vec::new();
+/// loop {
+///     reactor.poll(&mut events, None).unwrap();
+///     for event in &events {
+///         match event.token {
+///             DEVICE => {
+///                 let (_slot, xfer, result) = device.reap_nowait().unwrap();
+///                 println!("{:?}", result);
+///                 device.submit(xfer).unwrap();
+///             }
+///             HOTPLUG => println!("{:?}", monitor.event_nowait().unwrap()),
+///             _ => println!("tick, {} in flight", device.pending_count()),
+///         }
+///     }
+/// }
+/// ```
+pub struct Reactor {
+    epoll: File,
+    timers: HashMap<Token, TimerFd>,
+    events: Vec<EpollEvent>,
+}
+
+impl Reactor {
+    pub fn new() -> Result<Reactor> {
+        let fd = devfs::nix_result_to_result(epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC))?;
+        Ok(Reactor {
+            epoll: unsafe { File::from_raw_fd(fd) },
+            timers: HashMap::new(),
+            events: vec![EpollEvent::empty(); 32],
+        })
+    }
+
+    /// Report `device` whenever it has completed transfers to reap, or has been disconnected.
+    pub fn register_device<T: AsRawFd>(&mut self, device: &T, token: Token) -> Result<()> {
+        self.add(device.as_raw_fd(), EpollFlags::EPOLLOUT, token)
+    }
+
+    /// Report `monitor` whenever it has hotplug events pending.
+    pub fn register_hotplug(&mut self, monitor: &HotplugMonitor, token: Token) -> Result<()> {
+        self.add(monitor.as_raw_fd(), EpollFlags::EPOLLIN, token)
+    }
+
+    /// Stop reporting a device or hotplug monitor.  Closing its file descriptor also
+    /// removes it.
+    pub fn deregister<T: AsRawFd>(&mut self, source: &T) -> Result<()> {
+        devfs::nix_result_to_result(epoll_ctl(self.epoll.as_raw_fd(), EpollOp::EpollCtlDel,
+                                              source.as_raw_fd(), None))
+    }
+
+    /// Report `token` once `interval` has passed, and then every `interval` if `periodic`.
+    /// Setting a timer for a token that already has one restarts it.
+    pub fn add_timer(&mut self, token: Token, interval: Duration, periodic: bool) -> Result<()> {
+        if interval == Duration::ZERO {
+            return Err(Error::new(ErrorKind::InvalidParam, "zero timer interval"));
+        }
+        if !self.timers.contains_key(&token) {
+            let timer = devfs::nix_result_to_result(TimerFd::new(ClockId::CLOCK_MONOTONIC,
+                                                                 TimerFlags::TFD_NONBLOCK | TimerFlags::TFD_CLOEXEC))?;
+            self.add(timer.as_raw_fd(), EpollFlags::EPOLLIN, token)?;
+            self.timers.insert(token, timer);
+        }
+        let expiration = match periodic {
+            true => Expiration::Interval(TimeSpec::from_duration(interval)),
+            false => Expiration::OneShot(TimeSpec::from_duration(interval)),
+        };
+        devfs::nix_result_to_result(self.timers[&token].set(expiration, TimerSetTimeFlags::empty()))
+    }
+
+    /// Remove the timer of `token`.  Fails with `ErrorKind::NotFound` if there is none.
+    pub fn cancel_timer(&mut self, token: Token) -> Result<()> {
+        // closing the timerfd takes it out of the epoll set
+        self.timers.remove(&token)
+            .map(|_| ())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no timer for token"))
+    }
+
+    /// Wait until something is ready, or `timeout` passes, and replace the contents of
+    /// `events` with what is ready.  `None` waits indefinitely.  A signal interrupting the wait
+    /// gives `ErrorKind::Interrupted`.
+    pub fn poll(&mut self, events: &mut Vec<ReactorEvent>, timeout: Option<Duration>) -> Result<()> {
+        events.clear();
+        let timeout_ms = match timeout {
+            // round up, so short timeouts don't busy loop
+            Some(t) => t.as_nanos().div_ceil(1_000_000).min(isize::MAX as u128) as isize,
+            None => -1,
+        };
+        let n = devfs::nix_result_to_result(epoll_wait(self.epoll.as_raw_fd(), &mut self.events, timeout_ms))?;
+        for e in &self.events[..n] {
+            let token = Token(e.data());
+            if let Some(timer) = self.timers.get(&token) {
+                // acknowledge the expiry so the timer isn't reported again until the next one
+                let _ = timer.wait();
+            }
+            events.push(ReactorEvent {
+                token,
+                hangup: e.events().intersects(EpollFlags::EPOLLERR | EpollFlags::EPOLLHUP),
+            });
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, fd: RawFd, flags: EpollFlags, token: Token) -> Result<()> {
+        let mut event = EpollEvent::new(flags, token.0);
+        devfs::nix_result_to_result(epoll_ctl(self.epoll.as_raw_fd(), EpollOp::EpollCtlAdd, fd, &mut event))
+    }
+}
+
+impl AsRawFd for Reactor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.epoll.as_raw_fd()
+    }
+}
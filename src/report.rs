@@ -0,0 +1,280 @@
+use std::fmt::{self, Write};
+use std::fs;
+
+use super::*;
+use deviceinfo::SYSFS_DEVICE_PATH;
+
+/// Snapshot of every USB device on the host, arranged by bus and hub topology, as taken by
+/// `DeviceTree::scan()`.
+///
+/// Text renderings comparable to `lsusb -t` and `lsusb -v` are provided by `render_tree()`
+/// and `render_verbose()`.  With the `serde` feature the tree can be serialized whole, eg.
+/// to JSON for inventory tools.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let tree = DeviceTree::scan().unwrap();
+/// print!("{}", tree.render_tree());
+/// for device in tree.devices() {
+///     println!("{}: {} configurations", device.summary.port_path, device.configurations.len());
+/// }
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct DeviceTree {
+    pub buses: Vec<BusNode>,
+}
+
+/// A bus of a `DeviceTree`, with the devices attached directly to its root hub.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct BusNode {
+    pub busnum: u32,
+    /// The root hub, which represents the host controller.
+    pub root_hub: DeviceNode,
+}
+
+/// A device of a `DeviceTree`, with its configuration descriptors and, for hubs, the devices
+/// attached to it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct DeviceNode {
+    pub summary: DeviceSummary,
+    /// Configuration descriptors as cached by the kernel, each with its interfaces and their
+    /// endpoints.
+    pub configurations: Vec<ConfigDescriptor>,
+    /// `bConfigurationValue` of the active configuration, or `None` if unconfigured.
+    pub active_configuration: Option<u8>,
+    pub children: Vec<DeviceNode>,
+}
+
+impl DeviceTree {
+    /// Collect the tree from sysfs.  No requests are sent to the devices.  Devices that go
+    /// away during the scan are left out.
+    pub fn scan() -> Result<DeviceTree> {
+        let mut buses: Vec<BusNode> = fs::read_dir(SYSFS_DEVICE_PATH)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let busnum = name.strip_prefix("usb")?.parse().ok()?;
+                let root_hub = DeviceNode::scan(&DeviceInfo::from_dirname(&name))?;
+                Some(BusNode { busnum, root_hub })
+            })
+            .collect();
+        buses.sort_by_key(|bus| bus.busnum);
+        Ok(DeviceTree { buses })
+    }
+
+    /// Every device in the tree, root hubs excluded, depth first.
+    pub fn devices(&self) -> Vec<&DeviceNode> {
+        let mut devices = Vec::new();
+        for bus in &self.buses {
+            bus.root_hub.collect_children(&mut devices);
+        }
+        devices
+    }
+
+    /// Render the topology in the manner of `lsusb -t`, one line per device and interface.
+    pub fn render_tree(&self) -> String {
+        let mut out = String::new();
+        for bus in &self.buses {
+            let root = &bus.root_hub;
+            let _ = writeln!(out, "/:  Bus {:03}: {}, {}", bus.busnum, root.id_line(), speed_name(root.summary.speed));
+            for child in &root.children {
+                child.render_tree(&mut out, 1);
+            }
+        }
+        out
+    }
+
+    /// Render every device's descriptors in the manner of `lsusb -v`.
+    pub fn render_verbose(&self) -> String {
+        self.devices().iter().map(|d| d.render_verbose() + "\n").collect()
+    }
+}
+
+impl DeviceNode {
+    fn scan(info: &DeviceInfo) -> Option<DeviceNode> {
+        let mut children: Vec<DeviceNode> = info.children().filter_map(|child| DeviceNode::scan(&child)).collect();
+        children.sort_by_key(|child| port_number(&child.summary.port_path));
+        Some(DeviceNode {
+            summary: info.summary().ok()?,
+            configurations: info.configurations().unwrap_or_default(),
+            active_configuration: info.configuration_value().ok().filter(|&v| v != 0).map(|v| v as u8),
+            children,
+        })
+    }
+
+    /// Descriptor of the active configuration.
+    pub fn active_config(&self) -> Option<&ConfigDescriptor> {
+        let value = self.active_configuration?;
+        self.configurations.iter().find(|c| c.bConfigurationValue == value)
+    }
+
+    fn collect_children<'a>(&'a self, devices: &mut Vec<&'a DeviceNode>) {
+        for child in &self.children {
+            devices.push(child);
+            child.collect_children(devices);
+        }
+    }
+
+    // "ID vvvv:pppp Product"
+    fn id_line(&self) -> String {
+        let s = &self.summary;
+        let mut line = format!("ID {:04x}:{:04x}", s.descriptor.idVendor, s.descriptor.idProduct);
+        if let Some(ref product) = s.product {
+            line.push(' ');
+            line.push_str(product);
+        }
+        line
+    }
+
+    fn render_tree(&self, out: &mut String, depth: usize) {
+        let indent = "    ".repeat(depth);
+        let s = &self.summary;
+        let _ = writeln!(out, "{}|__ Port {}: Dev {:03}, {}, {}",
+                         indent, port_number(&s.port_path), s.devnum, self.id_line(), speed_name(s.speed));
+        if let Some(config) = self.active_config() {
+            for intf in config.interfaces.iter().filter(|i| i.bAlternateSetting == 0) {
+                let _ = writeln!(out, "{}    If {}, Class={}, {} endpoints",
+                                 indent, intf.bInterfaceNumber, class_name(intf.bInterfaceClass), intf.bNumEndpoints);
+            }
+        }
+        for child in &self.children {
+            child.render_tree(out, depth + 1);
+        }
+    }
+
+    /// Render the device's descriptors in the manner of `lsusb -v`.
+    pub fn render_verbose(&self) -> String {
+        let mut out = String::new();
+        let _ = self.write_verbose(&mut out);
+        out
+    }
+
+    fn write_verbose(&self, out: &mut String) -> fmt::Result {
+        let s = &self.summary;
+        let d = &s.descriptor;
+        let string = |index: u8, value: &Option<String>| match *value {
+            Some(ref value) if index != 0 => format!("{:>6} {}", index, value),
+            _ => format!("{:>6}", index),
+        };
+        writeln!(out, "Bus {:03} Device {:03}: {}", s.busnum, s.devnum, self.id_line())?;
+        writeln!(out, "Device Descriptor:")?;
+        field(out, 1, "bLength", d.bLength)?;
+        field(out, 1, "bDescriptorType", d.bDescriptorType)?;
+        field(out, 1, "bcdUSB", bcd(d.bcdUSB))?;
+        field(out, 1, "bDeviceClass", format!("{:>6} {}", d.bDeviceClass, class_name(d.bDeviceClass)))?;
+        field(out, 1, "bDeviceSubClass", d.bDeviceSubClass)?;
+        field(out, 1, "bDeviceProtocol", d.bDeviceProtocol)?;
+        field(out, 1, "bMaxPacketSize0", d.bMaxPacketSize0)?;
+        field(out, 1, "idVendor", format!("0x{:04x}", d.idVendor))?;
+        field(out, 1, "idProduct", format!("0x{:04x}", d.idProduct))?;
+        field(out, 1, "bcdDevice", bcd(d.bcdDevice))?;
+        field(out, 1, "iManufacturer", string(d.iManufacturer, &s.manufacturer))?;
+        field(out, 1, "iProduct", string(d.iProduct, &s.product))?;
+        field(out, 1, "iSerial", string(d.iSerialNumber, &s.serial))?;
+        field(out, 1, "bNumConfigurations", d.bNumConfigurations)?;
+
+        // SuperSpeed devices draw power in units of 8mA rather than 2mA
+        let power_unit = match s.speed {
+            Some(speed) if speed >= Speed::Super => 8,
+            _ => 2,
+        };
+        for config in &self.configurations {
+            writeln!(out, "  Configuration Descriptor:")?;
+            field(out, 2, "wTotalLength", format!("0x{:04x}", config.wTotalLength))?;
+            field(out, 2, "bNumInterfaces", config.bNumInterfaces)?;
+            field(out, 2, "bConfigurationValue", config.bConfigurationValue)?;
+            field(out, 2, "iConfiguration", config.iConfiguration)?;
+            field(out, 2, "bmAttributes", format!("0x{:02x}", config.bmAttributes))?;
+            if config.bmAttributes & 0x40 != 0 {
+                writeln!(out, "      Self Powered")?;
+            }
+            if config.bmAttributes & 0x20 != 0 {
+                writeln!(out, "      Remote Wakeup")?;
+            }
+            field(out, 2, "MaxPower", format!("{}mA", config.bMaxPower as u32 * power_unit))?;
+            for intf in &config.interfaces {
+                writeln!(out, "    Interface Descriptor:")?;
+                field(out, 3, "bInterfaceNumber", intf.bInterfaceNumber)?;
+                field(out, 3, "bAlternateSetting", intf.bAlternateSetting)?;
+                field(out, 3, "bNumEndpoints", intf.bNumEndpoints)?;
+                field(out, 3, "bInterfaceClass", format!("{:>6} {}", intf.bInterfaceClass, class_name(intf.bInterfaceClass)))?;
+                field(out, 3, "bInterfaceSubClass", intf.bInterfaceSubClass)?;
+                field(out, 3, "bInterfaceProtocol", intf.bInterfaceProtocol)?;
+                field(out, 3, "iInterface", intf.iInterface)?;
+                for ep in &intf.endpoints {
+                    writeln!(out, "      Endpoint Descriptor:")?;
+                    field(out, 4, "bEndpointAddress", format!("0x{:02x}  EP {} {}", ep.bEndpointAddress,
+                                                              ep.bEndpointAddress & 0x0f,
+                                                              if ep.is_in() { "IN" } else { "OUT" }))?;
+                    field(out, 4, "bmAttributes", ep.bmAttributes)?;
+                    writeln!(out, "          Transfer Type            {:?}", ep.transfer_type())?;
+                    let transactions = 1 + ((ep.wMaxPacketSize >> 11) & 0x03);
+                    field(out, 4, "wMaxPacketSize", format!("0x{:04x}  {}x {} bytes", ep.wMaxPacketSize,
+                                                            transactions, ep.max_packet_size()))?;
+                    field(out, 4, "bInterval", ep.bInterval)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// One "name value" line of a verbose rendering, `level` steps deep.  Numbers line up on their
+// last digit; longer values carry their own alignment.
+fn field<T: fmt::Display>(out: &mut String, level: usize, name: &str, value: T) -> fmt::Result {
+    writeln!(out, "{}{:<20}{:>6}", "  ".repeat(level), name, value)
+}
+
+// Binary coded decimal version number, eg. 0x0210 as "2.10".
+fn bcd(value: u16) -> String {
+    format!("{:x}.{:02x}", value >> 8, value & 0xff)
+}
+
+// Last component of a port path, the port on the parent hub.
+fn port_number(port_path: &str) -> u32 {
+    port_path.rsplit(['.', '-']).next().and_then(|p| p.parse().ok()).unwrap_or(0)
+}
+
+fn speed_name(speed: Option<Speed>) -> &'static str {
+    match speed {
+        Some(Speed::Low) => "1.5M",
+        Some(Speed::Full) => "12M",
+        Some(Speed::High) => "480M",
+        Some(Speed::Super) => "5000M",
+        Some(Speed::SuperPlus) => "10000M",
+        None => "unknown speed",
+    }
+}
+
+// Name of a device or interface class code.
+fn class_name(class: u8) -> &'static str {
+    match class {
+        0x00 => "(Defined at Interface level)",
+        0x01 => "Audio",
+        0x02 => "Communications",
+        0x03 => "Human Interface Device",
+        0x05 => "Physical Interface Device",
+        0x06 => "Imaging",
+        0x07 => "Printer",
+        0x08 => "Mass Storage",
+        0x09 => "Hub",
+        0x0a => "CDC Data",
+        0x0b => "Chip/SmartCard",
+        0x0d => "Content Security",
+        0x0e => "Video",
+        0x0f => "Personal Healthcare",
+        0x10 => "Audio/Video",
+        0x11 => "Billboard",
+        0xdc => "Diagnostic",
+        0xe0 => "Wireless",
+        0xef => "Miscellaneous Device",
+        0xfe => "Application Specific Interface",
+        0xff => "Vendor Specific Class",
+        _ => "[unknown]",
+    }
+}
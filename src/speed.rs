@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use super::*;
+
+/// Negotiated bus speed of a device.
+///
+/// Speeds are ordered, so `speed >= Speed::High` picks out devices scheduled in 125us
+/// microframes.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let info = deviceinfo_find(0xffff, 3).unwrap();
+/// let speed = info.speed().unwrap();
+/// println!("{:?}, bulk packets up to {} bytes", speed, speed.max_packet_size(UrbType::Bulk));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum Speed {
+    /// 1.5 Mbit/s
+    Low,
+    /// 12 Mbit/s
+    Full,
+    /// 480 Mbit/s
+    High,
+    /// 5 Gbit/s
+    Super,
+    /// 10 or 20 Gbit/s
+    SuperPlus,
+}
+
+impl Speed {
+    // value of the sysfs `speed` attribute, in Mbit/s
+    pub(crate) fn from_sysfs(speed: &str) -> Result<Speed> {
+        match speed {
+            "1.5" => Ok(Speed::Low),
+            "12" => Ok(Speed::Full),
+            "480" => Ok(Speed::High),
+            "5000" => Ok(Speed::Super),
+            "10000" | "20000" => Ok(Speed::SuperPlus),
+            _ => Err(Error::new(ErrorKind::Unsupported, "unknown device speed")),
+        }
+    }
+
+    /// Largest `wMaxPacketSize` the USB specification allows for endpoints of `transfer_type`
+    /// at this speed, or 0 if the type isn't allowed at all (bulk and isochronous endpoints on
+    /// low speed devices).
+    ///
+    /// High speed isochronous and interrupt endpoints may move up to 3 such packets per
+    /// microframe; see `EndpointDescriptor::max_bytes_per_interval()`.
+    pub fn max_packet_size(&self, transfer_type: UrbType) -> usize {
+        match (*self, transfer_type) {
+            (Speed::Low, UrbType::Control) | (Speed::Low, UrbType::Interrupt) => 8,
+            (Speed::Low, _) => 0,
+            (Speed::Full, UrbType::Iso) => 1023,
+            (Speed::Full, _) => 64,
+            (Speed::High, UrbType::Control) => 64,
+            (Speed::High, UrbType::Bulk) => 512,
+            (Speed::High, _) => 1024,
+            (_, UrbType::Control) => 512,
+            (_, _) => 1024,
+        }
+    }
+}
+
+impl Device {
+    /// Negotiated speed of the device.  Needs Linux 4.13 or later.
+    pub fn speed(&self) -> Result<Speed> {
+        // enum usb_device_speed
+        match unsafe { devfs::nix_result_to_result(devfs::get_speed(self))? } {
+            1 => Ok(Speed::Low),
+            2 => Ok(Speed::Full),
+            3 => Ok(Speed::High),
+            5 => Ok(Speed::Super),
+            6 => Ok(Speed::SuperPlus),
+            _ => Err(Error::new(ErrorKind::Unsupported, "unknown device speed")),
+        }
+    }
+}
+
+impl EndpointDescriptor {
+    /// Most bytes the endpoint moves per service interval at `speed`, which bounds the packet
+    /// length of isochronous transfers.
+    ///
+    /// Accounts for the additional transactions of high speed, high bandwidth endpoints and,
+    /// at SuperSpeed, for the bursts described by the endpoint companion descriptor.
+    pub fn max_bytes_per_interval(&self, speed: Speed) -> usize {
+        let periodic = matches!(self.transfer_type(), UrbType::Iso | UrbType::Interrupt);
+        match speed {
+            Speed::High if periodic => self.max_packet_size() * (1 + ((self.wMaxPacketSize >> 11) & 0x03) as usize),
+            Speed::Super | Speed::SuperPlus if periodic => {
+                let companion = self.extra.iter()
+                    .find(|d| d.descriptor_type() == DescriptorType::SuperSpeedEndpointCompanion as u8 && d.0.len() >= 6);
+                match companion {
+                    Some(d) => descriptors::le16(&d.0, 4) as usize,
+                    None => self.max_packet_size(),
+                }
+            }
+            _ => self.max_packet_size(),
+        }
+    }
+
+    /// Time between the host's polls of a periodic endpoint at `speed`, decoded from
+    /// `bInterval`.  Full and low speed interrupt endpoints give it in frames; all others as an
+    /// exponent, in frames or in 125us microframes from high speed up.
+    pub fn service_interval(&self, speed: Speed) -> Duration {
+        let exponent = self.bInterval.clamp(1, 16) as u32 - 1;
+        match (speed, self.transfer_type()) {
+            (Speed::Low, _) | (Speed::Full, UrbType::Interrupt) => Duration::from_millis(self.bInterval.max(1) as u64),
+            (Speed::Full, _) => Duration::from_millis(1 << exponent),
+            (_, _) => Duration::from_micros(125 << exponent),
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::{mem, ptr, thread};
+use std::collections::VecDeque;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use super::*;
+
+/// Submitting half of a split `AsyncDevice`.
+///
+/// Created by `AsyncDevice::split()`.  Transfers submitted here are reaped through the matching
+/// `ReapHandle`, which may live on another thread.  Submission only takes `&self`, so a
+/// `SubmitHandle` can be shared between several submitting threads.
+pub struct SubmitHandle<R> {
+    shared: Arc<Shared<R>>,
+}
+
+/// Reaping half of a split `AsyncDevice`.
+///
+/// Created by `AsyncDevice::split()`.  The methods mirror those of `AsyncDevice`.
+pub struct ReapHandle<R> {
+    shared: Arc<Shared<R>>,
+    reaped: VecDeque<(SlotId, R, TransferResult)>,  // carried over from the AsyncDevice, or held during discard()
+}
+
+// In-flight transfers live in a fixed table of atomic pointers indexed by slot number.
+// Submitters claim a null entry, reapers set it back to null, so neither side ever takes a lock.
+struct Shared<R> {
+    device: Device,
+    signr: u32,
+    reap_after_disconnect: bool,
+    slots: Box<[TableEntry<R>]>,
+}
+
+struct TableEntry<R> {
+    ptr: AtomicPtr<Slot<R>>,
+    generation: AtomicU32,  // only changed by the reaper, before it releases the slot
+}
+
+struct Slot<R> {
+    transfer: R,
+    urb: *mut Urb,
+}
+
+// Marks a slot claimed by a submitter whose submission hasn't finished yet.
+fn reserved<R>() -> *mut Slot<R> {
+    ptr::dangling_mut()
+}
+
+// Transfers are only ever moved between threads through the table, never shared.
+unsafe impl<R: Send> Send for Shared<R> {}
+unsafe impl<R: Send> Sync for Shared<R> {}
+
+impl<R> AsyncDevice<R>
+    where R: StableDeref,
+          R::Target: Transfer
+{
+    /// Split into a `SubmitHandle` and a `ReapHandle` so that transfers can be submitted and
+    /// reaped from different threads.  Both handles are `Send` if `R` is.
+    ///
+    /// At most `capacity` transfers can be in flight at once; further submissions fail with
+    /// `ErrorKind::WouldBlock` until some are reaped.  Transfers already in flight are
+    /// carried over, so `capacity` must be at least the current number of slots.
+    ///
+    /// A completion signal set with `set_completion_signal()` still applies to transfers
+    /// submitted through the `SubmitHandle`.
+    ///
+    /// When both handles have been dropped, in-flight transfers are cancelled and reaped as for
+    /// `AsyncDevice`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    /// use std::thread;
+    ///
+    /// let device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> =
+    ///     AsyncDevice::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+    /// let (submitter, mut reaper) = device.split(16).unwrap();
+    ///
+    /// let reaper_thread = thread::spawn(move || {
+    ///     while let Ok((_slot, xfer, result)) = reaper.reap_wait() {
+    ///         println!("{:?} {:?}", result, &xfer.buf[..8]);
+    ///     }
+    /// });
+    /// for _ in 0..16 {
+    ///     submitter.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 512]))).unwrap();
+    /// }
+    /// ```
+    pub fn split(mut self, capacity: usize) -> Result<(SubmitHandle<R>, ReapHandle<R>)> {
+        if capacity < self.transfers.len() {
+            return Err(Error::new(ErrorKind::InvalidParam, "capacity too small for transfers in flight"));
+        }
+        let device = self.device.try_clone()?;
+        let reap_after_disconnect = self.kernel_capabilities()?
+            .is_some_and(|caps| caps.contains(Capabilities::REAP_AFTER_DISCONNECT));
+
+        let mut slots: Vec<TableEntry<R>> = (0..capacity).map(|_| TableEntry {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            generation: AtomicU32::new(0),
+        }).collect();
+        for (slot, entry) in slots.iter_mut().zip(mem::take(&mut self.transfers)) {
+            *slot.generation.get_mut() = entry.generation;
+            if let Some(asyncdevice::Slot{transfer, urb, ..}) = entry.slot {
+                *slot.ptr.get_mut() = Box::into_raw(Box::new(Slot{transfer, urb}));
+            }
+        }
+        let reaped = self.reaped.drain(..).map(|(id, transfer, (), result, _)| (id, transfer, result)).collect();
+
+        let shared = Arc::new(Shared{device, signr: self.signr, reap_after_disconnect, slots: slots.into_boxed_slice()});
+        Ok((SubmitHandle{shared: shared.clone()}, ReapHandle{shared, reaped}))
+    }
+}
+
+impl<R> SubmitHandle<R>
+    where R: StableDeref,
+          R::Target: Transfer
+{
+    /// Submit a transfer for processing.  See `AsyncDevice::submit_give_back_on_fail()`.
+    pub fn submit_give_back_on_fail(&self, mut transfer: R) -> Result<SlotId, (Error, R)> {
+        let urbp: *mut Urb = unsafe { transfer.stable_mut() }.wire_urb();
+
+        let id = match self.shared.reserve() {
+            Some(id) => id,
+            None => return Err((Error::new(ErrorKind::WouldBlock, "too many transfers in flight"), transfer)),
+        };
+        unsafe {
+            (*urbp).usercontext = id.index();
+            (*urbp).signr = self.shared.signr;
+        }
+
+        // the slot stays reserved until the submission succeeds, so the reaper never sees a
+        // slot that might be taken back
+        match unsafe { devfs::nix_result_to_result(devfs::submiturb(&self.shared.device, urbp)) } {
+            Ok(_) => {
+                let slot = Box::into_raw(Box::new(Slot{transfer, urb: urbp}));
+                self.shared.slots[id.index()].ptr.store(slot, Ordering::Release);
+                Ok(id)
+            }
+            Err(err) => {
+                self.shared.slots[id.index()].ptr.store(ptr::null_mut(), Ordering::Release);
+                Err((err, transfer))
+            }
+        }
+    }
+
+    /// Submit a transfer for processing.  Same as `submit_give_back_on_fail()`, but drop the
+    /// transfer upon failure.
+    pub fn submit(&self, transfer: R) -> Result<SlotId> {
+        self.submit_give_back_on_fail(transfer).map_err(|(err, _)| err)
+    }
+}
+
+impl<R> ReapHandle<R>
+    where R: StableDeref,
+          R::Target: Transfer
+{
+    /// Collect a previously submitted transfer.  See `AsyncDevice::reap_nowait()`.
+    ///
+    /// As with `AsyncDevice`, transfers in flight when the device is disconnected are still
+    /// returned, with `TransferResult::NoDevice`.
+    pub fn reap_nowait(&mut self) -> Result<(SlotId, R, TransferResult)> {
+        self.reap_main(false)
+    }
+
+    /// Wait for a previously submitted transfer to finish.  See `AsyncDevice::reap_wait()`.
+    pub fn reap_wait(&mut self) -> Result<(SlotId, R, TransferResult)> {
+        self.reap_main(true)
+    }
+
+    /// Wait at most `timeout` for a previously submitted transfer to finish.  See
+    /// `AsyncDevice::reap_timeout()`.
+    pub fn reap_timeout(&mut self, timeout: Duration) -> Result<(SlotId, R, TransferResult)> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.reap_nowait() {
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => (),
+                result => return result,
+            }
+            if !self.shared.device.wait_writable(Some(deadline))? {
+                return Err(Error::new(ErrorKind::Timeout, "reap timed out"));
+            }
+        }
+    }
+
+    /// Abort an in-flight transfer by slot number.  See `AsyncDevice::discard()`.
+    pub fn discard(&mut self, id: SlotId) -> Result<R> {
+        let urbp = self.shared.urb(id)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidParam, "invalid transfer id"))?;
+
+        unsafe { devfs::nix_result_to_result(devfs::discardurb(&self.shared.device, urbp))? };
+
+        loop {
+            let urbp = self.shared.reap_urb(true)?;
+            let (reaped_id, transfer, result) = match self.shared.take(urbp) {
+                Some(taken) => taken,
+                None => continue,
+            };
+            if reaped_id == id {
+                return Ok(transfer);
+            }
+            self.reaped.push_back((reaped_id, transfer, result));
+        }
+    }
+
+    /// Request cancellation of every in-flight transfer.  See `AsyncDevice::cancel_all()`.
+    pub fn cancel_all(&mut self) {
+        self.shared.cancel_all();
+    }
+
+    fn reap_main(&mut self, wait: bool) -> Result<(SlotId, R, TransferResult)> {
+        if let Some(reaped) = self.reaped.pop_front() {
+            return Ok(reaped);
+        }
+        match self.shared.reap_urb(wait) {
+            Ok(urbp) => match self.shared.take(urbp) {
+                Some(taken) => Ok(taken),
+                None => Err(Error::new(ErrorKind::Other, "reaped an URB not submitted through this handle")),
+            },
+            Err(ref err) if err.kind() == ErrorKind::Disconnected && self.shared.has_submitted() => {
+                let reaped = &mut self.reaped;
+                self.shared.reap_disconnected(|id, transfer, result| reaped.push_back((id, transfer, result)))?;
+                Ok(self.reaped.pop_front().unwrap())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<R> Shared<R> {
+    // Claim a free slot.
+    fn reserve(&self) -> Option<SlotId> {
+        self.slots.iter().position(|slot| {
+            slot.ptr.compare_exchange(ptr::null_mut(), reserved(), Ordering::Acquire, Ordering::Relaxed).is_ok()
+        }).map(|index| SlotId::new(index, self.slots[index].generation.load(Ordering::Relaxed)))
+    }
+
+    // Take the transfer whose urb `urbp` has been reaped, along with its result.  `None` if no
+    // slot holds that urb, which was then submitted through another handle on the same file;
+    // it may already be freed, so nothing is read through `urbp` until a slot is found.
+    fn take(&self, urbp: *mut Urb) -> Option<(SlotId, R, TransferResult)> {
+        let index = self.slots.iter().position(|entry| {
+            let mut slot = entry.ptr.load(Ordering::Acquire);
+            while slot == reserved() {
+                // possibly reaped before the submitter got around to filling in the slot
+                std::hint::spin_loop();
+                slot = entry.ptr.load(Ordering::Acquire);
+            }
+            !slot.is_null() && unsafe { (*slot).urb } == urbp
+        })?;
+        let entry = &self.slots[index];
+        let slot = entry.ptr.load(Ordering::Acquire);
+        let generation = entry.generation.load(Ordering::Relaxed);
+        entry.generation.store(generation.wrapping_add(1), Ordering::Relaxed);
+        entry.ptr.store(ptr::null_mut(), Ordering::Release);
+        let result = TransferResult::from_urb(unsafe { &*urbp });
+        Some((SlotId::new(index, generation), unsafe { Box::from_raw(slot) }.transfer, result))
+    }
+
+    // Urb of the slot if it holds a submitted transfer.  Slots still reserved by a submitter are
+    // left alone; their submission either fails and frees them, or fills them in.
+    fn submitted_urb(&self, index: usize) -> Option<*mut Urb> {
+        let slot = self.slots[index].ptr.load(Ordering::Acquire);
+        match slot.is_null() || slot == reserved() {
+            true => None,
+            false => Some(unsafe { (*slot).urb }),
+        }
+    }
+
+    fn has_submitted(&self) -> bool {
+        (0..self.slots.len()).any(|index| self.submitted_urb(index).is_some())
+    }
+
+    // Urb pointer of an in-flight transfer.  Only the reaper frees slots of submitted transfers,
+    // so the slot stays valid while the reaper looks at it.
+    fn urb(&self, id: SlotId) -> Option<*mut Urb> {
+        let entry = self.slots.get(id.index())?;
+        let slot = entry.ptr.load(Ordering::Acquire);
+        if slot.is_null() || slot == reserved() || entry.generation.load(Ordering::Relaxed) != id.generation() {
+            return None;
+        }
+        Some(unsafe { (*slot).urb })
+    }
+
+    fn cancel_all(&self) {
+        for (index, entry) in self.slots.iter().enumerate() {
+            let id = SlotId::new(index, entry.generation.load(Ordering::Relaxed));
+            if let Some(urbp) = self.urb(id) {
+                // fails harmlessly for urbs that have already completed
+                let _ = unsafe { devfs::discardurb(&self.device, urbp) };
+            }
+        }
+    }
+
+    // Hand everything still in flight to `f` once the device is gone and the kernel has nothing
+    // to reap right now.  As for `AsyncDevice`, urbs that REAP_AFTER_DISCONNECT kernels have
+    // yet to kill are waited for, while older kernels leave them alone for good.
+    fn reap_disconnected<F>(&self, mut f: F) -> Result<()>
+        where F: FnMut(SlotId, R, TransferResult)
+    {
+        if !self.reap_after_disconnect {
+            for index in 0..self.slots.len() {
+                if let Some(urbp) = self.submitted_urb(index) {
+                    let (id, transfer, _) = self.take(urbp).unwrap();
+                    f(id, transfer, TransferResult::NoDevice);
+                }
+            }
+            return Ok(());
+        }
+        while self.has_submitted() {
+            match self.reap_urb(false) {
+                Ok(urbp) => {
+                    if let Some((id, transfer, result)) = self.take(urbp) {
+                        f(id, transfer, result);
+                    }
+                }
+                Err(ref err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::Disconnected | ErrorKind::Interrupted) => {
+                    thread::sleep(devfs::DISCONNECT_REAP_INTERVAL);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    // reap one urb from the kernel and return its address
+    fn reap_urb(&self, wait: bool) -> Result<*mut Urb> {
+        let mut urbp: *mut Urb = ptr::null_mut();
+
+        match wait {
+            false => unsafe { devfs::nix_result_to_result(devfs::reapurbndelay(&self.device, &mut urbp))? },
+            true => unsafe { devfs::nix_result_to_result(devfs::reapurb(&self.device, &mut urbp))? },
+        };
+        Ok(urbp)
+    }
+}
+
+// Both handles are gone; wait for the kernel to finish with every urb before freeing it.
+impl<R> Drop for Shared<R> {
+    fn drop(&mut self) {
+        self.cancel_all();
+        loop {
+            if self.slots.iter_mut().all(|slot| slot.ptr.get_mut().is_null()) {
+                return;
+            }
+            match self.reap_urb(true) {
+                Ok(urbp) => {
+                    self.take(urbp);
+                }
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(ref err) if err.kind() == ErrorKind::Disconnected => {
+                    if self.reap_disconnected(|_, _, _| ()).is_err() {
+                        return;
+                    }
+                }
+                // can't tell what the kernel is still doing with the urbs; leak them rather than
+                // risk it writing into freed memory
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+impl<R> AsRawFd for SubmitHandle<R> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.shared.device.as_raw_fd()
+    }
+}
+
+impl<R> AsRawFd for ReapHandle<R> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.shared.device.as_raw_fd()
+    }
+}
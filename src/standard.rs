@@ -0,0 +1,258 @@
+use super::*;
+
+/// Standard request codes, the `bRequest` of chapter 9 control requests.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StandardRequest {
+    GetStatus = 0,
+    ClearFeature = 1,
+    SetFeature = 3,
+    SetAddress = 5,
+    GetDescriptor = 6,
+    SetDescriptor = 7,
+    GetConfiguration = 8,
+    SetConfiguration = 9,
+    GetInterface = 10,
+    SetInterface = 11,
+    SynchFrame = 12,
+}
+
+/// Standard descriptor types, for `Device::get_descriptor()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DescriptorType {
+    Device = 1,
+    Configuration = 2,
+    String = 3,
+    Interface = 4,
+    Endpoint = 5,
+    DeviceQualifier = 6,
+    OtherSpeedConfiguration = 7,
+    InterfacePower = 8,
+    Otg = 9,
+    Debug = 10,
+    InterfaceAssociation = 11,
+    Bos = 15,
+    DeviceCapability = 16,
+    SuperSpeedEndpointCompanion = 48,
+}
+
+/// Standard feature selectors for `Device::set_feature()` and `Device::clear_feature()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StandardFeature {
+    /// Halt an endpoint.  Addressed to an endpoint.
+    EndpointHalt,
+    /// Allow the device to wake the host.  Addressed to the device.
+    DeviceRemoteWakeup,
+    /// Enter a test mode.  Addressed to the device; the test selector goes in the high byte of
+    /// the index.
+    TestMode,
+}
+
+impl StandardFeature {
+    fn selector(self) -> u16 {
+        match self {
+            StandardFeature::EndpointHalt => 0,
+            StandardFeature::DeviceRemoteWakeup => 1,
+            StandardFeature::TestMode => 2,
+        }
+    }
+
+    fn recipient(self) -> SetupRecipient {
+        match self {
+            StandardFeature::EndpointHalt => SetupRecipient::Endpoint,
+            _ => SetupRecipient::Device,
+        }
+    }
+}
+
+bitflags! {
+    /// Device status, as returned by `Device::get_device_status()`.
+    pub struct DeviceStatus: u16 {
+        const SELF_POWERED  = 0x01;
+        const REMOTE_WAKEUP = 0x02;
+        const U1_ENABLE     = 0x04;
+        const U2_ENABLE     = 0x08;
+        const LTM_ENABLE    = 0x10;
+    }
+}
+
+bitflags! {
+    /// Interface status, as returned by `Device::get_interface_status()`.  Only SuperSpeed
+    /// devices report anything here.
+    pub struct InterfaceStatus: u16 {
+        const FUNCTION_REMOTE_WAKE_CAPABLE = 0x01;
+        const FUNCTION_REMOTE_WAKEUP       = 0x02;
+    }
+}
+
+bitflags! {
+    /// Endpoint status, as returned by `Device::get_endpoint_status()`.
+    pub struct EndpointStatus: u16 {
+        const HALT = 0x01;
+    }
+}
+
+/// Typed wrappers for the standard requests of USB 2.0 chapter 9.
+///
+/// These are built on `control_transfer()` and take the same `timeout_ms`.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let descr = device.get_device_descriptor(1000).unwrap();
+/// let languages = device.get_languages(1000).unwrap();
+/// if descr.iProduct != 0 && !languages.is_empty() {
+///     println!("{}", device.get_string_descriptor(descr.iProduct, languages[0], 1000).unwrap());
+/// }
+/// if device.get_endpoint_status(0x81, 1000).unwrap().contains(EndpointStatus::HALT) {
+///     device.clear_feature(StandardFeature::EndpointHalt, 0x81, 1000).unwrap();
+/// }
+/// ```
+impl Device {
+    /// Read descriptor `index` of type `desc_type` into `buf` with `GET_DESCRIPTOR`.
+    /// `desc_type` may be a `DescriptorType` or a class-specific type.  `language_id` is only
+    /// used for string descriptors and is 0 otherwise.
+    ///
+    /// The `Ok` result is the number of bytes received, which is less than `buf.len()` if the
+    /// descriptor is shorter.
+    pub fn get_descriptor(&self,
+                          desc_type: u8,
+                          index: u8,
+                          language_id: u16,
+                          buf: &mut [u8],
+                          timeout_ms: u32)
+                          -> Result<usize> {
+        self.control_transfer_in(SetupType::Standard,
+                                 SetupRecipient::Device,
+                                 StandardRequest::GetDescriptor as u8,
+                                 (desc_type as u16) << 8 | index as u16,
+                                 language_id,
+                                 Some(buf),
+                                 timeout_ms)
+            .map(|len| len as usize)
+    }
+
+    /// Read the device descriptor from the device itself.  See also
+    /// `DeviceInfo::device_descriptor()`, which reads the copy cached by the kernel.
+    pub fn get_device_descriptor(&self, timeout_ms: u32) -> Result<DeviceDescriptor> {
+        let mut buf = [0u8; DeviceDescriptor::SIZE];
+        if self.get_descriptor(DescriptorType::Device as u8, 0, 0, &mut buf, timeout_ms)? < buf.len() {
+            return Err(Error::new(ErrorKind::Other, "short device descriptor"));
+        }
+        DeviceDescriptor::parse(&buf)
+    }
+
+    /// Read string descriptor `index` in language `language_id` and decode it.
+    pub fn get_string_descriptor(&self, index: u8, language_id: u16, timeout_ms: u32) -> Result<String> {
+        let mut buf = [0u8; 255];
+        let len = self.get_string_descriptor_raw(index, language_id, &mut buf, timeout_ms)?;
+        let units: Vec<u16> = buf[2..len].chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        Ok(String::from_utf16_lossy(&units))
+    }
+
+    /// Language ids supported by the string descriptors of the device, from string
+    /// descriptor 0.  Empty if the device has no strings.
+    pub fn get_languages(&self, timeout_ms: u32) -> Result<Vec<u16>> {
+        let mut buf = [0u8; 255];
+        let len = match self.get_string_descriptor_raw(0, 0, &mut buf, timeout_ms) {
+            Ok(len) => len,
+            Err(ref err) if err.kind() == ErrorKind::Stall => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        Ok(buf[2..len].chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect())
+    }
+
+    /// `bConfigurationValue` of the active configuration, or 0 if the device is unconfigured.
+    pub fn get_configuration(&self, timeout_ms: u32) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.standard_in(SetupRecipient::Device, StandardRequest::GetConfiguration, 0, 0, &mut buf, timeout_ms)?;
+        Ok(buf[0])
+    }
+
+    /// Selected alternate setting of `interface`.
+    pub fn get_interface(&self, interface: u16, timeout_ms: u32) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.standard_in(SetupRecipient::Interface, StandardRequest::GetInterface, 0, interface, &mut buf, timeout_ms)?;
+        Ok(buf[0])
+    }
+
+    /// Enable `feature` with `SET_FEATURE`.  `index` is the endpoint address for
+    /// `EndpointHalt`, and 0 (or the test selector) for device features.
+    pub fn set_feature(&self, feature: StandardFeature, index: u16, timeout_ms: u32) -> Result<()> {
+        self.control_transfer_out(SetupType::Standard,
+                                  feature.recipient(),
+                                  StandardRequest::SetFeature as u8,
+                                  feature.selector(),
+                                  index,
+                                  None,
+                                  timeout_ms)
+            .map(|_| ())
+    }
+
+    /// Disable `feature` with `CLEAR_FEATURE`.  See `set_feature()`.
+    ///
+    /// To clear a stalled endpoint, prefer `clear_halt()`, which also resets the host side
+    /// data toggle.
+    pub fn clear_feature(&self, feature: StandardFeature, index: u16, timeout_ms: u32) -> Result<()> {
+        self.control_transfer_out(SetupType::Standard,
+                                  feature.recipient(),
+                                  StandardRequest::ClearFeature as u8,
+                                  feature.selector(),
+                                  index,
+                                  None,
+                                  timeout_ms)
+            .map(|_| ())
+    }
+
+    pub fn get_device_status(&self, timeout_ms: u32) -> Result<DeviceStatus> {
+        self.get_status(SetupRecipient::Device, 0, timeout_ms)
+            .map(DeviceStatus::from_bits_truncate)
+    }
+
+    pub fn get_interface_status(&self, interface: u16, timeout_ms: u32) -> Result<InterfaceStatus> {
+        self.get_status(SetupRecipient::Interface, interface, timeout_ms)
+            .map(InterfaceStatus::from_bits_truncate)
+    }
+
+    /// Status of `endpoint`.  Include the direction bit in `endpoint`.
+    pub fn get_endpoint_status(&self, endpoint: u8, timeout_ms: u32) -> Result<EndpointStatus> {
+        self.get_status(SetupRecipient::Endpoint, endpoint as u16, timeout_ms)
+            .map(EndpointStatus::from_bits_truncate)
+    }
+
+    fn get_status(&self, recipient: SetupRecipient, index: u16, timeout_ms: u32) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        self.standard_in(recipient, StandardRequest::GetStatus, 0, index, &mut buf, timeout_ms)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    // string descriptor into `buf`, checking its header; returns bLength
+    fn get_string_descriptor_raw(&self, index: u8, language_id: u16, buf: &mut [u8], timeout_ms: u32) -> Result<usize> {
+        let len = self.get_descriptor(DescriptorType::String as u8, index, language_id, buf, timeout_ms)?;
+        if len < 2 || buf[0] < 2 || buf[1] != DescriptorType::String as u8 {
+            return Err(Error::new(ErrorKind::Other, "bad string descriptor"));
+        }
+        Ok(std::cmp::min(buf[0] as usize, len))
+    }
+
+    // IN request that must fill all of `buf`
+    fn standard_in(&self,
+                   recipient: SetupRecipient,
+                   request: StandardRequest,
+                   wValue: u16,
+                   wIndex: u16,
+                   buf: &mut [u8],
+                   timeout_ms: u32)
+                   -> Result<()> {
+        let expected = buf.len();
+        let len = self.control_transfer_in(SetupType::Standard, recipient, request as u8,
+                                           wValue, wIndex, Some(buf), timeout_ms)?;
+        if (len as usize) < expected {
+            return Err(Error::new(ErrorKind::Other, "short response"));
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,3 @@
-
-use std::{ptr};
-
 //use usbtypes::*;
 //use usbtypes::devfs::*;
 //use device::Transfer;
@@ -17,10 +14,10 @@ impl<T> Buffer for T where T: AsMut<[u8]> {}
 
 
 
-/// ///////////////////////////////////////////////////////////////////////////
-///
-/// StdBufTransfer
-///
+/////////////////////////////////////////////////////////////////////////////
+//
+// StdBufTransfer
+//
 
 #[derive(Debug)]
 #[repr(C)]
@@ -55,6 +52,12 @@ unsafe impl<B: Buffer> Transfer for StdBufTransfer<B> {
 
 impl<B: Buffer> StdBufTransfer<B> {
 
+    /// Control transfer whose buffer starts with room for the setup packet, which is filled
+    /// in here, followed by the data stage.
+    ///
+    /// # Panics
+    /// Panics if `buf` can't hold the setup packet plus at most 65535 bytes of data; see
+    /// `try_control()`.
     pub fn control(direction: SetupDirection,
                    stype: SetupType,
                    recipient: SetupRecipient,
@@ -64,8 +67,28 @@ impl<B: Buffer> StdBufTransfer<B> {
                    flags: UrbFlags,
                    buf: B)
                    -> StdBufTransfer<B> {
+        Self::try_control(direction, stype, recipient, request, value, index, flags, buf)
+            .expect("control transfer buffer must be 8 to 65543 bytes")
+    }
 
-        let mut xfer = StdBufTransfer {
+    /// Like `control()`, but fails with `ErrorKind::InvalidParam` if `buf` can't hold the
+    /// setup packet plus at most 65535 bytes of data.
+    pub fn try_control(direction: SetupDirection,
+                       stype: SetupType,
+                       recipient: SetupRecipient,
+                       request: u8,
+                       value: u16,
+                       index: u16,
+                       flags: UrbFlags,
+                       mut buf: B)
+                       -> Result<StdBufTransfer<B>> {
+        let len = buf.as_mut().len();
+        if len < 8 || len - 8 > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidParam, "control buffer must be 8 to 65543 bytes"));
+        }
+        let setup = Setup::new(direction, stype, recipient, request, value, index, (len - 8) as u16);
+        try_write_setup_struct(&setup, buf.as_mut())?;
+        Ok(StdBufTransfer {
             urb: Urb {
                 urbtype: UrbType::Control as u8,
                 endpoint: direction as u8,
@@ -74,20 +97,7 @@ impl<B: Buffer> StdBufTransfer<B> {
             },
             iso_packets: Default::default(),
             buf,
-        };
-
-        if xfer.buf.as_mut().len() < 8 {
-            panic!("control transfer buffer must be at least 8 bytes");
-        }
-        let setup = Setup::new(direction,
-                               stype,
-                               recipient,
-                               request,
-                               value,
-                               index,
-                               (xfer.buf.as_mut().len() - 8) as u16);
-        write_setup_struct(&setup.into(), xfer.buf.as_mut());
-        xfer
+        })
     }
 
     pub fn bulk(endpoint: u8, flags: UrbFlags, buf: B) -> StdBufTransfer<B> {
@@ -103,6 +113,37 @@ impl<B: Buffer> StdBufTransfer<B> {
         }
     }
 
+    /// Bulk transfer on a USB 3.0 stream allocated with `Device::alloc_streams()`.
+    pub fn bulk_stream(endpoint: u8, stream_id: u32, flags: UrbFlags, buf: B) -> StdBufTransfer<B> {
+        let mut xfer = Self::bulk(endpoint, flags, buf);
+        xfer.urb.set_stream_id(stream_id);
+        xfer
+    }
+
+    /// Terminate an OUT transfer whose length is a multiple of the maximum packet size with a
+    /// zero-length packet (`URB_ZERO_PACKET`).
+    pub fn with_zlp(mut self) -> StdBufTransfer<B> {
+        self.urb.flags.insert(UrbFlags::URB_ZERO_PACKET);
+        self
+    }
+
+    /// Fail an IN transfer that ends in a short packet (`URB_SHORT_NOT_OK`), reporting
+    /// `TransferResult::ShortPacket` instead of `Completed`.
+    pub fn with_short_not_ok(mut self) -> StdBufTransfer<B> {
+        self.urb.flags.insert(UrbFlags::URB_SHORT_NOT_OK);
+        self
+    }
+
+    /// Consume the reaped transfer, returning its buffer and a copy of its outcome.
+    pub fn into_result(self) -> (B, TransferOutcome) {
+        let packets = match self.urb.urbtype {
+            urbtype if (UrbType::Iso as u8) == urbtype => &self.iso_packets[..],
+            _ => &[][..],
+        };
+        let outcome = TransferOutcome::from_urb(&self.urb, packets);
+        (self.buf, outcome)
+    }
+
     pub fn interrupt(endpoint: u8, flags: UrbFlags, buf: B) -> StdBufTransfer<B> {
         StdBufTransfer {
             urb: Urb {
@@ -122,7 +163,7 @@ impl<B: Buffer> StdBufTransfer<B> {
                 urbtype: UrbType::Iso as u8,
                 endpoint,
                 flags,
-                number_of_packets: 1,
+                packets_or_stream: UrbPacketsOrStream { number_of_packets: 1 },
                 ..Urb::default()
             },
             iso_packets: Default::default(),
@@ -154,6 +195,11 @@ impl<B: Buffer> StdBufTransfer<B> {
         Ok(&mut self.data_mut()[0..actual_length])
     }
 
+    /// For an isochronous transfer, whether its packet failed, as counted by the kernel.
+    pub fn error_count(&self) -> u32 {
+        self.urb.error_count.max(0) as u32
+    }
+
     pub fn result_length(&self) -> nix::Result<usize> {
         let (status, length) = match self.urb.urbtype {
             urbtype if (UrbType::Iso as u8) == urbtype => {
@@ -178,15 +224,24 @@ fn status_to_nixresult(status: i32) -> nix::Result<()> {
 
 /// Copy a setup packet into the given buffer.
 ///
-/// Async control transfers require an 8 byte bus-endian setup packet at the beginning
-/// of the data buffer.  This function installs that packet.
+/// Async control transfers require the 8 byte setup packet, as sent on the bus, at the
+/// beginning of the data buffer.  This function installs that packet.
 ///
 /// # Panics
-/// This function will panic if the buffer is too short for the 8 byte setup packet.
-pub fn write_setup_struct(setup: &Setup<BusEndian>, buf: &mut [u8]) {
-    // Write setup packet into buffer
-    if buf.len() < 8 {
-        panic!("buf() too short for setup packet");
+/// This function will panic if the buffer is too short for the 8 byte setup packet; see
+/// `try_write_setup_struct()`.
+pub fn write_setup_struct(setup: &Setup, buf: &mut [u8]) {
+    try_write_setup_struct(setup, buf).expect("buf() too short for setup packet")
+}
+
+/// Like `write_setup_struct()`, but fails with `ErrorKind::InvalidParam` if the buffer is
+/// too short for the setup packet.
+pub fn try_write_setup_struct(setup: &Setup, buf: &mut [u8]) -> Result<()> {
+    match buf.get_mut(..8) {
+        Some(head) => {
+            head.copy_from_slice(&setup.to_le_bytes());
+            Ok(())
+        }
+        None => Err(Error::new(ErrorKind::InvalidParam, "buffer too short for setup packet")),
     }
-    unsafe { ptr::copy_nonoverlapping(setup, buf.as_mut_ptr() as *mut Setup<BusEndian>, 1) };
 }
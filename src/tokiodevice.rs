@@ -0,0 +1,212 @@
+use std::io;
+use std::collections::VecDeque;
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::unix::AsyncFd;
+
+use super::*;
+
+/// [tokio](https://tokio.rs) integration for `AsyncDevice`.
+///
+/// `TokioDevice` registers the usbfs file descriptor with the tokio reactor using `AsyncFd`.
+/// Transfers can be awaited one at a time with `transfer()` and the `bulk_*`, `interrupt_*`,
+/// and `control_*` helpers, or many can be kept in flight by `submit()`ing them and consuming
+/// the device as a `Stream` of completed transfers.  The stream form suits isochronous
+/// streaming, with each completed `IsoBufTransfer` being processed and resubmitted.
+///
+/// Completions of other transfers that arrive while a single transfer is being awaited are held
+/// and yielded by the stream.
+///
+/// This type is available with the `tokio` feature and must be created within a tokio runtime.
+///
+/// # Examples
+/// ```no_run,edition2018
+/// # use usbfs::*;
+/// # async fn example() -> std::io::Result<()> {
+/// let device = AsyncDevice::new(&deviceinfo_find(0xffff, 3).unwrap())?;
+/// let mut device = TokioDevice::new(device)?;
+/// let data = device.bulk_in(0x81, 512).await?;
+/// device.bulk_out(0x01, data).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TokioDevice<R> {
+    inner: AsyncFd<AsyncDevice<R>>,
+    pending: VecDeque<(SlotId, R, TransferResult)>,
+}
+
+impl<R> TokioDevice<R>
+    where R: StableDeref + Unpin,
+          R::Target: Transfer
+{
+    /// Register `device` with the current tokio runtime.
+    pub fn new(device: AsyncDevice<R>) -> io::Result<Self> {
+        Ok(TokioDevice{inner: AsyncFd::new(device)?, pending: VecDeque::new()})
+    }
+
+    pub fn get_ref(&self) -> &AsyncDevice<R> {
+        self.inner.get_ref()
+    }
+
+    /// Recover the wrapped `AsyncDevice`, deregistering it from the reactor.
+    pub fn into_inner(self) -> AsyncDevice<R> {
+        self.inner.into_inner()
+    }
+
+    /// Submit a transfer without waiting for it.  The completed transfer is yielded by the
+    /// device's `Stream` implementation.
+    pub fn submit(&mut self, transfer: R) -> io::Result<SlotId> {
+        self.inner.get_mut().submit(transfer).map_err(io::Error::from)
+    }
+
+    /// Submit a transfer and wait for it to complete.
+    ///
+    /// The transfer is submitted immediately, before the future is first polled.  Dropping the
+    /// future does not cancel the transfer: it stays in flight, and once it completes it is
+    /// yielded by the device's `Stream` implementation like a `submit()`ted one.
+    pub fn transfer(&mut self, transfer: R) -> impl Future<Output=io::Result<(R, TransferResult)>> + Unpin + '_ {
+        let mut submitted = Some(self.submit(transfer));
+        poll_fn(move |cx| {
+            let slot = match submitted.take() {
+                Some(Ok(slot)) => slot,
+                Some(Err(err)) => return Poll::Ready(Err(err)),
+                None => panic!("transfer polled after completion"),
+            };
+            loop {
+                match self.poll_reap(cx) {
+                    Poll::Ready(Ok((s, xfer, result))) if s == slot => return Poll::Ready(Ok((xfer, result))),
+                    Poll::Ready(Ok(other)) => self.pending.push_back(other),
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => {
+                        submitted = Some(Ok(slot));
+                        return Poll::Pending;
+                    }
+                }
+            }
+        })
+    }
+
+    // Reap one transfer from the kernel, waiting for the descriptor to become writable.
+    fn poll_reap(&mut self, cx: &mut Context) -> Poll<io::Result<(SlotId, R, TransferResult)>> {
+        loop {
+            let mut guard = match self.inner.poll_write_ready_mut(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            };
+            match guard.try_io(|fd| fd.get_mut().reap_nowait().map_err(io::Error::from)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl<R> Stream for TokioDevice<R>
+    where R: StableDeref + Unpin,
+          R::Target: Transfer
+{
+    type Item = io::Result<(SlotId, R, TransferResult)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(reaped) = this.pending.pop_front() {
+            return Poll::Ready(Some(Ok(reaped)));
+        }
+        this.poll_reap(cx).map(Some)
+    }
+}
+
+/// Helpers for devices using `StdBufTransfer`s with `Vec` buffers.  Like `transfer()`, these
+/// submit immediately, and a transfer whose future is dropped is later yielded by the `Stream`.
+impl TokioDevice<Box<StdBufTransfer<Vec<u8>>>> {
+    /// Read up to `len` bytes from bulk `endpoint`.
+    pub fn bulk_in(&mut self, endpoint: u8, len: usize) -> impl Future<Output=io::Result<Vec<u8>>> + '_ {
+        let xfer = StdBufTransfer::bulk(endpoint | 0x80, UrbFlags::empty(), vec![0; len]);
+        self.transfer_data(Ok(xfer), 0)
+    }
+
+    /// Write `data` to bulk `endpoint`, returning the number of bytes sent.
+    pub fn bulk_out(&mut self, endpoint: u8, data: Vec<u8>) -> impl Future<Output=io::Result<usize>> + '_ {
+        let xfer = StdBufTransfer::bulk(endpoint & 0x7f, UrbFlags::empty(), data);
+        self.transfer_len(Ok(xfer))
+    }
+
+    /// Read up to `len` bytes from interrupt `endpoint`.
+    pub fn interrupt_in(&mut self, endpoint: u8, len: usize) -> impl Future<Output=io::Result<Vec<u8>>> + '_ {
+        let xfer = StdBufTransfer::interrupt(endpoint | 0x80, UrbFlags::empty(), vec![0; len]);
+        self.transfer_data(Ok(xfer), 0)
+    }
+
+    /// Write `data` to interrupt `endpoint`, returning the number of bytes sent.
+    pub fn interrupt_out(&mut self, endpoint: u8, data: Vec<u8>) -> impl Future<Output=io::Result<usize>> + '_ {
+        let xfer = StdBufTransfer::interrupt(endpoint & 0x7f, UrbFlags::empty(), data);
+        self.transfer_len(Ok(xfer))
+    }
+
+    /// Perform a control transfer reading up to `len` bytes from the device.  The future fails
+    /// with `InvalidInput` if `len` is over 65535.
+    pub fn control_in(&mut self,
+                      setuptype: SetupType,
+                      setuprecipient: SetupRecipient,
+                      bRequest: u8,
+                      wValue: u16,
+                      wIndex: u16,
+                      len: usize)
+                      -> impl Future<Output=io::Result<Vec<u8>>> + '_ {
+        let xfer = StdBufTransfer::try_control(SetupDirection::DeviceToHost, setuptype, setuprecipient,
+                                               bRequest, wValue, wIndex, UrbFlags::empty(), vec![0; 8 + len]);
+        self.transfer_data(xfer, 8)
+    }
+
+    /// Perform a control transfer sending `data` to the device, returning the number of bytes
+    /// sent.  The future fails with `InvalidInput` if `data` is over 65535 bytes.
+    pub fn control_out(&mut self,
+                       setuptype: SetupType,
+                       setuprecipient: SetupRecipient,
+                       bRequest: u8,
+                       wValue: u16,
+                       wIndex: u16,
+                       data: &[u8])
+                       -> impl Future<Output=io::Result<usize>> + '_ {
+        let mut buf = vec![0; 8];
+        buf.extend_from_slice(data);
+        let xfer = StdBufTransfer::try_control(SetupDirection::HostToDevice, setuptype, setuprecipient,
+                                               bRequest, wValue, wIndex, UrbFlags::empty(), buf);
+        self.transfer_len(xfer)
+    }
+
+    // Run `xfer` and return the received data, which starts at `offset` in the buffer.  If
+    // `xfer` couldn't be built, the future fails with that error.
+    fn transfer_data(&mut self, xfer: Result<StdBufTransfer<Vec<u8>>>, offset: usize) -> impl Future<Output=io::Result<Vec<u8>>> + '_ {
+        let mut fut = match xfer {
+            Ok(xfer) => Ok(self.transfer(Box::new(xfer))),
+            Err(err) => Err(Some(err)),
+        };
+        poll_fn(move |cx| match fut {
+            Ok(ref mut fut) => Pin::new(fut).poll(cx).map(|r| r.and_then(|(xfer, result)| {
+                let len = result.into_io_result()?;
+                let mut buf = xfer.buf;
+                buf.truncate(offset + len);
+                buf.drain(..offset);
+                Ok(buf)
+            })),
+            Err(ref mut err) => Poll::Ready(Err(err.take().expect("transfer polled after completion").into())),
+        })
+    }
+
+    // Run `xfer` and return the number of bytes transferred.
+    fn transfer_len(&mut self, xfer: Result<StdBufTransfer<Vec<u8>>>) -> impl Future<Output=io::Result<usize>> + '_ {
+        let mut fut = match xfer {
+            Ok(xfer) => Ok(self.transfer(Box::new(xfer))),
+            Err(err) => Err(Some(err)),
+        };
+        poll_fn(move |cx| match fut {
+            Ok(ref mut fut) => Pin::new(fut).poll(cx).map(|r| r.and_then(|(_, result)| result.into_io_result())),
+            Err(ref mut err) => Poll::Ready(Err(err.take().expect("transfer polled after completion").into())),
+        })
+    }
+}
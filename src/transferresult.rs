@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+use libc;
+
+use super::*;
+
+/// Status of a urb or isochronous packet, the negated errno usbfs reports in `Urb::status`
+/// and `IsoPacketDesc::status`.
+///
+/// The kernel reuses general errno values with USB specific meanings, documented in its
+/// `Documentation/driver-api/usb/error-codes.rst`; `Display` gives those meanings rather than
+/// the errno's usual text, for logs and error messages.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// # fn f(xfer: &IsoBufTransfer<Vec<u8>, 8>) {
+/// for packet in xfer.status() {
+///     println!("{} bytes: {}", packet.actual_length, UsbStatus::from_status(packet.status));
+/// }
+/// # }
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum UsbStatus {
+    /// 0.
+    Success,
+    /// The transfer has not completed yet (`-EINPROGRESS`).
+    InProgress,
+    /// The endpoint stalled (`-EPIPE`).
+    Stall,
+    /// The device sent more data than the packet could hold (`-EOVERFLOW`).
+    Babble,
+    /// Bit stuffing or another low level protocol error (`-EPROTO`).
+    Protocol,
+    /// CRC mismatch (`-EILSEQ`).
+    Crc,
+    /// The transfer was killed synchronously (`-ENOENT`).
+    Killed,
+    /// The transfer was unlinked asynchronously (`-ECONNRESET`).
+    Unlinked,
+    /// The device or its host controller was shut down (`-ESHUTDOWN`).
+    Shutdown,
+    /// The device is gone (`-ENODEV`).
+    NoDevice,
+    /// The transfer timed out (`-ETIMEDOUT`).
+    Timeout,
+    /// A short packet ended a transfer submitted with `URB_SHORT_NOT_OK` (`-EREMOTEIO`).
+    ShortPacket,
+    /// An isochronous packet was missed or only partly transferred (`-EXDEV`).
+    PartialIso,
+    /// Any other status, as a positive errno value.
+    Other(i32),
+}
+
+impl UsbStatus {
+    /// Decode a `status` field.
+    pub fn from_status(status: i32) -> UsbStatus {
+        match -status {
+            0 => UsbStatus::Success,
+            libc::EINPROGRESS => UsbStatus::InProgress,
+            libc::EPIPE => UsbStatus::Stall,
+            libc::EOVERFLOW => UsbStatus::Babble,
+            libc::EPROTO => UsbStatus::Protocol,
+            libc::EILSEQ => UsbStatus::Crc,
+            libc::ENOENT => UsbStatus::Killed,
+            libc::ECONNRESET => UsbStatus::Unlinked,
+            libc::ESHUTDOWN => UsbStatus::Shutdown,
+            libc::ENODEV => UsbStatus::NoDevice,
+            libc::ETIMEDOUT => UsbStatus::Timeout,
+            libc::EREMOTEIO => UsbStatus::ShortPacket,
+            libc::EXDEV => UsbStatus::PartialIso,
+            errno => UsbStatus::Other(errno),
+        }
+    }
+
+    /// The `status` value, 0 or a negated errno.
+    pub fn status(&self) -> i32 {
+        -match *self {
+            UsbStatus::Success => 0,
+            UsbStatus::InProgress => libc::EINPROGRESS,
+            UsbStatus::Stall => libc::EPIPE,
+            UsbStatus::Babble => libc::EOVERFLOW,
+            UsbStatus::Protocol => libc::EPROTO,
+            UsbStatus::Crc => libc::EILSEQ,
+            UsbStatus::Killed => libc::ENOENT,
+            UsbStatus::Unlinked => libc::ECONNRESET,
+            UsbStatus::Shutdown => libc::ESHUTDOWN,
+            UsbStatus::NoDevice => libc::ENODEV,
+            UsbStatus::Timeout => libc::ETIMEDOUT,
+            UsbStatus::ShortPacket => libc::EREMOTEIO,
+            UsbStatus::PartialIso => libc::EXDEV,
+            UsbStatus::Other(errno) => errno,
+        }
+    }
+}
+
+impl fmt::Display for UsbStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UsbStatus::Success => write!(f, "success"),
+            UsbStatus::InProgress => write!(f, "in progress"),
+            UsbStatus::Stall => write!(f, "endpoint stalled"),
+            UsbStatus::Babble => write!(f, "babble, device sent too much data"),
+            UsbStatus::Protocol => write!(f, "protocol error"),
+            UsbStatus::Crc => write!(f, "CRC error"),
+            UsbStatus::Killed => write!(f, "killed"),
+            UsbStatus::Unlinked => write!(f, "unlinked"),
+            UsbStatus::Shutdown => write!(f, "device or host controller shut down"),
+            UsbStatus::NoDevice => write!(f, "no device"),
+            UsbStatus::Timeout => write!(f, "timed out"),
+            UsbStatus::ShortPacket => write!(f, "short packet"),
+            UsbStatus::PartialIso => write!(f, "isochronous packet missed or incomplete"),
+            UsbStatus::Other(errno) => write!(f, "{}", io::Error::from_raw_os_error(errno)),
+        }
+    }
+}
+
+/// Outcome of a reaped transfer, decoded from the `status` and `actual_length` of its `Urb`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum TransferResult {
+    /// The transfer completed and `len` bytes were transferred.
+    Completed { len: usize },
+    /// An IN transfer submitted with `URB_SHORT_NOT_OK` ended in a short packet after `len`
+    /// bytes (`-EREMOTEIO`).
+    ShortPacket { len: usize },
+    /// The endpoint stalled (`-EPIPE`).
+    Stalled,
+    /// The transfer was discarded before completion (`-ENOENT` or `-ECONNRESET`).
+    Cancelled,
+    /// The device was disconnected (`-ENODEV` or `-ESHUTDOWN`).
+    NoDevice,
+    /// The device sent more data than expected (`-EOVERFLOW`).
+    Babble,
+    /// The transfer timed out (`-ETIMEDOUT`).
+    Timeout,
+    /// Any other failure, as a positive errno value.
+    Other(i32),
+}
+
+impl TransferResult {
+    /// Decode a urb (or iso packet) `status` and `actual_length`.
+    pub fn from_status(status: i32, actual_length: i32) -> TransferResult {
+        match -status {
+            0 => TransferResult::Completed { len: actual_length as usize },
+            libc::EREMOTEIO => TransferResult::ShortPacket { len: actual_length as usize },
+            libc::EPIPE => TransferResult::Stalled,
+            libc::ENOENT | libc::ECONNRESET => TransferResult::Cancelled,
+            libc::ENODEV | libc::ESHUTDOWN => TransferResult::NoDevice,
+            libc::EOVERFLOW => TransferResult::Babble,
+            libc::ETIMEDOUT => TransferResult::Timeout,
+            errno => TransferResult::Other(errno),
+        }
+    }
+
+    /// Decode the result recorded in a reaped `Urb`.
+    pub fn from_urb(urb: &Urb) -> TransferResult {
+        Self::from_status(urb.status, urb.actual_length)
+    }
+
+    /// The status the result was decoded from.  `Cancelled` and `NoDevice` each stand for two
+    /// statuses, of which this gives `Killed` and `NoDevice`.
+    pub fn usb_status(&self) -> UsbStatus {
+        match *self {
+            TransferResult::Completed { .. } => UsbStatus::Success,
+            TransferResult::ShortPacket { .. } => UsbStatus::ShortPacket,
+            TransferResult::Stalled => UsbStatus::Stall,
+            TransferResult::Cancelled => UsbStatus::Killed,
+            TransferResult::NoDevice => UsbStatus::NoDevice,
+            TransferResult::Babble => UsbStatus::Babble,
+            TransferResult::Timeout => UsbStatus::Timeout,
+            TransferResult::Other(errno) => UsbStatus::from_status(-errno),
+        }
+    }
+
+    /// `true` for `Completed`.
+    pub fn is_ok(&self) -> bool {
+        matches!(*self, TransferResult::Completed { .. })
+    }
+
+    /// How an IN transfer of `requested` bytes on an endpoint with `max_packet_size` came to
+    /// an end, or `None` if it failed for any reason other than a short packet.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// # fn f(result: TransferResult) {
+    /// match result.end(4096, 512) {
+    ///     Some(TransferEnd::ZeroLengthPacket) => println!("message ended on a packet boundary"),
+    ///     Some(TransferEnd::ShortPacket) => println!("message ended"),
+    ///     Some(TransferEnd::Filled) => println!("message continues"),
+    ///     None => println!("failed: {:?}", result),
+    /// }
+    /// # }
+    /// ```
+    pub fn end(&self, requested: usize, max_packet_size: usize) -> Option<TransferEnd> {
+        let len = match *self {
+            TransferResult::Completed { len } | TransferResult::ShortPacket { len } => len,
+            _ => return None,
+        };
+        Some(if len >= requested {
+            TransferEnd::Filled
+        } else if max_packet_size != 0 && len % max_packet_size == 0 {
+            TransferEnd::ZeroLengthPacket
+        } else {
+            TransferEnd::ShortPacket
+        })
+    }
+
+    /// Convert to an `io::Result` holding the number of bytes transferred.  Failures become the
+    /// corresponding OS error.
+    pub fn into_io_result(self) -> io::Result<usize> {
+        let errno = match self {
+            TransferResult::Completed { len } => return Ok(len),
+            TransferResult::ShortPacket { .. } => libc::EREMOTEIO,
+            TransferResult::Stalled => libc::EPIPE,
+            TransferResult::Cancelled => libc::ENOENT,
+            TransferResult::NoDevice => libc::ENODEV,
+            TransferResult::Babble => libc::EOVERFLOW,
+            TransferResult::Timeout => libc::ETIMEDOUT,
+            TransferResult::Other(errno) => errno,
+        };
+        Err(io::Error::from_raw_os_error(errno))
+    }
+}
+
+impl fmt::Display for TransferResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TransferResult::Completed { len } => write!(f, "completed, {} bytes", len),
+            TransferResult::ShortPacket { len } => write!(f, "short packet after {} bytes", len),
+            TransferResult::Cancelled => write!(f, "cancelled"),
+            ref other => other.usb_status().fmt(f),
+        }
+    }
+}
+
+/// Owned copy of everything the kernel reported for a reaped transfer, as returned by the
+/// `into_result()` methods of the transfer types along with the transfer's buffer.
+///
+/// Unlike the transfer itself this holds no `Urb` or raw pointers, so it can be handed to other
+/// threads or kept after the buffer has been reused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct TransferOutcome {
+    pub result: TransferResult,
+    /// Raw urb status, 0 or a negative errno.
+    pub status: i32,
+    /// Bytes transferred, not counting the setup packet of control transfers.
+    pub actual_length: usize,
+    /// Start frame of an isochronous transfer as it was submitted.  usbfs does not report the
+    /// frame the host controller actually scheduled.
+    pub start_frame: u32,
+    /// Number of isochronous packets that failed.
+    pub error_count: u32,
+    /// Isochronous packet descriptors; empty for other transfers.
+    pub iso_packets: Vec<IsoPacketDesc>,
+}
+
+impl TransferOutcome {
+    /// Snapshot a reaped `urb` and its isochronous packet descriptors.
+    pub fn from_urb(urb: &Urb, iso_packets: &[IsoPacketDesc]) -> TransferOutcome {
+        TransferOutcome {
+            result: TransferResult::from_urb(urb),
+            status: urb.status,
+            actual_length: urb.actual_length.max(0) as usize,
+            start_frame: urb.start_frame as u32,
+            error_count: urb.error_count.max(0) as u32,
+            iso_packets: iso_packets.to_vec(),
+        }
+    }
+
+    /// Failed isochronous packets, counted by status.
+    pub fn error_summary(&self) -> IsoErrorSummary {
+        IsoErrorSummary::from_packets(&self.iso_packets)
+    }
+}
+
+/// Isochronous packets counted by outcome, from `IsoBufTransfer::error_summary()`.
+///
+/// Summaries of successive transfers can be merged to follow the quality of a stream's link:
+/// CRC errors (`-EILSEQ`) and protocol errors (`-EPROTO`) point at cabling or signal problems,
+/// and missed packets (`-EXDEV`) at a host that can't keep up.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// # fn f(transfers: Vec<IsoBufTransfer<Vec<u8>, 8>>) {
+/// let mut summary = IsoErrorSummary::default();
+/// for xfer in &transfers {
+///     summary.merge(&xfer.error_summary());
+/// }
+/// println!("{} of {} packets failed: {:?}", summary.failed, summary.packets, summary.by_status);
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct IsoErrorSummary {
+    /// Number of packets.
+    pub packets: usize,
+    /// Number of packets with a nonzero status.
+    pub failed: usize,
+    /// Number of failed packets with each status, a negative errno; see `UsbStatus`.
+    pub by_status: BTreeMap<i32, usize>,
+}
+
+impl IsoErrorSummary {
+    /// Count the outcomes of reaped packet descriptors.
+    pub fn from_packets(packets: &[IsoPacketDesc]) -> IsoErrorSummary {
+        let mut summary = IsoErrorSummary { packets: packets.len(), ..IsoErrorSummary::default() };
+        for packet in packets.iter().filter(|packet| packet.status != 0) {
+            summary.failed += 1;
+            *summary.by_status.entry(packet.status).or_insert(0) += 1;
+        }
+        summary
+    }
+
+    /// Add the counts of `other`.
+    pub fn merge(&mut self, other: &IsoErrorSummary) {
+        self.packets += other.packets;
+        self.failed += other.failed;
+        for (&status, &count) in &other.by_status {
+            *self.by_status.entry(status).or_insert(0) += count;
+        }
+    }
+
+    /// Fraction of packets that failed, or 0 if there were none.
+    pub fn failure_rate(&self) -> f64 {
+        match self.packets {
+            0 => 0.0,
+            packets => self.failed as f64 / packets as f64,
+        }
+    }
+}
+
+/// How a transfer ended, see `TransferResult::end()`.
+///
+/// The device ends a transfer early by sending a packet shorter than the endpoint's maximum
+/// packet size.  When the data happens to end on a packet boundary that packet has no data at
+/// all: a zero-length packet.  OUT transfers get the same treatment with `URB_ZERO_PACKET`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum TransferEnd {
+    /// The whole buffer was transferred.
+    Filled,
+    /// The transfer ended in a short packet carrying the last of the data.
+    ShortPacket,
+    /// The transfer ended in a zero-length packet.
+    ZeroLengthPacket,
+}
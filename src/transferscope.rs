@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use super::*;
+
+/// Submission of borrowed transfers, from `Device::transfer_scope()`.
+///
+/// Transfers are submitted as `&'a mut T`, borrowing from outside the scope, and handed back
+/// the same way when reaped.  The methods mirror those of `AsyncDevice`.
+pub struct TransferScope<'a, T: Transfer + ?Sized + 'a> {
+    // not exposed, so the queue can't be moved out and leaked with transfers in flight
+    device: AsyncDevice<Lent<'a, T>>,
+}
+
+// A transfer borrowed by a `TransferScope`.  Only `transfer_scope()` puts these in an
+// `AsyncDevice`, and the scope reaps them all before the borrow ends, which is what makes the
+// `StableDeref` promise hold for a mere reference.
+struct Lent<'a, T: ?Sized>(&'a mut T);
+
+unsafe impl<'a, T: ?Sized> StableDeref for Lent<'a, T> {
+    type Target = T;
+
+    unsafe fn stable_mut(&mut self) -> &mut T {
+        self.0
+    }
+}
+
+impl Device {
+    /// Run `f` with a `TransferScope` on this device, for submitting transfers that borrow
+    /// their buffers, eg. from the stack, rather than owning them in a `Box`.
+    ///
+    /// Like `std::thread::scope()`, every transfer submitted in the scope is done with before
+    /// `transfer_scope()` returns: any still in flight when `f` returns, or panics, are
+    /// cancelled and reaped, waiting as long as that takes.  This is what makes lending
+    /// buffers to the kernel safe, since an `AsyncDevice` of borrowed transfers could
+    /// otherwise be leaked while the kernel still writes into them.  Should reaping fail for
+    /// good, the process is aborted rather than hand the buffers back early.  Only transfers
+    /// borrowed from outside `f` can be submitted.
+    ///
+    /// The scope submits and reaps on the device's own file, which stays borrowed mutably
+    /// until it ends, so nothing else reaps from its completion queue.  Handles from
+    /// `try_clone()` share that queue, and must not have transfers in flight meanwhile.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use usbfs::*;
+    ///
+    /// let mut device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+    /// let mut request = [0x55u8; 4];
+    /// let mut response = [0u8; 64];
+    /// let mut command = BulkTransferMut::new(0x01, UrbFlags::empty(), &mut request[..]);
+    /// let mut reply = BulkTransferMut::new(0x81, UrbFlags::empty(), &mut response[..]);
+    /// device.transfer_scope(|scope| -> Result<()> {
+    ///     scope.submit(&mut command)?;
+    ///     scope.submit(&mut reply)?;
+    ///     for _ in 0..2 {
+    ///         let (_slot, _xfer, result) = scope.reap_wait()?;
+    ///         result.into_io_result()?;
+    ///     }
+    ///     Ok(())
+    /// }).unwrap();
+    /// println!("{:?}", reply.buf);
+    /// ```
+    pub fn transfer_scope<'a, T, F, O>(&mut self, f: F) -> O
+        where T: Transfer + ?Sized + 'a,
+              F: FnOnce(&mut TransferScope<'a, T>) -> O
+    {
+        // the scope, and with it the loan, ends before the borrow of `self` does
+        let mut scope = TransferScope{device: AsyncDevice::from(unsafe { self.lend() })};
+        f(&mut scope)
+    }
+}
+
+// Ending the scope, whether `f` returned or panicked, waits out every transfer still lent to
+// the kernel.
+impl<'a, T: Transfer + ?Sized + 'a> Drop for TransferScope<'a, T> {
+    fn drop(&mut self) {
+        self.device.cancel_all();
+        if let Err(err) = self.device.drain() {
+            // the kernel may still write into the borrowed transfers, and there's no letting
+            // their owners have them back until it's done
+            log_debug!("{} transfers of a transfer scope can't be reaped: {}", self.device.pending_count(), err);
+            std::process::abort();
+        }
+    }
+}
+
+impl<'a, T: Transfer + ?Sized + 'a> TransferScope<'a, T> {
+    /// See `AsyncDevice::submit_give_back_on_fail()`.
+    pub fn submit_give_back_on_fail(&mut self, transfer: &'a mut T) -> Result<SlotId, (Error, &'a mut T)> {
+        self.device.submit_give_back_on_fail(Lent(transfer)).map_err(|(err, lent)| (err, lent.0))
+    }
+
+    /// See `AsyncDevice::submit()`.
+    pub fn submit(&mut self, transfer: &'a mut T) -> Result<SlotId> {
+        self.device.submit(Lent(transfer))
+    }
+
+    /// See `AsyncDevice::reap_nowait()`.
+    pub fn reap_nowait(&mut self) -> Result<(SlotId, &'a mut T, TransferResult)> {
+        self.device.reap_nowait().map(|(id, lent, result)| (id, lent.0, result))
+    }
+
+    /// See `AsyncDevice::reap_wait()`.
+    pub fn reap_wait(&mut self) -> Result<(SlotId, &'a mut T, TransferResult)> {
+        self.device.reap_wait().map(|(id, lent, result)| (id, lent.0, result))
+    }
+
+    /// See `AsyncDevice::reap_timeout()`.
+    pub fn reap_timeout(&mut self, timeout: Duration) -> Result<(SlotId, &'a mut T, TransferResult)> {
+        self.device.reap_timeout(timeout).map(|(id, lent, result)| (id, lent.0, result))
+    }
+
+    /// See `AsyncDevice::discard()`.
+    pub fn discard(&mut self, id: SlotId) -> Result<&'a mut T> {
+        self.device.discard(id).map(|lent| lent.0)
+    }
+
+    /// See `AsyncDevice::cancel_all()`.
+    pub fn cancel_all(&mut self) {
+        self.device.cancel_all()
+    }
+
+    /// Number of transfers in flight.
+    pub fn pending_count(&self) -> usize {
+        self.device.pending_count()
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device.device
+    }
+}
@@ -0,0 +1,461 @@
+use std::cmp;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use super::*;
+
+/// A streaming format of an audio streaming interface: one alternate setting with its
+/// endpoints, as listed by `UacStreaming::formats()`.
+///
+/// Available with the `uac` feature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct UacFormat {
+    pub altsetting: u8,
+    pub channels: u8,
+    /// Bytes per sample of one channel.
+    pub subframe_size: u8,
+    /// Significant bits per sample.
+    pub bit_resolution: u8,
+    /// Rates listed by the format descriptor.  `None` for UAC2, where rates are a property of
+    /// the clock source.
+    pub sample_rates: Option<SampleRates>,
+    /// Isochronous data endpoint; IN for capture, OUT for playback.
+    pub endpoint: u8,
+    /// Isochronous IN endpoint through which an asynchronous sink reports its rate.
+    pub feedback_endpoint: Option<u8>,
+    /// Bytes the feedback endpoint moves per service interval at the device's speed, 3 for
+    /// full speed UAC1 sinks and usually 4 otherwise.  0 without a feedback endpoint.
+    pub feedback_packet_size: usize,
+}
+
+impl UacFormat {
+    /// Whether this is a capture format, with samples flowing from the device.
+    pub fn is_input(&self) -> bool {
+        self.endpoint & 0x80 != 0
+    }
+
+    /// Bytes per sample frame, ie. one sample of every channel.
+    pub fn frame_size(&self) -> usize {
+        self.channels as usize * self.subframe_size as usize
+    }
+
+    /// Whether the format descriptor admits `rate`.  Always true for UAC2.
+    pub fn supports_rate(&self, rate: u32) -> bool {
+        match self.sample_rates {
+            Some(SampleRates::Discrete(ref rates)) => rates.contains(&rate),
+            Some(SampleRates::Continuous { min, max }) => min <= rate && rate <= max,
+            None => true,
+        }
+    }
+}
+
+// UAC class requests and controls
+const SET_CUR: u8 = 0x01;
+const SAMPLING_FREQ_CONTROL: u16 = 0x01;
+
+// Packets per transfer of a `UacStream`.
+const PACKETS: usize = 8;
+
+/// A claimed audio streaming interface of a USB Audio Class 1 or 2 device.  Available with
+/// the `uac` feature.
+///
+/// `formats()` lists the alternate settings.  `stream()` selects one and sets the sample
+/// rate, returning a `UacStream` which moves the samples.
+///
+/// # Examples
+/// Play a 440Hz tone on a 48kHz, 16 bit stereo output:
+///
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let mut uac = UacStreaming::new(device.claim(1).unwrap()).unwrap();
+/// let format = uac.formats().into_iter()
+///     .find(|f| !f.is_input() && f.channels == 2 && f.subframe_size == 2 && f.supports_rate(48000))
+///     .unwrap();
+/// let mut stream = uac.stream(&format, 48000, 4).unwrap();
+/// let mut t = 0u64;
+/// loop {
+///     stream.next_playback(|samples| {
+///         for frame in samples.chunks_exact_mut(4) {
+///             let v = ((t as f64 * 440.0 / 48000.0 * 6.283).sin() * 8000.0) as i16;
+///             frame[..2].copy_from_slice(&v.to_le_bytes());
+///             frame[2..].copy_from_slice(&v.to_le_bytes());
+///             t += 1;
+///         }
+///     }).unwrap();
+/// }
+/// ```
+pub struct UacStreaming<'a> {
+    intf: Interface<'a>,
+    timeout_ms: u32,
+}
+
+impl<'a> UacStreaming<'a> {
+    /// Wrap claimed interface `intf`.  Interfaces that aren't audio streaming interfaces give
+    /// `ErrorKind::InvalidParam`.
+    pub fn new(intf: Interface<'a>) -> Result<UacStreaming<'a>> {
+        let d = intf.descriptor();
+        if d.bInterfaceClass != 0x01 || d.bInterfaceSubClass != 0x02 {
+            return Err(Error::new(ErrorKind::InvalidParam, "not an audio streaming interface"));
+        }
+        Ok(UacStreaming { intf, timeout_ms: 1000 })
+    }
+
+    pub fn interface(&self) -> &Interface<'a> {
+        &self.intf
+    }
+
+    /// Whether the interface follows USB Audio Class 2.
+    pub fn is_uac2(&self) -> bool {
+        self.intf.descriptor().bInterfaceProtocol == 0x20
+    }
+
+    /// Timeout of each control request; 0 waits forever.  Defaults to 1000ms.
+    pub fn set_timeout(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    /// Type I (PCM) formats of the interface, one per alternate setting.
+    pub fn formats(&self) -> Vec<UacFormat> {
+        let uac2 = self.is_uac2();
+        let speed = self.intf.device().speed().unwrap_or(Speed::Full);
+        self.intf.altsettings().iter().filter_map(|alt| format_of(alt, uac2, speed)).collect()
+    }
+
+    /// Select `format` and set the sample rate to `sample_rate` Hz.
+    pub fn configure(&mut self, format: &UacFormat, sample_rate: u32) -> Result<()> {
+        if !format.supports_rate(sample_rate) {
+            return Err(Error::new(ErrorKind::InvalidParam, "sample rate not supported by format"));
+        }
+        self.intf.set_altsetting(format.altsetting)?;
+        let result = match self.is_uac2() {
+            false => self.set_endpoint_rate(format.endpoint, sample_rate),
+            true => self.set_clock_rate(sample_rate),
+        };
+        match result {
+            // devices with a single fixed rate may not implement the control
+            Err(ref err) if err.kind() == ErrorKind::Stall => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Configure `format` at `sample_rate` Hz and start streaming with `depth` transfers
+    /// queued.  The stream works on its own handle on the device; the interface must stay
+    /// claimed while it is used.
+    ///
+    /// The handle is a duplicate of the device's file descriptor, and shares the file's
+    /// completion queue with every other handle on it.  The device must be otherwise idle
+    /// while the stream runs: any transfer in flight elsewhere on the file, including on
+    /// another stream, could be reaped by the wrong side, which then fails with
+    /// `ErrorKind::Other`.
+    pub fn stream(&mut self, format: &UacFormat, sample_rate: u32, depth: usize) -> Result<UacStream> {
+        self.configure(format, sample_rate)?;
+        let speed = self.intf.device().speed().unwrap_or(Speed::Full);
+        let ep = self.intf.endpoint(format.endpoint)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "format endpoint not in alternate setting"))?;
+        UacStream::new(self.intf.device().try_clone()?, format.clone(), sample_rate, speed, ep, depth)
+    }
+
+    /// Return to alternate setting 0, which stops the stream.
+    pub fn stop(&mut self) -> Result<()> {
+        self.intf.set_altsetting(0)
+    }
+
+    // UAC1: sampling frequency control of the data endpoint, 3 bytes.
+    fn set_endpoint_rate(&self, endpoint: u8, rate: u32) -> Result<()> {
+        ControlRequest::new()
+            .request_type(SetupType::Class)
+            .recipient(SetupRecipient::Endpoint)
+            .request(SET_CUR)
+            .value(SAMPLING_FREQ_CONTROL << 8)
+            .index(endpoint as u16)
+            .timeout(self.timeout_ms)
+            .data_out(&rate.to_le_bytes()[..3])
+            .send(self.intf.device())
+            .map(|_| ())
+    }
+
+    // UAC2: sampling frequency control of the function's clock source, 4 bytes.
+    fn set_clock_rate(&self, rate: u32) -> Result<()> {
+        let (ac_interface, clock_id) = self.clock_source()?;
+        ControlRequest::new()
+            .request_type(SetupType::Class)
+            .recipient(SetupRecipient::Interface)
+            .request(SET_CUR)
+            .value(SAMPLING_FREQ_CONTROL << 8)
+            .index((clock_id as u16) << 8 | ac_interface as u16)
+            .timeout(self.timeout_ms)
+            .data_out(&rate.to_le_bytes())
+            .send(self.intf.device())
+            .map(|_| ())
+    }
+
+    // Audio control interface and ID of the first clock source of a UAC2 function.
+    fn clock_source(&self) -> Result<(u8, u8)> {
+        self.intf.device().active_config_descriptor()?
+            .into_iter()
+            .flat_map(|c| c.interfaces)
+            .filter(|i| i.bInterfaceClass == 0x01 && i.bInterfaceSubClass == 0x01)
+            .find_map(|i| {
+                // CLOCK_SOURCE descriptor
+                let clock = i.extra.iter().find(|d| d.0.len() >= 4 && d.0[1] == CS_INTERFACE && d.0[2] == 0x0a)?;
+                Some((i.bInterfaceNumber, clock.0[3]))
+            })
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no UAC2 clock source"))
+    }
+}
+
+// The format of alternate setting `alt`, if it has a type I format and a data endpoint.
+fn format_of(alt: &InterfaceDescriptor, uac2: bool, speed: Speed) -> Option<UacFormat> {
+    let cs = |subtype: u8| alt.extra.iter()
+        .map(|d| &d.0[..])
+        .find(|d| d.len() >= 3 && d[1] == CS_INTERFACE && d[2] == subtype);
+    let general = cs(0x01)?;
+    let format = cs(0x02)?;
+    let (channels, subframe_size, bit_resolution, sample_rates) = match uac2 {
+        // AS_GENERAL carries the channel count; FORMAT_TYPE_I only the slot size
+        true if general.len() >= 11 && format.len() >= 6 && format[3] == 1 => {
+            (general[10], format[4], format[5], None)
+        }
+        false => match UacDescriptor::parse(0x02, &RawDescriptor(format.to_vec()))? {
+            UacDescriptor::FormatTypeI { bNrChannels, bSubframeSize, bBitResolution, sample_rates } => {
+                (bNrChannels, bSubframeSize, bBitResolution, Some(sample_rates))
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let iso = |is_in: bool| alt.endpoints.iter().filter(move |ep| ep.transfer_type() == UrbType::Iso && ep.is_in() == is_in);
+    // a data endpoint is never a feedback endpoint (usage type 1)
+    let data = iso(true).chain(iso(false)).find(|ep| (ep.bmAttributes >> 4) & 0x03 != 1)?;
+    let feedback = match data.is_in() {
+        true => None,
+        false => iso(true).next(),
+    };
+    Some(UacFormat {
+        altsetting: alt.bAlternateSetting,
+        channels,
+        subframe_size,
+        bit_resolution,
+        sample_rates,
+        endpoint: data.bEndpointAddress,
+        feedback_endpoint: feedback.map(|ep| ep.bEndpointAddress),
+        feedback_packet_size: feedback.map_or(0, |ep| ep.max_bytes_per_interval(speed)),
+    })
+}
+
+/// Buffer of a `UacStream` transfer, with a length for each packet.
+#[derive(Debug)]
+pub struct UacPackets {
+    data: Vec<u8>,
+    lengths: Vec<usize>,
+    max_packet: usize,
+}
+
+impl AsRef<[u8]> for UacPackets {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl AsMut<[u8]> for UacPackets {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl IsoBuffer for UacPackets {
+    fn packet_length(&self) -> usize {
+        self.max_packet
+    }
+
+    fn packet_lengths(&self) -> impl Iterator<Item=usize> {
+        self.lengths.iter().copied()
+    }
+}
+
+impl UacPackets {
+    fn set_lengths<I: IntoIterator<Item=usize>>(&mut self, lengths: I) {
+        self.lengths.clear();
+        self.lengths.extend(lengths);
+        self.data.resize(self.lengths.iter().sum(), 0);
+    }
+}
+
+type UacTransfer = IsoBufTransfer<UacPackets, PACKETS>;
+
+/// A running audio stream, returned by `UacStreaming::stream()`.
+///
+/// Each call to `next_playback()` or `next_capture()` waits for the oldest transfer to
+/// complete, passes its samples to a closure, and resubmits it, keeping the queue depth
+/// constant.  Samples are interleaved sample frames in the format's little endian layout.
+///
+/// For playback the number of samples per packet follows the sample rate, spreading the
+/// fractional part over successive packets.  When the sink is asynchronous and has a feedback
+/// endpoint, the rate it reports replaces the nominal one, so the device's buffer neither
+/// overflows nor runs dry.
+pub struct UacStream {
+    device: AsyncDevice<Box<UacTransfer>>,
+    format: UacFormat,
+    sample_rate: u32,
+    max_packet: usize,
+    /// Sample frames per packet in 16.16 fixed point.
+    rate: u32,
+    nominal_rate: u32,
+    /// (Micro)frames per packet, the unit of feedback values.
+    frames_per_packet: u32,
+    remainder: u32,
+    samples: Vec<u8>,
+}
+
+impl UacStream {
+    fn new(device: Device, format: UacFormat, sample_rate: u32, speed: Speed,
+           ep: &EndpointDescriptor, depth: usize) -> Result<UacStream> {
+        let frames_per_packet = match speed {
+            Speed::Low | Speed::Full => 1,
+            _ => 1 << (ep.bInterval.clamp(1, 16) - 1),
+        };
+        let frames_per_second = match speed {
+            Speed::Low | Speed::Full => 1000,
+            _ => 8000,
+        };
+        let nominal_rate = (((sample_rate as u64) << 16) * frames_per_packet as u64 / frames_per_second) as u32;
+        let mut stream = UacStream {
+            device: device.into(),
+            max_packet: ep.max_bytes_per_interval(speed),
+            format,
+            sample_rate,
+            rate: nominal_rate,
+            nominal_rate,
+            frames_per_packet,
+            remainder: 0,
+            samples: Vec::new(),
+        };
+        for _ in 0..depth {
+            let xfer = stream.data_transfer();
+            stream.device.submit(xfer)?;
+        }
+        if let Some(endpoint) = stream.format.feedback_endpoint {
+            // packets longer than the endpoint's are refused with EMSGSIZE
+            let size = stream.format.feedback_packet_size;
+            for _ in 0..2 {
+                let mut buf = UacPackets { data: Vec::new(), lengths: Vec::new(), max_packet: size };
+                buf.set_lengths(Some(size));
+                stream.device.submit(Box::new(IsoBufTransfer::isochronous(endpoint, UrbFlags::URB_ISO_ASAP, buf)))?;
+            }
+        }
+        Ok(stream)
+    }
+
+    pub fn format(&self) -> &UacFormat {
+        &self.format
+    }
+
+    /// Current rate in sample frames per second, as adjusted by feedback.
+    pub fn rate_hz(&self) -> f64 {
+        self.sample_rate as f64 * self.rate as f64 / self.nominal_rate as f64
+    }
+
+    /// Wait for a playback transfer to complete and call `f` to fill the next transfer's
+    /// samples.  The slice holds a whole number of sample frames; its length varies with
+    /// the rate.
+    pub fn next_playback<F: FnOnce(&mut [u8])>(&mut self, f: F) -> Result<()> {
+        if self.format.is_input() {
+            return Err(Error::new(ErrorKind::InvalidParam, "capture stream"));
+        }
+        let mut xfer = self.reap_data()?;
+        let lengths: Vec<usize> = (0..PACKETS).map(|_| self.next_packet_length()).collect();
+        xfer.buf.set_lengths(lengths);
+        f(&mut xfer.buf.data);
+        self.resubmit(xfer)
+    }
+
+    /// Wait for a capture transfer to complete and call `f` with the samples received, the
+    /// packets that failed left out.
+    pub fn next_capture<F: FnOnce(&[u8])>(&mut self, f: F) -> Result<()> {
+        if !self.format.is_input() {
+            return Err(Error::new(ErrorKind::InvalidParam, "playback stream"));
+        }
+        let mut xfer = self.reap_data()?;
+        self.samples.clear();
+        for (packet, data) in xfer.packets() {
+            if packet.status == 0 {
+                self.samples.extend_from_slice(data);
+            }
+        }
+        f(&self.samples);
+        let max_packet = self.max_packet;
+        xfer.buf.set_lengths((0..PACKETS).map(|_| max_packet));
+        self.resubmit(xfer)
+    }
+
+    /// Stop streaming, returning the underlying `AsyncDevice`.  Transfers still in flight
+    /// stay in it and are returned by its `reap_*()` methods.
+    pub fn into_inner(self) -> AsyncDevice<Box<IsoBufTransfer<UacPackets, PACKETS>>> {
+        self.device
+    }
+
+    // A data transfer, silent for playback.
+    fn data_transfer(&mut self) -> Box<UacTransfer> {
+        let mut buf = UacPackets { data: Vec::new(), lengths: Vec::new(), max_packet: self.max_packet };
+        let lengths: Vec<usize> = match self.format.is_input() {
+            true => vec![self.max_packet; PACKETS],
+            false => (0..PACKETS).map(|_| self.next_packet_length()).collect(),
+        };
+        buf.set_lengths(lengths);
+        Box::new(IsoBufTransfer::isochronous(self.format.endpoint, UrbFlags::URB_ISO_ASAP, buf))
+    }
+
+    // Bytes in the next playback packet, carrying the fractional sample over.
+    fn next_packet_length(&mut self) -> usize {
+        self.remainder += self.rate;
+        let frames = (self.remainder >> 16) as usize;
+        self.remainder &= 0xffff;
+        cmp::min(frames * self.format.frame_size(), self.max_packet)
+    }
+
+    // Reap until a data transfer completes, handling feedback transfers on the way.
+    fn reap_data(&mut self) -> Result<Box<UacTransfer>> {
+        loop {
+            let (_slot, xfer, result) = self.device.reap_wait()?;
+            match result {
+                // -EXDEV reports that only some packets completed
+                TransferResult::Completed{..} | TransferResult::Other(libc::EXDEV) => (),
+                result => return Err(result.into_io_result().unwrap_err().into()),
+            }
+            if Some(xfer.get_urb().endpoint) != self.format.feedback_endpoint {
+                return Ok(xfer);
+            }
+            if let Some((_, data)) = xfer.packets().find(|(packet, _)| packet.status == 0) {
+                self.apply_feedback(data);
+            }
+            self.device.submit_give_back_on_fail(xfer).map_err(|(err, _)| err)?;
+        }
+    }
+
+    // Feedback is sample frames per (micro)frame: 10.14 fixed point in 3 bytes at full
+    // speed, 16.16 in 4 bytes at high speed.
+    fn apply_feedback(&mut self, data: &[u8]) {
+        let per_frame = match data.len() {
+            3 => (data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16) << 2,
+            4 => u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            _ => return,
+        };
+        let rate = per_frame.saturating_mul(self.frames_per_packet);
+        // ignore values far from nominal, as sent by devices still settling
+        if rate > self.nominal_rate / 2 && rate < self.nominal_rate.saturating_mul(2) {
+            self.rate = rate;
+        }
+    }
+
+    fn resubmit(&mut self, xfer: Box<UacTransfer>) -> Result<()> {
+        self.device.submit_give_back_on_fail(xfer).map(|_| ()).map_err(|(err, _)| err)
+    }
+}
+
+impl AsRawFd for UacStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}
@@ -0,0 +1,149 @@
+use super::*;
+use descriptors::{le16, le32};
+
+/// Sample rates supported by an audio streaming format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum SampleRates {
+    /// A list of discrete rates in Hz.
+    Discrete(Vec<u32>),
+    /// Any rate between `min` and `max` Hz.
+    Continuous { min: u32, max: u32 },
+}
+
+/// USB Audio Class 1.0 class-specific interface descriptor, found among the `extra`
+/// descriptors of audio control and audio streaming interfaces.
+///
+/// Available with the `uac` feature.
+///
+/// # Examples
+/// List the formats of every audio streaming interface:
+///
+/// ```no_run
+/// use usbfs::*;
+///
+/// let config = &deviceinfo_find(0xffff, 3).unwrap().configurations().unwrap()[0];
+/// for intf in config.interfaces.iter() {
+///     for descr in intf.uac_descriptors() {
+///         if let UacDescriptor::FormatTypeI{bNrChannels, bBitResolution, sample_rates, ..} = descr {
+///             println!("interface {} alt {}: {} ch, {} bit, {:?}", intf.bInterfaceNumber,
+///                      intf.bAlternateSetting, bNrChannels, bBitResolution, sample_rates);
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum UacDescriptor {
+    /// Audio control header, listing the audio streaming interfaces of the function.
+    Header { bcdADC: u16, interfaces: Vec<u8> },
+    InputTerminal {
+        bTerminalID: u8,
+        wTerminalType: u16,
+        bAssocTerminal: u8,
+        bNrChannels: u8,
+        wChannelConfig: u16,
+    },
+    OutputTerminal {
+        bTerminalID: u8,
+        wTerminalType: u16,
+        bAssocTerminal: u8,
+        bSourceID: u8,
+    },
+    /// Feature unit, with the raw `bmaControls` bitmaps of the master channel and each
+    /// logical channel.
+    FeatureUnit { bUnitID: u8, bSourceID: u8, controls: Vec<Vec<u8>> },
+    /// General audio streaming interface descriptor.
+    AsGeneral { bTerminalLink: u8, bDelay: u8, wFormatTag: u16 },
+    /// Type I (PCM-like) format descriptor.
+    FormatTypeI {
+        bNrChannels: u8,
+        bSubframeSize: u8,
+        bBitResolution: u8,
+        sample_rates: SampleRates,
+    },
+    /// Any other descriptor, with the bytes following its subtype.
+    Other { subtype: u8, data: Vec<u8> },
+}
+
+const SUBCLASS_AUDIOCONTROL: u8 = 1;
+const SUBCLASS_AUDIOSTREAMING: u8 = 2;
+
+impl UacDescriptor {
+    /// Parse a raw descriptor of an interface with subclass `subclass` (1 for audio control,
+    /// 2 for audio streaming).  `None` if `raw` is not a `CS_INTERFACE` descriptor, or is too
+    /// short for its subtype; subtypes not parsed here are returned as `Other`.
+    pub fn parse(subclass: u8, raw: &RawDescriptor) -> Option<UacDescriptor> {
+        let d = &raw.0;
+        if d.len() < 3 || d[1] != CS_INTERFACE {
+            return None;
+        }
+        let other = || Some(UacDescriptor::Other { subtype: d[2], data: d[3..].to_vec() });
+        let descr = match (subclass, d[2]) {
+            (SUBCLASS_AUDIOCONTROL, 0x01) if d.len() >= 8 && d.len() >= 8 + d[7] as usize => {
+                UacDescriptor::Header {
+                    bcdADC: le16(d, 3),
+                    interfaces: d[8..8 + d[7] as usize].to_vec(),
+                }
+            }
+            (SUBCLASS_AUDIOCONTROL, 0x02) if d.len() >= 12 => UacDescriptor::InputTerminal {
+                bTerminalID: d[3],
+                wTerminalType: le16(d, 4),
+                bAssocTerminal: d[6],
+                bNrChannels: d[7],
+                wChannelConfig: le16(d, 8),
+            },
+            (SUBCLASS_AUDIOCONTROL, 0x03) if d.len() >= 9 => UacDescriptor::OutputTerminal {
+                bTerminalID: d[3],
+                wTerminalType: le16(d, 4),
+                bAssocTerminal: d[6],
+                bSourceID: d[7],
+            },
+            (SUBCLASS_AUDIOCONTROL, 0x06) if d.len() >= 7 && d[5] > 0 => {
+                // bmaControls is followed by a single iFeature byte
+                let size = d[5] as usize;
+                UacDescriptor::FeatureUnit {
+                    bUnitID: d[3],
+                    bSourceID: d[4],
+                    controls: d[6..d.len() - 1].chunks_exact(size).map(|c| c.to_vec()).collect(),
+                }
+            }
+            (SUBCLASS_AUDIOSTREAMING, 0x01) if d.len() >= 7 => UacDescriptor::AsGeneral {
+                bTerminalLink: d[3],
+                bDelay: d[4],
+                wFormatTag: le16(d, 5),
+            },
+            (SUBCLASS_AUDIOSTREAMING, 0x02) if d.len() >= 4 && d[3] == 1 => {
+                if d.len() < 8 {
+                    return None;
+                }
+                let count = d[7] as usize;
+                let rate = |i: usize| le32(&[d[8 + 3 * i], d[9 + 3 * i], d[10 + 3 * i], 0], 0);
+                let sample_rates = match count {
+                    0 if d.len() >= 14 => SampleRates::Continuous { min: rate(0), max: rate(1) },
+                    _ if count > 0 && d.len() >= 8 + 3 * count => SampleRates::Discrete((0..count).map(rate).collect()),
+                    _ => return None,
+                };
+                UacDescriptor::FormatTypeI {
+                    bNrChannels: d[4],
+                    bSubframeSize: d[5],
+                    bBitResolution: d[6],
+                    sample_rates,
+                }
+            }
+            // truncated or malformed instances of the subtypes above
+            (SUBCLASS_AUDIOCONTROL, 0x01 | 0x02 | 0x03 | 0x06) | (SUBCLASS_AUDIOSTREAMING, 0x01) => return None,
+            (SUBCLASS_AUDIOSTREAMING, 0x02) if d.len() < 4 => return None,
+            _ => return other(),
+        };
+        Some(descr)
+    }
+}
+
+impl InterfaceDescriptor {
+    /// Class-specific descriptors of a USB Audio Class 1.0 interface.  Available with the
+    /// `uac` feature.
+    pub fn uac_descriptors(&self) -> impl Iterator<Item=UacDescriptor> + '_ {
+        self.extra.iter().filter_map(move |raw| UacDescriptor::parse(self.bInterfaceSubClass, raw))
+    }
+}
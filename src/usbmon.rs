@@ -0,0 +1,191 @@
+use std::fs::{File, OpenOptions};
+use std::mem::size_of;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+
+use super::*;
+use capture::{HEADER_LEN, ISO_DESC_LEN};
+use devfs::ioctl_num_type;
+
+// struct mon_bin_get {
+//  struct mon_bin_hdr __user *hdr; /* Can be 48 bytes or 64. */
+//  void __user *data;
+//  size_t alloc;                   /* Length of data (can be zero) */
+// };
+#[repr(C)]
+struct MonBinGet {
+    hdr: *mut u8,
+    data: *mut u8,
+    alloc: usize,
+}
+
+// struct mon_bin_stats {
+//  u32 queued;
+//  u32 dropped;
+// };
+#[repr(C)]
+#[derive(Default)]
+struct MonBinStats {
+    queued: u32,
+    dropped: u32,
+}
+
+// #define MON_IOCG_STATS _IOR(MON_IOC_MAGIC, 3, struct mon_bin_stats)
+const MON_IOCG_STATS: ioctl_num_type = request_code_read!(0x92, 3, size_of::<MonBinStats>());
+// #define MON_IOCT_RING_SIZE _IO(MON_IOC_MAGIC, 4)
+const MON_IOCT_RING_SIZE: ioctl_num_type = request_code_none!(0x92, 4);
+// #define MON_IOCQ_RING_SIZE _IO(MON_IOC_MAGIC, 5)
+const MON_IOCQ_RING_SIZE: ioctl_num_type = request_code_none!(0x92, 5);
+// #define MON_IOCX_GETX _IOW(MON_IOC_MAGIC, 10, struct mon_bin_get)
+const MON_IOCX_GETX: ioctl_num_type = request_code_write!(0x92, 10, size_of::<MonBinGet>());
+
+/// Bus traffic seen by the kernel's usbmon, read from its binary interface `/dev/usbmonN`.
+///
+/// usbmon reports every urb submitted and completed on a bus, whoever submitted it: this
+/// program, other programs, and kernel drivers.  An application can watch what actually goes
+/// to its device alongside its own transfers, without running Wireshark.  Events are
+/// `CaptureEvent`s, as written by `Recorder`; their ids are kernel urb addresses, so they
+/// don't match `SlotId`s, but endpoint, data, and timing do.
+///
+/// The `usbmon` kernel module must be loaded, and the device nodes are normally only readable
+/// by root.  Events the application doesn't read quickly enough are dropped by the kernel, see
+/// `dropped()` and `set_buffer_size()`.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let info = deviceinfo_find(0xffff, 3).unwrap();
+/// let mut mon = Usbmon::for_device(&info).unwrap();
+/// loop {
+///     let event = mon.next_event().unwrap();
+///     println!("{:?} ep {:#04x} status {} {:02x?}", event.kind, event.endpoint, event.status, event.data);
+/// }
+/// ```
+pub struct Usbmon {
+    file: File,
+    // devnum to keep events of, on the monitored bus
+    devnum: Option<u8>,
+    buf: Vec<u8>,
+}
+
+impl Usbmon {
+    /// Monitor bus `busnum`, or with 0 every bus.
+    pub fn open(busnum: u32) -> Result<Usbmon> {
+        let file = OpenOptions::new().read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(format!("/dev/usbmon{}", busnum))?;
+        Ok(Usbmon { file, devnum: None, buf: vec![0; 64 * 1024] })
+    }
+
+    /// Monitor the bus of `info`, keeping only the device's events.
+    pub fn for_device(info: &DeviceInfo) -> Result<Usbmon> {
+        let mut mon = Usbmon::open(info.busnum()?)?;
+        mon.set_devnum(Some(info.devnum()? as u8));
+        Ok(mon)
+    }
+
+    /// Keep only events of device `devnum`, or with `None` every event on the bus.  Reopen a
+    /// device's monitor once it re-enumerates, since it comes back with another address.
+    pub fn set_devnum(&mut self, devnum: Option<u8>) {
+        self.devnum = devnum;
+    }
+
+    pub fn devnum(&self) -> Option<u8> {
+        self.devnum
+    }
+
+    /// Wait for the next event.
+    pub fn next_event(&mut self) -> Result<CaptureEvent> {
+        loop {
+            match self.next_event_nowait() {
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                    let mut fds = [PollFd::new(self.file.as_raw_fd(), PollFlags::POLLIN)];
+                    devfs::nix_result_to_result(poll(&mut fds, -1))?;
+                }
+                other => return other,
+            }
+        }
+    }
+
+    /// The next event, or `ErrorKind::WouldBlock` if there is none yet.
+    pub fn next_event_nowait(&mut self) -> Result<CaptureEvent> {
+        loop {
+            let mut header = [0u8; HEADER_LEN];
+            let mut get = MonBinGet {
+                hdr: header.as_mut_ptr(),
+                data: self.buf.as_mut_ptr(),
+                alloc: self.buf.len(),
+            };
+            devfs::nix_result_to_result(Errno::result(unsafe {
+                libc::ioctl(self.file.as_raw_fd(), MON_IOCX_GETX, &mut get)
+            }))?;
+            if self.devnum.is_some_and(|devnum| devnum != header[11]) {
+                continue;
+            }
+            return CaptureEvent::from_usbmon(&header, &self.buf);
+        }
+    }
+
+    /// Number of events the kernel dropped because they weren't read in time.
+    pub fn dropped(&self) -> Result<u32> {
+        let mut stats = MonBinStats::default();
+        devfs::nix_result_to_result(Errno::result(unsafe {
+            libc::ioctl(self.file.as_raw_fd(), MON_IOCG_STATS, &mut stats)
+        }))?;
+        Ok(stats.dropped)
+    }
+
+    /// Size in bytes of the kernel's buffer of events not yet read.
+    pub fn buffer_size(&self) -> Result<usize> {
+        let size = devfs::nix_result_to_result(Errno::result(unsafe {
+            libc::ioctl(self.file.as_raw_fd(), MON_IOCQ_RING_SIZE)
+        }))?;
+        Ok(size as usize)
+    }
+
+    /// Resize the kernel's buffer of events, to drop fewer on busy buses.  The kernel accepts
+    /// between 8 KiB and 1200 KiB.  Events already buffered are lost.
+    pub fn set_buffer_size(&mut self, size: usize) -> Result<()> {
+        devfs::nix_result_to_result(Errno::result(unsafe {
+            libc::ioctl(self.file.as_raw_fd(), MON_IOCT_RING_SIZE, size as libc::c_ulong)
+        }))?;
+        Ok(())
+    }
+
+    /// Capture up to `size` bytes of each event's data.  The default is 64 KiB.
+    pub fn set_snapshot_length(&mut self, size: usize) {
+        self.buf.resize(size, 0);
+    }
+}
+
+impl CaptureEvent {
+    /// Parse an event read from usbmon's binary interface, from its 64 byte header and the
+    /// buffer `buf` the kernel copied the event's data into.  The kernel copies isochronous
+    /// packet descriptors first and then as much of the data as still fits, so the event keeps
+    /// only the descriptors and data that made it into `buf`.
+    pub fn from_usbmon(header: &[u8; 64], buf: &[u8]) -> Result<CaptureEvent> {
+        let mut header = *header;
+        let u32_at = |i: usize| u32::from_ne_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]) as usize;
+        let (ndesc, len_cap) = (u32_at(60), u32_at(36));
+        let received = ndesc.saturating_mul(ISO_DESC_LEN).saturating_add(len_cap).min(buf.len());
+        let (ndesc, len_cap) = match received.checked_sub(ndesc.saturating_mul(ISO_DESC_LEN)) {
+            Some(len_cap) => (ndesc, len_cap),
+            None => (received / ISO_DESC_LEN, 0),
+        };
+        header[60..64].copy_from_slice(&(ndesc as u32).to_ne_bytes());
+        header[36..40].copy_from_slice(&(len_cap as u32).to_ne_bytes());
+        let mut packet = header.to_vec();
+        packet.extend_from_slice(&buf[..ndesc * ISO_DESC_LEN + len_cap]);
+        CaptureEvent::parse(&packet)
+    }
+}
+
+impl AsRawFd for Usbmon {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
@@ -0,0 +1,306 @@
+use std::thread;
+use std::time::Duration;
+
+use super::*;
+use descriptors::{le16, le32};
+
+// Bulk message IDs
+const DEV_DEP_MSG_OUT: u8 = 1;
+const REQUEST_DEV_DEP_MSG_IN: u8 = 2;
+const DEV_DEP_MSG_IN: u8 = 2;
+
+// USBTMC and USB488 class requests
+const INITIATE_ABORT_BULK_OUT: u8 = 1;
+const CHECK_ABORT_BULK_OUT_STATUS: u8 = 2;
+const INITIATE_ABORT_BULK_IN: u8 = 3;
+const CHECK_ABORT_BULK_IN_STATUS: u8 = 4;
+const INITIATE_CLEAR: u8 = 5;
+const CHECK_CLEAR_STATUS: u8 = 6;
+const GET_CAPABILITIES: u8 = 7;
+const INDICATOR_PULSE: u8 = 64;
+const READ_STATUS_BYTE: u8 = 128;
+
+// USBTMC_status values
+const STATUS_SUCCESS: u8 = 0x01;
+const STATUS_PENDING: u8 = 0x02;
+
+const HEADER_SIZE: usize = 12;
+
+/// Response to GET_CAPABILITIES.  The USB488 fields are 0 for devices that don't implement
+/// the USB488 subclass.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct UsbtmcCapabilities {
+    pub bcdUSBTMC: u16,
+    /// Bit 2: indicator pulse; bit 1: talk-only; bit 0: listen-only.
+    pub interface_capabilities: u8,
+    /// Bit 0: the device supports ending bulk IN transfers on a termination character.
+    pub device_capabilities: u8,
+    pub bcdUSB488: u16,
+    pub usb488_interface_capabilities: u8,
+    pub usb488_device_capabilities: u8,
+}
+
+/// A claimed USB Test and Measurement Class interface, as found on oscilloscopes, meters, and
+/// other instruments speaking SCPI.  Available with the `usbtmc` feature.
+///
+/// Messages are framed with the USBTMC bulk headers by `write()` and `read()`; `query()`
+/// does both.  The kernel's `usbtmc` driver must be detached from the interface first.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let mut tmc = Usbtmc::new(device.claim(0).unwrap()).unwrap();
+/// let idn = tmc.query(b"*IDN?\n").unwrap();
+/// println!("{}", String::from_utf8_lossy(&idn));
+/// ```
+pub struct Usbtmc<'a> {
+    intf: Interface<'a>,
+    ep_in: u8,
+    ep_out: u8,
+    ep_interrupt: Option<u8>,
+    tag: u8,
+    status_tag: u8,
+    timeout_ms: u32,
+    transfer_size: u32,
+    term_char: Option<u8>,
+}
+
+impl<'a> Usbtmc<'a> {
+    /// Wrap claimed interface `intf`.  Interfaces that aren't USBTMC interfaces (class 0xfe,
+    /// subclass 0x03) give `ErrorKind::InvalidParam`, and those without a bulk IN and a bulk
+    /// OUT endpoint give `ErrorKind::NotFound`.
+    pub fn new(intf: Interface<'a>) -> Result<Usbtmc<'a>> {
+        let d = intf.descriptor();
+        if d.bInterfaceClass != 0xfe || d.bInterfaceSubClass != 0x03 {
+            return Err(Error::new(ErrorKind::InvalidParam, "not a USBTMC interface"));
+        }
+        let find = |ty: UrbType, is_in: bool| intf.endpoints()
+            .find(|ep| ep.transfer_type() == ty && ep.is_in() == is_in)
+            .map(|ep| ep.bEndpointAddress);
+        let not_found = || Error::new(ErrorKind::NotFound, "no bulk endpoints on USBTMC interface");
+        let ep_in = find(UrbType::Bulk, true).ok_or_else(not_found)?;
+        let ep_out = find(UrbType::Bulk, false).ok_or_else(not_found)?;
+        let ep_interrupt = find(UrbType::Interrupt, true);
+        Ok(Usbtmc {
+            intf,
+            ep_in,
+            ep_out,
+            ep_interrupt,
+            tag: 0,
+            status_tag: 1,
+            timeout_ms: 5000,
+            transfer_size: 1024 * 1024,
+            term_char: None,
+        })
+    }
+
+    pub fn interface(&self) -> &Interface<'a> {
+        &self.intf
+    }
+
+    /// Timeout of each transfer and control request; 0 waits forever.  Defaults to 5000ms.
+    pub fn set_timeout(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    /// Largest message the device is asked for by each REQUEST_DEV_DEP_MSG_IN.  Longer
+    /// responses are read in several transfers.  Defaults to 1MiB.
+    pub fn set_transfer_size(&mut self, transfer_size: u32) {
+        self.transfer_size = transfer_size.max(1);
+    }
+
+    /// Have the device end responses at `term_char`, eg. `b'\n'`, if it supports this (see
+    /// `UsbtmcCapabilities::device_capabilities`).
+    pub fn set_term_char(&mut self, term_char: Option<u8>) {
+        self.term_char = term_char;
+    }
+
+    /// Send `message` to the device as one DEV_DEP_MSG_OUT.
+    pub fn write(&mut self, message: &[u8]) -> Result<()> {
+        let tag = self.next_tag();
+        let mut transfer = header(DEV_DEP_MSG_OUT, tag, message.len() as u32, 0x01, 0);
+        transfer.extend_from_slice(message);
+        transfer.resize(transfer.len().next_multiple_of(4), 0);
+        self.intf.device().bulk_transfer_out(self.ep_out, &transfer, self.timeout_ms)?;
+        Ok(())
+    }
+
+    /// Read a whole response message, requesting more until the device marks its end.
+    pub fn read(&mut self) -> Result<Vec<u8>> {
+        let mut message = Vec::new();
+        loop {
+            let tag = self.next_tag();
+            let attributes = if self.term_char.is_some() { 0x02 } else { 0 };
+            let request = header(REQUEST_DEV_DEP_MSG_IN, tag, self.transfer_size, attributes, self.term_char.unwrap_or(0));
+            self.intf.device().bulk_transfer_out(self.ep_out, &request, self.timeout_ms)?;
+
+            let mut buf = vec![0u8; HEADER_SIZE + (self.transfer_size as usize).next_multiple_of(4)];
+            let mut received = self.intf.device().bulk_transfer_in(self.ep_in, &mut buf, self.timeout_ms)? as usize;
+            if received < HEADER_SIZE || buf[0] != DEV_DEP_MSG_IN || buf[1] != tag || buf[2] != !tag {
+                return Err(Error::new(ErrorKind::Other, "malformed USBTMC response header"));
+            }
+            let size = (le32(&buf, 4) as usize).min(buf.len() - HEADER_SIZE);
+            let eom = buf[8] & 0x01 != 0;
+            // the device may split a response over several transfers
+            while received < HEADER_SIZE + size {
+                let n = self.intf.device().bulk_transfer_in(self.ep_in, &mut buf[received..], self.timeout_ms)? as usize;
+                if n == 0 {
+                    break;
+                }
+                received += n;
+            }
+            message.extend_from_slice(&buf[HEADER_SIZE..HEADER_SIZE + size.min(received - HEADER_SIZE)]);
+            if eom {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Send `message` and read the response.
+    pub fn query(&mut self, message: &[u8]) -> Result<Vec<u8>> {
+        self.write(message)?;
+        self.read()
+    }
+
+    /// Read the USB488 status byte (the IEEE 488.2 STB), through the interrupt endpoint if the
+    /// interface has one.
+    pub fn read_status_byte(&mut self) -> Result<u8> {
+        // status byte tags run from 2 to 127
+        self.status_tag = if self.status_tag >= 127 { 2 } else { self.status_tag + 1 };
+        let tag = self.status_tag;
+        let response = self.request(READ_STATUS_BYTE, tag as u16, 3)?;
+        check_status(&response)?;
+        match self.ep_interrupt {
+            Some(ep) => {
+                let mut notify = [0u8; 2];
+                let n = self.intf.device().bulk_transfer_in(ep, &mut notify, self.timeout_ms)?;
+                if n < 2 || notify[0] != 0x80 | tag {
+                    return Err(Error::new(ErrorKind::Other, "unexpected USB488 interrupt notification"));
+                }
+                Ok(notify[1])
+            }
+            None if response.len() >= 3 => Ok(response[2]),
+            None => Err(Error::new(ErrorKind::Other, "short READ_STATUS_BYTE response")),
+        }
+    }
+
+    pub fn capabilities(&self) -> Result<UsbtmcCapabilities> {
+        let d = self.request(GET_CAPABILITIES, 0, 0x18)?;
+        check_status(&d)?;
+        if d.len() < 16 {
+            return Err(Error::new(ErrorKind::Other, "short GET_CAPABILITIES response"));
+        }
+        Ok(UsbtmcCapabilities {
+            bcdUSBTMC: le16(&d, 2),
+            interface_capabilities: d[4],
+            device_capabilities: d[5],
+            bcdUSB488: le16(&d, 12),
+            usb488_interface_capabilities: d[14],
+            usb488_device_capabilities: d[15],
+        })
+    }
+
+    /// Flash the instrument's activity indicator, to tell it apart from its neighbours.
+    pub fn indicator_pulse(&self) -> Result<()> {
+        check_status(&self.request(INDICATOR_PULSE, 0, 1)?)
+    }
+
+    /// Abort the last `write()`, eg. after it timed out, and clear the stall left on the bulk
+    /// OUT endpoint.
+    pub fn abort_bulk_out(&mut self) -> Result<()> {
+        let response = self.endpoint_request(INITIATE_ABORT_BULK_OUT, self.tag as u16, self.ep_out, 2)?;
+        check_status(&response)?;
+        self.wait_pending(false, |tmc| tmc.endpoint_request(CHECK_ABORT_BULK_OUT_STATUS, 0, tmc.ep_out, 8))?;
+        self.intf.device().clear_halt(self.ep_out)
+    }
+
+    /// Abort the last `read()`, discarding whatever the device still has queued.
+    pub fn abort_bulk_in(&mut self) -> Result<()> {
+        let response = self.endpoint_request(INITIATE_ABORT_BULK_IN, self.tag as u16, self.ep_in, 2)?;
+        check_status(&response)?;
+        self.drain_in()?;
+        self.wait_pending(true, |tmc| tmc.endpoint_request(CHECK_ABORT_BULK_IN_STATUS, 0, tmc.ep_in, 8))
+    }
+
+    /// Clear the device's input and output buffers, as after a communications failure.
+    pub fn clear(&mut self) -> Result<()> {
+        check_status(&self.request(INITIATE_CLEAR, 0, 1)?)?;
+        self.wait_pending(true, |tmc| tmc.request(CHECK_CLEAR_STATUS, 0, 2))?;
+        self.intf.device().clear_halt(self.ep_out)
+    }
+
+    // Next bulk message tag, skipping 0.
+    fn next_tag(&mut self) -> u8 {
+        self.tag = self.tag.checked_add(1).unwrap_or(1);
+        self.tag
+    }
+
+    // Read and discard bulk IN data until a short packet ends it.
+    fn drain_in(&self) -> Result<()> {
+        let max_packet = self.intf.endpoint(self.ep_in).map_or(512, |ep| ep.max_packet_size());
+        let mut buf = vec![0u8; max_packet];
+        while self.intf.device().bulk_transfer_in(self.ep_in, &mut buf, self.timeout_ms)? as usize == max_packet {}
+        Ok(())
+    }
+
+    // Repeat a CHECK_*_STATUS request while it answers STATUS_PENDING.  With `drain`, bit 0
+    // of the second byte (bmAbortBulkIn or bmClear) means the device waits for the bulk IN
+    // endpoint to be read before it can finish.
+    fn wait_pending<F>(&self, drain: bool, check: F) -> Result<()>
+        where F: Fn(&Self) -> Result<Vec<u8>>
+    {
+        loop {
+            let response = check(self)?;
+            match response.first() {
+                Some(&STATUS_PENDING) if drain && response.get(1).is_some_and(|b| b & 0x01 != 0) => self.drain_in()?,
+                Some(&STATUS_PENDING) => thread::sleep(Duration::from_millis(10)),
+                _ => return check_status(&response),
+            }
+        }
+    }
+
+    fn request(&self, request: u8, value: u16, length: u16) -> Result<Vec<u8>> {
+        ControlRequest::new()
+            .request_type(SetupType::Class)
+            .recipient(SetupRecipient::Interface)
+            .request(request)
+            .value(value)
+            .index(self.intf.number() as u16)
+            .timeout(self.timeout_ms)
+            .data_in(length)
+            .send(self.intf.device())
+    }
+
+    fn endpoint_request(&self, request: u8, value: u16, endpoint: u8, length: u16) -> Result<Vec<u8>> {
+        ControlRequest::new()
+            .request_type(SetupType::Class)
+            .recipient(SetupRecipient::Endpoint)
+            .request(request)
+            .value(value)
+            .index(endpoint as u16)
+            .timeout(self.timeout_ms)
+            .data_in(length)
+            .send(self.intf.device())
+    }
+}
+
+// Bulk message header.
+fn header(msg_id: u8, tag: u8, transfer_size: u32, attributes: u8, term_char: u8) -> Vec<u8> {
+    let mut h = Vec::with_capacity(HEADER_SIZE);
+    h.extend_from_slice(&[msg_id, tag, !tag, 0]);
+    h.extend_from_slice(&transfer_size.to_le_bytes());
+    h.extend_from_slice(&[attributes, term_char, 0, 0]);
+    h
+}
+
+// Check the USBTMC_status byte leading a control response.
+fn check_status(response: &[u8]) -> Result<()> {
+    match response.first() {
+        Some(&STATUS_SUCCESS) => Ok(()),
+        Some(&status) => Err(Error::new(ErrorKind::Other, &format!("USBTMC request failed with status 0x{:02x}", status))),
+        None => Err(Error::new(ErrorKind::Other, "empty USBTMC control response")),
+    }
+}
@@ -1,29 +1,11 @@
+use super::*;
+use descriptors::{le16, bad_descriptor};
 
-use::std::{marker};
-
-
-/// Marker type for dual-endian structs.
-///
-/// Indicates endian used in USB (little endian.)
-#[derive(Debug, Copy, Clone)]
-pub struct BusEndian;
-
-
-/// Marker type for dual-endian structs.
-///
-/// Indicates endian used on the host (big or little endian depending on platform.)
-#[derive(Debug, Copy, Clone)]
-pub struct NativeEndian;
-
-
-// Maybe something better can be done with endianness and serde, such as:
-// Treat BusEndian as byte array and have C struct endian decoder/encoder.
-
-
-/// // usb_types
+// usb_types
 
 /// Control request direction, part of Setup::bmRequestType.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
 pub enum SetupDirection {
     HostToDevice = 0,
     DeviceToHost = 1<<7,
@@ -31,6 +13,7 @@ pub enum SetupDirection {
 
 /// Control request type, part of Setup::bmRequestType.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
 pub enum SetupType {
     Standard = 0<<5,
     Class = 1<<5,
@@ -39,6 +22,7 @@ pub enum SetupType {
 
 /// Control request recipient, part of Setup::bmRequestType.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
 pub enum SetupRecipient {
     Device = 0,
     Interface = 1,
@@ -47,19 +31,20 @@ pub enum SetupRecipient {
 }
 
 /// USB [Setup packet](http://www.beyondlogic.org/usbnutshell/usb6.shtml) used for Control requests.
-#[derive(Debug, Copy, Clone)]
-#[repr(C)]
-pub struct Setup<E> {
+///
+/// Fields hold host values; `to_le_bytes()` and `from_le_bytes()` convert to and from the
+/// little endian packet sent on the bus.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Setup {
     pub bmRequestType: u8,
     pub bRequest: u8,
     pub wValue: u16,
     pub wIndex: u16,
     pub wLength: u16,
-    endian: marker::PhantomData<E>,
 }
 
-impl Setup<NativeEndian> {
-    /// Construct a native-endian Setup packet.
+impl Setup {
+    /// Construct a Setup packet.
     ///
     /// `setupdirection`, `setuptype`, and `setuprecipient` are combined to form `bmRequestType`.
     pub fn new(setupdirection: SetupDirection,
@@ -69,37 +54,43 @@ impl Setup<NativeEndian> {
                value: u16,
                index: u16,
                length: u16)
-               -> Setup<NativeEndian> {
+               -> Setup {
         Setup {
             bmRequestType: (setupdirection as u8) | (setuptype as u8) | (setuprecipient as u8),
             bRequest: request,
             wValue: value,
             wIndex: index,
             wLength: length,
-            endian: marker::PhantomData,
         }
     }
-}
 
-impl From<Setup<NativeEndian>> for Setup<BusEndian> {
-    fn from(f: Setup<NativeEndian>) -> Setup<BusEndian> {
+    /// Decode a packet as sent on the bus.
+    pub fn from_le_bytes(b: [u8; 8]) -> Setup {
         Setup {
-            bmRequestType: f.bmRequestType.to_le(),
-            bRequest: f.bRequest.to_le(),
-            wValue: f.wValue.to_le(),
-            wIndex: f.wIndex.to_le(),
-            wLength: f.wLength.to_le(),
-            endian: marker::PhantomData,
+            bmRequestType: b[0],
+            bRequest: b[1],
+            wValue: u16::from_le_bytes([b[2], b[3]]),
+            wIndex: u16::from_le_bytes([b[4], b[5]]),
+            wLength: u16::from_le_bytes([b[6], b[7]]),
         }
     }
+
+    /// The packet as sent on the bus.
+    pub fn to_le_bytes(&self) -> [u8; 8] {
+        let (v, i, l) = (self.wValue.to_le_bytes(), self.wIndex.to_le_bytes(), self.wLength.to_le_bytes());
+        [self.bmRequestType, self.bRequest, v[0], v[1], i[0], i[1], l[0], l[1]]
+    }
 }
 
 
 /// USB [Device Descriptor](http://www.beyondlogic.org/usbnutshell/usb5.shtml)
 /// used for examining USB devices attached to the host.
-#[derive(Debug, Copy, Clone)]
-#[repr(C)]
-pub struct DeviceDescriptor<E> {
+///
+/// Fields hold host values; `from_le_bytes()` and `to_le_bytes()` convert to and from the
+/// descriptor as sent on the bus.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct DeviceDescriptor {
     pub bLength: u8,
     pub bDescriptorType: u8,
     pub bcdUSB: u16,
@@ -114,29 +105,54 @@ pub struct DeviceDescriptor<E> {
     pub iProduct: u8,
     pub iSerialNumber: u8,
     pub bNumConfigurations: u8,
-    endian: marker::PhantomData<E>,
 }
 
-impl From<DeviceDescriptor<BusEndian>> for DeviceDescriptor<NativeEndian> {
-    fn from(f: DeviceDescriptor<BusEndian>) -> DeviceDescriptor<NativeEndian> {
+impl DeviceDescriptor {
+    /// Length of a device descriptor in bytes.
+    pub const SIZE: usize = 18;
+
+    /// Decode a device descriptor as returned by `GET_DESCRIPTOR(DEVICE)`.  Bytes beyond
+    /// `SIZE` are ignored.
+    pub fn parse(buf: &[u8]) -> Result<DeviceDescriptor> {
+        match buf.get(..Self::SIZE) {
+            Some(d) if d[1] == DescriptorType::Device as u8 => {
+                let mut b = [0u8; Self::SIZE];
+                b.copy_from_slice(d);
+                Ok(DeviceDescriptor::from_le_bytes(b))
+            }
+            _ => Err(bad_descriptor()),
+        }
+    }
+
+    /// Decode a descriptor as sent on the bus, without checking its header.
+    pub fn from_le_bytes(b: [u8; Self::SIZE]) -> DeviceDescriptor {
         DeviceDescriptor {
-            bLength: u8::from_le(f.bLength),
-            bDescriptorType: u8::from_le(f.bDescriptorType),
-            bcdUSB: u16::from_le(f.bcdUSB),
-            bDeviceClass: u8::from_le(f.bDeviceClass),
-            bDeviceSubClass: u8::from_le(f.bDeviceSubClass),
-            bDeviceProtocol: u8::from_le(f.bDeviceProtocol),
-            bMaxPacketSize0: u8::from_le(f.bMaxPacketSize0),
-            idVendor: u16::from_le(f.idVendor),
-            idProduct: u16::from_le(f.idProduct),
-            bcdDevice: u16::from_le(f.bcdDevice),
-            iManufacturer: u8::from_le(f.iManufacturer),
-            iProduct: u8::from_le(f.iProduct),
-            iSerialNumber: u8::from_le(f.iSerialNumber),
-            bNumConfigurations: u8::from_le(f.bNumConfigurations),
-            endian: marker::PhantomData,
+            bLength: b[0],
+            bDescriptorType: b[1],
+            bcdUSB: le16(&b, 2),
+            bDeviceClass: b[4],
+            bDeviceSubClass: b[5],
+            bDeviceProtocol: b[6],
+            bMaxPacketSize0: b[7],
+            idVendor: le16(&b, 8),
+            idProduct: le16(&b, 10),
+            bcdDevice: le16(&b, 12),
+            iManufacturer: b[14],
+            iProduct: b[15],
+            iSerialNumber: b[16],
+            bNumConfigurations: b[17],
         }
     }
+
+    /// The descriptor as sent on the bus.
+    pub fn to_le_bytes(&self) -> [u8; Self::SIZE] {
+        let (usb, vendor, product, device) = (self.bcdUSB.to_le_bytes(), self.idVendor.to_le_bytes(),
+                                              self.idProduct.to_le_bytes(), self.bcdDevice.to_le_bytes());
+        [self.bLength, self.bDescriptorType, usb[0], usb[1],
+         self.bDeviceClass, self.bDeviceSubClass, self.bDeviceProtocol, self.bMaxPacketSize0,
+         vendor[0], vendor[1], product[0], product[1], device[0], device[1],
+         self.iManufacturer, self.iProduct, self.iSerialNumber, self.bNumConfigurations]
+    }
 }
 
 // Definitions corresponding to https://github.com/torvalds/linux/blob/master/include/uapi/linux/usbdevice_fs.h
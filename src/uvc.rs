@@ -0,0 +1,403 @@
+use std::collections::VecDeque;
+
+use super::*;
+use descriptors::{le16, le32};
+
+/// Video probe and commit control, the parameters a host and a camera negotiate before
+/// streaming starts.  See `UvcStreaming::negotiate()`.
+///
+/// Available with the `uvc` feature.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct UvcStreamControl {
+    /// Fields the device must keep fixed while negotiating; bit 0 is `dwFrameInterval`.
+    pub bmHint: u16,
+    pub bFormatIndex: u8,
+    pub bFrameIndex: u8,
+    /// Frame interval in 100ns units.
+    pub dwFrameInterval: u32,
+    pub wKeyFrameRate: u16,
+    pub wPFrameRate: u16,
+    pub wCompQuality: u16,
+    pub wCompWindowSize: u16,
+    pub wDelay: u16,
+    pub dwMaxVideoFrameSize: u32,
+    /// Largest payload the device sends per (micro)frame, which selects the alternate setting.
+    pub dwMaxPayloadTransferSize: u32,
+    /// UVC 1.1 and later; 0 for UVC 1.0 devices.
+    pub dwClockFrequency: u32,
+    pub bmFramingInfo: u8,
+    pub bPreferedVersion: u8,
+    pub bMinVersion: u8,
+    pub bMaxVersion: u8,
+}
+
+impl UvcStreamControl {
+    /// Parse a control as returned by GET_CUR and friends.  UVC 1.0 devices send 26 bytes,
+    /// later ones 34 or more; missing fields are left 0.
+    pub fn parse(d: &[u8]) -> Result<UvcStreamControl> {
+        if d.len() < 26 {
+            return Err(descriptors::bad_descriptor());
+        }
+        let mut ctrl = UvcStreamControl {
+            bmHint: le16(d, 0),
+            bFormatIndex: d[2],
+            bFrameIndex: d[3],
+            dwFrameInterval: le32(d, 4),
+            wKeyFrameRate: le16(d, 8),
+            wPFrameRate: le16(d, 10),
+            wCompQuality: le16(d, 12),
+            wCompWindowSize: le16(d, 14),
+            wDelay: le16(d, 16),
+            dwMaxVideoFrameSize: le32(d, 18),
+            dwMaxPayloadTransferSize: le32(d, 22),
+            ..Default::default()
+        };
+        if d.len() >= 34 {
+            ctrl.dwClockFrequency = le32(d, 26);
+            ctrl.bmFramingInfo = d[30];
+            ctrl.bPreferedVersion = d[31];
+            ctrl.bMinVersion = d[32];
+            ctrl.bMaxVersion = d[33];
+        }
+        Ok(ctrl)
+    }
+
+    /// Serialize into `length` bytes, the size of the control on the device.
+    pub fn to_bytes(&self, length: usize) -> Vec<u8> {
+        let mut d = Vec::with_capacity(34.max(length));
+        d.extend_from_slice(&self.bmHint.to_le_bytes());
+        d.push(self.bFormatIndex);
+        d.push(self.bFrameIndex);
+        d.extend_from_slice(&self.dwFrameInterval.to_le_bytes());
+        d.extend_from_slice(&self.wKeyFrameRate.to_le_bytes());
+        d.extend_from_slice(&self.wPFrameRate.to_le_bytes());
+        d.extend_from_slice(&self.wCompQuality.to_le_bytes());
+        d.extend_from_slice(&self.wCompWindowSize.to_le_bytes());
+        d.extend_from_slice(&self.wDelay.to_le_bytes());
+        d.extend_from_slice(&self.dwMaxVideoFrameSize.to_le_bytes());
+        d.extend_from_slice(&self.dwMaxPayloadTransferSize.to_le_bytes());
+        d.extend_from_slice(&self.dwClockFrequency.to_le_bytes());
+        d.extend_from_slice(&[self.bmFramingInfo, self.bPreferedVersion, self.bMinVersion, self.bMaxVersion]);
+        d.resize(length, 0);
+        d
+    }
+}
+
+/// Request of a video probe or commit control.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UvcGetRequest {
+    Cur = 0x81,
+    Min = 0x82,
+    Max = 0x83,
+    Def = 0x87,
+}
+
+// UVC class requests and video streaming interface control selectors
+const SET_CUR: u8 = 0x01;
+const GET_LEN: u8 = 0x85;
+const VS_PROBE_CONTROL: u16 = 0x01;
+const VS_COMMIT_CONTROL: u16 = 0x02;
+
+/// A claimed video streaming interface of a USB Video Class camera.  Available with the `uvc`
+/// feature.
+///
+/// Streaming is set up by negotiating the format, frame size, and frame interval with
+/// `negotiate()`, then selecting an alternate setting with enough bandwidth with
+/// `start_streaming()`.  Video then arrives on the isochronous endpoint returned, to be read
+/// with an `IsoStream` and put back together with a `UvcFrameAssembler`.
+///
+/// # Examples
+/// ```no_run
+/// use usbfs::*;
+///
+/// #[derive(Debug)]
+/// struct Packets(Vec<u8>, usize);
+///
+/// impl AsMut<[u8]> for Packets {
+///     fn as_mut(&mut self) -> &mut [u8] { &mut self.0 }
+/// }
+///
+/// impl IsoBuffer for Packets {
+///     fn packet_length(&self) -> usize { self.1 }
+/// }
+///
+/// let device = Device::new(&deviceinfo_find(0xffff, 3).unwrap()).unwrap();
+/// let mut uvc = UvcStreaming::new(device.claim(1).unwrap()).unwrap();
+/// let ctrl = uvc.negotiate(1, 1, 333333).unwrap();
+/// let (endpoint, packet_length) = uvc.start_streaming(&ctrl).unwrap();
+///
+/// let mut stream: IsoStream<Packets, 32> =
+///     IsoStream::new(device.try_clone().unwrap(), endpoint, UrbFlags::URB_ISO_ASAP,
+///                    (0..4).map(|_| Packets(vec![0; 32 * packet_length], packet_length))).unwrap();
+/// let mut assembler = UvcFrameAssembler::new(ctrl.dwMaxVideoFrameSize as usize);
+/// loop {
+///     stream.next_packets(|result, packet| {
+///         if result.is_ok() {
+///             if let Some(frame) = assembler.push(packet) {
+///                 println!("frame of {} bytes", frame.len());
+///             }
+///         }
+///     }).unwrap();
+/// }
+/// ```
+pub struct UvcStreaming<'a> {
+    intf: Interface<'a>,
+    timeout_ms: u32,
+}
+
+impl<'a> UvcStreaming<'a> {
+    /// Wrap claimed interface `intf`.  Interfaces that aren't video streaming interfaces give
+    /// `ErrorKind::InvalidParam`.
+    pub fn new(intf: Interface<'a>) -> Result<UvcStreaming<'a>> {
+        let d = intf.descriptor();
+        if d.bInterfaceClass != 0x0e || d.bInterfaceSubClass != 0x02 {
+            return Err(Error::new(ErrorKind::InvalidParam, "not a video streaming interface"));
+        }
+        Ok(UvcStreaming { intf, timeout_ms: 1000 })
+    }
+
+    pub fn interface(&self) -> &Interface<'a> {
+        &self.intf
+    }
+
+    /// Timeout of each control request; 0 waits forever.  Defaults to 1000ms.
+    pub fn set_timeout(&mut self, timeout_ms: u32) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    /// Propose `ctrl` with SET_CUR on the probe control.
+    pub fn set_probe(&self, ctrl: &UvcStreamControl) -> Result<()> {
+        self.set(VS_PROBE_CONTROL, ctrl)
+    }
+
+    /// Read the probe control; `UvcGetRequest::Cur` gives the device's answer to the last
+    /// proposal.
+    pub fn get_probe(&self, request: UvcGetRequest) -> Result<UvcStreamControl> {
+        self.get(VS_PROBE_CONTROL, request)
+    }
+
+    /// Settle on `ctrl` with SET_CUR on the commit control.
+    pub fn set_commit(&self, ctrl: &UvcStreamControl) -> Result<()> {
+        self.set(VS_COMMIT_CONTROL, ctrl)
+    }
+
+    pub fn get_commit(&self) -> Result<UvcStreamControl> {
+        self.get(VS_COMMIT_CONTROL, UvcGetRequest::Cur)
+    }
+
+    /// Negotiate streaming of frame `frame_index` of format `format_index` (the
+    /// `bFormatIndex` and `bFrameIndex` of the interface's `UvcDescriptor`s) at
+    /// `frame_interval` (in 100ns units), then commit to what the device answers.  The
+    /// committed control is returned; the device may have adjusted the frame interval.
+    pub fn negotiate(&self, format_index: u8, frame_index: u8, frame_interval: u32) -> Result<UvcStreamControl> {
+        let proposal = UvcStreamControl {
+            bmHint: 0x0001,
+            bFormatIndex: format_index,
+            bFrameIndex: frame_index,
+            dwFrameInterval: frame_interval,
+            ..Default::default()
+        };
+        self.set_probe(&proposal)?;
+        let ctrl = self.get_probe(UvcGetRequest::Cur)?;
+        if ctrl.bFormatIndex != format_index || ctrl.bFrameIndex != frame_index {
+            return Err(Error::new(ErrorKind::Unsupported, "device rejected video format"));
+        }
+        self.set_commit(&ctrl)?;
+        Ok(ctrl)
+    }
+
+    /// Select the alternate setting with the least bandwidth that still carries
+    /// `ctrl.dwMaxPayloadTransferSize` per interval, returning its isochronous IN endpoint and
+    /// the packet length to read it with.  Settings without enough bandwidth give
+    /// `ErrorKind::Unsupported`.
+    pub fn start_streaming(&mut self, ctrl: &UvcStreamControl) -> Result<(u8, usize)> {
+        let speed = self.intf.device().speed().unwrap_or(Speed::High);
+        let needed = ctrl.dwMaxPayloadTransferSize as usize;
+        let (altsetting, endpoint, length) = self.intf.altsettings().iter()
+            .filter_map(|alt| {
+                let ep = alt.endpoints.iter().find(|ep| ep.transfer_type() == UrbType::Iso && ep.is_in())?;
+                Some((alt.bAlternateSetting, ep.bEndpointAddress, ep.max_bytes_per_interval(speed)))
+            })
+            .filter(|&(_, _, length)| length >= needed)
+            .min_by_key(|&(_, _, length)| length)
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "no alternate setting with enough bandwidth"))?;
+        self.intf.set_altsetting(altsetting)?;
+        Ok((endpoint, length))
+    }
+
+    /// Return to alternate setting 0, which stops the video stream.
+    pub fn stop_streaming(&mut self) -> Result<()> {
+        self.intf.set_altsetting(0)
+    }
+
+    // Size of the probe and commit controls, as reported by GET_LEN.  Devices that don't
+    // implement it are assumed to follow their UVC version.
+    fn control_length(&self, selector: u16) -> usize {
+        let reported = self.request(GET_LEN, selector).data_in(2).send(self.intf.device());
+        match reported {
+            Ok(ref d) if d.len() == 2 && le16(d, 0) >= 26 => le16(d, 0) as usize,
+            _ => 34,
+        }
+    }
+
+    fn set(&self, selector: u16, ctrl: &UvcStreamControl) -> Result<()> {
+        let data = ctrl.to_bytes(self.control_length(selector));
+        self.request(SET_CUR, selector)
+            .data_out(&data)
+            .send(self.intf.device())
+            .map(|_| ())
+    }
+
+    fn get(&self, selector: u16, request: UvcGetRequest) -> Result<UvcStreamControl> {
+        let length = self.control_length(selector);
+        let data = self.request(request as u8, selector)
+            .data_in(length as u16)
+            .send(self.intf.device())?;
+        UvcStreamControl::parse(&data)
+    }
+
+    // A class request to a control of the interface.
+    fn request(&self, request: u8, selector: u16) -> ControlRequest {
+        ControlRequest::new()
+            .request_type(SetupType::Class)
+            .recipient(SetupRecipient::Interface)
+            .request(request)
+            .value(selector << 8)
+            .index(self.intf.number() as u16)
+            .timeout(self.timeout_ms)
+    }
+}
+
+/// Header at the start of every UVC payload, ie. of each isochronous packet of video.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct UvcPayloadHeader {
+    pub bHeaderLength: u8,
+    pub bmHeaderInfo: u8,
+    /// Presentation time stamp, in units of the device clock.
+    pub pts: Option<u32>,
+    /// Source clock reference: the device clock and the 1kHz SOF counter.
+    pub scr: Option<(u32, u16)>,
+}
+
+impl UvcPayloadHeader {
+    /// Split `packet` into its header and the video data following it.  `None` if the
+    /// packet is empty or its header is malformed.
+    pub fn parse(packet: &[u8]) -> Option<(UvcPayloadHeader, &[u8])> {
+        let length = *packet.first()? as usize;
+        if length < 2 || length > packet.len() {
+            return None;
+        }
+        let info = packet[1];
+        let mut offset = 2;
+        let mut field = |size: usize| {
+            let start = offset;
+            offset += size;
+            match offset <= length {
+                true => Some(start),
+                false => None,
+            }
+        };
+        let pts = match info & 0x04 != 0 {
+            true => Some(le32(packet, field(4)?)),
+            false => None,
+        };
+        let scr = match info & 0x08 != 0 {
+            true => {
+                let at = field(6)?;
+                Some((le32(packet, at), le16(packet, at + 4) & 0x07ff))
+            }
+            false => None,
+        };
+        let header = UvcPayloadHeader { bHeaderLength: length as u8, bmHeaderInfo: info, pts, scr };
+        Some((header, &packet[length..]))
+    }
+
+    /// Frame ID bit, which toggles at each new frame.
+    pub fn frame_id(&self) -> bool {
+        self.bmHeaderInfo & 0x01 != 0
+    }
+
+    pub fn end_of_frame(&self) -> bool {
+        self.bmHeaderInfo & 0x02 != 0
+    }
+
+    /// Whether the payload belongs to a still image.
+    pub fn still_image(&self) -> bool {
+        self.bmHeaderInfo & 0x20 != 0
+    }
+
+    /// Whether the device reports an error in the stream.
+    pub fn error(&self) -> bool {
+        self.bmHeaderInfo & 0x40 != 0
+    }
+}
+
+/// Reassembles video frames from the payloads of a UVC isochronous stream.
+///
+/// Feed it every packet received, eg. from `IsoStream::next_packets()`; it strips the
+/// payload headers and returns each frame once complete.  Frames end at the end of frame bit
+/// or, for devices that don't set it, when the frame ID toggles.  Frames with the error bit
+/// set in any payload, and frames exceeding the maximum size, are dropped.  See
+/// `UvcStreaming` for an example.
+///
+/// A packet that toggles the frame ID of an unfinished frame and also ends its own frame
+/// completes two frames.  The earlier one is returned first and the other is held for
+/// `next_frame()` or the following `push()`.
+pub struct UvcFrameAssembler {
+    frame: Vec<u8>,
+    max_frame_size: usize,
+    frame_id: Option<bool>,
+    bad: bool,
+    ready: VecDeque<Vec<u8>>,  // completed frames not yet returned
+}
+
+impl UvcFrameAssembler {
+    /// Create an assembler for frames of up to `max_frame_size` bytes, normally the
+    /// negotiated `dwMaxVideoFrameSize`.
+    pub fn new(max_frame_size: usize) -> UvcFrameAssembler {
+        UvcFrameAssembler {
+            frame: Vec::with_capacity(max_frame_size),
+            max_frame_size,
+            frame_id: None,
+            bad: false,
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Add the payload in `packet`, returning the oldest completed frame not yet returned, if
+    /// any.  Empty and malformed packets are ignored.
+    pub fn push(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        if let Some((header, data)) = UvcPayloadHeader::parse(packet) {
+            if self.frame_id.is_some_and(|id| id != header.frame_id()) {
+                self.finish();
+            }
+            self.frame_id = Some(header.frame_id());
+            self.bad |= header.error() || self.frame.len() + data.len() > self.max_frame_size;
+            if !self.bad {
+                self.frame.extend_from_slice(data);
+            }
+            if header.end_of_frame() {
+                // the next payload starts a new frame whatever its frame ID
+                self.frame_id = None;
+                self.finish();
+            }
+        }
+        self.ready.pop_front()
+    }
+
+    /// Take a completed frame that `push()` held back because its packet completed two.
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        self.ready.pop_front()
+    }
+
+    // Move the frame being assembled to the completed ones, unless it is bad or empty.
+    fn finish(&mut self) {
+        let bad = std::mem::replace(&mut self.bad, false);
+        let frame = std::mem::replace(&mut self.frame, Vec::with_capacity(self.max_frame_size));
+        if !bad && !frame.is_empty() {
+            self.ready.push_back(frame);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use super::*;
+use descriptors::{le16, le32, uuid};
+
+/// Frame intervals supported by a video frame descriptor, in 100ns units.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum FrameIntervals {
+    Discrete(Vec<u32>),
+    Continuous { min: u32, max: u32, step: u32 },
+}
+
+/// Uncompressed or MJPEG video frame descriptor.  See `UvcDescriptor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub struct UvcFrame {
+    pub bFrameIndex: u8,
+    pub wWidth: u16,
+    pub wHeight: u16,
+    pub dwMaxVideoFrameBufferSize: u32,
+    pub dwDefaultFrameInterval: u32,
+    pub intervals: FrameIntervals,
+}
+
+/// USB Video Class class-specific interface descriptor, found among the `extra` descriptors
+/// of video control and video streaming interfaces.
+///
+/// Available with the `uvc` feature.
+///
+/// # Examples
+/// List the MJPEG frame sizes of a camera:
+///
+/// ```no_run
+/// use usbfs::*;
+///
+/// let config = &deviceinfo_find(0xffff, 3).unwrap().configurations().unwrap()[0];
+/// for intf in config.interfaces.iter() {
+///     for descr in intf.uvc_descriptors() {
+///         if let UvcDescriptor::FrameMjpeg(frame) = descr {
+///             println!("{}x{} {:?}", frame.wWidth, frame.wHeight, frame.intervals);
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature="serde", derive(Serialize, Deserialize))]
+pub enum UvcDescriptor {
+    /// Video control header, listing the video streaming interfaces of the function.
+    VcHeader { bcdUVC: u16, dwClockFrequency: u32, interfaces: Vec<u8> },
+    InputTerminal { bTerminalID: u8, wTerminalType: u16, bAssocTerminal: u8 },
+    OutputTerminal { bTerminalID: u8, wTerminalType: u16, bAssocTerminal: u8, bSourceID: u8 },
+    ProcessingUnit { bUnitID: u8, bSourceID: u8 },
+    ExtensionUnit { bUnitID: u8, guidExtensionCode: [u8; 16] },
+    /// Video streaming input header.
+    VsInputHeader { bNumFormats: u8, bEndpointAddress: u8 },
+    FormatUncompressed {
+        bFormatIndex: u8,
+        bNumFrameDescriptors: u8,
+        guidFormat: [u8; 16],
+        bBitsPerPixel: u8,
+        bDefaultFrameIndex: u8,
+    },
+    FrameUncompressed(UvcFrame),
+    FormatMjpeg { bFormatIndex: u8, bNumFrameDescriptors: u8, bDefaultFrameIndex: u8 },
+    FrameMjpeg(UvcFrame),
+    /// Any other descriptor, with the bytes following its subtype.
+    Other { subtype: u8, data: Vec<u8> },
+}
+
+const SUBCLASS_VIDEOCONTROL: u8 = 1;
+const SUBCLASS_VIDEOSTREAMING: u8 = 2;
+
+impl UvcDescriptor {
+    /// Parse a raw descriptor of an interface with subclass `subclass` (1 for video control,
+    /// 2 for video streaming).  `None` if `raw` is not a `CS_INTERFACE` descriptor, or is too
+    /// short for its subtype; subtypes not parsed here are returned as `Other`.
+    pub fn parse(subclass: u8, raw: &RawDescriptor) -> Option<UvcDescriptor> {
+        let d = &raw.0;
+        if d.len() < 3 || d[1] != CS_INTERFACE {
+            return None;
+        }
+        let other = || Some(UvcDescriptor::Other { subtype: d[2], data: d[3..].to_vec() });
+        let descr = match (subclass, d[2]) {
+            (SUBCLASS_VIDEOCONTROL, 0x01) if d.len() >= 12 && d.len() >= 12 + d[11] as usize => {
+                UvcDescriptor::VcHeader {
+                    bcdUVC: le16(d, 3),
+                    dwClockFrequency: le32(d, 7),
+                    interfaces: d[12..12 + d[11] as usize].to_vec(),
+                }
+            }
+            (SUBCLASS_VIDEOCONTROL, 0x02) if d.len() >= 8 => UvcDescriptor::InputTerminal {
+                bTerminalID: d[3],
+                wTerminalType: le16(d, 4),
+                bAssocTerminal: d[6],
+            },
+            (SUBCLASS_VIDEOCONTROL, 0x03) if d.len() >= 9 => UvcDescriptor::OutputTerminal {
+                bTerminalID: d[3],
+                wTerminalType: le16(d, 4),
+                bAssocTerminal: d[6],
+                bSourceID: d[7],
+            },
+            (SUBCLASS_VIDEOCONTROL, 0x05) if d.len() >= 5 => UvcDescriptor::ProcessingUnit {
+                bUnitID: d[3],
+                bSourceID: d[4],
+            },
+            (SUBCLASS_VIDEOCONTROL, 0x06) if d.len() >= 20 => UvcDescriptor::ExtensionUnit {
+                bUnitID: d[3],
+                guidExtensionCode: uuid(d, 4),
+            },
+            (SUBCLASS_VIDEOSTREAMING, 0x01) if d.len() >= 7 => UvcDescriptor::VsInputHeader {
+                bNumFormats: d[3],
+                bEndpointAddress: d[6],
+            },
+            (SUBCLASS_VIDEOSTREAMING, 0x04) if d.len() >= 23 => UvcDescriptor::FormatUncompressed {
+                bFormatIndex: d[3],
+                bNumFrameDescriptors: d[4],
+                guidFormat: uuid(d, 5),
+                bBitsPerPixel: d[21],
+                bDefaultFrameIndex: d[22],
+            },
+            (SUBCLASS_VIDEOSTREAMING, 0x06) if d.len() >= 7 => UvcDescriptor::FormatMjpeg {
+                bFormatIndex: d[3],
+                bNumFrameDescriptors: d[4],
+                bDefaultFrameIndex: d[6],
+            },
+            (SUBCLASS_VIDEOSTREAMING, 0x05) => UvcDescriptor::FrameUncompressed(parse_frame(d)?),
+            (SUBCLASS_VIDEOSTREAMING, 0x07) => UvcDescriptor::FrameMjpeg(parse_frame(d)?),
+            // truncated instances of the subtypes above
+            (SUBCLASS_VIDEOCONTROL, 0x01 | 0x02 | 0x03 | 0x05 | 0x06) | (SUBCLASS_VIDEOSTREAMING, 0x01 | 0x04 | 0x06) => return None,
+            _ => return other(),
+        };
+        Some(descr)
+    }
+}
+
+// Uncompressed and MJPEG frame descriptors share their layout.
+fn parse_frame(d: &[u8]) -> Option<UvcFrame> {
+    if d.len() < 26 {
+        return None;
+    }
+    let count = d[25] as usize;
+    let intervals = match count {
+        0 if d.len() >= 38 => FrameIntervals::Continuous {
+            min: le32(d, 26),
+            max: le32(d, 30),
+            step: le32(d, 34),
+        },
+        0 => return None,
+        _ if d.len() >= 26 + 4 * count => {
+            FrameIntervals::Discrete((0..count).map(|i| le32(d, 26 + 4 * i)).collect())
+        }
+        _ => return None,
+    };
+    Some(UvcFrame {
+        bFrameIndex: d[3],
+        wWidth: le16(d, 5),
+        wHeight: le16(d, 7),
+        dwMaxVideoFrameBufferSize: le32(d, 17),
+        dwDefaultFrameInterval: le32(d, 21),
+        intervals,
+    })
+}
+
+impl InterfaceDescriptor {
+    /// Class-specific descriptors of a USB Video Class interface.  Available with the `uvc`
+    /// feature.
+    pub fn uvc_descriptors(&self) -> impl Iterator<Item=UvcDescriptor> + '_ {
+        self.extra.iter().filter_map(move |raw| UvcDescriptor::parse(self.bInterfaceSubClass, raw))
+    }
+}
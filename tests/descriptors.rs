@@ -0,0 +1,346 @@
+//! Parsing of configuration and class-specific descriptors from byte fixtures, including
+//! truncated ones, of BOS descriptors and Microsoft OS descriptor sets, of DFU file suffixes,
+//! and of UVC payloads and stream controls.  Built with the `hid`, `cdc`, `uac`, `uvc`, and `dfu` features.
+
+extern crate usbfs;
+
+use usbfs::*;
+
+fn raw(bytes: &[u8]) -> RawDescriptor {
+    RawDescriptor(bytes.to_vec())
+}
+
+// Every prefix of `bytes` shorter than `min` must parse to `None`.
+fn check_truncated<T, F>(bytes: &[u8], min: usize, parse: F)
+    where T: std::fmt::Debug,
+          F: Fn(&RawDescriptor) -> Option<T>
+{
+    for len in 0..min {
+        assert!(parse(&raw(&bytes[..len])).is_none(), "{:02x?} parsed as {:?}", &bytes[..len], parse(&raw(&bytes[..len])));
+    }
+}
+
+#[test]
+fn config_descriptor_truncated() {
+    let config = [
+        9, 2, 32, 0, 1, 1, 0, 0x80, 50,
+        9, 4, 0, 0, 2, 0xff, 0, 0, 0,
+        7, 5, 0x81, 2, 0x00, 0x02, 0,
+        7, 5, 0x02, 2, 0x00, 0x02, 0,
+    ];
+    let parsed = ConfigDescriptor::parse(&config).unwrap();
+    assert_eq!(parsed.interfaces[0].endpoints.len(), 2);
+    for len in 0..config.len() {
+        // errors or partial results are fine, panics are not
+        let _ = ConfigDescriptor::parse(&config[..len]);
+    }
+}
+
+#[test]
+fn hid_descriptor() {
+    let bytes = [9, 0x21, 0x11, 0x01, 0, 1, 0x22, 0x3f, 0x00];
+    let hid = HidDescriptor::parse(&raw(&bytes)).unwrap();
+    assert_eq!(hid, HidDescriptor { bcdHID: 0x0111, bCountryCode: 0, descriptors: vec![(0x22, 63)] });
+    assert_eq!(hid.report_descriptor_length(), Some(63));
+    check_truncated(&bytes, bytes.len(), HidDescriptor::parse);
+    assert_eq!(HidDescriptor::parse(&raw(&[9, 0x24, 0x11, 0x01, 0, 1, 0x22, 0x3f, 0x00])), None);
+}
+
+#[test]
+fn cdc_functional_descriptors() {
+    let fixtures: [(&[u8], usize, CdcFunctional); 5] = [
+        (&[5, 0x24, 0x00, 0x10, 0x01], 5, CdcFunctional::Header { bcdCDC: 0x0110 }),
+        (&[5, 0x24, 0x01, 0x03, 0x01], 5, CdcFunctional::CallManagement { bmCapabilities: 3, bDataInterface: 1 }),
+        (&[4, 0x24, 0x02, 0x02], 4, CdcFunctional::AbstractControlManagement { bmCapabilities: 2 }),
+        (&[5, 0x24, 0x06, 0x00, 0x01], 4, CdcFunctional::Union { control_interface: 0, subordinate_interfaces: vec![1] }),
+        (&[13, 0x24, 0x0f, 4, 0, 0, 0, 0, 0xea, 0x05, 0, 0, 0], 13, CdcFunctional::EthernetNetworking {
+            iMACAddress: 4, bmEthernetStatistics: 0, wMaxSegmentSize: 1514, wNumberMCFilters: 0, bNumberPowerFilters: 0,
+        }),
+    ];
+    for (bytes, min, expected) in fixtures {
+        assert_eq!(CdcFunctional::parse(&raw(bytes)), Some(expected));
+        check_truncated(bytes, min, CdcFunctional::parse);
+    }
+    assert_eq!(CdcFunctional::parse(&raw(&[4, 0x24, 0x0a, 1])), Some(CdcFunctional::Other { subtype: 0x0a, data: vec![1] }));
+}
+
+#[test]
+fn uac_descriptors() {
+    let control: [(&[u8], usize, UacDescriptor); 3] = [
+        (&[9, 0x24, 0x01, 0x00, 0x01, 0x1e, 0x00, 1, 1], 9, UacDescriptor::Header { bcdADC: 0x0100, interfaces: vec![1] }),
+        (&[12, 0x24, 0x02, 1, 0x01, 0x02, 0, 2, 3, 0, 0, 0], 12, UacDescriptor::InputTerminal {
+            bTerminalID: 1, wTerminalType: 0x0201, bAssocTerminal: 0, bNrChannels: 2, wChannelConfig: 3,
+        }),
+        (&[10, 0x24, 0x06, 2, 1, 1, 0x01, 0x02, 0x02, 0], 7, UacDescriptor::FeatureUnit {
+            bUnitID: 2, bSourceID: 1, controls: vec![vec![1], vec![2], vec![2]],
+        }),
+    ];
+    for (bytes, min, expected) in control {
+        assert_eq!(UacDescriptor::parse(1, &raw(bytes)), Some(expected));
+        check_truncated(bytes, min, |raw| UacDescriptor::parse(1, raw));
+    }
+    // a zero bControlSize can't divide the controls among channels
+    assert_eq!(UacDescriptor::parse(1, &raw(&[8, 0x24, 0x06, 2, 1, 0, 0x01, 0])), None);
+
+    let streaming: [(&[u8], usize, UacDescriptor); 3] = [
+        (&[7, 0x24, 0x01, 1, 1, 1, 0], 7, UacDescriptor::AsGeneral { bTerminalLink: 1, bDelay: 1, wFormatTag: 1 }),
+        (&[14, 0x24, 0x02, 1, 2, 2, 16, 2, 0x44, 0xac, 0x00, 0x80, 0xbb, 0x00], 14, UacDescriptor::FormatTypeI {
+            bNrChannels: 2, bSubframeSize: 2, bBitResolution: 16, sample_rates: SampleRates::Discrete(vec![44100, 48000]),
+        }),
+        (&[14, 0x24, 0x02, 1, 1, 2, 16, 0, 0x40, 0x1f, 0x00, 0x80, 0xbb, 0x00], 14, UacDescriptor::FormatTypeI {
+            bNrChannels: 1, bSubframeSize: 2, bBitResolution: 16, sample_rates: SampleRates::Continuous { min: 8000, max: 48000 },
+        }),
+    ];
+    for (bytes, min, expected) in streaming {
+        assert_eq!(UacDescriptor::parse(2, &raw(bytes)), Some(expected));
+        check_truncated(bytes, min, |raw| UacDescriptor::parse(2, raw));
+    }
+    // type II formats aren't parsed
+    assert_eq!(UacDescriptor::parse(2, &raw(&[5, 0x24, 0x02, 2, 0])), Some(UacDescriptor::Other { subtype: 2, data: vec![2, 0] }));
+}
+
+#[test]
+fn uvc_descriptors() {
+    let guid = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+    let mut extension = vec![20, 0x24, 0x06, 3];
+    extension.extend_from_slice(&guid);
+    let control: [(&[u8], usize, UvcDescriptor); 3] = [
+        (&[13, 0x24, 0x01, 0x00, 0x01, 0x33, 0x00, 0x80, 0x8d, 0x5b, 0x00, 1, 1], 13, UvcDescriptor::VcHeader {
+            bcdUVC: 0x0100, dwClockFrequency: 6_000_000, interfaces: vec![1],
+        }),
+        (&[8, 0x24, 0x02, 1, 0x01, 0x02, 0, 0], 8, UvcDescriptor::InputTerminal { bTerminalID: 1, wTerminalType: 0x0201, bAssocTerminal: 0 }),
+        (&extension, 20, UvcDescriptor::ExtensionUnit { bUnitID: 3, guidExtensionCode: guid }),
+    ];
+    for (bytes, min, expected) in control {
+        assert_eq!(UvcDescriptor::parse(1, &raw(bytes)), Some(expected));
+        check_truncated(bytes, min, |raw| UvcDescriptor::parse(1, raw));
+    }
+
+    let frame = [
+        30, 0x24, 0x07, 1, 0, 0x80, 0x02, 0xe0, 0x01,
+        0, 0, 0, 0, 0, 0, 0, 0,
+        0x00, 0x60, 0x09, 0x00, 0x15, 0x16, 0x05, 0x00,
+        1, 0x15, 0x16, 0x05, 0x00,
+    ];
+    let expected = UvcFrame {
+        bFrameIndex: 1, wWidth: 640, wHeight: 480, dwMaxVideoFrameBufferSize: 614400,
+        dwDefaultFrameInterval: 333333, intervals: FrameIntervals::Discrete(vec![333333]),
+    };
+    assert_eq!(UvcDescriptor::parse(2, &raw(&frame)), Some(UvcDescriptor::FrameMjpeg(expected)));
+    check_truncated(&frame, frame.len(), |raw| UvcDescriptor::parse(2, raw));
+}
+
+#[test]
+fn dfu_functional_descriptor() {
+    let bytes = [9, 0x21, 0x0b, 0xff, 0x00, 0x00, 0x04, 0x1a, 0x01];
+    let dfu = DfuFunctional::parse(&raw(&bytes)).unwrap();
+    assert_eq!(dfu, DfuFunctional { bmAttributes: 0x0b, wDetachTimeOut: 255, wTransferSize: 1024, bcdDFUVersion: 0x011a });
+    assert!(dfu.can_download() && dfu.can_upload() && !dfu.manifestation_tolerant() && dfu.will_detach());
+    check_truncated(&bytes, 7, DfuFunctional::parse);
+    // DFU 1.0 descriptors end before bcdDFUVersion
+    assert_eq!(DfuFunctional::parse(&raw(&bytes[..7])).unwrap().bcdDFUVersion, 0x0100);
+    assert_eq!(DfuFunctional::parse(&raw(&[9, 0x24, 0x0b, 0xff, 0x00, 0x00, 0x04, 0x1a, 0x01])), None);
+}
+
+#[test]
+fn dfu_suffix() {
+    let firmware = [0xde, 0xad, 0xbe, 0xef];
+    // suffix for device 0x1234:0x5678 release 1.00, DFU 1.1a, with its CRC
+    let suffix = [0x00, 0x01, 0x78, 0x56, 0x34, 0x12, 0x1a, 0x01, b'U', b'F', b'D', 16, 0xe8, 0x61, 0x2c, 0xbd];
+    let file = [&firmware[..], &suffix[..]].concat();
+    let (parsed, data) = DfuSuffix::parse(&file).unwrap();
+    assert_eq!(parsed, DfuSuffix {
+        bcdDevice: 0x0100, idProduct: 0x5678, idVendor: 0x1234, bcdDFU: 0x011a, bLength: 16, dwCRC: 0xbd2c61e8,
+    });
+    assert_eq!(data, firmware);
+    let descriptor = DeviceDescriptor::parse(&[18, 1, 0x00, 0x02, 0, 0, 0, 64, 0x34, 0x12, 0x78, 0x56, 0x00, 0x01, 1, 2, 3, 1]).unwrap();
+    assert!(parsed.matches(&descriptor));
+
+    // a longer suffix takes its extra bytes off the firmware
+    let long = [0xde, 0xad, 0xbe, 0xef, 1, 2, 3, 4, 0x00, 0x01, 0x78, 0x56, 0x34, 0x12, 0x1a, 0x01, b'U', b'F', b'D', 20, 0xcc, 0x20, 0x65, 0xe0];
+    assert_eq!(DfuSuffix::parse(&long).unwrap().1, firmware);
+
+    let invalid = |file: &[u8]| DfuSuffix::parse(file).err().unwrap().kind();
+    assert_eq!(invalid(&suffix[1..]), ErrorKind::InvalidParam);
+    let mut unsigned = file.clone();
+    unsigned[12] = b'X';
+    assert_eq!(invalid(&unsigned), ErrorKind::InvalidParam);
+    let mut corrupt = file.clone();
+    corrupt[0] ^= 1;
+    assert_eq!(invalid(&corrupt), ErrorKind::InvalidParam);
+    // bLength under 16 or beyond the file, with the CRC fixed up so only the length is wrong
+    for (length, crc) in [(15, [0x1d, 0x6c, 0x24, 0x30]), (21, [0x67, 0x95, 0x46, 0xcd])] {
+        let mut bad = file.clone();
+        bad[15] = length;
+        bad[16..].copy_from_slice(&crc);
+        assert_eq!(invalid(&bad), ErrorKind::InvalidParam);
+    }
+}
+
+#[test]
+fn uvc_stream_control() {
+    let ctrl = UvcStreamControl {
+        bmHint: 1, bFormatIndex: 2, bFrameIndex: 3, dwFrameInterval: 333333, wKeyFrameRate: 4, wPFrameRate: 5,
+        wCompQuality: 6, wCompWindowSize: 7, wDelay: 8, dwMaxVideoFrameSize: 614400, dwMaxPayloadTransferSize: 3072,
+        dwClockFrequency: 48_000_000, bmFramingInfo: 3, bPreferedVersion: 1, bMinVersion: 1, bMaxVersion: 2,
+    };
+    let bytes = ctrl.to_bytes(34);
+    assert_eq!(bytes[..8], [1, 0, 2, 3, 0x15, 0x16, 0x05, 0x00]);
+    assert_eq!(bytes[18..], [0x00, 0x60, 0x09, 0x00, 0x00, 0x0c, 0, 0, 0x00, 0x6c, 0xdc, 0x02, 3, 1, 1, 2]);
+    assert_eq!(UvcStreamControl::parse(&bytes).unwrap(), ctrl);
+
+    // UVC 1.0 controls stop after dwMaxPayloadTransferSize
+    let short = ctrl.to_bytes(26);
+    assert_eq!(short, bytes[..26]);
+    assert_eq!(UvcStreamControl::parse(&short).unwrap(), UvcStreamControl {
+        dwClockFrequency: 0, bmFramingInfo: 0, bPreferedVersion: 0, bMinVersion: 0, bMaxVersion: 0, ..ctrl
+    });
+    assert!(UvcStreamControl::parse(&bytes[..25]).is_err());
+}
+
+#[test]
+fn uvc_payload_header() {
+    let packet = [12, 0x8f, 0x78, 0x56, 0x34, 0x12, 0x44, 0x33, 0x22, 0x11, 0x34, 0xfa, 0xaa, 0xbb];
+    let (header, data) = UvcPayloadHeader::parse(&packet).unwrap();
+    // the SOF counter is 11 bits
+    assert_eq!(header, UvcPayloadHeader { bHeaderLength: 12, bmHeaderInfo: 0x8f, pts: Some(0x12345678), scr: Some((0x11223344, 0x0234)) });
+    assert_eq!(data, [0xaa, 0xbb]);
+    assert!(header.frame_id() && header.end_of_frame() && !header.still_image() && !header.error());
+
+    // SCR alone follows the fixed fields directly
+    let (header, data) = UvcPayloadHeader::parse(&[8, 0x88, 0x44, 0x33, 0x22, 0x11, 0x01, 0x00, 0xaa]).unwrap();
+    assert_eq!((header.pts, header.scr, data), (None, Some((0x11223344, 1)), &[0xaa][..]));
+    assert_eq!(UvcPayloadHeader::parse(&[2, 0x80]).unwrap().1, []);
+
+    for bad in [&[][..], &[1, 0x80], &[3, 0x80], &[5, 0x84, 0, 0, 0, 0xaa], &[6, 0x8c, 0, 0, 0, 0]] {
+        assert_eq!(UvcPayloadHeader::parse(bad), None, "{:02x?}", bad);
+    }
+}
+
+#[test]
+fn uvc_frame_assembler() {
+    let payload = |info: u8, data: &[u8]| [&[2, 0x80 | info][..], data].concat();
+    let mut assembler = UvcFrameAssembler::new(6);
+
+    // frames end at the end of frame bit
+    assert_eq!(assembler.push(&payload(0, &[1, 2])), None);
+    assert_eq!(assembler.push(&payload(0x02, &[3])), Some(vec![1, 2, 3]));
+    // or when the frame ID toggles
+    assert_eq!(assembler.push(&payload(0x01, &[4])), None);
+    assert_eq!(assembler.push(&payload(0x01, &[5])), None);
+    assert_eq!(assembler.push(&payload(0, &[6])), Some(vec![4, 5]));
+    // a toggle that also ends the new frame completes both, in order
+    assert_eq!(assembler.push(&payload(0x03, &[7])), Some(vec![6]));
+    assert_eq!(assembler.next_frame(), Some(vec![7]));
+    assert_eq!(assembler.next_frame(), None);
+    assert_eq!(assembler.push(&payload(0x01, &[8])), None);
+    assert_eq!(assembler.push(&payload(0x02, &[9])), Some(vec![8]));
+    assert_eq!(assembler.push(&[]), Some(vec![9]));
+
+    // the error bit drops the frame it appears in
+    assert_eq!(assembler.push(&payload(0x40, &[1])), None);
+    assert_eq!(assembler.push(&payload(0x02, &[2])), None);
+    // and so does running over the maximum size
+    assert_eq!(assembler.push(&payload(0, &[1, 2, 3, 4])), None);
+    assert_eq!(assembler.push(&payload(0x02, &[5, 6, 7])), None);
+    // header-only payloads and malformed packets add nothing
+    assert_eq!(assembler.push(&payload(0, &[1, 2, 3, 4, 5, 6])), None);
+    assert_eq!(assembler.push(&[9, 0x80, 1]), None);
+    assert_eq!(assembler.push(&payload(0x02, &[])), Some(vec![1, 2, 3, 4, 5, 6]));
+}
+
+fn utf16(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+#[test]
+fn ms_os_20_descriptor_set() {
+    let compat_id = [20, 0, 0x03, 0, b'W', b'I', b'N', b'U', b'S', b'B', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let guids = [&[132, 0, 0x04, 0, 7, 0, 42, 0][..], &utf16("DeviceInterfaceGUIDs\0"),
+                 &[80, 0], &utf16("{88BAE032-5A81-49F0-BC3D-A4FF138216D6}\0\0")].concat();
+    let idle = [&[50, 0, 0x04, 0, 4, 0, 36, 0][..], &utf16("DeviceIdleEnabled\0"), &[4, 0], &[1, 0, 0, 0]].concat();
+    // WinUSB on the function at interface 1, then a property of the whole configuration
+    let set = [
+        &[10, 0, 0x00, 0, 0x00, 0x00, 0x03, 0x06, 228, 0][..],
+        &[8, 0, 0x01, 0, 0, 0, 218, 0],
+        &[8, 0, 0x02, 0, 1, 0, 160, 0],
+        &compat_id,
+        &guids,
+        &idle,
+    ].concat();
+    assert_eq!(set.len(), 228);
+    let parsed = MsOs20DescriptorSet::parse(&set).unwrap();
+    assert_eq!(parsed.dwWindowsVersion, 0x06030000);
+    assert_eq!(parsed.compat_ids, [MsCompatId { interface: Some(1), compatible_id: "WINUSB".into(), sub_compatible_id: "".into() }]);
+    assert_eq!(parsed.properties.len(), 2);
+    assert_eq!((parsed.properties[0].interface, parsed.properties[0].data_type), (Some(1), 7));
+    assert_eq!(parsed.properties[0].name, "DeviceInterfaceGUIDs");
+    assert_eq!(parsed.properties[0].string_value().unwrap(), "{88BAE032-5A81-49F0-BC3D-A4FF138216D6}");
+    // the function subset ended with the GUIDs
+    assert_eq!(parsed.properties[1], MsRegistryProperty {
+        interface: None, data_type: 4, name: "DeviceIdleEnabled".into(), data: vec![1, 0, 0, 0],
+    });
+    assert_eq!(parsed.properties[1].string_value(), None);
+
+    // a property whose name or data runs past its wLength
+    for (offset, value) in [(6, 124), (8 + 42, 82)] {
+        let mut bad = set.clone();
+        bad[46 + offset] = value;
+        assert!(MsOs20DescriptorSet::parse(&bad).is_err(), "{} = {}", offset, value);
+    }
+    // a descriptor running past the set, and a bad set header
+    assert!(MsOs20DescriptorSet::parse(&set[..set.len() - 1]).is_err());
+    assert!(MsOs20DescriptorSet::parse(&set[..9]).is_err());
+    let mut bad = set.clone();
+    bad[2] = 0x01;
+    assert!(MsOs20DescriptorSet::parse(&bad).is_err());
+}
+
+#[test]
+fn bos_descriptor() {
+    let container = [0x6b, 0x1d, 0x53, 0x11, 0xbe, 0x2a, 0x4c, 0x4e, 0x8a, 0x1f, 0x2f, 0x39, 0x7e, 0x5c, 0x90, 0x12];
+    let ms_os_20 = [0xdf, 0x60, 0xdd, 0xd8, 0x89, 0x45, 0xc7, 0x4c, 0x9c, 0xd2, 0x65, 0x9d, 0x9e, 0x64, 0x8a, 0x9f];
+    let bos = [
+        &[5, 0x0f, 70, 0, 4][..],
+        // USB 2.0 extension with LPM and BESL
+        &[7, 0x10, 0x02, 0x06, 0, 0, 0],
+        // SuperSpeed at full, high, and SuperSpeed, U1 exit in 10us, U2 in 2047us
+        &[10, 0x10, 0x03, 0x00, 0x0e, 0x00, 0x01, 0x0a, 0xff, 0x07],
+        &[20, 0x10, 0x04, 0], &container,
+        // MS OS 2.0 set of 178 bytes for Windows 8.1, vendor code 1
+        &[28, 0x10, 0x05, 0], &ms_os_20, &[0x00, 0x00, 0x03, 0x06, 178, 0, 1, 0],
+    ].concat();
+    let parsed = BosDescriptor::parse(&bos).unwrap();
+    assert_eq!(parsed.capabilities, [
+        DeviceCapability::Usb2Extension { bmAttributes: 0x06 },
+        DeviceCapability::SuperSpeed {
+            bmAttributes: 0, wSpeedsSupported: 0x000e, bFunctionalitySupport: 1, bU1DevExitLat: 10, wU2DevExitLat: 0x07ff,
+        },
+        DeviceCapability::ContainerId { uuid: container },
+        DeviceCapability::Platform { uuid: ms_os_20, data: vec![0x00, 0x00, 0x03, 0x06, 178, 0, 1, 0] },
+    ]);
+    assert!(parsed.lpm_supported());
+    assert_eq!(parsed.superspeed(), Some(&parsed.capabilities[1]));
+    assert_eq!(parsed.container_id(), Some(container));
+    assert_eq!(parsed.ms_os_20(), Some(MsOs20Info {
+        dwWindowsVersion: 0x06030000, wMSOSDescriptorSetTotalLength: 178, bMS_VendorCode: 1, bAltEnumCode: 0,
+    }));
+
+    // bytes beyond wTotalLength are ignored
+    let mut longer = bos.clone();
+    longer.extend_from_slice(&[7, 0x10, 0x02, 0, 0, 0, 0]);
+    assert_eq!(BosDescriptor::parse(&longer).unwrap(), parsed);
+    // a wTotalLength beyond the buffer or inside the header is refused
+    assert!(BosDescriptor::parse(&bos[..69]).is_err());
+    assert!(BosDescriptor::parse(&[5, 0x0f, 4, 0, 0]).is_err());
+    assert!(BosDescriptor::parse(&[5, 0x02, 5, 0, 0]).is_err());
+
+    // capabilities too short for their type, and unknown types, keep their bytes
+    let other = BosDescriptor::parse(&[5, 0x0f, 15, 0, 2, 5, 0x10, 0x02, 0x02, 0, 5, 0x10, 0x0d, 1, 2]).unwrap();
+    assert_eq!(other.capabilities, [
+        DeviceCapability::Other { bDevCapabilityType: 0x02, data: vec![0x02, 0] },
+        DeviceCapability::Other { bDevCapabilityType: 0x0d, data: vec![1, 2] },
+    ]);
+    assert!(!other.lpm_supported());
+    assert_eq!((other.superspeed(), other.container_id(), other.ms_os_20()), (None, None, None));
+}
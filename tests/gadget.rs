@@ -0,0 +1,214 @@
+//! End-to-end tests against the Linux "gadget zero" test device.  Built with the
+//! `gadget-tests` feature; see `support` for setting up the gadget.
+//!
+//! ```text
+//! cargo test --features gadget-tests
+//! ```
+
+extern crate usbfs;
+
+#[macro_use]
+mod support;
+
+use std::time::Duration;
+
+use support::*;
+use usbfs::*;
+
+fn pattern(seed: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(7).wrapping_add(seed)).collect()
+}
+
+#[test]
+fn control_write_read() {
+    let gadget = gadget_or_skip!(SOURCESINK);
+    let data = pattern(1, 64);
+
+    ControlRequest::new()
+        .request_type(SetupType::Vendor)
+        .request(CONTROL_WRITE)
+        .data_out(&data)
+        .send(&gadget)
+        .unwrap();
+    let back = ControlRequest::new()
+        .request_type(SetupType::Vendor)
+        .request(CONTROL_READ)
+        .data_in(64)
+        .send(&gadget)
+        .unwrap();
+    assert_eq!(back, data);
+}
+
+#[test]
+fn control_async() {
+    let gadget = gadget_or_skip!(SOURCESINK);
+    let data = pattern(2, 32);
+    ControlRequest::new()
+        .request_type(SetupType::Vendor)
+        .request(CONTROL_WRITE)
+        .data_out(&data)
+        .send(&gadget)
+        .unwrap();
+
+    let mut device: AsyncDevice<Box<StdBufTransfer<Vec<u8>>>> = gadget.try_clone().into();
+    ControlRequest::new()
+        .request_type(SetupType::Vendor)
+        .request(CONTROL_READ)
+        .data_in(32)
+        .submit(&mut device)
+        .unwrap();
+    let (_slot, xfer, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(result, TransferResult::Completed{len: 32});
+    assert_eq!(&xfer.buf[8..], &data[..]);
+}
+
+#[test]
+fn bulk_loopback_sync() {
+    let gadget = gadget_or_skip!(LOOPBACK);
+    let intf = gadget.claim(0).unwrap();
+    let (_, ep_out) = some_or_skip!(find_endpoint(&intf, UrbType::Bulk, false), "bulk OUT endpoint");
+    let (_, ep_in) = some_or_skip!(find_endpoint(&intf, UrbType::Bulk, true), "bulk IN endpoint");
+
+    let data = pattern(3, 512);
+    let sent = gadget.bulk_transfer_out(ep_out.bEndpointAddress, &data, TIMEOUT_MS).unwrap();
+    assert_eq!(sent as usize, data.len());
+
+    let mut buf = vec![0u8; 4096];
+    let len = gadget.bulk_transfer_in(ep_in.bEndpointAddress, &mut buf, TIMEOUT_MS).unwrap();
+    assert_eq!(&buf[..len as usize], &data[..]);
+}
+
+#[test]
+fn bulk_loopback_async() {
+    let gadget = gadget_or_skip!(LOOPBACK);
+    let intf = gadget.claim(0).unwrap();
+    let (_, ep_out) = some_or_skip!(find_endpoint(&intf, UrbType::Bulk, false), "bulk OUT endpoint");
+    let (_, ep_in) = some_or_skip!(find_endpoint(&intf, UrbType::Bulk, true), "bulk IN endpoint");
+
+    let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = gadget.try_clone().into();
+    let messages: Vec<Vec<u8>> = (0..4).map(|i| pattern(i, 256)).collect();
+    for _ in &messages {
+        device.submit(Box::new(BulkTransferMut::new(ep_in.bEndpointAddress, UrbFlags::empty(), vec![0; 4096]))).unwrap();
+    }
+    for message in &messages {
+        device.submit(Box::new(BulkTransferMut::new(ep_out.bEndpointAddress, UrbFlags::empty(), message.clone()))).unwrap();
+    }
+
+    // transfers on one endpoint complete in order
+    let mut received = Vec::new();
+    for _ in 0..2 * messages.len() {
+        let (_slot, xfer, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+        let len = match result {
+            TransferResult::Completed{len} => len,
+            result => panic!("transfer failed: {:?}", result),
+        };
+        if xfer.urb.endpoint & 0x80 != 0 {
+            received.push(xfer.buf[..len].to_vec());
+        }
+    }
+    assert_eq!(received, messages);
+}
+
+// Needs a kernel and host controller that support usbfs mmap; run with `--ignored`.
+#[test]
+#[ignore]
+fn bulk_loopback_mmap() {
+    let gadget = gadget_or_skip!(LOOPBACK);
+    let intf = gadget.claim(0).unwrap();
+    let (_, ep_out) = some_or_skip!(find_endpoint(&intf, UrbType::Bulk, false), "bulk OUT endpoint");
+    let (_, ep_in) = some_or_skip!(find_endpoint(&intf, UrbType::Bulk, true), "bulk IN endpoint");
+
+    let data = pattern(5, 512);
+    let mut out_buf = MmapBuffer::new(&gadget, data.len()).unwrap();
+    out_buf.as_mut().copy_from_slice(&data);
+    let in_buf = MmapBuffer::new(&gadget, 4096).unwrap();
+    assert!(out_buf.is_mapped() && in_buf.is_mapped());
+
+    let mut device: AsyncDevice<Box<BulkTransferMut<MmapBuffer>>> = gadget.try_clone().into();
+    device.submit(Box::new(BulkTransferMut::new(ep_out.bEndpointAddress, UrbFlags::empty(), out_buf))).unwrap();
+    device.submit(Box::new(BulkTransferMut::new(ep_in.bEndpointAddress, UrbFlags::empty(), in_buf))).unwrap();
+    for _ in 0..2 {
+        let (_slot, xfer, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+        let len = match result {
+            TransferResult::Completed{len} => len,
+            result => panic!("transfer failed: {:?}", result),
+        };
+        if xfer.urb.endpoint & 0x80 != 0 {
+            assert_eq!(&xfer.buf.as_ref()[..len], &data[..]);
+        }
+    }
+}
+
+#[test]
+fn bulk_source_sink() {
+    let gadget = gadget_or_skip!(SOURCESINK);
+    let intf = gadget.claim(0).unwrap();
+    let (_, ep_out) = some_or_skip!(find_endpoint(&intf, UrbType::Bulk, false), "bulk OUT endpoint");
+    let (_, ep_in) = some_or_skip!(find_endpoint(&intf, UrbType::Bulk, true), "bulk IN endpoint");
+
+    // the default pattern is all zeros, which is also what the sink expects
+    let mut buf = vec![0xffu8; 4096];
+    for _ in 0..3 {
+        let len = gadget.bulk_transfer_in(ep_in.bEndpointAddress, &mut buf, TIMEOUT_MS).unwrap();
+        assert!(len > 0);
+    }
+    let zeros = vec![0u8; 4096];
+    let sent = gadget.bulk_transfer_out(ep_out.bEndpointAddress, &zeros, TIMEOUT_MS).unwrap();
+    assert_eq!(sent as usize, zeros.len());
+}
+
+#[test]
+fn interrupt_source() {
+    let gadget = gadget_or_skip!(SOURCESINK);
+    let mut intf = gadget.claim(0).unwrap();
+    let (alt, ep_in) = some_or_skip!(find_endpoint(&intf, UrbType::Interrupt, true), "interrupt IN endpoint");
+    intf.set_altsetting(alt).unwrap();
+
+    let mut device: AsyncDevice<Box<StdBufTransfer<Vec<u8>>>> = gadget.try_clone().into();
+    let xfer = intf.transfer(ep_in.bEndpointAddress, UrbFlags::empty(), vec![0; ep_in.max_packet_size()]).unwrap();
+    device.submit(Box::new(xfer)).unwrap();
+    let (_slot, _xfer, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert!(result.is_ok(), "interrupt transfer failed: {:?}", result);
+}
+
+#[derive(Debug)]
+struct Packets(Vec<u8>, usize);
+
+impl AsRef<[u8]> for Packets {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsMut<[u8]> for Packets {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl IsoBuffer for Packets {
+    fn packet_length(&self) -> usize {
+        self.1
+    }
+}
+
+#[test]
+fn iso_source() {
+    let gadget = gadget_or_skip!(SOURCESINK);
+    let mut intf = gadget.claim(0).unwrap();
+    let (alt, ep_in) = some_or_skip!(find_endpoint(&intf, UrbType::Iso, true), "isochronous IN endpoint");
+    intf.set_altsetting(alt).unwrap();
+
+    let packet_length = ep_in.max_packet_size();
+    let mut device: AsyncDevice<Box<IsoBufTransfer<Packets, 8>>> = gadget.try_clone().into();
+    for _ in 0..2 {
+        let xfer = intf.iso_transfer(ep_in.bEndpointAddress, UrbFlags::URB_ISO_ASAP,
+                                     Packets(vec![0; 8 * packet_length], packet_length)).unwrap();
+        device.submit(Box::new(xfer)).unwrap();
+    }
+    for _ in 0..2 {
+        let (_slot, xfer, _result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(xfer.packets().count(), 8);
+        assert!(xfer.packets().any(|(packet, data)| packet.status == 0 && !data.is_empty()));
+    }
+}
@@ -0,0 +1,150 @@
+//! Byte order of the packets and descriptors exchanged with devices, sizes of the public
+//! structs handed to the kernel, and the formats of usbmon's binary events and of uevents.  The
+//! full layouts of the ioctl structs are checked at compile time in `src/layout.rs`.
+
+extern crate libc;
+extern crate usbfs;
+
+use std::mem::size_of;
+
+use usbfs::*;
+
+#[test]
+fn setup_byte_order() {
+    let setup = Setup::new(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Interface,
+                           0x06, 0x0302, 0x0504, 0x0708);
+    let bytes = [0xc1, 0x06, 0x02, 0x03, 0x04, 0x05, 0x08, 0x07];
+    assert_eq!(setup.to_le_bytes(), bytes);
+    assert_eq!(Setup::from_le_bytes(bytes), setup);
+}
+
+#[test]
+fn device_descriptor_byte_order() {
+    let bytes = [18, 1, 0x10, 0x02, 0xef, 2, 1, 64, 0x34, 0x12, 0x78, 0x56, 0x01, 0x03, 1, 2, 3, 1];
+    let desc = DeviceDescriptor::parse(&bytes).unwrap();
+    assert_eq!((desc.bcdUSB, desc.idVendor, desc.idProduct, desc.bcdDevice), (0x0210, 0x1234, 0x5678, 0x0301));
+    assert_eq!(desc.to_le_bytes(), bytes);
+}
+
+#[test]
+fn kernel_struct_sizes() {
+    assert_eq!(size_of::<IsoPacketDesc>(), 12);
+    assert_eq!(size_of::<ConnectInfo>(), 8);
+    assert_eq!(size_of::<Urb>(), if cfg!(target_pointer_width="64") { 56 } else { 44 });
+
+    // number_of_packets and stream_id share storage
+    let mut urb = Urb::new(UrbType::Bulk, 0x81, UrbFlags::empty());
+    urb.set_stream_id(3);
+    assert_eq!(urb.number_of_packets(), 3);
+}
+
+// The mon_bin header of an isochronous IN completion on bus 1, device 3: two packets of 3
+// bytes, 6 bytes of data.
+fn usbmon_iso_header() -> [u8; 64] {
+    let mut header = [0u8; 64];
+    header[0..8].copy_from_slice(&0xffff_8880_1234_5600u64.to_ne_bytes());
+    header[8..16].copy_from_slice(&[b'C', 0, 0x81, 3, 1, 0, b'-', 0]);
+    header[16..24].copy_from_slice(&1_700_000_000i64.to_ne_bytes());
+    header[24..28].copy_from_slice(&250_000i32.to_ne_bytes());
+    header[32..36].copy_from_slice(&6u32.to_ne_bytes());    // length
+    header[36..40].copy_from_slice(&6u32.to_ne_bytes());    // len_cap
+    header[40..44].copy_from_slice(&1i32.to_ne_bytes());    // error_count
+    header[44..48].copy_from_slice(&2i32.to_ne_bytes());    // numdesc
+    header[48..52].copy_from_slice(&1i32.to_ne_bytes());    // interval
+    header[52..56].copy_from_slice(&812i32.to_ne_bytes());  // start_frame
+    header[56..60].copy_from_slice(&2u32.to_ne_bytes());    // URB_ISO_ASAP
+    header[60..64].copy_from_slice(&2u32.to_ne_bytes());    // ndesc
+    header
+}
+
+#[test]
+fn usbmon_iso_event() {
+    let header = usbmon_iso_header();
+    let mut buf = vec![0u8; 64];
+    for (i, &(status, offset, length)) in [(0, 0u32, 3u32), (-libc::EPROTO, 3, 3)].iter().enumerate() {
+        let desc = &mut buf[i * 16..];
+        desc[0..4].copy_from_slice(&status.to_ne_bytes());
+        desc[4..8].copy_from_slice(&offset.to_ne_bytes());
+        desc[8..12].copy_from_slice(&length.to_ne_bytes());
+    }
+    buf[32..38].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+
+    let event = CaptureEvent::from_usbmon(&header, &buf).unwrap();
+    assert_eq!((event.id, event.kind, event.transfer_type), (0xffff_8880_1234_5600, CaptureEventKind::Complete, UrbType::Iso));
+    assert_eq!((event.endpoint, event.busnum, event.devnum, event.setup), (0x81, 1, 3, None));
+    assert_eq!(event.timestamp, std::time::Duration::new(1_700_000_000, 250_000_000));
+    assert_eq!((event.status, event.length, event.error_count, event.start_frame, event.flags), (0, 6, 1, 812, 2));
+    assert_eq!(event.iso_packets, [
+        CaptureIsoPacket { status: 0, offset: 0, length: 3 },
+        CaptureIsoPacket { status: -libc::EPROTO, offset: 3, length: 3 },
+    ]);
+    assert_eq!(event.data, [1, 2, 3, 4, 5, 6]);
+
+    // a short buffer cuts the data after the descriptors
+    let event = CaptureEvent::from_usbmon(&header, &buf[..36]).unwrap();
+    assert_eq!((event.iso_packets.len(), event.length, &event.data[..]), (2, 6, &[1, 2, 3, 4][..]));
+    // and then the descriptors themselves
+    let event = CaptureEvent::from_usbmon(&header, &buf[..20]).unwrap();
+    assert_eq!((event.iso_packets.len(), event.data.len()), (1, 0));
+    let event = CaptureEvent::from_usbmon(&header, &[]).unwrap();
+    assert_eq!((event.iso_packets.len(), event.data.len()), (0, 0));
+
+    // a header claiming more than the kernel could have copied doesn't read past the buffer
+    let mut huge = header;
+    huge[60..64].copy_from_slice(&u32::MAX.to_ne_bytes());
+    huge[36..40].copy_from_slice(&u32::MAX.to_ne_bytes());
+    assert_eq!(CaptureEvent::from_usbmon(&huge, &buf).unwrap().iso_packets.len(), 4);
+
+    let mut bad = header;
+    bad[8] = b'X';
+    assert_eq!(CaptureEvent::from_usbmon(&bad, &buf).err().unwrap().kind(), ErrorKind::InvalidParam);
+}
+
+// A uevent for device 1-1 at bus 1 address 4, or with `devtype` "usb_interface" for its first
+// interface.
+fn uevent(action: &str, devtype: &str) -> Vec<u8> {
+    let devpath = match devtype {
+        "usb_device" => "/devices/pci0000:00/0000:00:14.0/usb1/1-1",
+        _ => "/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0",
+    };
+    let fields = [
+        format!("{}@{}", action, devpath),
+        format!("ACTION={}", action),
+        format!("DEVPATH={}", devpath),
+        "SUBSYSTEM=usb".to_string(),
+        "MAJOR=189".to_string(),
+        "MINOR=3".to_string(),
+        "DEVNAME=bus/usb/001/004".to_string(),
+        format!("DEVTYPE={}", devtype),
+        "PRODUCT=1234/5678/100".to_string(),
+        "TYPE=0/0/0".to_string(),
+        "BUSNUM=001".to_string(),
+        "DEVNUM=004".to_string(),
+        "SEQNUM=4321".to_string(),
+    ];
+    fields.iter().flat_map(|field| field.bytes().chain(Some(0))).collect()
+}
+
+#[test]
+fn uevent_parsing() {
+    for &(action, expected) in &[("add", HotplugAction::Add), ("remove", HotplugAction::Remove),
+                                 ("bind", HotplugAction::Bind), ("unbind", HotplugAction::Unbind)] {
+        let event = HotplugEvent::parse_uevent(&uevent(action, "usb_device")).unwrap();
+        assert_eq!((event.action, event.busnum, event.devnum), (expected, 1, 4));
+        assert_eq!((event.vendor, event.product, event.sysname.as_deref()), (Some(0x1234), Some(0x5678), Some("1-1")));
+    }
+
+    // interfaces, other subsystems, and other actions aren't device hotplug events
+    assert!(HotplugEvent::parse_uevent(&uevent("add", "usb_interface")).is_none());
+    assert!(HotplugEvent::parse_uevent(&uevent("change", "usb_device")).is_none());
+    let other = String::from_utf8(uevent("add", "usb_device")).unwrap().replace("SUBSYSTEM=usb", "SUBSYSTEM=net");
+    assert!(HotplugEvent::parse_uevent(other.as_bytes()).is_none());
+
+    // without a bus or device number the event can't name the device
+    let anonymous = String::from_utf8(uevent("remove", "usb_device")).unwrap().replace("DEVNUM=004", "");
+    assert!(HotplugEvent::parse_uevent(anonymous.as_bytes()).is_none());
+    // a missing or garbled PRODUCT only loses the ids
+    let garbled = String::from_utf8(uevent("remove", "usb_device")).unwrap().replace("1234/5678/100", "xyz");
+    let event = HotplugEvent::parse_uevent(garbled.as_bytes()).unwrap();
+    assert_eq!((event.vendor, event.product, event.devnum), (None, None, 4));
+}
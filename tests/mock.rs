@@ -0,0 +1,1692 @@
+//! Tests of `MockBackend`, and of the synchronous and asynchronous paths through `Backend`.
+//! Built with the `mock` feature.
+
+extern crate libc;
+extern crate nix;
+extern crate usbfs;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use usbfs::*;
+
+#[test]
+fn control_in_out() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+
+    mock.push(0, MockResponse::Complete(vec![]));
+    ControlRequest::new()
+        .request_type(SetupType::Vendor)
+        .request(0x10)
+        .value(0x1234)
+        .data_out(&[1, 2, 3])
+        .send(&device)
+        .unwrap();
+    mock.push(0x80, MockResponse::Complete(vec![4, 5]));
+    let data = ControlRequest::new()
+        .request_type(SetupType::Vendor)
+        .request(0x11)
+        .data_in(8)
+        .send(&device)
+        .unwrap();
+    assert_eq!(data, [4, 5]);
+
+    assert_eq!(mock.take_events(), vec![
+        MockEvent::Control { request_type: 0x40, request: 0x10, value: 0x1234, index: 0, length: 3, data: vec![1, 2, 3] },
+        MockEvent::Control { request_type: 0xc0, request: 0x11, value: 0, index: 0, length: 8, data: vec![] },
+    ]);
+    assert!(mock.take_events().is_empty());
+
+    // a data stage too long for wLength is refused without reaching the device
+    let oversized = ControlRequest::new().data_out(&vec![0; 0x10000]);
+    assert_eq!(oversized.send(&device).err().unwrap().kind(), ErrorKind::InvalidParam);
+    assert_eq!(oversized.transfer(UrbFlags::empty()).err().unwrap().kind(), ErrorKind::InvalidParam);
+    let mut async_device: AsyncDevice<Box<StdBufTransfer<Vec<u8>>>> = mock.device().unwrap().into();
+    assert_eq!(oversized.submit(&mut async_device).err().unwrap().kind(), ErrorKind::InvalidParam);
+    assert!(mock.take_events().is_empty());
+}
+
+#[test]
+fn sync_errors() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    let mut buf = [0u8; 4];
+
+    let err = device.bulk_transfer_in(0x81, &mut buf, 100).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Timeout);
+
+    mock.push(0x81, MockResponse::Fail(libc::EPIPE));
+    let err = device.bulk_transfer_in(0x81, &mut buf, 100).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Stall);
+
+    mock.push(0x81, MockResponse::Complete(vec![0; 8]));
+    let err = device.bulk_transfer_in(0x81, &mut buf, 100).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Overflow);
+}
+
+#[test]
+fn large_transfer_chunking() {
+    let mock = MockBackend::new().unwrap();
+    // no NO_PACKET_SIZE_LIM, so urbs are limited to 16k
+    mock.set_capabilities(Capabilities::BULK_CONTINUATION | Capabilities::REAP_AFTER_DISCONNECT);
+    let device = mock.device().unwrap();
+
+    let data: Vec<u8> = (0..40000).map(|i| i as u8).collect();
+    for _ in 0..3 {
+        mock.push(0x02, MockResponse::Complete(vec![]));
+    }
+    assert_eq!(device.bulk_transfer_out_large(0x82, &data, 1000).unwrap(), 40000);
+    assert_eq!(mock.take_events(), vec![
+        MockEvent::Transfer { endpoint: 0x02, length: 16384, data: data[..16384].to_vec() },
+        MockEvent::Transfer { endpoint: 0x02, length: 16384, data: data[16384..32768].to_vec() },
+        MockEvent::Transfer { endpoint: 0x02, length: 7232, data: data[32768..].to_vec() },
+    ]);
+
+    let mut buf = vec![0; 40000];
+    for fill in 1..4 {
+        mock.push(0x81, MockResponse::Complete(vec![fill; 16384]));
+    }
+    // more data than the last urb has room for overflows it
+    let err = device.bulk_transfer_in_large(0x01, &mut buf, 1000).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Overflow);
+    mock.take_events();
+
+    mock.push(0x81, MockResponse::Complete(vec![1; 16384]));
+    mock.push(0x81, MockResponse::Complete(vec![2; 16384]));
+    mock.push(0x81, MockResponse::Complete(vec![3; 7232]));
+    assert_eq!(device.bulk_transfer_in_large(0x01, &mut buf, 1000).unwrap(), 40000);
+    assert_eq!((buf[0], buf[16384], buf[39999]), (1, 2, 3));
+    let lengths: Vec<_> = mock.take_events().into_iter().map(|event| match event {
+        MockEvent::Transfer { endpoint: 0x81, length, .. } => length,
+        event => panic!("unexpected {:?}", event),
+    }).collect();
+    assert_eq!(lengths, [16384, 16384, 7232]);
+}
+
+#[test]
+fn large_transfer_short_packet() {
+    let mock = MockBackend::new().unwrap();
+    mock.set_capabilities(Capabilities::BULK_CONTINUATION | Capabilities::REAP_AFTER_DISCONNECT);
+    let device = mock.device().unwrap();
+    let mut buf = vec![0; 40000];
+
+    // with continuation all urbs are queued; the short one fails as URB_SHORT_NOT_OK, and the
+    // kernel cancels the one after it
+    mock.push(0x81, MockResponse::Complete(vec![1; 16384]));
+    mock.push(0x81, MockResponse::Complete(vec![2; 100]));
+    mock.push(0x81, MockResponse::Fail(libc::ECONNRESET));
+    assert_eq!(device.bulk_transfer_in_large(0x81, &mut buf, 1000).unwrap(), 16484);
+    assert_eq!(mock.take_events().len(), 3);
+
+    // without it, urbs go one at a time and a short packet ends the transfer
+    mock.set_capabilities(Capabilities::REAP_AFTER_DISCONNECT);
+    mock.push(0x81, MockResponse::Complete(vec![1; 16384]));
+    mock.push(0x81, MockResponse::Complete(vec![2; 100]));
+    assert_eq!(device.bulk_transfer_in_large(0x81, &mut buf, 1000).unwrap(), 16484);
+    assert_eq!(mock.take_events().len(), 2);
+    assert_eq!(mock.pending_count(), 0);
+}
+
+#[test]
+fn large_transfer_timeout() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    let mut buf = vec![0; 64];
+
+    let start = std::time::Instant::now();
+    let err = device.bulk_transfer_in_large(0x81, &mut buf, 50).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Timeout);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    // discarded and reaped before returning
+    assert_eq!(mock.pending_count(), 0);
+    assert_eq!(mock.take_events().len(), 1);
+}
+
+#[test]
+fn large_transfer_foreign_urb() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    let mut other: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+
+    other.submit(Box::new(BulkTransferMut::new(0x82, UrbFlags::empty(), vec![0; 8]))).unwrap();
+    mock.push(0x82, MockResponse::Complete(vec![2; 8]));
+    mock.push(0x81, MockResponse::Complete(vec![1; 8]));
+    let mut buf = vec![0; 8];
+    let err = device.bulk_transfer_in_large(0x81, &mut buf, 1000).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Other);
+    // its own urb was still reaped before returning
+    assert_eq!(mock.pending_count(), 0);
+
+    // the stolen urb is gone for good; don't wait for it
+    std::mem::forget(other);
+}
+
+#[test]
+fn large_transfer_disconnect() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    let killed = Arc::new(AtomicBool::new(false));
+
+    // reaps fail with ENODEV for a while before the kernel kills the urb
+    let unplug = {
+        let (mock, killed) = (mock.clone(), killed.clone());
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            mock.begin_disconnect();
+            std::thread::sleep(Duration::from_millis(50));
+            killed.store(true, Ordering::SeqCst);
+            mock.disconnect();
+        })
+    };
+    let mut buf = vec![0; 64];
+    let err = device.bulk_transfer_in_large(0x81, &mut buf, 0).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Disconnected);
+    assert!(killed.load(Ordering::SeqCst));
+    assert_eq!(mock.pending_count(), 0);
+    unplug.join().unwrap();
+
+    // older kernels never hand the urb back at all
+    let mock = MockBackend::new().unwrap();
+    mock.set_capabilities(Capabilities::NO_PACKET_SIZE_LIM);
+    let device = mock.device().unwrap();
+    let unplug = {
+        let mock = mock.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            mock.disconnect();
+        })
+    };
+    let err = device.bulk_transfer_in_large(0x81, &mut buf, 0).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Disconnected);
+    unplug.join().unwrap();
+}
+
+#[test]
+fn control_retry_on_stall() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    let policy = RetryPolicy::new().retries(2).backoff(Duration::from_millis(20));
+    let read = |buf: &mut [u8], policy: &RetryPolicy| {
+        device.control_transfer_retry(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Device,
+                                      0x01, 0, 0, Some(buf), 100, policy)
+    };
+    let mut buf = [0u8; 4];
+
+    // two stalls, then success; the sleeps are 20ms and then 40ms
+    mock.push(0, MockResponse::Fail(libc::EPIPE));
+    mock.push(0, MockResponse::Fail(libc::EPIPE));
+    mock.push(0, MockResponse::Complete(vec![1, 2]));
+    let start = std::time::Instant::now();
+    assert_eq!(read(&mut buf, &policy).unwrap(), 2);
+    assert!(start.elapsed() >= Duration::from_millis(60));
+    assert_eq!(mock.take_events().len(), 3);
+
+    // out of retries, the last stall is returned
+    for _ in 0..3 {
+        mock.push(0, MockResponse::Fail(libc::EPIPE));
+    }
+    assert_eq!(read(&mut buf, &policy).unwrap_err().kind(), ErrorKind::Stall);
+    assert_eq!(mock.take_events().len(), 3);
+
+    // only stalls are retried
+    mock.push(0, MockResponse::Fail(libc::EPROTO));
+    assert_eq!(read(&mut buf, &policy).unwrap_err().raw_os_error(), Some(libc::EPROTO));
+    mock.push(0, MockResponse::Fail(libc::EPIPE));
+    assert_eq!(read(&mut buf, &RetryPolicy::new().retries(0)).unwrap_err().kind(), ErrorKind::Stall);
+    assert_eq!(mock.take_events().len(), 2);
+}
+
+#[test]
+fn interface_operations() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    device.claim_interface(1).unwrap();
+    device.set_interface(1, 2).unwrap();
+    device.clear_halt(0x02).unwrap();
+    device.release_interface(1).unwrap();
+    assert_eq!(device.kernel_driver(1).unwrap(), None);
+    assert_eq!(device.speed().unwrap(), Speed::High);
+
+    assert_eq!(mock.take_events(), vec![
+        MockEvent::ClaimInterface(1),
+        MockEvent::SetInterface { interface: 1, altsetting: 2 },
+        MockEvent::ClearHalt(0x02),
+        MockEvent::ReleaseInterface(1),
+    ]);
+}
+
+#[test]
+fn interface_association_functions() {
+    let config = [
+        9, 2, 82, 0, 4, 1, 0, 0x80, 50,
+        8, 11, 0, 2, 2, 2, 1, 0,
+        9, 4, 0, 0, 1, 2, 2, 1, 0,
+        7, 5, 0x83, 3, 16, 0, 10,
+        9, 4, 1, 0, 2, 10, 0, 0, 0,
+        7, 5, 0x81, 2, 0, 2, 0,
+        7, 5, 0x02, 2, 0, 2, 0,
+        8, 11, 2, 1, 0xff, 0, 0, 0,
+        9, 4, 2, 0, 0, 0xff, 0, 0, 0,
+        9, 4, 3, 0, 0, 0xfe, 1, 1, 0,
+    ];
+    let parsed = ConfigDescriptor::parse(&config).unwrap();
+    let associations = parsed.associations();
+    assert_eq!(associations.len(), 2);
+    assert_eq!((associations[0].bFirstInterface, associations[0].bInterfaceCount), (0, 2));
+    let functions = parsed.functions();
+    let summary: Vec<_> = functions.iter()
+        .map(|f| (f.interfaces.clone(), f.class, f.association.is_some()))
+        .collect();
+    assert_eq!(summary, vec![(vec![0, 1], 2, true), (vec![2], 0xff, true), (vec![3], 0xfe, false)]);
+    assert_eq!(parsed.function(1), Some(functions[0].clone()));
+
+    // without sysfs the active configuration is read from the device
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    mock.push(0x80, MockResponse::Complete(vec![1]));
+    mock.push(0x80, MockResponse::Complete(vec![18, 1, 0x00, 0x02, 0xef, 2, 1, 64, 0x34, 0x12, 0x78, 0x56, 0x01, 0x01, 1, 2, 3, 1]));
+    mock.push(0x80, MockResponse::Complete(config[..9].to_vec()));
+    mock.push(0x80, MockResponse::Complete(config.to_vec()));
+    let claimed = device.claim_function(1).unwrap();
+    assert_eq!(claimed.iter().map(|i| i.number()).collect::<Vec<_>>(), vec![0, 1]);
+    mock.take_events();
+    drop(claimed);
+    assert_eq!(mock.take_events(), vec![MockEvent::ReleaseInterface(0), MockEvent::ReleaseInterface(1)]);
+}
+
+#[test]
+fn claim_all_interfaces() {
+    let config = [
+        9, 2, 50, 0, 2, 1, 0, 0x80, 50,
+        9, 4, 0, 0, 1, 0xff, 0, 0, 0,
+        7, 5, 0x81, 2, 0, 2, 0,
+        9, 4, 1, 0, 0, 0xff, 0, 0, 0,
+        9, 4, 1, 1, 1, 0xff, 0, 0, 0,
+        7, 5, 0x82, 1, 0, 2, 1,
+    ];
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    mock.push(0x80, MockResponse::Complete(vec![1]));
+    mock.push(0x80, MockResponse::Complete(vec![18, 1, 0x00, 0x02, 0xff, 0, 0, 64, 0x34, 0x12, 0x78, 0x56, 0x01, 0x01, 1, 2, 3, 1]));
+    mock.push(0x80, MockResponse::Complete(config[..9].to_vec()));
+    mock.push(0x80, MockResponse::Complete(config.to_vec()));
+    // GET_INTERFACE: interface 1 was left in setting 1
+    mock.push(0x80, MockResponse::Complete(vec![0]));
+    mock.push(0x80, MockResponse::Complete(vec![1]));
+    mock.take_events();
+    let claimed = device.claim_all(true).unwrap();
+    assert_eq!(claimed.iter().map(|i| i.number()).collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(claimed[1].altsettings().len(), 2);
+    assert_eq!(claimed.iter().map(|i| i.altsetting()).collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(claimed[1].endpoint(0x82).map(|e| e.bmAttributes), Some(1));
+    let claims: Vec<_> = mock.take_events().into_iter()
+        .filter(|e| matches!(e, MockEvent::ClaimInterface(_)))
+        .collect();
+    assert_eq!(claims, vec![MockEvent::ClaimInterface(0), MockEvent::ClaimInterface(1)]);
+    drop(claimed);
+    assert_eq!(mock.take_events(), vec![MockEvent::ReleaseInterface(0), MockEvent::ReleaseInterface(1)]);
+
+    // an unconfigured device has no interfaces to claim
+    mock.push(0x80, MockResponse::Complete(vec![0]));
+    mock.push(0x80, MockResponse::Complete(vec![18, 1, 0x00, 0x02, 0xff, 0, 0, 64, 0x34, 0x12, 0x78, 0x56, 0x01, 0x01, 1, 2, 3, 1]));
+    mock.push(0x80, MockResponse::Complete(config[..9].to_vec()));
+    mock.push(0x80, MockResponse::Complete(config.to_vec()));
+    assert_eq!(device.claim_all(false).err().unwrap().kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn async_completion_order() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+
+    let first = device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 16]))).unwrap();
+    let second = device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 16]))).unwrap();
+    assert_eq!(mock.pending_count(), 2);
+    assert_eq!(device.reap_nowait().err().unwrap().kind(), ErrorKind::WouldBlock);
+    assert_eq!(device.reap_timeout(Duration::from_millis(10)).err().unwrap().kind(), ErrorKind::Timeout);
+
+    mock.push(0x81, MockResponse::Complete(vec![1; 4]));
+    mock.push(0x81, MockResponse::Complete(vec![2; 20]));
+    let (slot, xfer, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!((slot, result), (first, TransferResult::Completed { len: 4 }));
+    assert_eq!(&xfer.buf[..4], &[1; 4]);
+    let (slot, _xfer, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!((slot, result), (second, TransferResult::Babble));
+}
+
+#[test]
+fn async_discard_and_disconnect() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+
+    let slot = device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 16]))).unwrap();
+    device.discard(slot).unwrap();
+    assert_eq!(mock.pending_count(), 0);
+
+    device.submit(Box::new(BulkTransferMut::new(0x02, UrbFlags::empty(), vec![7; 16]))).unwrap();
+    mock.disconnect();
+    let (_slot, _xfer, result) = device.reap_wait().unwrap();
+    assert_eq!(result, TransferResult::NoDevice);
+    assert_eq!(device.reap_wait().err().unwrap().kind(), ErrorKind::Disconnected);
+    assert!(device.submit(Box::new(BulkTransferMut::new(0x02, UrbFlags::empty(), vec![0; 16]))).is_err());
+
+    let events = mock.take_events();
+    assert_eq!(events.last(), Some(&MockEvent::Transfer { endpoint: 0x02, length: 16, data: vec![7; 16] }));
+}
+
+#[test]
+fn async_disconnect_before_urbs_killed() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+    let done = device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 8]))).unwrap();
+    let killed_slot = device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 8]))).unwrap();
+    mock.push(0x81, MockResponse::Complete(vec![1; 8]));
+
+    // reaps fail with ENODEV for a while before the kernel kills the pending urb
+    mock.begin_disconnect();
+    let killed = Arc::new(AtomicBool::new(false));
+    let unplug = {
+        let (mock, killed) = (mock.clone(), killed.clone());
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            killed.store(true, Ordering::SeqCst);
+            mock.disconnect();
+        })
+    };
+    let (slot, _xfer, result) = device.reap_nowait().unwrap();
+    assert_eq!((slot, result), (done, TransferResult::Completed { len: 8 }));
+    let (slot, _xfer, result) = device.reap_nowait().unwrap();
+    assert_eq!((slot, result), (killed_slot, TransferResult::NoDevice));
+    assert!(killed.load(Ordering::SeqCst));
+    assert_eq!(device.reap_wait().err().unwrap().kind(), ErrorKind::Disconnected);
+    unplug.join().unwrap();
+
+    // older kernels refuse to reap after a disconnect, and the transfers are made up
+    let mock = MockBackend::new().unwrap();
+    mock.set_capabilities(Capabilities::ZERO_PACKET);
+    let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+    device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 8]))).unwrap();
+    device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 8]))).unwrap();
+    mock.push(0x81, MockResponse::Complete(vec![1; 8]));
+    mock.disconnect();
+    let results: Vec<_> = device.drain().unwrap().into_iter().map(|(_, _, result)| result).collect();
+    assert_eq!(results, [TransferResult::NoDevice, TransferResult::NoDevice]);
+    assert_eq!(device.stats().errored, 2);
+}
+
+type BulkIn = Box<BulkTransferMut<Vec<u8>>>;
+
+fn bulk_in(endpoint: u8) -> BulkIn {
+    Box::new(BulkTransferMut::new(endpoint, UrbFlags::empty(), vec![0; 8]))
+}
+
+#[test]
+fn split_submit_and_reap_on_two_threads() {
+    let mock = MockBackend::new().unwrap();
+    let device: AsyncDevice<BulkIn> = mock.device().unwrap().into();
+    let (submitter, mut reaper) = device.split(4).unwrap();
+
+    let reaper = std::thread::spawn(move || {
+        (0..32).map(|_| {
+            let (_slot, xfer, result) = reaper.reap_wait().unwrap();
+            assert_eq!(result, TransferResult::Completed { len: 1 });
+            xfer.buf[0]
+        }).collect::<Vec<u8>>()
+    });
+    for i in 0..32 {
+        mock.push(0x81, MockResponse::Complete(vec![i]));
+        // only 4 fit at once, so this waits for the reaper
+        loop {
+            match submitter.submit(bulk_in(0x81)) {
+                Ok(_) => break,
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+    assert_eq!(reaper.join().unwrap(), (0..32).collect::<Vec<u8>>());
+}
+
+#[test]
+fn split_discard() {
+    let mock = MockBackend::new().unwrap();
+    let device: AsyncDevice<BulkIn> = mock.device().unwrap().into();
+    let (submitter, mut reaper) = device.split(2).unwrap();
+
+    let first = submitter.submit(bulk_in(0x81)).unwrap();
+    let second = submitter.submit(bulk_in(0x82)).unwrap();
+    mock.push(0x82, MockResponse::Complete(vec![2; 8]));
+    // the completed transfer is reaped on the way, and held
+    assert_eq!(reaper.discard(first).unwrap().urb.endpoint, 0x81);
+    assert_eq!(reaper.discard(first).err().unwrap().kind(), ErrorKind::InvalidParam);
+    let (slot, _xfer, result) = reaper.reap_nowait().unwrap();
+    assert_eq!((slot, result), (second, TransferResult::Completed { len: 8 }));
+    assert_eq!(mock.pending_count(), 0);
+
+    // the slot is reused under a new generation
+    let third = submitter.submit(bulk_in(0x81)).unwrap();
+    assert_eq!(third.index(), first.index());
+    assert_eq!(reaper.discard(first).err().unwrap().kind(), ErrorKind::InvalidParam);
+    reaper.discard(third).unwrap();
+}
+
+#[test]
+fn split_foreign_urb_rejected() {
+    let mock = MockBackend::new().unwrap();
+    let mut other: AsyncDevice<BulkIn> = mock.device().unwrap().into();
+    let device: AsyncDevice<BulkIn> = mock.device().unwrap().into();
+    let (submitter, mut reaper) = device.split(1).unwrap();
+
+    other.submit(bulk_in(0x82)).unwrap();
+    submitter.submit(bulk_in(0x81)).unwrap();
+    // same slot index in both tables, but not the same urb
+    mock.push(0x82, MockResponse::Complete(vec![2; 8]));
+    assert_eq!(reaper.reap_nowait().err().unwrap().kind(), ErrorKind::Other);
+    mock.push(0x81, MockResponse::Complete(vec![1; 8]));
+    assert_eq!(reaper.reap_nowait().unwrap().2, TransferResult::Completed { len: 8 });
+
+    // the stolen urb is gone for good; don't wait for it
+    std::mem::forget(other);
+}
+
+// Holds up each submission after the mock has taken the urb, so that it can be reaped before
+// the submitter hears back.
+struct SlowSubmit(MockBackend);
+
+unsafe impl Backend for SlowSubmit {
+    unsafe fn ioctl(&self, request: libc::c_ulong, arg: *mut libc::c_void) -> nix::Result<libc::c_int> {
+        // _IOR('U', 10, struct usbdevfs_urb)
+        let submiturb = (2 << 30) | (std::mem::size_of::<Urb>() as libc::c_ulong) << 16 | (b'U' as libc::c_ulong) << 8 | 10;
+        let result = self.0.ioctl(request, arg);
+        if request == submiturb {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        result
+    }
+}
+
+#[test]
+fn split_reap_before_slot_filled_in() {
+    let mock = MockBackend::new().unwrap();
+    let file = mock.device().unwrap().file().try_clone().unwrap();
+    let device: AsyncDevice<BulkIn> = Device::with_backend(file, Arc::new(SlowSubmit(mock.clone()))).into();
+    let (submitter, mut reaper) = device.split(1).unwrap();
+
+    // completes within the submit, then the slot stays reserved for a while
+    mock.push(0x81, MockResponse::Complete(vec![7; 8]));
+    let start = std::time::Instant::now();
+    let submit = std::thread::spawn(move || submitter.submit(bulk_in(0x81)).unwrap());
+    let (slot, xfer, result) = reaper.reap_wait().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!((slot, result), (submit.join().unwrap(), TransferResult::Completed { len: 8 }));
+    assert_eq!(xfer.buf, [7; 8]);
+}
+
+#[test]
+fn split_disconnect() {
+    let mock = MockBackend::new().unwrap();
+    let device: AsyncDevice<BulkIn> = mock.device().unwrap().into();
+    let (submitter, mut reaper) = device.split(2).unwrap();
+    let slot = submitter.submit(bulk_in(0x81)).unwrap();
+
+    // reaps fail with ENODEV for a while before the kernel kills the pending urb
+    mock.begin_disconnect();
+    let killed = Arc::new(AtomicBool::new(false));
+    let unplug = {
+        let (mock, killed) = (mock.clone(), killed.clone());
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            killed.store(true, Ordering::SeqCst);
+            mock.disconnect();
+        })
+    };
+    let (reaped, _xfer, result) = reaper.reap_wait().unwrap();
+    assert_eq!((reaped, result), (slot, TransferResult::NoDevice));
+    assert!(killed.load(Ordering::SeqCst));
+    assert_eq!(reaper.reap_wait().err().unwrap().kind(), ErrorKind::Disconnected);
+    assert!(submitter.submit(bulk_in(0x81)).is_err());
+    unplug.join().unwrap();
+
+    // older kernels refuse to reap after a disconnect, and the transfers are made up
+    let mock = MockBackend::new().unwrap();
+    mock.set_capabilities(Capabilities::ZERO_PACKET);
+    let device: AsyncDevice<BulkIn> = mock.device().unwrap().into();
+    let (submitter, mut reaper) = device.split(2).unwrap();
+    let slot = submitter.submit(bulk_in(0x81)).unwrap();
+    mock.disconnect();
+    let (reaped, _xfer, result) = reaper.reap_nowait().unwrap();
+    assert_eq!((reaped, result), (slot, TransferResult::NoDevice));
+    assert_eq!(reaper.reap_nowait().err().unwrap().kind(), ErrorKind::Disconnected);
+}
+
+#[test]
+fn split_handles_dropped_in_either_order() {
+    let mock = MockBackend::new().unwrap();
+
+    // without a reaper, transfers wait in flight until the submitter goes too
+    let device: AsyncDevice<BulkIn> = mock.device().unwrap().into();
+    let (submitter, reaper) = device.split(2).unwrap();
+    submitter.submit(bulk_in(0x81)).unwrap();
+    drop(reaper);
+    submitter.submit(bulk_in(0x81)).unwrap();
+    assert_eq!(mock.pending_count(), 2);
+    drop(submitter);
+    assert_eq!(mock.pending_count(), 0);
+
+    // without a submitter, the reaper still collects what's in flight
+    let device: AsyncDevice<BulkIn> = mock.device().unwrap().into();
+    let (submitter, mut reaper) = device.split(2).unwrap();
+    let slot = submitter.submit(bulk_in(0x81)).unwrap();
+    submitter.submit(bulk_in(0x81)).unwrap();
+    drop(submitter);
+    mock.push(0x81, MockResponse::Complete(vec![1; 8]));
+    let (reaped, _xfer, result) = reaper.reap_wait().unwrap();
+    assert_eq!((reaped, result), (slot, TransferResult::Completed { len: 8 }));
+    assert_eq!(mock.pending_count(), 1);
+    drop(reaper);
+    assert_eq!(mock.pending_count(), 0);
+}
+
+#[test]
+fn async_foreign_urb_rejected() {
+    let mock = MockBackend::new().unwrap();
+    // two handles on one simulated file share its reap queue, like a dup'd usbfs descriptor
+    let mut owner: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+    let mut other: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+
+    other.submit(Box::new(BulkTransferMut::new(0x82, UrbFlags::empty(), vec![0; 8]))).unwrap();
+    owner.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 8]))).unwrap();
+    mock.push(0x81, MockResponse::Complete(vec![1; 8]));
+    // same slot index in both tables, but not the same urb
+    assert_eq!(other.reap_nowait().err().unwrap().kind(), ErrorKind::Other);
+    assert_eq!(other.pending_count(), 1);
+    assert_eq!(other.stats().completed, 0);
+
+    // the stolen urb is gone for good; don't wait for it
+    std::mem::forget(owner);
+    mock.push(0x82, MockResponse::Complete(vec![2; 8]));
+    let (_slot, _xfer, result) = other.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(result, TransferResult::Completed { len: 8 });
+}
+
+#[test]
+fn async_backpressure_and_stats() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+    device.set_max_in_flight(Some(2));
+
+    device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 16]))).unwrap();
+    device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 16]))).unwrap();
+    assert_eq!(device.capacity(), Some(0));
+    let (err, _xfer) = device.submit_give_back_on_fail(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 16]))).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+
+    mock.push(0x81, MockResponse::Complete(vec![1; 4]));
+    mock.push(0x81, MockResponse::Fail(libc::EPIPE));
+    device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(device.capacity(), Some(1));
+    device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(device.stats(), QueueStats { submitted: 2, completed: 1, errored: 1, cancelled: 0 });
+
+    device.reset_stats();
+    assert_eq!(device.stats(), QueueStats::default());
+}
+
+#[test]
+fn async_reap_all() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+    assert!(device.reap_all_nowait().unwrap().is_empty());
+
+    let slots: Vec<SlotId> = (0..3)
+        .map(|_| device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 8]))).unwrap())
+        .collect();
+    mock.push(0x81, MockResponse::Complete(vec![1; 2]));
+    mock.push(0x81, MockResponse::Fail(libc::EPIPE));
+    let reaped: Vec<(SlotId, TransferResult)> = device.reap_all_nowait().unwrap().into_iter()
+        .map(|(slot, _xfer, result)| (slot, result))
+        .collect();
+    assert_eq!(reaped, vec![(slots[0], TransferResult::Completed { len: 2 }), (slots[1], TransferResult::Stalled)]);
+    assert_eq!(device.pending_count(), 1);
+    assert!(device.reap_all_nowait().unwrap().is_empty());
+}
+
+#[test]
+fn async_user_data() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>, String> = mock.device().unwrap().into();
+
+    let first = device.submit_with_data(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 8])),
+                                        "first".to_string()).ok().unwrap();
+    let second = device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 8]))).unwrap();
+    assert_eq!(device.data(first).unwrap(), "first");
+    device.data_mut(second).unwrap().push_str("second");
+
+    mock.push(0x81, MockResponse::Complete(vec![1]));
+    mock.push(0x81, MockResponse::Complete(vec![2]));
+    let (slot, _xfer, data, result) = device.reap_with_data_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!((slot, data.as_str(), result), (first, "first", TransferResult::Completed { len: 1 }));
+    let (slot, _xfer, data, _result) = device.reap_with_data_nowait().unwrap();
+    assert_eq!((slot, data.as_str()), (second, "second"));
+    assert!(device.data(first).is_none());
+
+    // resubmission keeps the data with its transfer
+    device.submit_with_data(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 8])),
+                            "again".to_string()).ok().unwrap();
+    mock.push(0x81, MockResponse::Complete(vec![3]));
+    device.reap_resubmit_wait(|_xfer, _result| ()).unwrap();
+    let slot = device.iterate_pending().next().unwrap();
+    assert_eq!(device.data(slot).unwrap(), "again");
+}
+
+#[test]
+fn scoped_borrowed_transfers() {
+    let mock = MockBackend::new().unwrap();
+    let mut device = mock.device().unwrap();
+
+    let mut buf = [0u8; 8];
+    let mut reply = BulkTransferMut::new(0x81, UrbFlags::empty(), &mut buf[..]);
+    let mut stuck_buf = [0u8; 8];
+    let mut stuck = BulkTransferMut::new(0x82, UrbFlags::empty(), &mut stuck_buf[..]);
+    mock.push(0x81, MockResponse::Complete(vec![1, 2, 3]));
+    let len = device.transfer_scope(|scope| {
+        scope.submit(&mut reply).unwrap();
+        // left in flight, to be cancelled when the scope ends
+        scope.submit(&mut stuck).unwrap();
+        let (_slot, xfer, result) = scope.reap_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(xfer.urb.endpoint, 0x81);
+        result.into_io_result().unwrap()
+    });
+    assert_eq!(len, 3);
+    assert_eq!(&reply.buf[..3], &[1, 2, 3]);
+    assert_eq!(mock.pending_count(), 0);
+
+    // a panic in the scope still waits for the kernel to hand the transfer back
+    let caught = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        device.transfer_scope(|scope| {
+            scope.submit(&mut stuck).unwrap();
+            panic!("scope body failed");
+        })
+    }));
+    assert!(caught.is_err());
+    assert_eq!(mock.pending_count(), 0);
+
+    // the device's own file was lent to the scopes, not closed by them
+    mock.push(0x80, MockResponse::Complete(vec![1]));
+    assert_eq!(device.get_configuration(100).unwrap(), 1);
+}
+
+#[test]
+fn paced_interrupt_stream() {
+    let mock = MockBackend::new().unwrap();
+    let interval = Duration::from_millis(30);
+    let mut stream = PacedInterruptStream::new(mock.device().unwrap(), 0x81, 8, interval).unwrap();
+    assert_eq!(mock.pending_count(), 1);
+
+    mock.push(0x81, MockResponse::Complete(vec![1, 2]));
+    mock.push(0x81, MockResponse::Complete(vec![3]));
+    assert_eq!(stream.next_report(|report| report.to_vec()).unwrap(), vec![1, 2]);
+    // the transfer waits for the timer rather than being resubmitted at once
+    assert_eq!(mock.pending_count(), 0);
+    assert_eq!(stream.next_report_nowait(|_| ()).unwrap_err().kind(), ErrorKind::WouldBlock);
+
+    let start = std::time::Instant::now();
+    assert_eq!(stream.next_report(|report| report.to_vec()).unwrap(), vec![3]);
+    assert!(start.elapsed() >= interval / 2);
+
+    let ep = EndpointDescriptor { bEndpointAddress: 0x81, bmAttributes: 3, wMaxPacketSize: 8, bInterval: 4, extra: vec![] };
+    assert_eq!(ep.service_interval(Speed::Full), Duration::from_millis(4));
+    assert_eq!(ep.service_interval(Speed::High), Duration::from_millis(1));
+}
+
+#[test]
+fn bench_on_borrowed_device() {
+    let mock = MockBackend::new().unwrap();
+    let mut device = mock.device().unwrap();
+    for _ in 0..3 {
+        mock.push(0x81, MockResponse::Complete(vec![0; 512]));
+    }
+    let report = Bench::new(0x01).transfer_size(512).depth(2).duration(Duration::from_millis(50))
+        .bulk_in(&mut device)
+        .unwrap();
+    assert_eq!((report.transfers, report.bytes, report.errors), (3, 3 * 512, 0));
+
+    // the run's transfers are reaped, and the device's own file left open for further use
+    assert_eq!(mock.pending_count(), 0);
+    assert!(device.file().metadata().is_ok());
+    mock.push(0x80, MockResponse::Complete(vec![1]));
+    assert_eq!(device.get_configuration(100).unwrap(), 1);
+}
+
+#[test]
+fn hub_port_power() {
+    let mock = MockBackend::new().unwrap();
+    mock.push(0x80, MockResponse::Complete(vec![9, 0x29, 4, 0x01, 0x00, 50, 100, 0, 0xff]));
+    let hub = Hub::new(mock.device().unwrap(), 1000).unwrap();
+    assert_eq!((hub.num_ports(), hub.power_switching()), (4, PowerSwitching::PerPort));
+
+    mock.push(0x80, MockResponse::Complete(vec![0x03, 0x01, 0x01, 0x00]));
+    let status = hub.port_status(2, 1000).unwrap();
+    assert!(status.connected() && status.enabled() && status.powered() && !status.over_current());
+    mock.push(0, MockResponse::Complete(vec![]));
+    mock.push(0, MockResponse::Complete(vec![]));
+    hub.power_cycle(2, Duration::ZERO, 1000).unwrap();
+    assert_eq!(hub.reset_port(5, 1000).unwrap_err().kind(), ErrorKind::InvalidParam);
+
+    assert_eq!(mock.take_events(), vec![
+        MockEvent::Control { request_type: 0xa0, request: 6, value: 0x2900, index: 0, length: 12, data: vec![] },
+        MockEvent::Control { request_type: 0xa3, request: 0, value: 0, index: 2, length: 4, data: vec![] },
+        MockEvent::Control { request_type: 0x23, request: 1, value: 8, index: 2, length: 0, data: vec![] },
+        MockEvent::Control { request_type: 0x23, request: 3, value: 8, index: 2, length: 0, data: vec![] },
+    ]);
+}
+
+#[test]
+fn async_transfer_timing() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+
+    let before = std::time::Instant::now();
+    device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 8]))).unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    mock.push(0x81, MockResponse::Complete(vec![]));
+    let (_slot, _xfer, _result, timing) = device.reap_with_timing_timeout(Duration::from_secs(1)).unwrap();
+    assert!(timing.submitted >= before);
+    assert!(timing.latency() >= Duration::from_millis(20));
+    assert!(timing.reaped <= std::time::Instant::now());
+}
+
+#[test]
+fn record_and_replay() {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mock = MockBackend::new().unwrap();
+    let capture = Shared::default();
+    let recorder = Recorder::new(mock.device().unwrap(), capture.clone()).unwrap();
+    let device = recorder.device().unwrap();
+
+    mock.push(0, MockResponse::Complete(vec![]));
+    device.control_transfer_out(SetupType::Vendor, SetupRecipient::Device, 0x10, 0x1234, 0,
+                                Some(&[1, 2, 3]), 1000).unwrap();
+    mock.push(0x80, MockResponse::Complete(vec![4, 5]));
+    let mut buf = [0; 8];
+    device.control_transfer_in(SetupType::Vendor, SetupRecipient::Device, 0x11, 0, 0,
+                               Some(&mut buf), 1000).unwrap();
+    let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = device.into();
+    mock.push(0x02, MockResponse::Complete(vec![]));
+    device.submit(Box::new(BulkTransferMut::new(0x02, UrbFlags::empty(), vec![6, 7]))).unwrap();
+    device.reap_wait().unwrap();
+    mock.push(0x81, MockResponse::Fail(libc::EPIPE));
+    device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 4]))).unwrap();
+    device.reap_wait().unwrap();
+    recorder.flush().unwrap();
+
+    let bytes = capture.0.lock().unwrap().clone();
+    let mut replayer = Replayer::new(&bytes[..]).unwrap();
+    let mut events = Vec::new();
+    while let Some(event) = replayer.next_event().unwrap() {
+        events.push(event);
+    }
+    let summary: Vec<_> = events.iter()
+        .map(|e| (e.kind, e.transfer_type, e.endpoint, e.status, e.length, e.data.clone()))
+        .collect();
+    assert_eq!(summary, vec![
+        (CaptureEventKind::Submit, UrbType::Control, 0, -libc::EINPROGRESS, 3, vec![1, 2, 3]),
+        (CaptureEventKind::Complete, UrbType::Control, 0, 0, 3, vec![]),
+        (CaptureEventKind::Submit, UrbType::Control, 0x80, -libc::EINPROGRESS, 8, vec![]),
+        (CaptureEventKind::Complete, UrbType::Control, 0x80, 0, 2, vec![4, 5]),
+        (CaptureEventKind::Submit, UrbType::Bulk, 0x02, -libc::EINPROGRESS, 2, vec![6, 7]),
+        (CaptureEventKind::Complete, UrbType::Bulk, 0x02, 0, 2, vec![]),
+        (CaptureEventKind::Submit, UrbType::Bulk, 0x81, -libc::EINPROGRESS, 4, vec![]),
+        (CaptureEventKind::Complete, UrbType::Bulk, 0x81, -libc::EPIPE, 0, vec![]),
+    ]);
+    assert_eq!(events[0].setup, Some([0x40, 0x10, 0x34, 0x12, 0, 0, 3, 0]));
+    assert_eq!(events[4].id, events[5].id);
+
+    // only what the host sent is replayed
+    let target = MockBackend::new().unwrap();
+    target.push(0, MockResponse::Complete(vec![]));
+    target.push(0x80, MockResponse::Complete(vec![]));
+    target.push(0x02, MockResponse::Complete(vec![]));
+    let count = Replayer::new(&bytes[..]).unwrap().replay(&target.device().unwrap(), 1000).unwrap();
+    assert_eq!(count, 3);
+    assert_eq!(target.take_events(), vec![
+        MockEvent::Control { request_type: 0x40, request: 0x10, value: 0x1234, index: 0, length: 3, data: vec![1, 2, 3] },
+        MockEvent::Control { request_type: 0xc0, request: 0x11, value: 0, index: 0, length: 8, data: vec![] },
+        MockEvent::Transfer { endpoint: 0x02, length: 2, data: vec![6, 7] },
+    ]);
+
+    // a block claiming more than the capture holds is an error, not a 4GiB allocation
+    let mut damaged = bytes.clone();
+    damaged.extend_from_slice(&6u32.to_ne_bytes());
+    damaged.extend_from_slice(&0xffff_fff0u32.to_ne_bytes());
+    damaged.extend_from_slice(&[0; 16]);
+    let mut replayer = Replayer::new(&damaged[..]).unwrap();
+    let err = loop {
+        match replayer.next_event() {
+            Ok(Some(_)) => (),
+            Ok(None) => panic!("damaged block read as the end of the capture"),
+            Err(err) => break err,
+        }
+    };
+    assert_eq!(err.kind(), ErrorKind::InvalidParam);
+}
+
+#[test]
+fn bulk_writer() {
+    use std::io::Write;
+
+    let mock = MockBackend::new().unwrap();
+    for _ in 0..3 {
+        mock.push(0x02, MockResponse::Complete(vec![]));
+    }
+    let mut writer = BulkWriter::new(mock.device().unwrap(), 0x02, 16, 2).unwrap();
+    writer.write_all(&[5; 40]).unwrap();
+    writer.flush().unwrap();
+    assert_eq!(writer.bytes_written(), 40);
+    assert_eq!(writer.pending_count(), 0);
+
+    let lengths: Vec<usize> = mock.take_events().into_iter()
+        .filter_map(|event| match event {
+            MockEvent::Transfer { endpoint: 0x02, length, .. } => Some(length),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(lengths, vec![16, 16, 8]);
+}
+
+#[test]
+fn bulk_reader() {
+    use std::io::Read;
+
+    let mock = MockBackend::new().unwrap();
+    let mut reader = BulkReader::new(mock.device().unwrap(), 0x81, 8, 2).unwrap();
+    assert_eq!(mock.pending_count(), 2);
+
+    mock.push(0x81, MockResponse::Complete(vec![1; 8]));
+    mock.push(0x81, MockResponse::Complete(vec![2; 3]));
+    let mut message = vec![1; 8];
+    message.extend_from_slice(&[2; 3]);
+    assert_eq!(reader.read_message().unwrap(), message);
+
+    mock.push(0x81, MockResponse::Complete(vec![]));
+    mock.push(0x81, MockResponse::Complete(vec![3; 5]));
+    let mut buf = [0; 4];
+    assert_eq!(reader.read(&mut buf).unwrap(), 4);
+    assert_eq!(reader.read(&mut buf).unwrap(), 1);
+    assert_eq!(reader.bytes_read(), 16);
+
+    mock.push(0x81, MockResponse::Fail(libc::EPIPE));
+    assert!(reader.next_chunk().is_err());
+    assert_eq!(reader.depth(), 1);
+}
+
+#[test]
+fn buffer_pool_reuse() {
+    let pool = BufferPool::new(64, 4096, 2).unwrap();
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<BulkTransferMut<PooledBuffer>>> = mock.device().unwrap().into();
+
+    while let Some(buf) = pool.get() {
+        assert_eq!(buf.as_ref().as_ptr() as usize % 4096, 0);
+        device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), buf))).unwrap();
+    }
+    assert_eq!(pool.available(), 0);
+
+    mock.push(0x81, MockResponse::Complete(vec![9; 64]));
+    let (_slot, xfer, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(result, TransferResult::Completed { len: 64 });
+    assert_eq!(xfer.buf.as_ref(), &[9; 64][..]);
+    drop(xfer);
+    assert_eq!(pool.available(), 1);
+
+    let mut buf = pool.get().unwrap();
+    buf.set_len(10);
+    assert_eq!(buf.as_mut().len(), 10);
+}
+
+#[test]
+fn mmap_buffer_fallback() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+
+    // no usbfs mmap in the kernel
+    mock.set_capabilities(Capabilities::empty());
+    assert_eq!(MmapBuffer::new(&device, 64).err().unwrap().kind(), ErrorKind::Unsupported);
+    assert!(!MmapBuffer::new_or_heap(&device, 64).unwrap().is_mapped());
+
+    // supported, but the mapping fails, since the mock's eventfd can't be mapped
+    mock.set_capabilities(Capabilities::MMAP);
+    assert!(MmapBuffer::new(&device, 64).is_err());
+    let buf = MmapBuffer::new_or_heap(&device, 64).unwrap();
+    assert!(!buf.is_mapped());
+    assert_eq!(buf.as_ref(), &[0; 64][..]);
+    assert_eq!(MmapBuffer::new_or_heap(&device, 0).err().unwrap().kind(), ErrorKind::InvalidParam);
+
+    // the fallback serves as a transfer buffer all the same
+    let mut device: AsyncDevice<Box<BulkTransferMut<MmapBuffer>>> = device.into();
+    device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), buf))).unwrap();
+    mock.push(0x81, MockResponse::Complete(vec![5; 16]));
+    let (_slot, xfer, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(result, TransferResult::Completed { len: 16 });
+    assert_eq!(&xfer.buf.as_ref()[..16], &[5; 16][..]);
+}
+
+#[test]
+fn aligned_buf_transfer() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<BulkTransferMut<AlignedBuf<512, 4096>>>> = mock.device().unwrap().into();
+
+    let buf = AlignedBuf::<512, 4096>::new();
+    assert_eq!(buf.as_ref().as_ptr() as usize % 4096, 0);
+    device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), buf))).unwrap();
+    mock.push(0x81, MockResponse::Complete(vec![3; 100]));
+    let (_slot, xfer, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(result, TransferResult::Completed { len: 100 });
+    assert_eq!(&xfer.buf.as_ref()[..100], &[3; 100][..]);
+    assert_eq!(xfer.buf.clone().as_ref(), xfer.buf.as_ref());
+}
+
+#[test]
+fn device_descriptor_decoding() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+
+    let raw = [18, 1, 0x00, 0x02, 0xff, 0, 0, 64, 0x34, 0x12, 0x78, 0x56, 0x01, 0x01, 1, 2, 3, 1];
+    mock.push(0x80, MockResponse::Complete(raw.to_vec()));
+    let descr = device.get_device_descriptor(100).unwrap();
+    assert_eq!((descr.bcdUSB, descr.idVendor, descr.idProduct, descr.bcdDevice), (0x200, 0x1234, 0x5678, 0x101));
+    assert_eq!((descr.bMaxPacketSize0, descr.iSerialNumber, descr.bNumConfigurations), (64, 3, 1));
+    assert_eq!(descr.to_le_bytes(), raw);
+
+    mock.push(0x80, MockResponse::Complete(vec![18, 1, 0x00, 0x02]));
+    assert!(device.get_device_descriptor(100).is_err());
+
+    let events = mock.take_events();
+    assert_eq!(events[0], MockEvent::Control { request_type: 0x80, request: 6, value: 0x100, index: 0, length: 18, data: vec![] });
+}
+
+#[test]
+fn setup_packet_encoding() {
+    let setup = Setup::new(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Interface,
+                           0x42, 0x1234, 0x0002, 0x0040);
+    let bytes = setup.to_le_bytes();
+    assert_eq!(bytes, [0xc1, 0x42, 0x34, 0x12, 0x02, 0x00, 0x40, 0x00]);
+    assert_eq!(Setup::from_le_bytes(bytes), setup);
+}
+
+#[test]
+fn transfer_constructor_validation() {
+    assert_eq!(BulkTransfer::try_new(0x81, UrbFlags::empty(), vec![0; 8]).err().unwrap().kind(), ErrorKind::InvalidParam);
+    assert_eq!(InterruptTransfer::try_new(0x81, UrbFlags::empty(), vec![0; 8]).err().unwrap().kind(), ErrorKind::InvalidParam);
+    assert!(BulkTransfer::try_new(0x01, UrbFlags::empty(), vec![0; 8]).is_ok());
+
+    let short = ControlTransferMut::try_new(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Device,
+                                            1, 0, 0, UrbFlags::empty(), vec![0; 4]);
+    assert_eq!(short.err().unwrap().kind(), ErrorKind::InvalidParam);
+    let short = StdBufTransfer::try_control(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Device,
+                                            1, 0, 0, UrbFlags::empty(), vec![0; 4]);
+    assert_eq!(short.err().unwrap().kind(), ErrorKind::InvalidParam);
+    let long = StdBufTransfer::try_control(SetupDirection::HostToDevice, SetupType::Vendor, SetupRecipient::Device,
+                                           1, 0, 0, UrbFlags::empty(), vec![0; 8 + 0x10000]);
+    assert_eq!(long.err().unwrap().kind(), ErrorKind::InvalidParam);
+    let setup = Setup::new(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Device, 1, 2, 3, 4);
+    let xfer = StdBufTransfer::try_control(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Device,
+                                           1, 2, 3, UrbFlags::empty(), vec![0; 12]).unwrap();
+    assert_eq!(xfer.buf[..8], setup.to_le_bytes());
+    assert_eq!(try_write_setup_struct(&setup, &mut [0; 7]).err().unwrap().kind(), ErrorKind::InvalidParam);
+
+    // a short buffer that slips through is rejected on submission rather than panicking
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<ControlTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+    let mut xfer = ControlTransferMut::new(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Device,
+                                           1, 0, 0, UrbFlags::empty(), vec![0; 4]);
+    assert!(xfer.payload().is_empty() && xfer.payload_mut().is_empty());
+    assert_eq!(device.submit(Box::new(xfer)).err().unwrap().kind(), ErrorKind::InvalidParam);
+    let xfer = ControlTransferMut::from_setup(setup, vec![0; 7]);
+    assert!(xfer.payload().is_empty());
+    assert_eq!(device.submit(Box::new(xfer)).err().unwrap().kind(), ErrorKind::InvalidParam);
+}
+
+#[test]
+fn transfer_outcome_snapshot() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<StdBufTransfer<Vec<u8>>>> = mock.device().unwrap().into();
+
+    device.submit(Box::new(StdBufTransfer::bulk(0x81, UrbFlags::empty(), vec![0; 16]))).unwrap();
+    mock.push(0x81, MockResponse::Complete(vec![7; 5]));
+    let (_slot, xfer, _result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+    let (buf, outcome) = xfer.into_result();
+    assert_eq!(&buf[..5], &[7; 5]);
+    assert_eq!(outcome.result, TransferResult::Completed { len: 5 });
+    assert_eq!((outcome.status, outcome.actual_length), (0, 5));
+    assert!(outcome.iso_packets.is_empty());
+
+    // outcomes carry no pointers and can cross threads
+    std::thread::spawn(move || assert!(outcome.result.is_ok())).join().unwrap();
+}
+
+#[test]
+fn usb_status_codes() {
+    for status in [0, -libc::EPIPE, -libc::EOVERFLOW, -libc::EPROTO, -libc::EILSEQ, -libc::ECONNRESET,
+                   -libc::ESHUTDOWN, -libc::EXDEV, -libc::EIO] {
+        assert_eq!(UsbStatus::from_status(status).status(), status);
+    }
+    assert_eq!(UsbStatus::from_status(-libc::EILSEQ), UsbStatus::Crc);
+    assert_eq!(UsbStatus::from_status(-libc::EPIPE).to_string(), "endpoint stalled");
+    assert_eq!(TransferResult::from_status(-libc::EPIPE, 0).usb_status(), UsbStatus::Stall);
+    assert_eq!(TransferResult::from_status(-libc::EPROTO, 0).usb_status(), UsbStatus::Protocol);
+    assert_eq!(TransferResult::Completed { len: 3 }.to_string(), "completed, 3 bytes");
+    assert_eq!(TransferResult::Timeout.to_string(), "timed out");
+}
+
+#[test]
+fn iso_error_summary() {
+    #[derive(Debug)]
+    struct Packets(Vec<u8>);
+    impl AsMut<[u8]> for Packets {
+        fn as_mut(&mut self) -> &mut [u8] {
+            &mut self.0
+        }
+    }
+    impl IsoBuffer for Packets {
+        fn packet_length(&self) -> usize {
+            4
+        }
+    }
+
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<IsoBufTransfer<Packets, 4>>> = mock.device().unwrap().into();
+    device.submit(Box::new(IsoBufTransfer::isochronous(0x81, UrbFlags::URB_ISO_ASAP, Packets(vec![0; 16])))).unwrap();
+    mock.push(0x81, MockResponse::Fail(libc::EPROTO));
+    let (_slot, xfer, _result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(xfer.error_count(), 4);
+    let mut summary = xfer.error_summary();
+    assert_eq!((summary.packets, summary.failed), (4, 4));
+    assert_eq!(summary.by_status.get(&-libc::EPROTO), Some(&4));
+    assert_eq!(xfer.into_result().1.error_summary(), summary);
+
+    let packets = [
+        IsoPacketDesc { length: 4, actual_length: 4, status: 0 },
+        IsoPacketDesc { length: 4, actual_length: 0, status: -libc::EXDEV },
+        IsoPacketDesc { length: 4, actual_length: 0, status: -libc::EPROTO },
+        IsoPacketDesc { length: 4, actual_length: 4, status: 0 },
+    ];
+    summary.merge(&IsoErrorSummary::from_packets(&packets));
+    assert_eq!((summary.packets, summary.failed), (8, 6));
+    assert_eq!(summary.by_status.get(&-libc::EPROTO), Some(&5));
+    assert_eq!(summary.by_status.get(&-libc::EXDEV), Some(&1));
+    assert_eq!(summary.failure_rate(), 0.75);
+}
+
+#[test]
+fn submit_checked_against_configuration() {
+    fn endpoint(address: u8, attributes: u8, max_packet_size: u16) -> EndpointDescriptor {
+        EndpointDescriptor { bEndpointAddress: address, bmAttributes: attributes, wMaxPacketSize: max_packet_size,
+                             bInterval: 1, extra: vec![] }
+    }
+    let config = ConfigDescriptor {
+        wTotalLength: 0, bNumInterfaces: 1, bConfigurationValue: 1, iConfiguration: 0, bmAttributes: 0x80,
+        bMaxPower: 50, extra: vec![],
+        interfaces: vec![InterfaceDescriptor {
+            bInterfaceNumber: 0, bAlternateSetting: 0, bNumEndpoints: 3, bInterfaceClass: 0xff,
+            bInterfaceSubClass: 0, bInterfaceProtocol: 0, iInterface: 0, extra: vec![],
+            endpoints: vec![endpoint(0x81, 2, 512), endpoint(0x82, 3, 64), endpoint(0x83, 1, 192)],
+        }],
+    };
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<StdBufTransfer<Vec<u8>>>> =
+        AsyncDevice::with_submit_check(mock.device().unwrap(), config);
+    assert_eq!(device.submit_check().unwrap().bConfigurationValue, 1);
+
+    device.submit(Box::new(StdBufTransfer::bulk(0x81, UrbFlags::empty(), vec![0; 512]))).unwrap();
+    // the kernel turns bulk urbs to interrupt endpoints into interrupt urbs
+    device.submit(Box::new(StdBufTransfer::bulk(0x82, UrbFlags::empty(), vec![0; 64]))).unwrap();
+    device.submit(Box::new(StdBufTransfer::isochronous(0x83, UrbFlags::URB_ISO_ASAP, vec![0; 192]))).unwrap();
+    assert_eq!(mock.take_events().len(), 3);
+
+    let rejected = [
+        (StdBufTransfer::bulk(0x84, UrbFlags::empty(), vec![0; 8]), "endpoint 0x84 is not in configuration 1"),
+        (StdBufTransfer::interrupt(0x81, UrbFlags::empty(), vec![0; 8]), "Interrupt transfer to Bulk endpoint 0x81"),
+        (StdBufTransfer::isochronous(0x83, UrbFlags::URB_ISO_ASAP, vec![0; 193]),
+         "packet 0 of 193 bytes exceeds the 192 bytes per interval of endpoint 0x83"),
+    ];
+    for (xfer, message) in rejected {
+        let err = device.submit(Box::new(xfer)).unwrap_err();
+        assert_eq!((err.kind(), err.to_string()), (ErrorKind::InvalidParam, message.to_string()));
+    }
+    assert!(mock.take_events().is_empty());
+
+    device.set_submit_check(None);
+    device.submit(Box::new(StdBufTransfer::bulk(0x84, UrbFlags::empty(), vec![0; 8]))).unwrap();
+}
+
+#[test]
+fn unsupported_flags_rejected() {
+    let mock = MockBackend::new().unwrap();
+    mock.set_capabilities(Capabilities::BULK_CONTINUATION);
+    let mut device: AsyncDevice<Box<BulkTransfer<Vec<u8>>>> = mock.device().unwrap().into();
+
+    let xfer = BulkTransfer::new(0x02, UrbFlags::empty(), vec![0; 512]).with_zlp();
+    let (err, _xfer) = device.submit_give_back_on_fail(Box::new(xfer)).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(mock.pending_count(), 0);
+
+    device.submit(Box::new(BulkTransfer::new(0x02, UrbFlags::URB_BULK_CONTINUATION, vec![0; 512]))).unwrap();
+    assert_eq!(mock.pending_count(), 1);
+}
+
+#[test]
+fn control_transfer_partial_length() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<ControlTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+
+    let mut xfer = ControlTransferMut::new(SetupDirection::HostToDevice, SetupType::Vendor, SetupRecipient::Device,
+                                           0x20, 0, 0, UrbFlags::empty(), vec![0; 72]);
+    xfer.payload_mut()[..3].copy_from_slice(&[1, 2, 3]);
+    mock.push(0, MockResponse::Complete(vec![]));
+    device.submit(Box::new(xfer.with_length(3))).unwrap();
+    let (_slot, mut xfer, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(result, TransferResult::Completed { len: 3 });
+
+    // reuse the buffer, this time sending all of it
+    xfer.set_length(None);
+    mock.push(0, MockResponse::Complete(vec![]));
+    device.submit(xfer).unwrap();
+    device.reap_timeout(Duration::from_secs(1)).unwrap();
+
+    assert_eq!(mock.take_events(), vec![
+        MockEvent::Control { request_type: 0x40, request: 0x20, value: 0, index: 0, length: 3, data: vec![1, 2, 3] },
+        MockEvent::Control { request_type: 0x40, request: 0x20, value: 0, index: 0, length: 64, data: vec![1, 2, 3].into_iter().chain(vec![0; 61]).collect() },
+    ]);
+}
+
+#[test]
+fn control_transfer_setup_reuse() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<ControlTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+
+    let write_register = Setup::new(SetupDirection::HostToDevice, SetupType::Vendor, SetupRecipient::Device,
+                                    0x06, 0, 0, 0);
+    let mut xfer = Box::new(ControlTransferMut::from_setup(write_register, vec![0; 8 + 2]));
+    for register in 0x10..0x12 {
+        xfer.set_index(register);
+        xfer.set_value(register * 2);
+        xfer.payload_mut().copy_from_slice(&register.to_le_bytes());
+        mock.push(0, MockResponse::Complete(vec![]));
+        device.submit(xfer).unwrap();
+        let (_slot, reaped, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(result, TransferResult::Completed { len: 2 });
+        xfer = reaped;
+    }
+    assert_eq!(xfer.setup(), &Setup { wValue: 0x22, wIndex: 0x11, wLength: 2, ..write_register });
+
+    mock.push(0, MockResponse::Complete(vec![]));
+    device.submit_control_setup(write_register, vec![0; 8]).unwrap();
+    device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(device.submit_control_setup(write_register, vec![0; 4]).unwrap_err().kind(), ErrorKind::InvalidParam);
+
+    assert_eq!(mock.take_events(), vec![
+        MockEvent::Control { request_type: 0x40, request: 0x06, value: 0x20, index: 0x10, length: 2, data: vec![0x10, 0] },
+        MockEvent::Control { request_type: 0x40, request: 0x06, value: 0x22, index: 0x11, length: 2, data: vec![0x11, 0] },
+        MockEvent::Control { request_type: 0x40, request: 0x06, value: 0, index: 0, length: 0, data: vec![] },
+    ]);
+}
+
+#[test]
+fn control_batch() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<ControlTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+
+    let write_reg = Setup::new(SetupDirection::HostToDevice, SetupType::Vendor, SetupRecipient::Device, 0x01, 0, 0, 0);
+    let read_id = Setup::new(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Device, 0x02, 0, 0, 4);
+    let mut batch = ControlBatch::new();
+    batch.write(Setup { wIndex: 0x10, ..write_reg }, &[1])
+        .write(Setup { wIndex: 0x11, ..write_reg }, &[2])
+        .read(read_id);
+    assert_eq!(batch.len(), 3);
+
+    // every request is sent, whatever becomes of the others
+    mock.push(0, MockResponse::Complete(vec![]));
+    mock.push(0, MockResponse::Fail(libc::EPIPE));
+    mock.push(0x80, MockResponse::Complete(vec![0xaa, 0xbb]));
+    let replies = batch.run(&mut device, Duration::from_secs(1)).unwrap();
+    let results: Vec<_> = replies.iter().map(|r| r.result).collect();
+    assert_eq!(results, vec![TransferResult::Completed { len: 1 }, TransferResult::Stalled, TransferResult::Completed { len: 2 }]);
+    assert_eq!((replies[0].data.clone(), replies[2].data.clone()), (vec![], vec![0xaa, 0xbb]));
+    assert_eq!(replies[1].setup, Setup { wIndex: 0x11, wLength: 1, ..write_reg });
+    assert_eq!(mock.take_events().len(), 3);
+
+    // all or nothing stops at the failure
+    let batch = batch.clone().all_or_nothing(true);
+    mock.push(0, MockResponse::Complete(vec![]));
+    mock.push(0, MockResponse::Fail(libc::EPIPE));
+    let err = batch.run(&mut device, Duration::from_secs(1)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Stall);
+    assert_eq!(mock.take_events().len(), 2);
+    assert_eq!(device.pending_count(), 0);
+
+    // a request that never completes is cancelled
+    let err = batch.run(&mut device, Duration::from_millis(10)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Timeout);
+    assert_eq!(device.pending_count(), 0);
+}
+
+#[test]
+fn async_submit_control() {
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<ControlTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+
+    mock.push(0x80, MockResponse::Complete(vec![1, 2]));
+    let slot = device.submit_control(SetupDirection::DeviceToHost, SetupType::Vendor, SetupRecipient::Interface,
+                                     0x33, 0x10, 2, vec![0; 8 + 4]).unwrap();
+    let (reaped, xfer, result) = device.reap_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!((reaped, result), (slot, TransferResult::Completed { len: 2 }));
+    assert_eq!(&xfer.payload()[..2], &[1, 2]);
+    assert_eq!(mock.take_events(), vec![
+        MockEvent::Control { request_type: 0xc1, request: 0x33, value: 0x10, index: 2, length: 4, data: vec![] },
+    ]);
+
+    let err = device.submit_control(SetupDirection::HostToDevice, SetupType::Vendor, SetupRecipient::Device,
+                                    0x34, 0, 0, vec![0; 4]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidParam);
+}
+
+#[cfg(feature="reactor")]
+#[test]
+fn reactor_devices_and_timers() {
+    const DEVICE: Token = Token(1);
+    const TIMER: Token = Token(2);
+
+    let mock = MockBackend::new().unwrap();
+    let mut device: AsyncDevice<Box<BulkTransferMut<Vec<u8>>>> = mock.device().unwrap().into();
+    let mut reactor = Reactor::new().unwrap();
+    reactor.register_device(&device, DEVICE).unwrap();
+    let mut events = Vec::new();
+    reactor.poll(&mut events, Some(Duration::from_millis(10))).unwrap();
+    assert!(events.is_empty());
+
+    reactor.add_timer(TIMER, Duration::from_millis(5), false).unwrap();
+    reactor.poll(&mut events, Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(events, vec![ReactorEvent { token: TIMER, hangup: false }]);
+    // a one-shot timer is reported once
+    reactor.poll(&mut events, Some(Duration::from_millis(20))).unwrap();
+    assert!(events.is_empty());
+
+    device.submit(Box::new(BulkTransferMut::new(0x81, UrbFlags::empty(), vec![0; 8]))).unwrap();
+    mock.push(0x81, MockResponse::Complete(vec![7; 3]));
+    reactor.poll(&mut events, Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(events, vec![ReactorEvent { token: DEVICE, hangup: false }]);
+    let (_slot, _xfer, result) = device.reap_nowait().unwrap();
+    assert_eq!(result, TransferResult::Completed { len: 3 });
+    reactor.poll(&mut events, Some(Duration::from_millis(10))).unwrap();
+    assert!(events.is_empty());
+
+    reactor.add_timer(TIMER, Duration::from_millis(1), true).unwrap();
+    reactor.cancel_timer(TIMER).unwrap();
+    assert_eq!(reactor.cancel_timer(TIMER).unwrap_err().kind(), ErrorKind::NotFound);
+    reactor.deregister(&device).unwrap();
+    reactor.poll(&mut events, Some(Duration::from_millis(10))).unwrap();
+    assert!(events.is_empty());
+}
+
+#[cfg(feature="async")]
+#[test]
+fn future_device_unpolled_driver() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn woken(flag: &Flag) -> bool {
+        for _ in 0..100 {
+            if flag.0.swap(false, Ordering::SeqCst) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    let mock = MockBackend::new().unwrap();
+    let device = FutureDevice::new(AsyncDevice::<BulkIn>::from(mock.device().unwrap())).unwrap();
+    let (flag_a, flag_b) = (Arc::new(Flag(AtomicBool::new(false))), Arc::new(Flag(AtomicBool::new(false))));
+    let (waker_a, waker_b) = (Waker::from(flag_a.clone()), Waker::from(flag_b.clone()));
+    let mut a = device.submit_async(bulk_in(0x81));
+    let mut b = device.submit_async(bulk_in(0x82));
+
+    // `a` watches the reactor on behalf of both
+    assert!(Pin::new(&mut a).poll(&mut Context::from_waker(&waker_a)).is_pending());
+    assert!(Pin::new(&mut b).poll(&mut Context::from_waker(&waker_b)).is_pending());
+    mock.push(0x81, MockResponse::Complete(vec![1; 8]));
+    assert!(woken(&flag_a));
+
+    // `a` is left unpolled, as if it lost a `select!`, and `b` reaps its completion for it
+    assert!(Pin::new(&mut b).poll(&mut Context::from_waker(&waker_b)).is_pending());
+    flag_b.0.store(false, Ordering::SeqCst);
+    mock.push(0x82, MockResponse::Complete(vec![2; 8]));
+    assert!(woken(&flag_b));
+    match Pin::new(&mut b).poll(&mut Context::from_waker(&waker_b)) {
+        Poll::Ready(Ok((_xfer, result))) => assert_eq!(result, TransferResult::Completed { len: 8 }),
+        poll => panic!("{:?}", poll.map(|result| result.map(|(_, result)| result))),
+    }
+    match Pin::new(&mut a).poll(&mut Context::from_waker(&waker_a)) {
+        Poll::Ready(Ok((_xfer, result))) => assert_eq!(result, TransferResult::Completed { len: 8 }),
+        poll => panic!("{:?}", poll.map(|result| result.map(|(_, result)| result))),
+    }
+}
+
+// A configuration with a UAC1 stereo 16 bit playback interface 1, whose alternate setting 1
+// has an asynchronous data endpoint 0x01 and feedback endpoint 0x81.
+#[cfg(feature="uac")]
+fn uac_playback_config(rate: u32, data_packet: u16, feedback_packet: u16, interval: u8) -> Vec<u8> {
+    let rate = rate.to_le_bytes();
+    let config = vec![
+        9, 2, 70, 0, 1, 1, 0, 0x80, 50,
+        9, 4, 1, 0, 0, 1, 2, 0, 0,
+        9, 4, 1, 1, 2, 1, 2, 0, 0,
+        7, 0x24, 0x01, 1, 1, 1, 0,
+        11, 0x24, 0x02, 1, 2, 2, 16, 1, rate[0], rate[1], rate[2],
+        9, 5, 0x01, 0x05, data_packet as u8, (data_packet >> 8) as u8, interval, 0, 0x81,
+        7, 0x25, 0x01, 1, 0, 0, 0,
+        9, 5, 0x81, 0x11, feedback_packet as u8, (feedback_packet >> 8) as u8, interval, 5, 0,
+    ];
+    assert_eq!(config.len(), 70);
+    config
+}
+
+// Script the reads of `claim()` for `config`.
+#[cfg(any(feature="uac", feature="dfu", feature="usbtmc"))]
+fn push_claim(mock: &MockBackend, config: &[u8]) {
+    mock.push(0x80, MockResponse::Complete(vec![1]));
+    mock.push(0x80, MockResponse::Complete(vec![18, 1, 0x00, 0x02, 0, 0, 0, 64, 0x34, 0x12, 0x78, 0x56, 0x01, 0x01, 1, 2, 3, 1]));
+    mock.push(0x80, MockResponse::Complete(config[..9].to_vec()));
+    mock.push(0x80, MockResponse::Complete(config.to_vec()));
+    mock.push(0x80, MockResponse::Complete(vec![0]));
+}
+
+// Lengths of the transfers submitted to `endpoint` since the last call.
+#[cfg(feature="uac")]
+fn submitted_lengths(mock: &MockBackend, endpoint: u8) -> Vec<usize> {
+    mock.take_events().into_iter().filter_map(|event| match event {
+        MockEvent::Transfer { endpoint: ep, length, .. } if ep == endpoint => Some(length),
+        _ => None,
+    }).collect()
+}
+
+#[cfg(feature="uac")]
+#[test]
+fn uac_full_speed_feedback() {
+    let mock = MockBackend::new().unwrap();
+    mock.set_speed(Speed::Full);
+    let device = mock.device().unwrap();
+    let config = uac_playback_config(44100, 192, 3, 1);
+    push_claim(&mock, &config);
+    let mut uac = UacStreaming::new(device.claim(1).unwrap()).unwrap();
+    let format = uac.formats().remove(0);
+    assert_eq!((format.endpoint, format.feedback_endpoint, format.feedback_packet_size), (0x01, Some(0x81), 3));
+
+    mock.push(0, MockResponse::Complete(vec![]));
+    mock.take_events();
+    let mut stream = uac.stream(&format, 44100, 2).unwrap();
+    // 44.1 frames of 4 bytes per packet: 352 frames in the first 8 packets, and the fraction
+    // carried over makes 353 in the next 8
+    assert_eq!(submitted_lengths(&mock, 0x01), [1408, 1412]);
+    let events = mock.take_events();
+    assert!(events.is_empty(), "{:?}", events);
+
+    let mut played = 0;
+    let mut play = |stream: &mut UacStream| {
+        mock.push(0x01, MockResponse::Complete(vec![]));
+        stream.next_playback(|samples| played = samples.len()).unwrap();
+        played
+    };
+
+    // 10.14 feedback of 45 frames per frame replaces the nominal rate
+    mock.push(0x81, MockResponse::Complete(vec![0x00, 0x40, 0x0b]));
+    assert_eq!(play(&mut stream), 8 * 45 * 4);
+    assert!((stream.rate_hz() - 45000.0).abs() < 0.1);
+    // the feedback transfer went straight back with its 3 byte packet
+    assert_eq!(submitted_lengths(&mock, 0x81), [3]);
+
+    // a value more than twice the nominal rate is ignored
+    mock.push(0x81, MockResponse::Complete(vec![0x00, 0x00, 0x19]));
+    assert_eq!(play(&mut stream), 8 * 45 * 4);
+    // and so is one under half of it
+    mock.push(0x81, MockResponse::Complete(vec![0x00, 0x40, 0x05]));
+    assert_eq!(play(&mut stream), 8 * 45 * 4);
+    assert!((stream.rate_hz() - 45000.0).abs() < 0.1);
+}
+
+#[cfg(feature="uac")]
+#[test]
+fn uac_high_speed_feedback() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    // packets every 8 microframes
+    let config = uac_playback_config(48000, 256, 4, 4);
+    push_claim(&mock, &config);
+    let mut uac = UacStreaming::new(device.claim(1).unwrap()).unwrap();
+    let format = uac.formats().remove(0);
+    assert_eq!(format.feedback_packet_size, 4);
+
+    mock.push(0, MockResponse::Complete(vec![]));
+    mock.take_events();
+    let mut stream = uac.stream(&format, 48000, 1).unwrap();
+    assert_eq!(submitted_lengths(&mock, 0x01), [8 * 48 * 4]);
+
+    // 16.16 feedback of 6.125 frames per microframe, 49 per packet
+    mock.push(0x81, MockResponse::Complete(vec![0x00, 0x20, 0x06, 0x00]));
+    mock.push(0x01, MockResponse::Complete(vec![]));
+    let mut played = 0;
+    stream.next_playback(|samples| played = samples.len()).unwrap();
+    assert_eq!(played, 8 * 49 * 4);
+    assert!((stream.rate_hz() - 49000.0).abs() < 0.1);
+}
+
+#[cfg(feature="dfu")]
+#[test]
+fn dfu_get_status() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    let config = [
+        9, 2, 27, 0, 1, 1, 0, 0x80, 50,
+        9, 4, 0, 0, 0, 0xfe, 1, 2, 0,
+        9, 0x21, 0x0b, 0xff, 0x00, 0x00, 0x04, 0x1a, 0x01,
+    ];
+    push_claim(&mock, &config);
+    let dfu = Dfu::new(device.claim(0).unwrap()).unwrap();
+    assert_eq!(dfu.functional_descriptor().wTransferSize, 1024);
+
+    // bwPollTimeout is 24 bits, followed by bState
+    mock.push(0x80, MockResponse::Complete(vec![0x00, 0x56, 0x34, 0x12, 4, 0]));
+    let status = dfu.get_status().unwrap();
+    assert_eq!(status, DfuStatus { status: 0, poll_timeout: 0x123456, state: DfuState::DfuDnbusy, string_index: 0 });
+    mock.push(0x80, MockResponse::Complete(vec![0x07, 0, 0, 0, 10, 3]));
+    let status = dfu.get_status().unwrap();
+    assert_eq!((status.status_name(), status.state, status.string_index), ("errVERIFY", DfuState::DfuError, 3));
+
+    // short responses and unknown states are refused
+    mock.push(0x80, MockResponse::Complete(vec![0x00, 0x56, 0x34, 0x12, 4]));
+    assert_eq!(dfu.get_status().err().unwrap().kind(), ErrorKind::Other);
+    mock.push(0x80, MockResponse::Complete(vec![0x00, 0, 0, 0, 11, 0]));
+    assert_eq!(dfu.get_status().err().unwrap().kind(), ErrorKind::Other);
+}
+
+// A USBTMC interface 0 with bulk endpoints 0x81 and 0x02, claimed and with the claim's events
+// taken.
+#[cfg(feature="usbtmc")]
+fn usbtmc_claim<'a>(mock: &MockBackend, device: &'a Device) -> Usbtmc<'a> {
+    let config = [
+        9, 2, 32, 0, 1, 1, 0, 0x80, 50,
+        9, 4, 0, 0, 2, 0xfe, 3, 1, 0,
+        7, 5, 0x81, 2, 64, 0, 0,
+        7, 5, 0x02, 2, 64, 0, 0,
+    ];
+    push_claim(mock, &config);
+    let tmc = Usbtmc::new(device.claim(0).unwrap()).unwrap();
+    mock.take_events();
+    tmc
+}
+
+#[cfg(feature="usbtmc")]
+#[test]
+fn usbtmc_write_framing() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    let mut tmc = usbtmc_claim(&mock, &device);
+
+    mock.push(0x02, MockResponse::Complete(vec![]));
+    tmc.write(b"*IDN?\n").unwrap();
+    // DEV_DEP_MSG_OUT, tag 1 and its inverse, 6 bytes ending the message, padded to 4 bytes
+    assert_eq!(mock.take_events(), vec![MockEvent::Transfer {
+        endpoint: 0x02,
+        length: 20,
+        data: [&[1, 1, 0xfe, 0, 6, 0, 0, 0, 1, 0, 0, 0][..], b"*IDN?\n", &[0, 0]].concat(),
+    }]);
+
+    // tags run to 255, then skip 0
+    let mut tags = vec![];
+    for _ in 0..256 {
+        mock.push(0x02, MockResponse::Complete(vec![]));
+        tmc.write(b"").unwrap();
+        match mock.take_events().remove(0) {
+            MockEvent::Transfer { data, .. } => tags.push((data[1], data[2])),
+            event => panic!("{:?}", event),
+        }
+    }
+    assert_eq!(tags[253..], [(255, 0), (1, 0xfe), (2, 0xfd)]);
+}
+
+#[cfg(feature="usbtmc")]
+#[test]
+fn usbtmc_read_reassembly() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    let mut tmc = usbtmc_claim(&mock, &device);
+    tmc.set_transfer_size(8);
+
+    // a first message of 8 bytes split over two transfers, then the last of 4 bytes
+    mock.push(0x02, MockResponse::Complete(vec![]));
+    mock.push(0x81, MockResponse::Complete([&[2, 1, 0xfe, 0, 8, 0, 0, 0, 0, 0, 0, 0][..], b"0123"].concat()));
+    mock.push(0x81, MockResponse::Complete(b"4567".to_vec()));
+    mock.push(0x02, MockResponse::Complete(vec![]));
+    mock.push(0x81, MockResponse::Complete([&[2, 2, 0xfd, 0, 4, 0, 0, 0, 1, 0, 0, 0][..], b"89AB"].concat()));
+    assert_eq!(tmc.read().unwrap(), b"0123456789AB");
+
+    let requests: Vec<_> = mock.take_events().into_iter().filter_map(|event| match event {
+        MockEvent::Transfer { endpoint: 0x02, data, .. } => Some(data),
+        _ => None,
+    }).collect();
+    assert_eq!(requests, [
+        vec![2, 1, 0xfe, 0, 8, 0, 0, 0, 0, 0, 0, 0],
+        vec![2, 2, 0xfd, 0, 8, 0, 0, 0, 0, 0, 0, 0],
+    ]);
+
+    // a response to another request is refused
+    mock.push(0x02, MockResponse::Complete(vec![]));
+    mock.push(0x81, MockResponse::Complete(vec![2, 2, 0xfd, 0, 4, 0, 0, 0, 1, 0, 0, 0]));
+    assert_eq!(tmc.read().err().unwrap().kind(), ErrorKind::Other);
+}
+
+#[cfg(feature="usbtmc")]
+#[test]
+fn usbtmc_abort_bulk_in() {
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+    let mut tmc = usbtmc_claim(&mock, &device);
+
+    mock.push(0x80, MockResponse::Complete(vec![1, 0]));
+    mock.push(0x81, MockResponse::Complete(vec![0; 10]));
+    // still pending with bmAbortBulkIn set: more data is queued on the endpoint
+    mock.push(0x80, MockResponse::Complete(vec![2, 1, 0, 0, 0, 0, 0, 0]));
+    mock.push(0x81, MockResponse::Complete(vec![0; 64]));
+    mock.push(0x81, MockResponse::Complete(vec![]));
+    mock.push(0x80, MockResponse::Complete(vec![1, 0, 0, 0, 74, 0, 0, 0]));
+    tmc.abort_bulk_in().unwrap();
+
+    let check = MockEvent::Control { request_type: 0xa2, request: 4, value: 0, index: 0x81, length: 8, data: vec![] };
+    let read = MockEvent::Transfer { endpoint: 0x81, length: 64, data: vec![] };
+    assert_eq!(mock.take_events(), vec![
+        MockEvent::Control { request_type: 0xa2, request: 3, value: 0, index: 0x81, length: 2, data: vec![] },
+        read.clone(),
+        check.clone(),
+        read.clone(),
+        read,
+        check,
+    ]);
+}
+
+#[test]
+fn ms_os_10_descriptors() {
+    let utf16 = |s: &str| -> Vec<u8> { s.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+    let mock = MockBackend::new().unwrap();
+    let device = mock.device().unwrap();
+
+    mock.push(0x80, MockResponse::Complete([&[18, 3][..], &utf16("MSFT100"), &[0x20, 0]].concat()));
+    assert_eq!(device.get_ms_os_vendor_code(1000).unwrap(), Some(0x20));
+
+    // the extended compat ID descriptor is read twice, for its length and then whole
+    let compat_ids = [
+        40, 0, 0, 0, 0x00, 0x01, 4, 0, 1, 0, 0, 0, 0, 0, 0, 0,
+        0, 1, b'W', b'I', b'N', b'U', b'S', b'B', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    mock.push(0x80, MockResponse::Complete(compat_ids[..16].to_vec()));
+    mock.push(0x80, MockResponse::Complete(compat_ids.to_vec()));
+    assert_eq!(device.get_ms_os_compat_ids(0x20, 1000).unwrap(), [
+        MsCompatId { interface: Some(0), compatible_id: "WINUSB".into(), sub_compatible_id: "".into() },
+    ]);
+
+    let properties = [
+        &[146, 0, 0, 0, 0x00, 0x01, 5, 0, 1, 0][..],
+        &[136, 0, 0, 0, 7, 0, 0, 0, 42, 0], &utf16("DeviceInterfaceGUIDs\0"),
+        &[80, 0, 0, 0], &utf16("{88BAE032-5A81-49F0-BC3D-A4FF138216D6}\0\0"),
+    ].concat();
+    mock.push(0x80, MockResponse::Complete(properties[..16].to_vec()));
+    mock.push(0x80, MockResponse::Complete(properties.clone()));
+    let parsed = device.get_ms_os_properties(0x20, 2, 1000).unwrap();
+    assert_eq!(parsed.len(), 1);
+    assert_eq!((parsed[0].interface, parsed[0].data_type, &parsed[0].name[..]), (Some(2), 7, "DeviceInterfaceGUIDs"));
+    assert_eq!(parsed[0].string_value().unwrap(), "{88BAE032-5A81-49F0-BC3D-A4FF138216D6}");
+
+    let events = mock.take_events();
+    let requests: Vec<_> = events.iter().map(|event| match *event {
+        MockEvent::Control { request_type, request, value, index, length, .. } => (request_type, request, value, index, length),
+        ref event => panic!("{:?}", event),
+    }).collect();
+    assert_eq!(requests, [
+        (0x80, 6, 0x03ee, 0, 18),
+        (0xc0, 0x20, 0, 4, 16),
+        (0xc0, 0x20, 0, 4, 40),
+        (0xc1, 0x20, 0x0200, 5, 16),
+        (0xc1, 0x20, 0x0200, 5, 146),
+    ]);
+
+    // a property whose data runs past its dwSize
+    let mut bad = properties.clone();
+    bad[10 + 10 + 42] = 82;
+    mock.push(0x80, MockResponse::Complete(bad[..16].to_vec()));
+    mock.push(0x80, MockResponse::Complete(bad));
+    assert!(device.get_ms_os_properties(0x20, 2, 1000).is_err());
+}
@@ -0,0 +1,116 @@
+//! Support for tests against the Linux "gadget zero" test device.
+//!
+//! Gadget zero is provided by the `g_zero` module, or by the `SourceSink` and `Loopback`
+//! functions of a configfs gadget with the same IDs.  Without real gadget hardware, load it
+//! on top of the `dummy_hcd` virtual host controller:
+//!
+//! ```text
+//! modprobe dummy_hcd
+//! modprobe g_zero
+//! ```
+//!
+//! The device offers two configurations: source/sink, which streams data on its IN endpoints
+//! and swallows data on its OUT endpoints, and loopback, which echoes OUT data back on IN.
+//! Tests find the device themselves and skip, with a message, when it isn't present.  Access
+//! to its usbfs node needs root or a suitable udev rule.
+
+#![allow(dead_code)]
+
+use std::ops::Deref;
+use std::sync::{Mutex, MutexGuard};
+
+use usbfs::*;
+
+pub const GADGET_ZERO_VENDOR: u16 = 0x0525;
+pub const GADGET_ZERO_PRODUCT: u16 = 0xa4a0;
+
+/// `bConfigurationValue` of the source/sink configuration.
+pub const SOURCESINK: u8 = 3;
+/// `bConfigurationValue` of the loopback configuration.
+pub const LOOPBACK: u8 = 2;
+
+/// Vendor request storing its data stage in the source/sink function.
+pub const CONTROL_WRITE: u8 = 0x5b;
+/// Vendor request returning what `CONTROL_WRITE` stored.
+pub const CONTROL_READ: u8 = 0x5c;
+
+pub const TIMEOUT_MS: u32 = 1000;
+
+// Tests switch configurations, so only one may use the gadget at a time.
+static GADGET_LOCK: Mutex<()> = Mutex::new(());
+
+/// Gadget zero, opened for the exclusive use of one test.
+pub struct Gadget {
+    pub device: Device,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Deref for Gadget {
+    type Target = Device;
+
+    fn deref(&self) -> &Device {
+        &self.device
+    }
+}
+
+impl Gadget {
+    /// Another handle on the device, for handing to an `AsyncDevice`.
+    pub fn try_clone(&self) -> Device {
+        self.device.try_clone().unwrap()
+    }
+}
+
+/// Open gadget zero in configuration `config`, with interface 0 claimed away from any kernel
+/// driver (`usbtest` binds to it).  `None` if there's no gadget zero.
+pub fn open(config: u8) -> Option<Gadget> {
+    let lock = GADGET_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let info = deviceinfo_find(GADGET_ZERO_VENDOR, GADGET_ZERO_PRODUCT)?;
+    let device = Device::new(&info).expect("can't open gadget zero");
+
+    if device.get_configuration(TIMEOUT_MS).expect("GET_CONFIGURATION failed") != config {
+        // interfaces bound to a driver keep the configuration from changing
+        let _ = device.disconnect_claim(0, DisconnectClaimFlags::empty(), "");
+        let _ = device.release_interface(0);
+        device.set_configuration(config as u32).expect("can't select configuration");
+    }
+    device.disconnect_claim(0, DisconnectClaimFlags::empty(), "").expect("can't claim interface 0");
+    Some(Gadget{device, _lock: lock})
+}
+
+/// First endpoint of `transfer_type` in direction `is_in` among the alternate settings of
+/// `intf`, along with the alternate setting it belongs to.
+pub fn find_endpoint(intf: &Interface, transfer_type: UrbType, is_in: bool) -> Option<(u8, EndpointDescriptor)> {
+    intf.altsettings().iter().find_map(|alt| {
+        alt.endpoints.iter()
+            .find(|ep| ep.transfer_type() == transfer_type && ep.is_in() == is_in)
+            .map(|ep| (alt.bAlternateSetting, ep.clone()))
+    })
+}
+
+/// Skip the test, returning early, unless gadget zero is present.  Evaluates to the opened
+/// `Gadget` in configuration `$config`.
+macro_rules! gadget_or_skip {
+    ($config:expr) => {
+        match support::open($config) {
+            Some(device) => device,
+            None => {
+                eprintln!("gadget zero not found, skipping");
+                return;
+            }
+        }
+    };
+}
+
+/// Skip the test, returning early, if `$opt` is `None`, eg. for endpoint types the gadget's
+/// host controller doesn't support.
+macro_rules! some_or_skip {
+    ($opt:expr, $what:expr) => {
+        match $opt {
+            Some(value) => value,
+            None => {
+                eprintln!("{} not available, skipping", $what);
+                return;
+            }
+        }
+    };
+}